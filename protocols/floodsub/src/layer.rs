@@ -18,11 +18,12 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::protocol::{FloodsubProtocol, FloodsubMessage, FloodsubRpc, FloodsubSubscription, FloodsubSubscriptionAction};
+use crate::protocol::{FloodsubProtocol, FloodsubMessage, RawFloodsubMessage, RawFloodsubRpc, FloodsubSubscription, FloodsubSubscriptionAction, MessageId, ValidationError};
 use crate::topic::Topic;
-use crate::FloodsubConfig;
-use cuckoofilter::CuckooFilter;
+use crate::{FloodsubConfig, MessageAuthenticity};
 use fnv::FnvHashSet;
+use log::{debug, warn};
+use lru_time_cache::LruCache;
 use libp2p_core::{Multiaddr, PeerId, connection::ConnectionId};
 use libp2p_swarm::{
     NetworkBehaviour,
@@ -36,13 +37,14 @@ use libp2p_swarm::{
 use rand;
 use smallvec::SmallVec;
 use std::{collections::VecDeque, iter};
-use std::collections::hash_map::{DefaultHasher, HashMap};
+use std::collections::HashMap;
 use std::task::{Context, Poll};
+use std::time::Instant;
 
 /// Network behaviour that handles the floodsub protocol.
 pub struct Floodsub {
     /// Events that need to be yielded to the outside when polling.
-    events: VecDeque<NetworkBehaviourAction<FloodsubRpc, FloodsubEvent>>,
+    events: VecDeque<NetworkBehaviourAction<RawFloodsubRpc, FloodsubEvent>>,
 
     config: FloodsubConfig,
 
@@ -58,9 +60,9 @@ pub struct Floodsub {
     // erroneously.
     subscribed_topics: SmallVec<[Topic; 16]>,
 
-    // We keep track of the messages we received (in the format `hash(source ID, seq_no)`) so that
-    // we don't dispatch the same message twice if we receive it twice on the network.
-    received: CuckooFilter<DefaultHasher>,
+    // We keep track of the ids of the messages we received within the configured time window so
+    // that we don't dispatch the same message twice if we receive it twice on the network.
+    received: LruCache<MessageId, Instant>,
 }
 
 impl Floodsub {
@@ -73,11 +75,11 @@ impl Floodsub {
     pub fn from_config(config: FloodsubConfig) -> Self {
         Floodsub {
             events: VecDeque::new(),
+            received: LruCache::with_capacity(config.duplicate_cache_capacity),
             config,
             target_peers: FnvHashSet::default(),
             connected_peers: HashMap::new(),
             subscribed_topics: SmallVec::new(),
-            received: CuckooFilter::new(),
         }
     }
 
@@ -90,7 +92,7 @@ impl Floodsub {
                 self.events.push_back(NetworkBehaviourAction::NotifyHandler {
                     peer_id: peer_id.clone(),
                     handler: NotifyHandler::Any,
-                    event: FloodsubRpc {
+                    event: RawFloodsubRpc {
                         messages: Vec::new(),
                         subscriptions: vec![FloodsubSubscription {
                             topic,
@@ -126,7 +128,7 @@ impl Floodsub {
             self.events.push_back(NetworkBehaviourAction::NotifyHandler {
                 peer_id: peer.clone(),
                 handler: NotifyHandler::Any,
-                event: FloodsubRpc {
+                event: RawFloodsubRpc {
                     messages: Vec::new(),
                     subscriptions: vec![FloodsubSubscription {
                         topic: topic.clone(),
@@ -157,7 +159,7 @@ impl Floodsub {
             self.events.push_back(NetworkBehaviourAction::NotifyHandler {
                 peer_id: peer.clone(),
                 handler: NotifyHandler::Any,
-                event: FloodsubRpc {
+                event: RawFloodsubRpc {
                     messages: Vec::new(),
                     subscriptions: vec![FloodsubSubscription {
                         topic: topic.clone(),
@@ -194,24 +196,43 @@ impl Floodsub {
     }
 
     fn publish_many_inner(&mut self, topic: impl IntoIterator<Item = impl Into<Topic>>, data: impl Into<Vec<u8>>, check_self_subscriptions: bool) {
-        let message = FloodsubMessage {
-            source: self.config.local_peer_id.clone(),
+        let source = match &self.config.message_authenticity {
+            MessageAuthenticity::Signed(keypair) => Some(keypair.public().into_peer_id()),
+            MessageAuthenticity::Author => Some(self.config.local_peer_id.clone()),
+            MessageAuthenticity::Anonymous => None,
+        };
+        // If the sequence numbers are predictable, then an attacker could flood the network
+        // with packets with the predetermined sequence numbers and absorb our legitimate
+        // messages. We therefore use a random number.
+        let sequence_number = match source {
+            Some(_) => rand::random::<[u8; 20]>().to_vec(),
+            None => Vec::new(),
+        };
+        let mut message = FloodsubMessage {
+            source,
             data: data.into(),
-            // If the sequence numbers are predictable, then an attacker could flood the network
-            // with packets with the predetermined sequence numbers and absorb our legitimate
-            // messages. We therefore use a random number.
-            sequence_number: rand::random::<[u8; 20]>().to_vec(),
+            sequence_number,
             topics: topic.into_iter().map(Into::into).collect(),
+            signature: None,
+            key: None,
         };
 
+        if let MessageAuthenticity::Signed(keypair) = &self.config.message_authenticity {
+            if let Err(err) = message.sign(keypair) {
+                warn!("Failed to sign message, not publishing it: {:?}", err);
+                return
+            }
+        }
+
         let self_subscribed = self.subscribed_topics.iter().any(|t| message.topics.iter().any(|u| t == u));
         if self_subscribed {
-            self.received.add(&message);
+            self.received.insert((self.config.message_id_fn)(&message), Instant::now());
             if self.config.subscribe_local_messages {
                 self.events.push_back(
                     NetworkBehaviourAction::GenerateEvent(FloodsubEvent::Message(message.clone())));
             }
         }
+        let raw_message = RawFloodsubMessage::from(message.clone());
         // Don't publish the message if we have to check subscriptions
         // and we're not subscribed ourselves to any of the topics.
         if check_self_subscriptions && !self_subscribed {
@@ -227,74 +248,43 @@ impl Floodsub {
             self.events.push_back(NetworkBehaviourAction::NotifyHandler {
                 peer_id: peer_id.clone(),
                 handler: NotifyHandler::Any,
-                event: FloodsubRpc {
+                event: RawFloodsubRpc {
                     subscriptions: Vec::new(),
-                    messages: vec![message.clone()],
+                    messages: vec![raw_message.clone()],
                 }
             });
         }
     }
 }
 
-impl NetworkBehaviour for Floodsub {
-    type ProtocolsHandler = OneShotHandler<FloodsubProtocol, FloodsubRpc, InnerMessage>;
-    type OutEvent = FloodsubEvent;
-
-    fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        Default::default()
-    }
-
-    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
-        Vec::new()
-    }
-
-    fn inject_connected(&mut self, id: &PeerId) {
-        // We need to send our subscriptions to the newly-connected node.
-        if self.target_peers.contains(id) {
-            for topic in self.subscribed_topics.iter().cloned() {
-                self.events.push_back(NetworkBehaviourAction::NotifyHandler {
-                    peer_id: id.clone(),
-                    handler: NotifyHandler::Any,
-                    event: FloodsubRpc {
-                        messages: Vec::new(),
-                        subscriptions: vec![FloodsubSubscription {
-                            topic,
-                            action: FloodsubSubscriptionAction::Subscribe,
-                        }],
-                    },
-                });
+impl Floodsub {
+    /// Checks that a received message is acceptable under the configured `MessageAuthenticity`.
+    fn validate(&self, message: &RawFloodsubMessage) -> Result<(), ValidationError> {
+        match self.config.message_authenticity {
+            MessageAuthenticity::Signed(_) => message.verify_signature(),
+            MessageAuthenticity::Author if message.message.source.is_none() =>
+                Err(ValidationError::MissingSource),
+            MessageAuthenticity::Author | MessageAuthenticity::Anonymous => {
+                if message.message.signature.is_some() {
+                    message.verify_signature()
+                } else {
+                    Ok(())
+                }
             }
         }
-
-        self.connected_peers.insert(id.clone(), SmallVec::new());
     }
 
-    fn inject_disconnected(&mut self, id: &PeerId) {
-        let was_in = self.connected_peers.remove(id);
-        debug_assert!(was_in.is_some());
-
-        // We can be disconnected by the remote in case of inactivity for example, so we always
-        // try to reconnect.
-        if self.target_peers.contains(id) {
-            self.events.push_back(NetworkBehaviourAction::DialPeer {
-                peer_id: id.clone(),
-                condition: DialPeerCondition::Disconnected
-            });
+    /// Returns `true` if a message with the given id has been received within the configured
+    /// `duplicate_cache_ttl` before `now`.
+    fn is_duplicate_at(&self, message_id: &MessageId, now: Instant) -> bool {
+        match self.received.peek(message_id) {
+            Some(received) => now < *received + self.config.duplicate_cache_ttl,
+            None => false,
         }
     }
 
-    fn inject_event(
-        &mut self,
-        propagation_source: PeerId,
-        _connection: ConnectionId,
-        event: InnerMessage,
-    ) {
-        // We ignore successful sends or timeouts.
-        let event = match event {
-            InnerMessage::Rx(event) => event,
-            InnerMessage::Sent => return,
-        };
-
+    /// Handles an RPC received from `propagation_source` at the time `now`.
+    fn inject_rpc_at(&mut self, propagation_source: PeerId, event: RawFloodsubRpc, now: Instant) {
         // Update connected peers topics
         for subscription in event.subscriptions {
             let remote_peer_topics = self.connected_peers
@@ -323,15 +313,31 @@ impl NetworkBehaviour for Floodsub {
         }
 
         // List of messages we're going to propagate on the network.
-        let mut rpcs_to_dispatch: Vec<(PeerId, FloodsubRpc)> = Vec::new();
+        let mut rpcs_to_dispatch: Vec<(PeerId, RawFloodsubRpc)> = Vec::new();
+
+        for raw_message in event.messages {
+            // Use `self.received` to skip the messages that we have already received recently,
+            // before spending time on verifying their signatures.
+            let message_id = (self.config.message_id_fn)(&raw_message.message);
+            if self.is_duplicate_at(&message_id, now) {
+                continue;
+            }
 
-        for message in event.messages {
-            // Use `self.received` to skip the messages that we have already received in the past.
-            // Note that this can false positive.
-            if !self.received.test_and_add(&message) {
+            if let Err(error) = self.validate(&raw_message) {
+                debug!("Dropping invalid message from {:?}: {}", propagation_source, error);
+                self.events.push_back(NetworkBehaviourAction::GenerateEvent(FloodsubEvent::InvalidMessage {
+                    propagation_source: propagation_source.clone(),
+                    message: raw_message.message,
+                    error,
+                }));
                 continue;
             }
 
+            // Only valid messages are remembered, such that an invalid message cannot prevent
+            // a valid one with the same id from being delivered.
+            self.received.insert(message_id, now);
+            let message = &raw_message.message;
+
             // Add the message to be dispatched to the user.
             if self.subscribed_topics.iter().any(|t| message.topics.iter().any(|u| t == u)) {
                 let event = FloodsubEvent::Message(message.clone());
@@ -349,11 +355,11 @@ impl NetworkBehaviour for Floodsub {
                 }
 
                 if let Some(pos) = rpcs_to_dispatch.iter().position(|(p, _)| p == peer_id) {
-                    rpcs_to_dispatch[pos].1.messages.push(message.clone());
+                    rpcs_to_dispatch[pos].1.messages.push(raw_message.clone());
                 } else {
-                    rpcs_to_dispatch.push((peer_id.clone(), FloodsubRpc {
+                    rpcs_to_dispatch.push((peer_id.clone(), RawFloodsubRpc {
                         subscriptions: Vec::new(),
-                        messages: vec![message.clone()],
+                        messages: vec![raw_message.clone()],
                     }));
                 }
             }
@@ -367,6 +373,69 @@ impl NetworkBehaviour for Floodsub {
             });
         }
     }
+}
+
+impl NetworkBehaviour for Floodsub {
+    type ProtocolsHandler = OneShotHandler<FloodsubProtocol, RawFloodsubRpc, InnerMessage>;
+    type OutEvent = FloodsubEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        Default::default()
+    }
+
+    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, id: &PeerId) {
+        // We need to send our subscriptions to the newly-connected node.
+        if self.target_peers.contains(id) {
+            for topic in self.subscribed_topics.iter().cloned() {
+                self.events.push_back(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: id.clone(),
+                    handler: NotifyHandler::Any,
+                    event: RawFloodsubRpc {
+                        messages: Vec::new(),
+                        subscriptions: vec![FloodsubSubscription {
+                            topic,
+                            action: FloodsubSubscriptionAction::Subscribe,
+                        }],
+                    },
+                });
+            }
+        }
+
+        self.connected_peers.insert(id.clone(), SmallVec::new());
+    }
+
+    fn inject_disconnected(&mut self, id: &PeerId) {
+        let was_in = self.connected_peers.remove(id);
+        debug_assert!(was_in.is_some());
+
+        // We can be disconnected by the remote in case of inactivity for example, so we always
+        // try to reconnect.
+        if self.target_peers.contains(id) {
+            self.events.push_back(NetworkBehaviourAction::DialPeer {
+                peer_id: id.clone(),
+                condition: DialPeerCondition::Disconnected
+            });
+        }
+    }
+
+    fn inject_event(
+        &mut self,
+        propagation_source: PeerId,
+        _connection: ConnectionId,
+        event: InnerMessage,
+    ) {
+        // We ignore successful sends or timeouts.
+        let event = match event {
+            InnerMessage::Rx(event) => event,
+            InnerMessage::Sent => return,
+        };
+
+        self.inject_rpc_at(propagation_source, event, Instant::now());
+    }
 
     fn poll(
         &mut self,
@@ -389,14 +458,14 @@ impl NetworkBehaviour for Floodsub {
 /// Transmission between the `OneShotHandler` and the `FloodsubHandler`.
 pub enum InnerMessage {
     /// We received an RPC from a remote.
-    Rx(FloodsubRpc),
+    Rx(RawFloodsubRpc),
     /// We successfully sent an RPC request.
     Sent,
 }
 
-impl From<FloodsubRpc> for InnerMessage {
+impl From<RawFloodsubRpc> for InnerMessage {
     #[inline]
-    fn from(rpc: FloodsubRpc) -> InnerMessage {
+    fn from(rpc: RawFloodsubRpc) -> InnerMessage {
         InnerMessage::Rx(rpc)
     }
 }
@@ -429,4 +498,122 @@ pub enum FloodsubEvent {
         /// The topic it has subscribed from.
         topic: Topic,
     },
+
+    /// A received message failed validation and has been dropped.
+    InvalidMessage {
+        /// Remote that has sent us the message.
+        propagation_source: PeerId,
+        /// The rejected message.
+        message: FloodsubMessage,
+        /// Why the message has been rejected.
+        error: ValidationError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, time::Duration};
+
+    fn received_messages(floodsub: &mut Floodsub) -> usize {
+        floodsub.events
+            .drain(..)
+            .filter(|e| matches!(e, NetworkBehaviourAction::GenerateEvent(FloodsubEvent::Message(_))))
+            .count()
+    }
+
+    fn raw_rpc(message: &FloodsubMessage) -> RawFloodsubRpc {
+        RawFloodsubRpc {
+            messages: vec![message.clone().into()],
+            subscriptions: Vec::new(),
+        }
+    }
+
+    fn rpc(message: &FloodsubMessage) -> InnerMessage {
+        InnerMessage::Rx(raw_rpc(message))
+    }
+
+    #[test]
+    fn duplicates_are_dropped_within_ttl() {
+        let mut config = FloodsubConfig::new(PeerId::random());
+        config.duplicate_cache_ttl = Duration::from_millis(500);
+        let mut floodsub = Floodsub::from_config(config);
+        let topic = Topic::new("topic");
+        floodsub.subscribe(topic.clone());
+
+        let peer = PeerId::random();
+        floodsub.inject_connected(&peer);
+        let message = FloodsubMessage {
+            source: Some(PeerId::random()),
+            data: b"hello".to_vec(),
+            sequence_number: vec![1],
+            topics: vec![topic],
+            signature: None,
+            key: None,
+        };
+
+        let now = Instant::now();
+        floodsub.inject_rpc_at(peer.clone(), raw_rpc(&message), now);
+        floodsub.inject_rpc_at(peer.clone(), raw_rpc(&message), now + Duration::from_millis(499));
+        assert_eq!(received_messages(&mut floodsub), 1);
+
+        let expired = now + Duration::from_millis(500);
+        floodsub.inject_rpc_at(peer, raw_rpc(&message), expired);
+        assert_eq!(received_messages(&mut floodsub), 1, "Message id should have expired");
+    }
+
+    #[test]
+    fn message_id_fn_is_used_for_duplicates() {
+        let mut config = FloodsubConfig::new(PeerId::random());
+        config.message_id_fn = Arc::new(|message: &FloodsubMessage| MessageId::new(&message.data));
+        let mut floodsub = Floodsub::from_config(config);
+        let topic = Topic::new("topic");
+        floodsub.subscribe(topic.clone());
+
+        let peer = PeerId::random();
+        floodsub.inject_connected(&peer);
+        for sequence_number in 0..3u8 {
+            let message = FloodsubMessage {
+                source: Some(PeerId::random()),
+                data: b"hello".to_vec(),
+                sequence_number: vec![sequence_number],
+                topics: vec![topic.clone()],
+                signature: None,
+                key: None,
+            };
+            floodsub.inject_event(peer.clone(), ConnectionId::new(0), rpc(&message));
+        }
+        assert_eq!(received_messages(&mut floodsub), 1);
+    }
+
+    #[test]
+    fn oldest_ids_are_evicted_at_capacity() {
+        let mut config = FloodsubConfig::new(PeerId::random());
+        config.duplicate_cache_capacity = 2;
+        let mut floodsub = Floodsub::from_config(config);
+        let topic = Topic::new("topic");
+        floodsub.subscribe(topic.clone());
+
+        let peer = PeerId::random();
+        floodsub.inject_connected(&peer);
+        let source = PeerId::random();
+        let messages = (0..3u8).map(|sequence_number| FloodsubMessage {
+            source: Some(source.clone()),
+            data: b"hello".to_vec(),
+            sequence_number: vec![sequence_number],
+            topics: vec![topic.clone()],
+            signature: None,
+            key: None,
+        }).collect::<Vec<_>>();
+
+        for message in &messages {
+            floodsub.inject_event(peer.clone(), ConnectionId::new(0), rpc(message));
+        }
+        assert_eq!(received_messages(&mut floodsub), 3);
+
+        // The first id has been evicted to make room for the third one.
+        floodsub.inject_event(peer.clone(), ConnectionId::new(0), rpc(&messages[2]));
+        floodsub.inject_event(peer, ConnectionId::new(0), rpc(&messages[0]));
+        assert_eq!(received_messages(&mut floodsub), 1);
+    }
 }
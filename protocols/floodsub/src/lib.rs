@@ -21,7 +21,8 @@
 //! Implements the floodsub protocol, see also the:
 //! [spec](https://github.com/libp2p/specs/tree/master/pubsub).
 
-use libp2p_core::PeerId;
+use libp2p_core::{PeerId, identity::Keypair};
+use std::{collections::hash_map::DefaultHasher, hash::{Hash, Hasher}, sync::Arc, time::Duration};
 
 pub mod protocol;
 
@@ -33,9 +34,33 @@ mod rpc_proto {
 }
 
 pub use self::layer::{Floodsub, FloodsubEvent};
-pub use self::protocol::{FloodsubMessage, FloodsubRpc};
+pub use self::protocol::{FloodsubMessage, FloodsubRpc, MessageId, RawFloodsubMessage, RawFloodsubRpc, ValidationError};
 pub use self::topic::Topic;
 
+/// Determines how published messages are authenticated and which received messages are
+/// accepted.
+#[derive(Clone)]
+pub enum MessageAuthenticity {
+    /// Published messages are signed with the given keypair, whose `PeerId` is used as their
+    /// source.
+    ///
+    /// Only received messages with a valid signature of their source are accepted.
+    Signed(Keypair),
+    /// Published messages are not signed. The `local_peer_id` of the [`FloodsubConfig`] is used
+    /// as their source.
+    ///
+    /// Received messages must have a source. Messages that are signed must carry a valid
+    /// signature.
+    Author,
+    /// Published messages are not signed and neither carry a source nor a sequence number.
+    ///
+    /// Received messages without a source are accepted. Messages that are signed must carry a
+    /// valid signature.
+    ///
+    /// > **Note**: Anonymous messages with the same data and topics are considered duplicates.
+    Anonymous,
+}
+
 /// Configuration options for the Floodsub protocol.
 pub struct FloodsubConfig {
     /// Peer id of the local node. Used for the source of the messages that we publish.
@@ -44,13 +69,64 @@ pub struct FloodsubConfig {
     /// `true` if messages published by local node should be propagated as messages received from
     /// the network, `false` by default.
     pub subscribe_local_messages: bool,
+
+    /// How published messages are authenticated, [`MessageAuthenticity::Author`] by default.
+    pub message_authenticity: MessageAuthenticity,
+
+    /// How long the id of a seen message is remembered to filter out duplicates, 2 minutes by
+    /// default.
+    ///
+    /// Duplicates arriving later than this are delivered and forwarded again.
+    pub duplicate_cache_ttl: Duration,
+
+    /// The maximum number of message ids remembered to filter out duplicates, 100 000 by
+    /// default. Once full, the least recently seen ids are forgotten first.
+    pub duplicate_cache_capacity: usize,
+
+    /// The function used to compute the id of a message. Messages with the same id are
+    /// considered duplicates.
+    ///
+    /// By default, the id is the concatenation of the source and the sequence number. Anonymous
+    /// messages are identified by a hash of their content and topics.
+    pub message_id_fn: Arc<dyn Fn(&FloodsubMessage) -> MessageId + Send + Sync>,
 }
 
 impl FloodsubConfig {
     pub fn new(local_peer_id: PeerId) -> Self {
         Self {
             local_peer_id,
-            subscribe_local_messages: false
+            subscribe_local_messages: false,
+            message_authenticity: MessageAuthenticity::Author,
+            duplicate_cache_ttl: Duration::from_secs(120),
+            duplicate_cache_capacity: 100_000,
+            message_id_fn: Arc::new(default_message_id),
+        }
+    }
+
+    /// Creates a configuration that signs published messages with `keypair` and only accepts
+    /// messages with a valid signature.
+    pub fn signed(keypair: Keypair) -> Self {
+        let local_peer_id = keypair.public().into_peer_id();
+        Self {
+            message_authenticity: MessageAuthenticity::Signed(keypair),
+            ..Self::new(local_peer_id)
+        }
+    }
+}
+
+/// The default [`FloodsubConfig::message_id_fn`].
+fn default_message_id(message: &FloodsubMessage) -> MessageId {
+    match &message.source {
+        Some(source) => {
+            let mut id = source.as_bytes().to_vec();
+            id.extend_from_slice(&message.sequence_number);
+            MessageId::from(id)
+        }
+        None => {
+            let mut hasher = DefaultHasher::new();
+            message.data.hash(&mut hasher);
+            message.topics.hash(&mut hasher);
+            MessageId::from(hasher.finish().to_be_bytes().to_vec())
         }
     }
 }
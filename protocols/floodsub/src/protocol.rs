@@ -21,10 +21,14 @@
 use crate::rpc_proto;
 use crate::topic::Topic;
 use libp2p_core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, PeerId, upgrade};
+use libp2p_core::identity::{Keypair, PublicKey, error::SigningError};
 use prost::Message;
 use std::{error, fmt, io, iter, pin::Pin};
 use futures::{Future, io::{AsyncRead, AsyncWrite}};
 
+/// Prefix of the bytes a message signature is computed over, as defined by the pubsub spec.
+const SIGNING_PREFIX: &[u8] = b"libp2p-pubsub:";
+
 /// Implementation of `ConnectionUpgrade` for the floodsub protocol.
 #[derive(Debug, Clone, Default)]
 pub struct FloodsubProtocol {}
@@ -49,31 +53,21 @@ impl<TSocket> InboundUpgrade<TSocket> for FloodsubProtocol
 where
     TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
 {
-    type Output = FloodsubRpc;
+    type Output = RawFloodsubRpc;
     type Error = FloodsubDecodeError;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
 
     fn upgrade_inbound(self, mut socket: TSocket, _: Self::Info) -> Self::Future {
         Box::pin(async move {
             let packet = upgrade::read_one(&mut socket, 2048).await?;
-            let rpc = rpc_proto::Rpc::decode(&packet[..])?;
-
-            let mut messages = Vec::with_capacity(rpc.publish.len());
-            for publish in rpc.publish.into_iter() {
-                messages.push(FloodsubMessage {
-                    source: PeerId::from_bytes(publish.from.unwrap_or_default()).map_err(|_| {
-                        FloodsubDecodeError::InvalidPeerId
-                    })?,
-                    data: publish.data.unwrap_or_default(),
-                    sequence_number: publish.seqno.unwrap_or_default(),
-                    topics: publish.topic_ids
-                        .into_iter()
-                        .map(Topic::new)
-                        .collect(),
-                });
-            }
-
-            Ok(FloodsubRpc {
+            let rpc = RawRpc::decode(&packet[..])?;
+
+            let messages = rpc.publish
+                .into_iter()
+                .map(RawFloodsubMessage::decode)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(RawFloodsubRpc {
                 messages,
                 subscriptions: rpc.subscriptions
                     .into_iter()
@@ -137,7 +131,197 @@ impl error::Error for FloodsubDecodeError {
     }
 }
 
-/// An RPC received by the floodsub system.
+/// The `RPC` protobuf message with the published messages left encoded, such that their
+/// signatures can be verified against the encoding they were received in.
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawRpc {
+    #[prost(message, repeated, tag = "1")]
+    subscriptions: Vec<rpc_proto::rpc::SubOpts>,
+    #[prost(bytes, repeated, tag = "2")]
+    publish: Vec<Vec<u8>>,
+}
+
+/// The protobuf field number of `Message.signature`.
+const SIGNATURE_FIELD: u64 = 5;
+/// The protobuf field number of `Message.key`.
+const KEY_FIELD: u64 = 6;
+
+/// An RPC exchanged with a remote, with its messages in the encoding they were received in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawFloodsubRpc {
+    /// List of messages that were part of this RPC query.
+    pub messages: Vec<RawFloodsubMessage>,
+    /// List of subscriptions.
+    pub subscriptions: Vec<FloodsubSubscription>,
+}
+
+impl UpgradeInfo for RawFloodsubRpc {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(b"/floodsub/1.0.0")
+    }
+}
+
+impl<TSocket> OutboundUpgrade<TSocket> for RawFloodsubRpc
+where
+    TSocket: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+{
+    type Output = ();
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
+
+    fn upgrade_outbound(self, mut socket: TSocket, _: Self::Info) -> Self::Future {
+        Box::pin(async move {
+            let bytes = self.into_bytes();
+            upgrade::write_one(&mut socket, bytes).await?;
+            Ok(())
+        })
+    }
+}
+
+impl RawFloodsubRpc {
+    /// Turns this `RawFloodsubRpc` into a message that can be sent to a substream.
+    ///
+    /// Messages are sent in the encoding they were received in, so that their signatures remain
+    /// valid for the peers they are forwarded to.
+    fn into_bytes(self) -> Vec<u8> {
+        let rpc = RawRpc {
+            publish: self.messages.into_iter()
+                .map(|message| message.bytes)
+                .collect(),
+
+            subscriptions: self.subscriptions.into_iter()
+                .map(|topic| {
+                    rpc_proto::rpc::SubOpts {
+                        subscribe: Some(topic.action == FloodsubSubscriptionAction::Subscribe),
+                        topic_id: Some(topic.topic.into())
+                    }
+                })
+                .collect()
+        };
+
+        let mut buf = Vec::with_capacity(rpc.encoded_len());
+        rpc.encode(&mut buf).expect("Vec<u8> provides capacity as needed");
+        buf
+    }
+}
+
+/// A message as received from a remote, together with the encoding it was received in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawFloodsubMessage {
+    /// The decoded message.
+    pub message: FloodsubMessage,
+    /// The protobuf encoding of the message, as sent by the remote.
+    pub bytes: Vec<u8>,
+}
+
+impl RawFloodsubMessage {
+    /// Decodes a message from its protobuf encoding.
+    pub fn decode(bytes: Vec<u8>) -> Result<Self, FloodsubDecodeError> {
+        let publish = rpc_proto::Message::decode(&bytes[..])?;
+        let message = FloodsubMessage {
+            source: publish.from.map(PeerId::from_bytes).transpose().map_err(|_| {
+                FloodsubDecodeError::InvalidPeerId
+            })?,
+            data: publish.data.unwrap_or_default(),
+            sequence_number: publish.seqno.unwrap_or_default(),
+            topics: publish.topic_ids
+                .into_iter()
+                .map(Topic::new)
+                .collect(),
+            signature: publish.signature,
+            key: publish.key,
+        };
+        Ok(RawFloodsubMessage { message, bytes })
+    }
+
+    /// Checks that the message carries a valid signature of its source.
+    ///
+    /// The signature is verified against the encoding the message was received in, since
+    /// decoding does not preserve it, e.g. a missing `data` field is decoded as empty data.
+    pub(crate) fn verify_signature(&self) -> Result<(), ValidationError> {
+        let message = &self.message;
+        let source = message.source.as_ref().ok_or(ValidationError::MissingSource)?;
+        let signature = message.signature.as_ref().ok_or(ValidationError::MissingSignature)?;
+
+        // If the message carries a key, use it. Otherwise the key must be inlined in the source.
+        let public_key = match &message.key {
+            Some(key) => PublicKey::from_protobuf_encoding(key),
+            None => PublicKey::from_protobuf_encoding(&source.as_bytes()[2..]),
+        }.map_err(|_| ValidationError::InvalidPublicKey)?;
+
+        if public_key.clone().into_peer_id() != *source {
+            return Err(ValidationError::KeyMismatch);
+        }
+
+        // The publisher signed the encoding of the message without the signature and key fields.
+        let mut signing_bytes = SIGNING_PREFIX.to_vec();
+        if !strip_fields(&self.bytes, &[SIGNATURE_FIELD, KEY_FIELD], &mut signing_bytes) {
+            return Err(ValidationError::InvalidSignature);
+        }
+        if !public_key.verify(&signing_bytes, signature) {
+            return Err(ValidationError::InvalidSignature);
+        }
+
+        Ok(())
+    }
+}
+
+impl From<FloodsubMessage> for RawFloodsubMessage {
+    fn from(message: FloodsubMessage) -> Self {
+        let proto: rpc_proto::Message = message.clone().into();
+        let mut bytes = Vec::with_capacity(proto.encoded_len());
+        proto.encode(&mut bytes).expect("Vec<u8> provides capacity as needed");
+        RawFloodsubMessage { message, bytes }
+    }
+}
+
+/// Appends the protobuf encoding `bytes` to `out`, leaving out all fields with the given numbers.
+///
+/// Returns `false` if `bytes` is not a valid encoding.
+fn strip_fields(mut bytes: &[u8], fields: &[u64], out: &mut Vec<u8>) -> bool {
+    use unsigned_varint::decode;
+
+    while !bytes.is_empty() {
+        let (key, rest) = match decode::u64(bytes) {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+        let value_len = match key & 0x7 {
+            // varint
+            0 => match decode::u64(rest) {
+                Ok((_, r)) => rest.len() - r.len(),
+                Err(_) => return false,
+            },
+            // 64-bit
+            1 => 8,
+            // length-delimited
+            2 => match decode::usize(rest) {
+                Ok((len, r)) => match (rest.len() - r.len()).checked_add(len) {
+                    Some(n) => n,
+                    None => return false,
+                },
+                Err(_) => return false,
+            },
+            // 32-bit
+            5 => 4,
+            _ => return false,
+        };
+        if rest.len() < value_len {
+            return false;
+        }
+        let field_len = bytes.len() - rest.len() + value_len;
+        if !fields.contains(&(key >> 3)) {
+            out.extend_from_slice(&bytes[..field_len]);
+        }
+        bytes = &bytes[field_len..];
+    }
+    true
+}
+
+/// An RPC sent by the floodsub system.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FloodsubRpc {
     /// List of messages that were part of this RPC query.
@@ -177,17 +361,7 @@ impl FloodsubRpc {
     fn into_bytes(self) -> Vec<u8> {
         let rpc = rpc_proto::Rpc {
             publish: self.messages.into_iter()
-                .map(|msg| {
-                    rpc_proto::Message {
-                        from: Some(msg.source.into_bytes()),
-                        data: Some(msg.data),
-                        seqno: Some(msg.sequence_number),
-                        topic_ids: msg.topics
-                            .into_iter()
-                            .map(|topic| topic.into())
-                            .collect()
-                    }
-                })
+                .map(Into::into)
                 .collect(),
 
             subscriptions: self.subscriptions.into_iter()
@@ -209,19 +383,124 @@ impl FloodsubRpc {
 /// A message received by the floodsub system.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FloodsubMessage {
-    /// Id of the peer that published this message.
-    pub source: PeerId,
+    /// Id of the peer that published this message, if it was not published anonymously.
+    pub source: Option<PeerId>,
 
     /// Content of the message. Its meaning is out of scope of this library.
     pub data: Vec<u8>,
 
-    /// An incrementing sequence number.
+    /// An incrementing sequence number. Empty if the message was published anonymously.
     pub sequence_number: Vec<u8>,
 
     /// List of topics this message belongs to.
     ///
     /// Each message can belong to multiple topics at once.
     pub topics: Vec<Topic>,
+
+    /// The signature of the message, if it was signed by its source.
+    pub signature: Option<Vec<u8>>,
+
+    /// The protobuf encoding of the public key of the source, if it cannot be extracted from
+    /// the source's `PeerId`.
+    pub key: Option<Vec<u8>>,
+}
+
+impl FloodsubMessage {
+    /// Signs the message with `keypair`, setting its `signature` and, if the public key cannot be
+    /// inlined in the source `PeerId`, its `key`.
+    ///
+    /// The `source` of the message must be the `PeerId` of `keypair`.
+    pub(crate) fn sign(&mut self, keypair: &Keypair) -> Result<(), SigningError> {
+        let public_key = keypair.public();
+        debug_assert_eq!(self.source, Some(public_key.clone().into_peer_id()));
+
+        let key = public_key.into_protobuf_encoding();
+        // Keys up to 42 bytes are inlined in the `PeerId` of the source.
+        self.key = if key.len() <= 42 { None } else { Some(key) };
+        self.signature = Some(keypair.sign(&self.signing_bytes())?);
+        Ok(())
+    }
+
+    /// Returns the bytes the signature of this message is computed over, i.e. the protobuf
+    /// encoding of the message without signature and key, prefixed with `libp2p-pubsub:`.
+    fn signing_bytes(&self) -> Vec<u8> {
+        let message: rpc_proto::Message = FloodsubMessage {
+            signature: None,
+            key: None,
+            ..self.clone()
+        }.into();
+
+        let mut buf = SIGNING_PREFIX.to_vec();
+        buf.reserve(message.encoded_len());
+        message.encode(&mut buf).expect("Vec<u8> provides capacity as needed");
+        buf
+    }
+}
+
+impl From<FloodsubMessage> for rpc_proto::Message {
+    fn from(msg: FloodsubMessage) -> Self {
+        rpc_proto::Message {
+            from: msg.source.map(PeerId::into_bytes),
+            data: Some(msg.data),
+            seqno: if msg.sequence_number.is_empty() { None } else { Some(msg.sequence_number) },
+            topic_ids: msg.topics
+                .into_iter()
+                .map(|topic| topic.into())
+                .collect(),
+            signature: msg.signature,
+            key: msg.key,
+        }
+    }
+}
+
+/// Reason for a received message to be rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The message has no source.
+    MissingSource,
+    /// The message is not signed.
+    MissingSignature,
+    /// The public key of the source could not be decoded.
+    InvalidPublicKey,
+    /// The public key of the message does not belong to its source.
+    KeyMismatch,
+    /// The signature of the message is invalid.
+    InvalidSignature,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ValidationError::MissingSource =>
+                write!(f, "Message has no source"),
+            ValidationError::MissingSignature =>
+                write!(f, "Message is not signed"),
+            ValidationError::InvalidPublicKey =>
+                write!(f, "Invalid public key of the message source"),
+            ValidationError::KeyMismatch =>
+                write!(f, "Public key does not match the message source"),
+            ValidationError::InvalidSignature =>
+                write!(f, "Invalid message signature"),
+        }
+    }
+}
+
+impl error::Error for ValidationError {}
+
+/// A type for floodsub message ids, used to detect duplicate messages.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageId(Vec<u8>);
+
+impl MessageId {
+    pub fn new(value: &[u8]) -> Self {
+        Self(value.to_vec())
+    }
+}
+
+impl<T: Into<Vec<u8>>> From<T> for MessageId {
+    fn from(value: T) -> Self {
+        Self(value.into())
+    }
 }
 
 /// A subscription received by the floodsub system.
@@ -241,3 +520,105 @@ pub enum FloodsubSubscriptionAction {
     /// The remote wants to unsubscribe from the given topic.
     Unsubscribe,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(keypair: &Keypair) -> FloodsubMessage {
+        FloodsubMessage {
+            source: Some(keypair.public().into_peer_id()),
+            data: b"hello".to_vec(),
+            sequence_number: vec![1, 2, 3],
+            topics: vec![Topic::new("topic")],
+            signature: None,
+            key: None,
+        }
+    }
+
+    fn verify(message: &FloodsubMessage) -> Result<(), ValidationError> {
+        RawFloodsubMessage::from(message.clone()).verify_signature()
+    }
+
+    #[test]
+    fn signed_messages_verify() {
+        let ed25519 = Keypair::generate_ed25519();
+        let mut msg = message(&ed25519);
+        msg.sign(&ed25519).unwrap();
+        assert!(msg.key.is_none());
+        assert_eq!(verify(&msg), Ok(()));
+
+        let secp256k1 = Keypair::generate_secp256k1();
+        let mut msg = message(&secp256k1);
+        msg.sign(&secp256k1).unwrap();
+        assert_eq!(verify(&msg), Ok(()));
+    }
+
+    #[test]
+    fn tampered_messages_fail_verification() {
+        let keypair = Keypair::generate_ed25519();
+        let mut msg = message(&keypair);
+        assert_eq!(verify(&msg), Err(ValidationError::MissingSignature));
+
+        msg.sign(&keypair).unwrap();
+        let mut tampered = msg.clone();
+        tampered.data = b"bye".to_vec();
+        assert_eq!(verify(&tampered), Err(ValidationError::InvalidSignature));
+
+        let mut forged = msg.clone();
+        forged.source = Some(Keypair::generate_ed25519().public().into_peer_id());
+        assert_eq!(verify(&forged), Err(ValidationError::InvalidSignature));
+
+        let mut wrong_key = msg;
+        wrong_key.key = Some(Keypair::generate_ed25519().public().into_protobuf_encoding());
+        assert_eq!(verify(&wrong_key), Err(ValidationError::KeyMismatch));
+    }
+
+    #[test]
+    fn signature_survives_encoding() {
+        let keypair = Keypair::generate_ed25519();
+        let mut msg = message(&keypair);
+        msg.sign(&keypair).unwrap();
+
+        let rpc = FloodsubRpc { messages: vec![msg.clone()], subscriptions: Vec::new() };
+        let decoded = rpc_proto::Rpc::decode(&rpc.into_bytes()[..]).unwrap();
+        let publish = decoded.publish.into_iter().next().unwrap();
+        assert_eq!(publish.signature, msg.signature);
+        assert_eq!(publish.key, None);
+    }
+
+    #[test]
+    fn signature_is_verified_against_received_encoding() {
+        // Another implementation may omit empty fields, which decoding does not preserve.
+        let keypair = Keypair::generate_ed25519();
+        let unsigned = rpc_proto::Message {
+            from: Some(keypair.public().into_peer_id().into_bytes()),
+            data: None,
+            seqno: Some(vec![1]),
+            topic_ids: vec!["topic".to_owned()],
+            signature: None,
+            key: None,
+        };
+        let mut signing_bytes = SIGNING_PREFIX.to_vec();
+        unsigned.encode(&mut signing_bytes).unwrap();
+        let signed = rpc_proto::Message {
+            signature: Some(keypair.sign(&signing_bytes).unwrap()),
+            ..unsigned
+        };
+        let mut bytes = Vec::new();
+        signed.encode(&mut bytes).unwrap();
+
+        let raw = RawFloodsubMessage::decode(bytes).unwrap();
+        assert_eq!(raw.verify_signature(), Ok(()));
+        // Re-encoding the decoded message yields different bytes.
+        assert_eq!(verify(&raw.message), Err(ValidationError::InvalidSignature));
+    }
+
+    #[test]
+    fn stripping_fields_rejects_truncated_encodings() {
+        let mut out = Vec::new();
+        assert!(strip_fields(&[0x0a, 0x02, 0x01, 0x02, 0x28, 0x01], &[5], &mut out));
+        assert_eq!(out, vec![0x0a, 0x02, 0x01, 0x02]);
+        assert!(!strip_fields(&[0x0a, 0x05, 0x01], &[], &mut Vec::new()));
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::prelude::*;
+use libp2p_core::{
+    identity, multiaddr::Protocol, muxing::StreamMuxerBox, transport::MemoryTransport, upgrade,
+    Multiaddr, PeerId, Transport,
+};
+use libp2p_floodsub::{
+    Floodsub, FloodsubConfig, FloodsubEvent, FloodsubMessage, MessageAuthenticity, Topic,
+    ValidationError,
+};
+use libp2p_plaintext::PlainText2Config;
+use libp2p_swarm::Swarm;
+use libp2p_yamux as yamux;
+use rand::random;
+use std::{io, task::Poll, time::Duration};
+
+fn build_node(
+    key: identity::Keypair,
+    authenticity: MessageAuthenticity,
+) -> (Multiaddr, Swarm<Floodsub>) {
+    let public_key = key.public();
+    let transport = MemoryTransport::default()
+        .upgrade(upgrade::Version::V1)
+        .authenticate(PlainText2Config {
+            local_public_key: public_key.clone(),
+        })
+        .multiplex(yamux::Config::default())
+        .map(|(p, m), _| (p, StreamMuxerBox::new(m)))
+        .map_err(|e| -> io::Error { panic!("Failed to create transport: {:?}", e) })
+        .boxed();
+
+    let peer_id = public_key.into_peer_id();
+    let mut config = FloodsubConfig::new(peer_id.clone());
+    config.message_authenticity = authenticity;
+    let mut swarm = Swarm::new(transport, Floodsub::from_config(config), peer_id);
+
+    let addr: Multiaddr = Protocol::Memory(1 + random::<u64>()).into();
+    Swarm::listen_on(&mut swarm, addr.clone()).unwrap();
+    (addr, swarm)
+}
+
+/// Connects `publisher` to `subscriber`, which subscribes to `topic`, and publishes `data` once
+/// the publisher has learned about the subscription.
+///
+/// Returns the first message related event of the subscriber.
+fn publish(
+    (_, mut publisher): (Multiaddr, Swarm<Floodsub>),
+    (subscriber_addr, mut subscriber): (Multiaddr, Swarm<Floodsub>),
+    topic: Topic,
+    data: &'static [u8],
+) -> FloodsubEvent {
+    // The subscriber only announces its subscriptions to the peers in its partial view.
+    subscriber.subscribe(topic.clone());
+    subscriber.add_node_to_partial_view(Swarm::local_peer_id(&publisher).clone());
+    Swarm::dial_addr(&mut publisher, subscriber_addr).unwrap();
+
+    let fut = future::poll_fn(move |cx| {
+        loop {
+            match publisher.poll_next_unpin(cx) {
+                Poll::Ready(Some(FloodsubEvent::Subscribed { topic: t, .. })) if t == topic => {
+                    publisher.publish_any(topic.clone(), data)
+                }
+                Poll::Ready(Some(_)) => {}
+                Poll::Ready(None) => panic!("publisher terminated"),
+                Poll::Pending => break,
+            }
+        }
+        loop {
+            match subscriber.poll_next_unpin(cx) {
+                Poll::Ready(Some(event @ FloodsubEvent::Message(_)))
+                | Poll::Ready(Some(event @ FloodsubEvent::InvalidMessage { .. })) => {
+                    return Poll::Ready(event)
+                }
+                Poll::Ready(Some(_)) => {}
+                Poll::Ready(None) => panic!("subscriber terminated"),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    });
+
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), fut))
+        .expect("no message received in time")
+}
+
+#[test]
+fn signed_message_is_delivered() {
+    let key = identity::Keypair::generate_ed25519();
+    let publisher_id = PeerId::from(key.public());
+    let publisher = build_node(key.clone(), MessageAuthenticity::Signed(key));
+    let subscriber_key = identity::Keypair::generate_ed25519();
+    let subscriber = build_node(
+        subscriber_key.clone(),
+        MessageAuthenticity::Signed(subscriber_key),
+    );
+
+    match publish(publisher, subscriber, Topic::new("signed"), b"hello") {
+        FloodsubEvent::Message(FloodsubMessage {
+            source,
+            data,
+            signature,
+            ..
+        }) => {
+            assert_eq!(source, Some(publisher_id));
+            assert_eq!(data, b"hello");
+            assert!(signature.is_some());
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[test]
+fn unsigned_message_is_rejected() {
+    let publisher = build_node(
+        identity::Keypair::generate_ed25519(),
+        MessageAuthenticity::Author,
+    );
+    let subscriber_key = identity::Keypair::generate_ed25519();
+    let subscriber = build_node(
+        subscriber_key.clone(),
+        MessageAuthenticity::Signed(subscriber_key),
+    );
+
+    match publish(publisher, subscriber, Topic::new("signed"), b"hello") {
+        FloodsubEvent::InvalidMessage { error, .. } => {
+            assert_eq!(error, ValidationError::MissingSignature)
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
@@ -20,11 +20,13 @@
 
 use crate::config::{GossipsubConfig, ValidationMode};
 use crate::error::PublishError;
-use crate::handler::GossipsubHandler;
+use crate::handler::{
+    DroppedMessages, GossipsubHandler, GossipsubHandlerEvent, GossipsubHandlerIn, MessagePriority,
+};
 use crate::mcache::MessageCache;
 use crate::protocol::{
-    GossipsubControlAction, GossipsubMessage, GossipsubSubscription, GossipsubSubscriptionAction,
-    MessageId, SIGNING_PREFIX,
+    FastMessageId, GossipsubControlAction, GossipsubMessage, GossipsubSubscription,
+    GossipsubSubscriptionAction, MessageId, PeerKind, RawGossipsubMessage, SIGNING_PREFIX,
 };
 use crate::rpc_proto;
 use crate::topic::{Topic, TopicHash};
@@ -153,7 +155,7 @@ pub struct Gossipsub {
     config: GossipsubConfig,
 
     /// Events that need to be yielded to the outside when polling.
-    events: VecDeque<NetworkBehaviourAction<Arc<GossipsubRpc>, GossipsubEvent>>,
+    events: VecDeque<NetworkBehaviourAction<GossipsubHandlerIn, GossipsubEvent>>,
 
     /// Pools non-urgent control messages between heartbeats.
     control_pool: HashMap<PeerId, Vec<GossipsubControlAction>>,
@@ -165,12 +167,20 @@ pub struct Gossipsub {
     /// duplicates from being propagated to the application and on the network.
     duplication_cache: LruCache<MessageId, ()>,
 
+    /// An LRU Time cache for storing the fast ids of seen messages, if a `fast_message_id_fn` is
+    /// configured. This cache allows dropping duplicates before validating them.
+    fast_message_id_cache: LruCache<FastMessageId, ()>,
+
     /// A map of all connected peers - A map of topic hash to a list of gossipsub peer Ids.
     topic_peers: HashMap<TopicHash, BTreeSet<PeerId>>,
 
     /// A map of all connected peers to their subscribed topics.
     peer_topics: HashMap<PeerId, BTreeSet<TopicHash>>,
 
+    /// Connected peers that only speak floodsub. These are never part of the mesh or fanout, but
+    /// receive all messages of the topics they are subscribed to.
+    floodsub_peers: HashSet<PeerId>,
+
     /// Overlay network of connected peers - Maps topics to connected gossipsub peers.
     mesh: HashMap<TopicHash, BTreeSet<PeerId>>,
 
@@ -203,15 +213,17 @@ impl Gossipsub {
             control_pool: HashMap::new(),
             publish_config: privacy.into(),
             duplication_cache: LruCache::with_expiry_duration(config.duplicate_cache_time),
+            fast_message_id_cache: LruCache::with_expiry_duration(config.duplicate_cache_time),
             topic_peers: HashMap::new(),
             peer_topics: HashMap::new(),
+            floodsub_peers: HashSet::new(),
             mesh: HashMap::new(),
             fanout: HashMap::new(),
             fanout_last_pub: HashMap::new(),
             mcache: MessageCache::new(
                 config.history_gossip,
                 config.history_length,
-                config.message_id_fn,
+                config.message_id_fn.clone(),
             ),
             heartbeat: Interval::new_at(
                 Instant::now() + config.heartbeat_initial_delay,
@@ -265,7 +277,7 @@ impl Gossipsub {
 
             for peer in peer_list {
                 debug!("Sending SUBSCRIBE to peer: {:?}", peer);
-                self.send_message(peer, event.clone());
+                self.send_message(peer, event.clone(), MessagePriority::High);
             }
         }
 
@@ -303,7 +315,7 @@ impl Gossipsub {
 
             for peer in peer_list {
                 debug!("Sending UNSUBSCRIBE to peer: {}", peer.to_string());
-                self.send_message(peer, event.clone());
+                self.send_message(peer, event.clone(), MessagePriority::High);
             }
         }
 
@@ -365,10 +377,13 @@ impl Gossipsub {
                 } else {
                     // we have no fanout peers, select mesh_n of them and add them to the fanout
                     let mesh_n = self.config.mesh_n;
-                    let new_peers =
-                        Self::get_random_peers(&self.topic_peers, &topic_hash, mesh_n, {
-                            |_| true
-                        });
+                    let new_peers = Self::get_random_peers(
+                        &self.topic_peers,
+                        &self.floodsub_peers,
+                        &topic_hash,
+                        mesh_n,
+                        |_| true,
+                    );
                     // add the new peers to the fanout and recipient peers
                     self.fanout.insert(topic_hash.clone(), new_peers.clone());
                     for peer in new_peers {
@@ -394,7 +409,7 @@ impl Gossipsub {
         // Send to peers we know are subscribed to the topic.
         for peer_id in recipient_peers.iter() {
             debug!("Sending message to peer: {:?}", peer_id);
-            self.send_message(peer_id.clone(), event.clone());
+            self.send_message(peer_id.clone(), event.clone(), MessagePriority::High);
         }
 
         info!("Published message: {:?}", msg_id);
@@ -472,6 +487,7 @@ impl Gossipsub {
             // get the peers
             let new_peers = Self::get_random_peers(
                 &self.topic_peers,
+                &self.floodsub_peers,
                 topic_hash,
                 self.config.mesh_n - added_peers.len(),
                 |peer| !added_peers.contains(peer),
@@ -588,6 +604,7 @@ impl Gossipsub {
                     messages: message_list,
                     control_msgs: Vec::new(),
                 },
+                MessagePriority::Low,
             );
         }
         debug!("Completed IWANT handling for peer: {:?}", peer_id);
@@ -633,6 +650,7 @@ impl Gossipsub {
                     messages: Vec::new(),
                     control_msgs: prune_messages,
                 },
+                MessagePriority::High,
             );
         }
         debug!("Completed GRAFT handling for peer: {:?}", peer_id);
@@ -656,7 +674,58 @@ impl Gossipsub {
         debug!("Completed PRUNE handling for peer: {}", peer_id.to_string());
     }
 
-    /// Handles a newly received GossipsubMessage.
+    /// Handles a peer that has been found to only speak floodsub. The peer is removed from all
+    /// meshes and fanouts, as floodsub peers don't take part in the mesh.
+    fn handle_floodsub_peer(&mut self, peer_id: &PeerId) {
+        if !self.floodsub_peers.insert(peer_id.clone()) {
+            return;
+        }
+        debug!("Peer only supports floodsub: {:?}", peer_id);
+
+        for peers in self.mesh.values_mut().chain(self.fanout.values_mut()) {
+            peers.remove(peer_id);
+        }
+        // floodsub peers don't understand control messages
+        self.control_pool.remove(peer_id);
+    }
+
+    /// Handles a message as received on the wire. Drops it if its fast id has already been seen,
+    /// otherwise validates it and handles it as a newly received message.
+    fn handle_raw_message(&mut self, raw_message: RawGossipsubMessage, propagation_source: &PeerId) {
+        let fast_message_id = self
+            .config
+            .fast_message_id_fn
+            .as_ref()
+            .map(|fast_id_fn| fast_id_fn(&raw_message));
+
+        if let Some(fast_message_id) = &fast_message_id {
+            if self.fast_message_id_cache.contains_key(fast_message_id) {
+                debug!(
+                    "Message already received, ignoring. Fast message id: {:?}",
+                    fast_message_id
+                );
+                return;
+            }
+        }
+
+        let message = match raw_message.validate(&self.config.validation_mode) {
+            Some(message) => message,
+            None => {
+                debug!("Invalid message from peer: {}", propagation_source);
+                return;
+            }
+        };
+
+        // Only valid messages are remembered, such that an invalid message can't prevent a valid
+        // one with the same fast id from being received.
+        if let Some(fast_message_id) = fast_message_id {
+            self.fast_message_id_cache.insert(fast_message_id, ());
+        }
+
+        self.handle_received_message(message, propagation_source);
+    }
+
+    /// Handles a newly received and validated GossipsubMessage.
     /// Forwards the message to all peers in the mesh.
     fn handle_received_message(&mut self, mut msg: GossipsubMessage, propagation_source: &PeerId) {
         let msg_id = (self.config.message_id_fn)(&msg);
@@ -707,6 +776,7 @@ impl Gossipsub {
             subscriptions,
             propagation_source.to_string()
         );
+        let is_floodsub_peer = self.floodsub_peers.contains(propagation_source);
         let subscribed_topics = match self.peer_topics.get_mut(propagation_source) {
             Some(topics) => topics,
             None => {
@@ -744,8 +814,13 @@ impl Gossipsub {
                     // add to the peer_topics mapping
                     subscribed_topics.insert(subscription.topic_hash.clone());
 
-                    // if the mesh needs peers add the peer to the mesh
-                    if let Some(peers) = self.mesh.get_mut(&subscription.topic_hash) {
+                    // if the mesh needs peers add the peer to the mesh, unless it is a floodsub
+                    // peer
+                    if let Some(peers) = self
+                        .mesh
+                        .get_mut(&subscription.topic_hash)
+                        .filter(|_| !is_floodsub_peer)
+                    {
                         if peers.len() < self.config.mesh_n_low {
                             if peers.insert(propagation_source.clone()) {
                                 debug!(
@@ -810,6 +885,7 @@ impl Gossipsub {
                     messages: Vec::new(),
                     control_msgs: grafts,
                 },
+                MessagePriority::High,
             );
         }
 
@@ -843,10 +919,13 @@ impl Gossipsub {
                 );
                 // not enough peers - get mesh_n - current_length more
                 let desired_peers = self.config.mesh_n - peers.len();
-                let peer_list =
-                    Self::get_random_peers(&self.topic_peers, topic_hash, desired_peers, {
-                        |peer| !peers.contains(peer)
-                    });
+                let peer_list = Self::get_random_peers(
+                    &self.topic_peers,
+                    &self.floodsub_peers,
+                    topic_hash,
+                    desired_peers,
+                    |peer| !peers.contains(peer),
+                );
                 for peer in &peer_list {
                     let current_topic = to_graft.entry(peer.clone()).or_insert_with(Vec::new);
                     current_topic.push(topic_hash.clone());
@@ -932,10 +1011,13 @@ impl Gossipsub {
                     self.config.mesh_n
                 );
                 let needed_peers = self.config.mesh_n - peers.len();
-                let new_peers =
-                    Self::get_random_peers(&self.topic_peers, topic_hash, needed_peers, |peer| {
-                        !peers.contains(peer)
-                    });
+                let new_peers = Self::get_random_peers(
+                    &self.topic_peers,
+                    &self.floodsub_peers,
+                    topic_hash,
+                    needed_peers,
+                    |peer| !peers.contains(peer),
+                );
                 peers.extend(new_peers);
             }
         }
@@ -967,6 +1049,7 @@ impl Gossipsub {
             // get gossip_lazy random peers
             let to_msg_peers = Self::get_random_peers(
                 &self.topic_peers,
+                &self.floodsub_peers,
                 &topic_hash,
                 self.config.gossip_lazy,
                 |peer| !peers.contains(peer),
@@ -1023,6 +1106,7 @@ impl Gossipsub {
                     messages: Vec::new(),
                     control_msgs,
                 },
+                MessagePriority::High,
             );
         }
 
@@ -1041,12 +1125,17 @@ impl Gossipsub {
                     messages: Vec::new(),
                     control_msgs: remaining_prunes,
                 },
+                MessagePriority::High,
             );
         }
     }
 
-    /// Helper function which forwards a message to mesh\[topic\] peers.
+    /// Helper function which forwards a message to mesh\[topic\] peers and to the floodsub peers
+    /// subscribed to any of its topics.
     /// Returns true if at least one peer was messaged.
+    ///
+    /// Messages without a `source` are our own and are queued with priority; messages received
+    /// from another peer may be dropped if the recipient can't keep up.
     fn forward_msg(&mut self, message: GossipsubMessage, source: Option<&PeerId>) -> bool {
         let msg_id = (self.config.message_id_fn)(&message);
         debug!("Forwarding message: {:?}", msg_id);
@@ -1062,6 +1151,15 @@ impl Gossipsub {
                     }
                 }
             }
+
+            // floodsub peers subscribed to the topic
+            if let Some(topic_peers) = self.topic_peers.get(topic) {
+                for peer_id in topic_peers {
+                    if Some(peer_id) != source && self.floodsub_peers.contains(peer_id) {
+                        recipient_peers.insert(peer_id.clone());
+                    }
+                }
+            }
         }
 
         // forward the message to peers
@@ -1072,9 +1170,15 @@ impl Gossipsub {
                 control_msgs: Vec::new(),
             });
 
+            let priority = if source.is_none() {
+                MessagePriority::High
+            } else {
+                MessagePriority::Low
+            };
+
             for peer in recipient_peers.iter() {
                 debug!("Sending message: {:?} to peer {:?}", msg_id, peer);
-                self.send_message(peer.clone(), event.clone());
+                self.send_message(peer.clone(), event.clone(), priority);
             }
             debug!("Completed forwarding message");
             true
@@ -1174,16 +1278,21 @@ impl Gossipsub {
     }
 
     /// Helper function to get a set of `n` random gossipsub peers for a `topic_hash`
-    /// filtered by the function `f`.
+    /// filtered by the function `f`. Floodsub peers are never selected.
     fn get_random_peers(
         topic_peers: &HashMap<TopicHash, BTreeSet<PeerId>>,
+        floodsub_peers: &HashSet<PeerId>,
         topic_hash: &TopicHash,
         n: usize,
         mut f: impl FnMut(&PeerId) -> bool,
     ) -> BTreeSet<PeerId> {
         let mut gossip_peers = match topic_peers.get(topic_hash) {
             // if they exist, filter the peers by `f`
-            Some(peer_list) => peer_list.iter().cloned().filter(|p| f(p)).collect(),
+            Some(peer_list) => peer_list
+                .iter()
+                .cloned()
+                .filter(|p| !floodsub_peers.contains(p) && f(p))
+                .collect(),
             None => Vec::new(),
         };
 
@@ -1233,17 +1342,26 @@ impl Gossipsub {
                     messages: Vec::new(),
                     control_msgs: controls,
                 },
+                MessagePriority::High,
             );
         }
     }
 
-    /// Send a GossipsubRpc message to a peer. This will wrap the message in an arc if it
-    /// is not already an arc.
-    fn send_message(&mut self, peer_id: PeerId, message: impl Into<Arc<GossipsubRpc>>) {
+    /// Send a GossipsubRpc message to a peer, to be queued by the handler according to the given
+    /// priority. This will wrap the message in an arc if it is not already an arc.
+    fn send_message(
+        &mut self,
+        peer_id: PeerId,
+        message: impl Into<Arc<GossipsubRpc>>,
+        priority: MessagePriority,
+    ) {
         self.events
             .push_back(NetworkBehaviourAction::NotifyHandler {
                 peer_id,
-                event: message.into(),
+                event: GossipsubHandlerIn {
+                    rpc: message.into(),
+                    priority,
+                },
                 handler: NotifyHandler::Any,
             })
     }
@@ -1254,11 +1372,7 @@ impl NetworkBehaviour for Gossipsub {
     type OutEvent = GossipsubEvent;
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        GossipsubHandler::new(
-            self.config.protocol_id.clone(),
-            self.config.max_transmit_size,
-            self.config.validation_mode.clone(),
-        )
+        GossipsubHandler::new(&self.config)
     }
 
     fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
@@ -1285,6 +1399,7 @@ impl NetworkBehaviour for Gossipsub {
                     subscriptions,
                     control_msgs: Vec::new(),
                 },
+                MessagePriority::High,
             );
         }
 
@@ -1333,9 +1448,39 @@ impl NetworkBehaviour for Gossipsub {
         // remove peer from peer_topics
         let was_in = self.peer_topics.remove(id);
         debug_assert!(was_in.is_some());
+        self.floodsub_peers.remove(id);
     }
 
-    fn inject_event(&mut self, propagation_source: PeerId, _: ConnectionId, event: GossipsubRpc) {
+    fn inject_event(
+        &mut self,
+        propagation_source: PeerId,
+        _: ConnectionId,
+        event: GossipsubHandlerEvent,
+    ) {
+        let event = match event {
+            GossipsubHandlerEvent::Rpc(rpc) => rpc,
+            GossipsubHandlerEvent::PeerKind(kind) => {
+                if kind == PeerKind::Floodsub {
+                    self.handle_floodsub_peer(&propagation_source);
+                }
+                return;
+            }
+            GossipsubHandlerEvent::MessagesDropped(dropped) => {
+                debug!(
+                    "Dropped {} messages for slow peer: {:?}",
+                    dropped.total(),
+                    propagation_source
+                );
+                self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    GossipsubEvent::SlowPeer {
+                        peer_id: propagation_source,
+                        dropped,
+                    },
+                ));
+                return;
+            }
+        };
+
         // Handle subscriptions
         // Update connected peers topics
         if !event.subscriptions.is_empty() {
@@ -1344,7 +1489,7 @@ impl NetworkBehaviour for Gossipsub {
 
         // Handle messages
         for message in event.messages {
-            self.handle_received_message(message, &propagation_source);
+            self.handle_raw_message(message, &propagation_source);
         }
 
         // Handle control messages
@@ -1389,33 +1534,7 @@ impl NetworkBehaviour for Gossipsub {
         >,
     > {
         if let Some(event) = self.events.pop_front() {
-            return Poll::Ready(match event {
-                NetworkBehaviourAction::NotifyHandler {
-                    peer_id,
-                    handler,
-                    event: send_event,
-                } => {
-                    // clone send event reference if others references are present
-                    let event = Arc::try_unwrap(send_event).unwrap_or_else(|e| (*e).clone());
-                    NetworkBehaviourAction::NotifyHandler {
-                        peer_id,
-                        event,
-                        handler,
-                    }
-                }
-                NetworkBehaviourAction::GenerateEvent(e) => {
-                    NetworkBehaviourAction::GenerateEvent(e)
-                }
-                NetworkBehaviourAction::DialAddress { address } => {
-                    NetworkBehaviourAction::DialAddress { address }
-                }
-                NetworkBehaviourAction::DialPeer { peer_id, condition } => {
-                    NetworkBehaviourAction::DialPeer { peer_id, condition }
-                }
-                NetworkBehaviourAction::ReportObservedAddr { address } => {
-                    NetworkBehaviourAction::ReportObservedAddr { address }
-                }
-            });
+            return Poll::Ready(event);
         }
 
         while let Poll::Ready(Some(())) = self.heartbeat.poll_next_unpin(cx) {
@@ -1476,6 +1595,15 @@ pub enum GossipsubEvent {
         /// The topic it has subscribed from.
         topic: TopicHash,
     },
+
+    /// Messages queued for a remote were dropped because the remote is not reading them fast
+    /// enough. The application may want to penalise or disconnect such a peer.
+    SlowPeer {
+        /// The slow remote.
+        peer_id: PeerId,
+        /// The number of dropped messages since the last report, per reason.
+        dropped: DroppedMessages,
+    },
 }
 
 /// Validates the combination of signing, privacy and message validation to ensure the
@@ -1515,6 +1643,7 @@ impl fmt::Debug for Gossipsub {
          .field("publish_config", &self.publish_config)
          .field("topic_peers", &self.topic_peers)
          .field("peer_topics", &self.peer_topics)
+         .field("floodsub_peers", &self.floodsub_peers)
          .field("mesh", &self.mesh)
          .field("fanout", &self.fanout)
          .field("fanout_last_pub", &self.fanout_last_pub)
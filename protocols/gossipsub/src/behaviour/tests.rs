@@ -95,7 +95,7 @@ mod tests {
                 .iter()
                 .fold(vec![], |mut collected_subscriptions, e| match e {
                     NetworkBehaviourAction::NotifyHandler { event, .. } => {
-                        for s in &event.subscriptions {
+                        for s in &event.rpc.subscriptions {
                             match s.action {
                                 GossipsubSubscriptionAction::Subscribe => {
                                     collected_subscriptions.push(s.clone())
@@ -158,7 +158,7 @@ mod tests {
                 .iter()
                 .fold(vec![], |mut collected_subscriptions, e| match e {
                     NetworkBehaviourAction::NotifyHandler { event, .. } => {
-                        for s in &event.subscriptions {
+                        for s in &event.rpc.subscriptions {
                             match s.action {
                                 GossipsubSubscriptionAction::Unsubscribe => {
                                     collected_subscriptions.push(s.clone())
@@ -321,7 +321,7 @@ mod tests {
             .iter()
             .fold(vec![], |mut collected_publish, e| match e {
                 NetworkBehaviourAction::NotifyHandler { event, .. } => {
-                    for s in &event.messages {
+                    for s in &event.rpc.messages {
                         collected_publish.push(s.clone());
                     }
                     collected_publish
@@ -386,7 +386,7 @@ mod tests {
             .iter()
             .fold(vec![], |mut collected_publish, e| match e {
                 NetworkBehaviourAction::NotifyHandler { event, .. } => {
-                    for s in &event.messages {
+                    for s in &event.rpc.messages {
                         collected_publish.push(s.clone());
                     }
                     collected_publish
@@ -420,12 +420,12 @@ mod tests {
 
         // check that our subscriptions are sent to each of the peers
         // collect all the SendEvents
-        let send_events: Vec<&NetworkBehaviourAction<Arc<GossipsubRpc>, GossipsubEvent>> = gs
+        let send_events: Vec<&NetworkBehaviourAction<GossipsubHandlerIn, GossipsubEvent>> = gs
             .events
             .iter()
             .filter(|e| match e {
                 NetworkBehaviourAction::NotifyHandler { event, .. } => {
-                    !event.subscriptions.is_empty()
+                    !event.rpc.subscriptions.is_empty()
                 }
                 _ => false,
             })
@@ -436,7 +436,7 @@ mod tests {
             match sevent {
                 NetworkBehaviourAction::NotifyHandler { event, .. } => {
                     assert!(
-                        event.subscriptions.len() == 2,
+                        event.rpc.subscriptions.len() == 2,
                         "There should be two subscriptions sent to each peer (1 for each topic)."
                     );
                 }
@@ -565,28 +565,62 @@ mod tests {
         gs.topic_peers
             .insert(topic_hash.clone(), peers.iter().cloned().collect());
 
-        let random_peers = Gossipsub::get_random_peers(&gs.topic_peers, &topic_hash, 5, |_| true);
+        let random_peers = Gossipsub::get_random_peers(
+            &gs.topic_peers,
+            &gs.floodsub_peers,
+            &topic_hash,
+            5,
+            |_| true,
+        );
         assert_eq!(random_peers.len(), 5, "Expected 5 peers to be returned");
-        let random_peers = Gossipsub::get_random_peers(&gs.topic_peers, &topic_hash, 30, |_| true);
+        let random_peers = Gossipsub::get_random_peers(
+            &gs.topic_peers,
+            &gs.floodsub_peers,
+            &topic_hash,
+            30,
+            |_| true,
+        );
         assert!(random_peers.len() == 20, "Expected 20 peers to be returned");
         assert!(
             random_peers == peers.iter().cloned().collect(),
             "Expected no shuffling"
         );
-        let random_peers = Gossipsub::get_random_peers(&gs.topic_peers, &topic_hash, 20, |_| true);
+        let random_peers = Gossipsub::get_random_peers(
+            &gs.topic_peers,
+            &gs.floodsub_peers,
+            &topic_hash,
+            20,
+            |_| true,
+        );
         assert!(random_peers.len() == 20, "Expected 20 peers to be returned");
         assert!(
             random_peers == peers.iter().cloned().collect(),
             "Expected no shuffling"
         );
-        let random_peers = Gossipsub::get_random_peers(&gs.topic_peers, &topic_hash, 0, |_| true);
+        let random_peers = Gossipsub::get_random_peers(
+            &gs.topic_peers,
+            &gs.floodsub_peers,
+            &topic_hash,
+            0,
+            |_| true,
+        );
         assert!(random_peers.len() == 0, "Expected 0 peers to be returned");
         // test the filter
-        let random_peers = Gossipsub::get_random_peers(&gs.topic_peers, &topic_hash, 5, |_| false);
+        let random_peers = Gossipsub::get_random_peers(
+            &gs.topic_peers,
+            &gs.floodsub_peers,
+            &topic_hash,
+            5,
+            |_| false,
+        );
         assert!(random_peers.len() == 0, "Expected 0 peers to be returned");
-        let random_peers = Gossipsub::get_random_peers(&gs.topic_peers, &topic_hash, 10, {
-            |peer| peers.contains(peer)
-        });
+        let random_peers = Gossipsub::get_random_peers(
+            &gs.topic_peers,
+            &gs.floodsub_peers,
+            &topic_hash,
+            10,
+            |peer| peers.contains(peer),
+        );
         assert!(random_peers.len() == 10, "Expected 10 peers to be returned");
     }
 
@@ -595,7 +629,7 @@ mod tests {
     fn test_handle_iwant_msg_cached() {
         let (mut gs, peers, _) = build_and_inject_nodes(20, Vec::new(), true);
 
-        let id = gs.config.message_id_fn;
+        let id = gs.config.message_id_fn.clone();
 
         let message = GossipsubMessage {
             source: Some(peers[11].clone()),
@@ -617,7 +651,7 @@ mod tests {
             .iter()
             .fold(vec![], |mut collected_messages, e| match e {
                 NetworkBehaviourAction::NotifyHandler { event, .. } => {
-                    for c in &event.messages {
+                    for c in &event.rpc.messages {
                         collected_messages.push(c.clone())
                     }
                     collected_messages
@@ -636,7 +670,7 @@ mod tests {
     fn test_handle_iwant_msg_cached_shifted() {
         let (mut gs, peers, _) = build_and_inject_nodes(20, Vec::new(), true);
 
-        let id = gs.config.message_id_fn;
+        let id = gs.config.message_id_fn.clone();
         // perform 10 memshifts and check that it leaves the cache
         for shift in 1..10 {
             let message = GossipsubMessage {
@@ -659,7 +693,7 @@ mod tests {
             // is the message is being sent?
             let message_exists = gs.events.iter().any(|e| match e {
                 NetworkBehaviourAction::NotifyHandler { event, .. } => {
-                    event.messages.iter().any(|msg| id(msg) == msg_id)
+                    event.rpc.messages.iter().any(|msg| id(msg) == msg_id)
                 }
                 _ => false,
             });
@@ -896,6 +930,227 @@ mod tests {
         assert_eq!(gs.mesh.get(&topics[0]).unwrap().len(), config.mesh_n);
     }
 
+    /// Tests that our own published messages are queued with priority, while forwarded messages
+    /// are not.
+    #[test]
+    fn test_publish_and_forward_priority() {
+        let publish_topic = String::from("test_priority");
+        let (mut gs, peers, topic_hashes) =
+            build_and_inject_nodes(20, vec![publish_topic.clone()], true);
+        gs.events.clear();
+
+        gs.publish(&Topic::new(publish_topic), vec![0; 42]).unwrap();
+
+        let publish_priorities = gs
+            .events
+            .drain(..)
+            .filter_map(|e| match e {
+                NetworkBehaviourAction::NotifyHandler { event, .. } => Some(event.priority),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(!publish_priorities.is_empty(), "Should send the published message");
+        assert!(
+            publish_priorities.iter().all(|p| *p == MessagePriority::High),
+            "Published messages should be queued with priority"
+        );
+
+        let message = GossipsubMessage {
+            source: Some(peers[0].clone()),
+            data: vec![1, 2, 3, 4],
+            sequence_number: Some(1u64),
+            topics: topic_hashes.clone(),
+            signature: None,
+            key: None,
+            validated: true,
+        };
+        gs.handle_received_message(message, &peers[0]);
+
+        let forward_priorities = gs
+            .events
+            .iter()
+            .filter_map(|e| match e {
+                NetworkBehaviourAction::NotifyHandler { event, .. } => Some(event.priority),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(!forward_priorities.is_empty(), "Should forward the received message");
+        assert!(
+            forward_priorities.iter().all(|p| *p == MessagePriority::Low),
+            "Forwarded messages should not be queued with priority"
+        );
+    }
+
+    /// Tests that messages dropped by a handler are reported as a slow peer.
+    #[test]
+    fn test_messages_dropped_reports_slow_peer() {
+        let (mut gs, peers, _) = build_and_inject_nodes(1, Vec::new(), true);
+        gs.events.clear();
+
+        let dropped = DroppedMessages {
+            priority: 0,
+            non_priority: 3,
+            stale: 2,
+        };
+        gs.inject_event(
+            peers[0].clone(),
+            ConnectionId::new(0),
+            GossipsubHandlerEvent::MessagesDropped(dropped),
+        );
+
+        match gs.events.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(GossipsubEvent::SlowPeer {
+                peer_id,
+                dropped: reported,
+            })) => {
+                assert_eq!(peer_id, peers[0]);
+                assert_eq!(reported, dropped);
+            }
+            e => panic!("Expected a SlowPeer event, got {:?}", e),
+        }
+    }
+
+    /// Tests that floodsub peers are removed from the mesh but receive published and forwarded
+    /// messages of the topics they are subscribed to.
+    #[test]
+    fn test_floodsub_peers_receive_messages() {
+        let topic = String::from("test_floodsub");
+        let (mut gs, peers, topic_hashes) = build_and_inject_nodes(20, vec![topic.clone()], true);
+
+        // mark every peer in the mesh as floodsub peer
+        let floodsub_peers = gs.mesh.get(&topic_hashes[0]).unwrap().clone();
+        assert!(!floodsub_peers.is_empty());
+        for peer in &floodsub_peers {
+            gs.inject_event(
+                peer.clone(),
+                ConnectionId::new(0),
+                GossipsubHandlerEvent::PeerKind(PeerKind::Floodsub),
+            );
+        }
+        assert!(
+            gs.mesh.get(&topic_hashes[0]).unwrap().is_disjoint(&floodsub_peers),
+            "Floodsub peers should be removed from the mesh"
+        );
+
+        // the mesh is refilled with gossipsub peers only
+        gs.heartbeat();
+        assert!(
+            gs.mesh.get(&topic_hashes[0]).unwrap().is_disjoint(&floodsub_peers),
+            "Floodsub peers should not be added to the mesh"
+        );
+
+        fn message_recipients(gs: &mut Gossipsub) -> BTreeSet<PeerId> {
+            gs.events
+                .drain(..)
+                .filter_map(|e| match e {
+                    NetworkBehaviourAction::NotifyHandler { peer_id, event, .. }
+                        if !event.rpc.messages.is_empty() =>
+                    {
+                        Some(peer_id)
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+
+        gs.events.clear();
+        gs.publish(&Topic::new(topic), vec![0; 42]).unwrap();
+        assert!(
+            message_recipients(&mut gs).is_superset(&floodsub_peers),
+            "Published messages should be sent to all floodsub peers"
+        );
+
+        let source = peers
+            .iter()
+            .find(|p| !floodsub_peers.contains(*p))
+            .unwrap()
+            .clone();
+        let message = GossipsubMessage {
+            source: Some(source.clone()),
+            data: vec![1, 2, 3, 4],
+            sequence_number: Some(1u64),
+            topics: topic_hashes.clone(),
+            signature: None,
+            key: None,
+            validated: true,
+        };
+        gs.handle_received_message(message, &source);
+        assert!(
+            message_recipients(&mut gs).is_superset(&floodsub_peers),
+            "Received messages should be forwarded to all floodsub peers"
+        );
+    }
+
+    /// Tests that messages with a known fast message id are dropped before their message id is
+    /// computed, and that invalid messages don't prevent valid ones from being received.
+    #[test]
+    fn test_fast_message_id_drops_duplicates() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let id_calls = Arc::new(AtomicUsize::new(0));
+        let id_calls_fn = id_calls.clone();
+        let gs_config = crate::GossipsubConfigBuilder::new()
+            .validation_mode(ValidationMode::Permissive)
+            .message_id_fn(move |message: &GossipsubMessage| {
+                id_calls_fn.fetch_add(1, Ordering::SeqCst);
+                MessageId::from(message.data.clone())
+            })
+            .fast_message_id_fn(|message: &RawGossipsubMessage| {
+                FastMessageId::from(message.bytes.clone())
+            })
+            .build();
+        let mut gs = Gossipsub::new(MessageAuthenticity::Author(PeerId::random()), gs_config);
+
+        let topic = Topic::new("test_fast_id".into());
+        gs.subscribe(topic.clone());
+        let peer = PeerId::random();
+        <Gossipsub as NetworkBehaviour>::inject_connected(&mut gs, &peer);
+        gs.events.clear();
+
+        let message: RawGossipsubMessage = GossipsubMessage {
+            source: Some(peer.clone()),
+            data: vec![1, 2, 3, 4],
+            sequence_number: Some(1),
+            topics: vec![topic.no_hash()],
+            signature: None,
+            key: None,
+            validated: false,
+        }
+        .into();
+
+        // a message that fails to decode is not remembered
+        let invalid = RawGossipsubMessage {
+            bytes: vec![0xff, 0xff, 0xff],
+        };
+        gs.handle_raw_message(invalid.clone(), &peer);
+        assert_eq!(id_calls.load(Ordering::SeqCst), 0);
+        assert!(!gs
+            .fast_message_id_cache
+            .contains_key(&FastMessageId::from(invalid.bytes)));
+
+        gs.handle_raw_message(message.clone(), &peer);
+        let calls = id_calls.load(Ordering::SeqCst);
+        assert!(calls > 0, "Message id should be computed for a new message");
+        let received = gs
+            .events
+            .iter()
+            .filter(|e| {
+                matches!(
+                    e,
+                    NetworkBehaviourAction::GenerateEvent(GossipsubEvent::Message(..))
+                )
+            })
+            .count();
+        assert_eq!(received, 1, "Valid message should be received");
+
+        gs.handle_raw_message(message, &peer);
+        assert_eq!(
+            id_calls.load(Ordering::SeqCst),
+            calls,
+            "Message id should not be computed for a duplicate"
+        );
+    }
+
     // Some very basic test of public api methods.
     #[test]
     fn test_public_api() {
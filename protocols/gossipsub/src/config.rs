@@ -18,11 +18,18 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::protocol::{GossipsubMessage, MessageId};
+use crate::protocol::{FastMessageId, GossipsubMessage, MessageId, RawGossipsubMessage};
 use libp2p_core::PeerId;
 use std::borrow::Cow;
+use std::sync::Arc;
 use std::time::Duration;
 
+/// A user-defined function computing the id of a message.
+pub(crate) type MessageIdFn = Arc<dyn Fn(&GossipsubMessage) -> MessageId + Send + Sync>;
+
+/// A user-defined function computing the fast id of a message as received on the wire.
+pub(crate) type FastMessageIdFn = Arc<dyn Fn(&RawGossipsubMessage) -> FastMessageId + Send + Sync>;
+
 /// The types of message validation that can be employed by gossipsub.
 #[derive(Debug, Clone)]
 pub enum ValidationMode {
@@ -51,6 +58,14 @@ pub struct GossipsubConfig {
     /// The protocol id to negotiate this protocol (default is `/meshsub/1.0.0`).
     pub protocol_id: Cow<'static, [u8]>,
 
+    /// When set, `/floodsub/1.0.0` is negotiated with peers that don't support gossipsub.
+    /// Floodsub peers are never added to meshes, but receive every message of the topics they
+    /// are subscribed to (default is false).
+    ///
+    /// NOTE: Floodsub messages are not signed, so the [`ValidationMode`] needs to permit unsigned
+    /// messages for messages of floodsub peers to be accepted.
+    pub support_floodsub: bool,
+
     // Overlay network parameters.
     /// Number of heartbeats to keep in the `memcache` (default is 5).
     pub history_length: usize,
@@ -83,6 +98,20 @@ pub struct GossipsubConfig {
     /// The maximum byte size for each gossip (default is 2048 bytes).
     pub max_transmit_size: usize,
 
+    /// The maximum number of RPCs queued per connection for sending control messages,
+    /// subscriptions and our own published messages. Further RPCs are dropped until the remote
+    /// catches up (default is 5000).
+    pub priority_queue_size: usize,
+
+    /// The maximum number of RPCs queued per connection for sending forwarded messages and gossip
+    /// responses. Further RPCs are dropped until the remote catches up (default is 1000).
+    pub non_priority_queue_size: usize,
+
+    /// The maximum time a forwarded message or gossip response may wait in the send queue of a
+    /// connection. Messages that could not be sent within this time are dropped, as the remote
+    /// has likely received them from other peers by then (default is 1 second).
+    pub forward_queue_duration: Duration,
+
     /// Duplicates are prevented by storing message id's of known messages in an LRU time cache.
     /// This settings sets the time period that messages are stored in the cache. Duplicates can be
     /// received if duplicate messages are sent at a time greater than this setting apart. The
@@ -112,13 +141,23 @@ pub struct GossipsubConfig {
     ///
     /// The function takes a `GossipsubMessage` as input and outputs a String to be interpreted as
     /// the message id.
-    pub message_id_fn: fn(&GossipsubMessage) -> MessageId,
+    pub message_id_fn: MessageIdFn,
+
+    /// An optional user-defined function computing a cheap id of a message as received on the
+    /// wire (default is `None`). Received messages whose fast id has already been seen within the
+    /// `duplicate_cache_time` are dropped before they are decoded, have their signature verified
+    /// or their `message_id_fn` computed.
+    ///
+    /// The fast id must be a function of the message contents only, such that two messages with
+    /// the same fast id have the same message id.
+    pub fast_message_id_fn: Option<FastMessageIdFn>,
 }
 
 impl Default for GossipsubConfig {
     fn default() -> GossipsubConfig {
         GossipsubConfig {
             protocol_id: Cow::Borrowed(b"/meshsub/1.0.0"),
+            support_floodsub: false,
             history_length: 5,
             history_gossip: 3,
             mesh_n: 6,
@@ -129,11 +168,14 @@ impl Default for GossipsubConfig {
             heartbeat_interval: Duration::from_secs(1),
             fanout_ttl: Duration::from_secs(60),
             max_transmit_size: 2048,
+            priority_queue_size: 5000,
+            non_priority_queue_size: 1000,
+            forward_queue_duration: Duration::from_secs(1),
             duplicate_cache_time: Duration::from_secs(60),
             hash_topics: false, // default compatibility with floodsub
             validate_messages: false,
             validation_mode: ValidationMode::Strict,
-            message_id_fn: |message| {
+            message_id_fn: Arc::new(|message| {
                 // default message id is: source + sequence number
                 // NOTE: If either the peer_id or source is not provided, we set to 0;
                 let mut source_string = if let Some(peer_id) = message.source.as_ref() {
@@ -145,7 +187,8 @@ impl Default for GossipsubConfig {
                 };
                 source_string.push_str(&message.sequence_number.unwrap_or_default().to_string());
                 MessageId::from(source_string)
-            },
+            }),
+            fast_message_id_fn: None,
         }
     }
 }
@@ -177,6 +220,14 @@ impl GossipsubConfigBuilder {
         self
     }
 
+    /// When set, `/floodsub/1.0.0` is negotiated with peers that don't support gossipsub.
+    /// Floodsub peers are never added to meshes, but receive every message of the topics they
+    /// are subscribed to.
+    pub fn support_floodsub(&mut self) -> &mut Self {
+        self.config.support_floodsub = true;
+        self
+    }
+
     /// Number of heartbeats to keep in the `memcache` (default is 5).
     pub fn history_length(&mut self, history_length: usize) -> &mut Self {
         assert!(
@@ -258,6 +309,29 @@ impl GossipsubConfigBuilder {
         self
     }
 
+    /// The maximum number of RPCs queued per connection for sending control messages,
+    /// subscriptions and our own published messages (default is 5000).
+    pub fn priority_queue_size(&mut self, priority_queue_size: usize) -> &mut Self {
+        assert!(priority_queue_size > 0, "The priority_queue_size must be non-zero");
+        self.config.priority_queue_size = priority_queue_size;
+        self
+    }
+
+    /// The maximum number of RPCs queued per connection for sending forwarded messages and gossip
+    /// responses (default is 1000).
+    pub fn non_priority_queue_size(&mut self, non_priority_queue_size: usize) -> &mut Self {
+        assert!(non_priority_queue_size > 0, "The non_priority_queue_size must be non-zero");
+        self.config.non_priority_queue_size = non_priority_queue_size;
+        self
+    }
+
+    /// The maximum time a forwarded message or gossip response may wait in the send queue of a
+    /// connection before being dropped (default is 1 second).
+    pub fn forward_queue_duration(&mut self, forward_queue_duration: Duration) -> &mut Self {
+        self.config.forward_queue_duration = forward_queue_duration;
+        self
+    }
+
     /// Duplicates are prevented by storing message id's of known messages in an LRU time cache.
     /// This settings sets the time period that messages are stored in the cache. Duplicates can be
     /// received if duplicate messages are sent at a time greater than this setting apart. The
@@ -296,9 +370,26 @@ impl GossipsubConfigBuilder {
     /// of the same content from being duplicated.
     ///
     /// The function takes a `GossipsubMessage` as input and outputs a String to be interpreted as
-    /// the message id.
-    pub fn message_id_fn(&mut self, id_fn: fn(&GossipsubMessage) -> MessageId) -> &mut Self {
-        self.config.message_id_fn = id_fn;
+    /// the message id. The function may capture its environment.
+    pub fn message_id_fn<F>(&mut self, id_fn: F) -> &mut Self
+    where
+        F: Fn(&GossipsubMessage) -> MessageId + Send + Sync + 'static,
+    {
+        self.config.message_id_fn = Arc::new(id_fn);
+        self
+    }
+
+    /// A user-defined function computing a cheap id of a message as received on the wire.
+    /// Received messages whose fast id has already been seen are dropped before they are decoded,
+    /// have their signature verified or their message id computed.
+    ///
+    /// The fast id must be a function of the message contents only, such that two messages with
+    /// the same fast id have the same message id.
+    pub fn fast_message_id_fn<F>(&mut self, fast_id_fn: F) -> &mut Self
+    where
+        F: Fn(&RawGossipsubMessage) -> FastMessageId + Send + Sync + 'static,
+    {
+        self.config.fast_message_id_fn = Some(Arc::new(fast_id_fn));
         self
     }
 
@@ -316,6 +407,7 @@ impl std::fmt::Debug for GossipsubConfig {
         } else {
             builder.field("protocol_id", &hex_fmt::HexFmt(&self.protocol_id))
         };
+        let _ = builder.field("support_floodsub", &self.support_floodsub);
         let _ = builder.field("history_length", &self.history_length);
         let _ = builder.field("history_gossip", &self.history_gossip);
         let _ = builder.field("mesh_n", &self.mesh_n);
@@ -326,6 +418,9 @@ impl std::fmt::Debug for GossipsubConfig {
         let _ = builder.field("heartbeat_interval", &self.heartbeat_interval);
         let _ = builder.field("fanout_ttl", &self.fanout_ttl);
         let _ = builder.field("max_transmit_size", &self.max_transmit_size);
+        let _ = builder.field("priority_queue_size", &self.priority_queue_size);
+        let _ = builder.field("non_priority_queue_size", &self.non_priority_queue_size);
+        let _ = builder.field("forward_queue_duration", &self.forward_queue_duration);
         let _ = builder.field("duplicate_cache_time", &self.duplicate_cache_time);
         let _ = builder.field("hash_topics", &self.hash_topics);
         let _ = builder.field("validate_messages", &self.validate_messages);
@@ -19,8 +19,8 @@
 // DEALINGS IN THE SOFTWARE.
 
 use crate::behaviour::GossipsubRpc;
-use crate::config::ValidationMode;
-use crate::protocol::{GossipsubCodec, ProtocolConfig};
+use crate::config::GossipsubConfig;
+use crate::protocol::{GossipsubCodec, PeerKind, ProtocolConfig, RawGossipsubRpc};
 use futures::prelude::*;
 use futures_codec::Framed;
 use libp2p_core::upgrade::{InboundUpgrade, OutboundUpgrade};
@@ -29,13 +29,67 @@ use libp2p_swarm::protocols_handler::{
 };
 use libp2p_swarm::NegotiatedSubstream;
 use log::{debug, error, trace, warn};
-use smallvec::SmallVec;
 use std::{
-    borrow::Cow,
+    collections::VecDeque,
     io,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
+use wasm_timer::Instant;
+
+/// The queue an outgoing RPC is placed into by the [`GossipsubHandler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessagePriority {
+    /// Control messages, subscriptions and messages we publish ourselves. These are always sent
+    /// before any message of the non-priority queue.
+    High,
+    /// Forwarded messages and gossip (IWANT) responses. These are dropped if they could not be
+    /// sent within the configured `forward_queue_duration`.
+    Low,
+}
+
+/// An RPC to send to the remote, as handed from the behaviour to the [`GossipsubHandler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GossipsubHandlerIn {
+    /// The RPC to send. Shared between all the peers the RPC is sent to.
+    pub rpc: Arc<GossipsubRpc>,
+    /// The queue the RPC is placed into.
+    pub priority: MessagePriority,
+}
+
+/// Event produced by the [`GossipsubHandler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GossipsubHandlerEvent {
+    /// An RPC has been received from the remote. Its messages have not been validated yet.
+    Rpc(RawGossipsubRpc),
+    /// The kind of protocol the remote speaks has been determined by negotiating the first
+    /// substream.
+    PeerKind(PeerKind),
+    /// Outgoing RPCs have been dropped since the last report, because the remote is not reading
+    /// them fast enough.
+    MessagesDropped(DroppedMessages),
+}
+
+/// Number of outgoing RPCs that were dropped by a [`GossipsubHandler`], per reason.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DroppedMessages {
+    /// RPCs dropped because the priority queue was full.
+    pub priority: usize,
+    /// RPCs dropped because the non-priority queue was full.
+    pub non_priority: usize,
+    /// RPCs dropped from the non-priority queue because they waited for longer than the
+    /// `forward_queue_duration`.
+    pub stale: usize,
+}
+
+impl DroppedMessages {
+    /// Returns the total number of dropped RPCs.
+    pub fn total(&self) -> usize {
+        self.priority + self.non_priority + self.stale
+    }
+}
 
 /// Protocol Handler that manages a single long-lived substream with a peer.
 pub struct GossipsubHandler {
@@ -48,8 +102,8 @@ pub struct GossipsubHandler {
     /// The single long-lived inbound substream.
     inbound_substream: Option<InboundSubstreamState>,
 
-    /// Queue of values that we want to send to the remote.
-    send_queue: SmallVec<[GossipsubRpc; 16]>,
+    /// Bounded queues of RPCs that we want to send to the remote.
+    send_queue: SendQueue,
 
     /// Flag indicating that an outbound substream is being established to prevent duplicate
     /// requests.
@@ -57,6 +111,107 @@ pub struct GossipsubHandler {
 
     /// Flag determining whether to maintain the connection to the peer.
     keep_alive: KeepAlive,
+
+    /// The kind of protocol the remote speaks, once known.
+    peer_kind: Option<PeerKind>,
+
+    /// Flag indicating that the `peer_kind` has been reported to the behaviour.
+    peer_kind_reported: bool,
+}
+
+/// The outgoing RPCs of a [`GossipsubHandler`], split into a priority and a non-priority queue.
+///
+/// Both queues are bounded. RPCs that don't fit are dropped and counted, so that the behaviour
+/// can be informed about peers that can't keep up.
+struct SendQueue {
+    /// RPCs that are sent first, in FIFO order.
+    priority: VecDeque<Arc<GossipsubRpc>>,
+    /// Maximum number of RPCs in the priority queue.
+    max_priority: usize,
+    /// RPCs that are only sent once the priority queue is empty, together with the time they
+    /// were enqueued.
+    non_priority: VecDeque<(Arc<GossipsubRpc>, Instant)>,
+    /// Maximum number of RPCs in the non-priority queue.
+    max_non_priority: usize,
+    /// Time after which an RPC of the non-priority queue is considered stale and dropped.
+    forward_queue_duration: Duration,
+    /// RPCs dropped since the last call to `take_dropped`.
+    dropped: DroppedMessages,
+}
+
+impl SendQueue {
+    fn new(max_priority: usize, max_non_priority: usize, forward_queue_duration: Duration) -> Self {
+        SendQueue {
+            priority: VecDeque::new(),
+            max_priority,
+            non_priority: VecDeque::new(),
+            max_non_priority,
+            forward_queue_duration,
+            dropped: DroppedMessages::default(),
+        }
+    }
+
+    /// Adds an RPC to the queue matching its priority, or drops it if that queue is full.
+    fn push(&mut self, rpc: Arc<GossipsubRpc>, priority: MessagePriority) {
+        match priority {
+            MessagePriority::High => {
+                if self.priority.len() >= self.max_priority {
+                    debug!("Priority send queue full, dropping message");
+                    self.dropped.priority += 1;
+                } else {
+                    self.priority.push_back(rpc);
+                }
+            }
+            MessagePriority::Low => {
+                if self.non_priority.len() >= self.max_non_priority {
+                    debug!("Non-priority send queue full, dropping message");
+                    self.dropped.non_priority += 1;
+                } else {
+                    self.non_priority.push_back((rpc, Instant::now()));
+                }
+            }
+        }
+    }
+
+    /// Puts an RPC back at the front of the priority queue, regardless of its length.
+    fn push_front(&mut self, rpc: GossipsubRpc) {
+        self.priority.push_front(Arc::new(rpc));
+    }
+
+    /// Removes the next RPC to send, dropping any stale RPCs of the non-priority queue.
+    fn pop(&mut self) -> Option<GossipsubRpc> {
+        self.pop_at(Instant::now())
+    }
+
+    /// Like `pop`, with the staleness of RPCs determined relative to `now`.
+    fn pop_at(&mut self, now: Instant) -> Option<GossipsubRpc> {
+        let rpc = match self.priority.pop_front() {
+            Some(rpc) => rpc,
+            None => loop {
+                match self.non_priority.pop_front() {
+                    Some((_, enqueued)) if enqueued + self.forward_queue_duration < now => {
+                        self.dropped.stale += 1;
+                    }
+                    Some((rpc, _)) => break rpc,
+                    None => return None,
+                }
+            },
+        };
+        // clone the RPC if it is still queued for other peers
+        Some(Arc::try_unwrap(rpc).unwrap_or_else(|rpc| (*rpc).clone()))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.priority.is_empty() && self.non_priority.is_empty()
+    }
+
+    /// Returns the RPCs dropped since the last call, if any.
+    fn take_dropped(&mut self) -> Option<DroppedMessages> {
+        if self.dropped.total() == 0 {
+            return None;
+        }
+        Some(std::mem::take(&mut self.dropped))
+    }
 }
 
 /// State of the inbound substream, opened either by us or by the remote.
@@ -77,37 +232,52 @@ enum OutboundSubstreamState {
     PendingSend(Framed<NegotiatedSubstream, GossipsubCodec>, GossipsubRpc),
     /// Waiting to flush the substream so that the data arrives to the remote.
     PendingFlush(Framed<NegotiatedSubstream, GossipsubCodec>),
-    /// The substream is being closed. Used by either substream.
-    _Closing(Framed<NegotiatedSubstream, GossipsubCodec>),
+    /// The substream is being closed. Outbound substreams to floodsub peers are closed after
+    /// each message.
+    Closing(Framed<NegotiatedSubstream, GossipsubCodec>),
     /// An error occurred during processing.
     Poisoned,
 }
 
 impl GossipsubHandler {
-    /// Builds a new `GossipsubHandler`.
-    pub fn new(
-        protocol_id: impl Into<Cow<'static, [u8]>>,
-        max_transmit_size: usize,
-        validation_mode: ValidationMode,
-    ) -> Self {
+    /// Builds a new `GossipsubHandler` from the given configuration.
+    pub fn new(config: &GossipsubConfig) -> Self {
         GossipsubHandler {
             listen_protocol: SubstreamProtocol::new(ProtocolConfig::new(
-                protocol_id,
-                max_transmit_size,
-                validation_mode,
+                config.protocol_id.clone(),
+                config.support_floodsub,
+                config.max_transmit_size,
             ), ()),
             inbound_substream: None,
             outbound_substream: None,
             outbound_substream_establishing: false,
-            send_queue: SmallVec::new(),
+            send_queue: SendQueue::new(
+                config.priority_queue_size,
+                config.non_priority_queue_size,
+                config.forward_queue_duration,
+            ),
             keep_alive: KeepAlive::Yes,
+            peer_kind: None,
+            peer_kind_reported: false,
         }
     }
+
+    /// Records the kind of protocol negotiated on a substream, if not yet known.
+    fn set_peer_kind(&mut self, kind: PeerKind) {
+        if self.peer_kind.is_none() {
+            self.peer_kind = Some(kind);
+        }
+    }
+
+    /// Returns true if the remote only speaks floodsub.
+    fn is_floodsub(&self) -> bool {
+        self.peer_kind == Some(PeerKind::Floodsub)
+    }
 }
 
 impl ProtocolsHandler for GossipsubHandler {
-    type InEvent = GossipsubRpc;
-    type OutEvent = GossipsubRpc;
+    type InEvent = GossipsubHandlerIn;
+    type OutEvent = GossipsubHandlerEvent;
     type Error = io::Error;
     type InboundProtocol = ProtocolConfig;
     type OutboundProtocol = ProtocolConfig;
@@ -120,9 +290,10 @@ impl ProtocolsHandler for GossipsubHandler {
 
     fn inject_fully_negotiated_inbound(
         &mut self,
-        substream: <Self::InboundProtocol as InboundUpgrade<NegotiatedSubstream>>::Output,
+        (substream, kind): <Self::InboundProtocol as InboundUpgrade<NegotiatedSubstream>>::Output,
         _info: Self::InboundOpenInfo
     ) {
+        self.set_peer_kind(kind);
         // new inbound substream. Replace the current one, if it exists.
         trace!("New inbound substream request");
         self.inbound_substream = Some(InboundSubstreamState::WaitingInput(substream));
@@ -130,23 +301,24 @@ impl ProtocolsHandler for GossipsubHandler {
 
     fn inject_fully_negotiated_outbound(
         &mut self,
-        substream: <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Output,
+        (substream, kind): <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Output,
         message: Self::OutboundOpenInfo,
     ) {
+        self.set_peer_kind(kind);
         self.outbound_substream_establishing = false;
         // Should never establish a new outbound substream if one already exists.
         // If this happens, an outbound message is not sent.
         if self.outbound_substream.is_some() {
             warn!("Established an outbound substream with one already available");
-            // Add the message back to the send queue
-            self.send_queue.push(message);
+            // Add the message back to the front of the send queue
+            self.send_queue.push_front(message);
         } else {
             self.outbound_substream = Some(OutboundSubstreamState::PendingSend(substream, message));
         }
     }
 
-    fn inject_event(&mut self, message: GossipsubRpc) {
-        self.send_queue.push(message);
+    fn inject_event(&mut self, message: GossipsubHandlerIn) {
+        self.send_queue.push(message.rpc, message.priority);
     }
 
     fn inject_dial_upgrade_error(
@@ -177,17 +349,32 @@ impl ProtocolsHandler for GossipsubHandler {
             Self::Error,
         >,
     > {
+        // report the kind of protocol the remote speaks, before any of its messages
+        if let (Some(kind), false) = (self.peer_kind, self.peer_kind_reported) {
+            self.peer_kind_reported = true;
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(
+                GossipsubHandlerEvent::PeerKind(kind),
+            ));
+        }
+
+        // report messages that were dropped for this peer
+        if let Some(dropped) = self.send_queue.take_dropped() {
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(
+                GossipsubHandlerEvent::MessagesDropped(dropped),
+            ));
+        }
+
         // determine if we need to create the stream
         if !self.send_queue.is_empty()
             && self.outbound_substream.is_none()
             && !self.outbound_substream_establishing
         {
-            let message = self.send_queue.remove(0);
-            self.send_queue.shrink_to_fit();
-            self.outbound_substream_establishing = true;
-            return Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
-                protocol: self.listen_protocol.clone().map_info(|()| message)
-            });
+            if let Some(message) = self.send_queue.pop() {
+                self.outbound_substream_establishing = true;
+                return Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                    protocol: self.listen_protocol.clone().map_info(|()| message)
+                });
+            }
         }
 
         loop {
@@ -201,7 +388,9 @@ impl ProtocolsHandler for GossipsubHandler {
                         Poll::Ready(Some(Ok(message))) => {
                             self.inbound_substream =
                                 Some(InboundSubstreamState::WaitingInput(substream));
-                            return Poll::Ready(ProtocolsHandlerEvent::Custom(message));
+                            return Poll::Ready(ProtocolsHandlerEvent::Custom(
+                                GossipsubHandlerEvent::Rpc(message),
+                            ));
                         }
                         Poll::Ready(Some(Err(e))) => {
                             match e.kind() {
@@ -243,7 +432,8 @@ impl ProtocolsHandler for GossipsubHandler {
                             }
 
                             self.inbound_substream = None;
-                            if self.outbound_substream.is_none() {
+                            // Floodsub peers open a new substream for every message.
+                            if self.outbound_substream.is_none() && !self.is_floodsub() {
                                 self.keep_alive = KeepAlive::No;
                             }
                             break;
@@ -272,9 +462,7 @@ impl ProtocolsHandler for GossipsubHandler {
             ) {
                 // outbound idle state
                 Some(OutboundSubstreamState::WaitingOutput(substream)) => {
-                    if !self.send_queue.is_empty() {
-                        let message = self.send_queue.remove(0);
-                        self.send_queue.shrink_to_fit();
+                    if let Some(message) = self.send_queue.pop() {
                         self.outbound_substream =
                             Some(OutboundSubstreamState::PendingSend(substream, message));
                     } else {
@@ -316,8 +504,14 @@ impl ProtocolsHandler for GossipsubHandler {
                 Some(OutboundSubstreamState::PendingFlush(mut substream)) => {
                     match Sink::poll_flush(Pin::new(&mut substream), cx) {
                         Poll::Ready(Ok(())) => {
-                            self.outbound_substream =
-                                Some(OutboundSubstreamState::WaitingOutput(substream))
+                            // Floodsub peers only read a single message per substream.
+                            if self.is_floodsub() {
+                                self.outbound_substream =
+                                    Some(OutboundSubstreamState::Closing(substream))
+                            } else {
+                                self.outbound_substream =
+                                    Some(OutboundSubstreamState::WaitingOutput(substream))
+                            }
                         }
                         Poll::Ready(Err(e)) => return Poll::Ready(ProtocolsHandlerEvent::Close(e)),
                         Poll::Pending => {
@@ -327,9 +521,16 @@ impl ProtocolsHandler for GossipsubHandler {
                         }
                     }
                 }
-                // Currently never used - manual shutdown may implement this in the future
-                Some(OutboundSubstreamState::_Closing(mut substream)) => {
+                Some(OutboundSubstreamState::Closing(mut substream)) => {
                     match Sink::poll_close(Pin::new(&mut substream), cx) {
+                        Poll::Ready(Ok(())) if self.is_floodsub() => {
+                            // Open a new substream for the next message, if any.
+                            self.outbound_substream = None;
+                            if !self.send_queue.is_empty() {
+                                cx.waker().wake_by_ref();
+                            }
+                            break;
+                        }
                         Poll::Ready(Ok(())) => {
                             self.outbound_substream = None;
                             if self.inbound_substream.is_none() {
@@ -337,6 +538,11 @@ impl ProtocolsHandler for GossipsubHandler {
                             }
                             break;
                         }
+                        Poll::Ready(Err(e)) if self.is_floodsub() => {
+                            debug!("Outbound floodsub substream error while closing: {:?}", e);
+                            self.outbound_substream = None;
+                            break;
+                        }
                         Poll::Ready(Err(e)) => {
                             debug!("Outbound substream error while closing: {:?}", e);
                             return Poll::Ready(ProtocolsHandlerEvent::Close(io::Error::new(
@@ -346,7 +552,7 @@ impl ProtocolsHandler for GossipsubHandler {
                         }
                         Poll::Pending => {
                             self.outbound_substream =
-                                Some(OutboundSubstreamState::_Closing(substream));
+                                Some(OutboundSubstreamState::Closing(substream));
                             break;
                         }
                     }
@@ -361,6 +567,86 @@ impl ProtocolsHandler for GossipsubHandler {
             }
         }
 
+        // report messages that went stale while dequeuing
+        if let Some(dropped) = self.send_queue.take_dropped() {
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(
+                GossipsubHandlerEvent::MessagesDropped(dropped),
+            ));
+        }
+
         Poll::Pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc(data: u8) -> Arc<GossipsubRpc> {
+        Arc::new(GossipsubRpc {
+            messages: vec![crate::protocol::GossipsubMessage {
+                source: None,
+                data: vec![data],
+                sequence_number: None,
+                topics: Vec::new(),
+                signature: None,
+                key: None,
+                validated: true,
+            }],
+            subscriptions: Vec::new(),
+            control_msgs: Vec::new(),
+        })
+    }
+
+    fn data(rpc: Option<GossipsubRpc>) -> Option<u8> {
+        rpc.map(|rpc| rpc.messages[0].data[0])
+    }
+
+    #[test]
+    fn priority_messages_are_sent_first() {
+        let mut queue = SendQueue::new(10, 10, Duration::from_secs(60));
+        queue.push(rpc(1), MessagePriority::Low);
+        queue.push(rpc(2), MessagePriority::High);
+        queue.push(rpc(3), MessagePriority::Low);
+        queue.push(rpc(4), MessagePriority::High);
+
+        assert_eq!(data(queue.pop()), Some(2));
+        assert_eq!(data(queue.pop()), Some(4));
+        assert_eq!(data(queue.pop()), Some(1));
+        assert_eq!(data(queue.pop()), Some(3));
+        assert_eq!(data(queue.pop()), None);
+        assert!(queue.take_dropped().is_none());
+    }
+
+    #[test]
+    fn full_queues_drop_messages() {
+        let mut queue = SendQueue::new(1, 2, Duration::from_secs(60));
+        for i in 0..3 {
+            queue.push(rpc(i), MessagePriority::High);
+        }
+        for i in 0..5 {
+            queue.push(rpc(i), MessagePriority::Low);
+        }
+
+        assert_eq!(
+            queue.take_dropped(),
+            Some(DroppedMessages { priority: 2, non_priority: 3, stale: 0 })
+        );
+        assert!(queue.take_dropped().is_none());
+    }
+
+    #[test]
+    fn stale_forwards_are_dropped() {
+        let mut queue = SendQueue::new(10, 10, Duration::from_secs(1));
+        queue.push(rpc(1), MessagePriority::Low);
+        queue.push(rpc(2), MessagePriority::High);
+
+        let later = Instant::now() + Duration::from_secs(2);
+        assert_eq!(data(queue.pop_at(later)), Some(2));
+        assert_eq!(data(queue.pop_at(later)), None);
+        assert_eq!(
+            queue.take_dropped(),
+            Some(DroppedMessages { priority: 0, non_priority: 0, stale: 1 })
+        );
+    }
+}
@@ -149,6 +149,7 @@ mod rpc_proto {
 }
 
 pub use self::behaviour::{Gossipsub, GossipsubEvent, GossipsubRpc, MessageAuthenticity};
+pub use self::handler::DroppedMessages;
 pub use self::config::{GossipsubConfig, GossipsubConfigBuilder, ValidationMode};
-pub use self::protocol::{GossipsubMessage, MessageId};
+pub use self::protocol::{FastMessageId, GossipsubMessage, MessageId, RawGossipsubMessage};
 pub use self::topic::{Topic, TopicHash};
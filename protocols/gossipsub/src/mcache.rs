@@ -20,6 +20,7 @@
 
 
 
+use crate::config::MessageIdFn;
 use crate::protocol::{GossipsubMessage, MessageId};
 use crate::topic::TopicHash;
 use std::{collections::HashMap, fmt};
@@ -37,7 +38,7 @@ pub struct MessageCache {
     msgs: HashMap<MessageId, GossipsubMessage>,
     history: Vec<Vec<CacheEntry>>,
     gossip: usize,
-    msg_id: fn(&GossipsubMessage) -> MessageId,
+    msg_id: MessageIdFn,
 }
 
 impl fmt::Debug for MessageCache {
@@ -55,7 +56,7 @@ impl MessageCache {
     pub fn new(
         gossip: usize,
         history_capacity: usize,
-        msg_id: fn(&GossipsubMessage) -> MessageId,
+        msg_id: MessageIdFn,
     ) -> MessageCache {
         MessageCache {
             gossip,
@@ -169,7 +170,7 @@ mod tests {
             MessageId::from(source_string)
         };
 
-        MessageCache::new(gossip_size, history, default_id)
+        MessageCache::new(gossip_size, history, std::sync::Arc::new(default_id))
     }
 
     #[test]
@@ -28,47 +28,87 @@ use bytes::BytesMut;
 use futures::future;
 use futures::prelude::*;
 use futures_codec::{Decoder, Encoder, Framed};
-use libp2p_core::{identity::PublicKey, InboundUpgrade, OutboundUpgrade, PeerId, UpgradeInfo};
+use libp2p_core::{
+    identity::PublicKey, upgrade::ProtocolName, InboundUpgrade, OutboundUpgrade, PeerId,
+    UpgradeInfo,
+};
 use log::{debug, warn};
 use prost::Message as ProtobufMessage;
-use std::{borrow::Cow, fmt, io, iter, pin::Pin};
+use std::{borrow::Cow, fmt, io, pin::Pin};
 use unsigned_varint::codec;
 
 pub const SIGNING_PREFIX: &'static [u8] = b"libp2p-pubsub:";
 
+/// The protocol id of floodsub, which gossipsub can optionally negotiate for compatibility.
+pub const FLOODSUB_PROTOCOL_ID: &[u8] = b"/floodsub/1.0.0";
+
+/// The kind of protocol a remote speaks, as determined by protocol negotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerKind {
+    /// The remote speaks gossipsub.
+    Gossipsub,
+    /// The remote only speaks floodsub.
+    Floodsub,
+}
+
+/// A protocol id along with the kind of peer that speaks it.
+#[derive(Debug, Clone)]
+pub struct ProtocolId {
+    /// The protocol id to negotiate.
+    protocol_id: Cow<'static, [u8]>,
+    /// The kind of peer that negotiates this protocol id.
+    kind: PeerKind,
+}
+
+impl ProtocolName for ProtocolId {
+    fn protocol_name(&self) -> &[u8] {
+        &self.protocol_id
+    }
+}
+
 /// Implementation of the `ConnectionUpgrade` for the Gossipsub protocol.
 #[derive(Clone)]
 pub struct ProtocolConfig {
-    /// The gossipsub protocol id to listen on.
-    protocol_id: Cow<'static, [u8]>,
+    /// The protocol ids to listen on, in order of preference.
+    protocol_ids: Vec<ProtocolId>,
     /// The maximum transmit size for a packet.
     max_transmit_size: usize,
-    /// Determines the level of validation to be done on incoming messages.
-    validation_mode: ValidationMode,
 }
 
 impl ProtocolConfig {
     /// Builds a new `ProtocolConfig`.
     /// Sets the maximum gossip transmission size.
+    ///
+    /// If `support_floodsub` is set, the floodsub protocol id is negotiated as a fallback to the
+    /// gossipsub protocol id.
     pub fn new(
         protocol_id: impl Into<Cow<'static, [u8]>>,
+        support_floodsub: bool,
         max_transmit_size: usize,
-        validation_mode: ValidationMode,
     ) -> ProtocolConfig {
-        ProtocolConfig {
+        let mut protocol_ids = vec![ProtocolId {
             protocol_id: protocol_id.into(),
+            kind: PeerKind::Gossipsub,
+        }];
+        if support_floodsub {
+            protocol_ids.push(ProtocolId {
+                protocol_id: Cow::Borrowed(FLOODSUB_PROTOCOL_ID),
+                kind: PeerKind::Floodsub,
+            });
+        }
+        ProtocolConfig {
+            protocol_ids,
             max_transmit_size,
-            validation_mode,
         }
     }
 }
 
 impl UpgradeInfo for ProtocolConfig {
-    type Info = Cow<'static, [u8]>;
-    type InfoIter = iter::Once<Self::Info>;
+    type Info = ProtocolId;
+    type InfoIter = Vec<Self::Info>;
 
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(self.protocol_id.clone())
+        self.protocol_ids.clone()
     }
 }
 
@@ -76,16 +116,16 @@ impl<TSocket> InboundUpgrade<TSocket> for ProtocolConfig
 where
     TSocket: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    type Output = Framed<TSocket, GossipsubCodec>;
+    type Output = (Framed<TSocket, GossipsubCodec>, PeerKind);
     type Error = io::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
 
-    fn upgrade_inbound(self, socket: TSocket, _: Self::Info) -> Self::Future {
+    fn upgrade_inbound(self, socket: TSocket, protocol_id: Self::Info) -> Self::Future {
         let mut length_codec = codec::UviBytes::default();
         length_codec.set_max_len(self.max_transmit_size);
-        Box::pin(future::ok(Framed::new(
-            socket,
-            GossipsubCodec::new(length_codec, self.validation_mode),
+        Box::pin(future::ok((
+            Framed::new(socket, GossipsubCodec::new(length_codec)),
+            protocol_id.kind,
         )))
     }
 }
@@ -94,16 +134,16 @@ impl<TSocket> OutboundUpgrade<TSocket> for ProtocolConfig
 where
     TSocket: AsyncWrite + AsyncRead + Unpin + Send + 'static,
 {
-    type Output = Framed<TSocket, GossipsubCodec>;
+    type Output = (Framed<TSocket, GossipsubCodec>, PeerKind);
     type Error = io::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
 
-    fn upgrade_outbound(self, socket: TSocket, _: Self::Info) -> Self::Future {
+    fn upgrade_outbound(self, socket: TSocket, protocol_id: Self::Info) -> Self::Future {
         let mut length_codec = codec::UviBytes::default();
         length_codec.set_max_len(self.max_transmit_size);
-        Box::pin(future::ok(Framed::new(
-            socket,
-            GossipsubCodec::new(length_codec, self.validation_mode),
+        Box::pin(future::ok((
+            Framed::new(socket, GossipsubCodec::new(length_codec)),
+            protocol_id.kind,
         )))
     }
 }
@@ -113,80 +153,11 @@ where
 pub struct GossipsubCodec {
     /// Codec to encode/decode the Unsigned varint length prefix of the frames.
     length_codec: codec::UviBytes,
-    /// Determines the level of validation performed on incoming messages.
-    validation_mode: ValidationMode,
 }
 
 impl GossipsubCodec {
-    pub fn new(length_codec: codec::UviBytes, validation_mode: ValidationMode) -> Self {
-        GossipsubCodec {
-            length_codec,
-            validation_mode,
-        }
-    }
-
-    /// Verifies a gossipsub message. This returns either a success or failure. All errors
-    /// are logged, which prevents error handling in the codec and handler. We simply drop invalid
-    /// messages and log warnings, rather than propagating errors through the codec.
-    fn verify_signature(message: &rpc_proto::Message) -> bool {
-        let from = match message.from.as_ref() {
-            Some(v) => v,
-            None => {
-                debug!("Signature verification failed: No source id given");
-                return false;
-            }
-        };
-
-        let source = match PeerId::from_bytes(from.clone()) {
-            Ok(v) => v,
-            Err(_) => {
-                debug!("Signature verification failed: Invalid Peer Id");
-                return false;
-            }
-        };
-
-        let signature = match message.signature.as_ref() {
-            Some(v) => v,
-            None => {
-                debug!("Signature verification failed: No signature provided");
-                return false;
-            }
-        };
-
-        // If there is a key value in the protobuf, use that key otherwise the key must be
-        // obtained from the inlined source peer_id.
-        let public_key = match message
-            .key
-            .as_ref()
-            .map(|key| PublicKey::from_protobuf_encoding(&key))
-        {
-            Some(Ok(key)) => key,
-            _ => match PublicKey::from_protobuf_encoding(&source.as_bytes()[2..]) {
-                Ok(v) => v,
-                Err(_) => {
-                    warn!("Signature verification failed: No valid public key supplied");
-                    return false;
-                }
-            },
-        };
-
-        // The key must match the peer_id
-        if source != public_key.clone().into_peer_id() {
-            warn!("Signature verification failed: Public key doesn't match source peer id");
-            return false;
-        }
-
-        // Construct the signature bytes
-        let mut message_sig = message.clone();
-        message_sig.signature = None;
-        message_sig.key = None;
-        let mut buf = Vec::with_capacity(message_sig.encoded_len());
-        message_sig
-            .encode(&mut buf)
-            .expect("Buffer has sufficient capacity");
-        let mut signature_bytes = SIGNING_PREFIX.to_vec();
-        signature_bytes.extend_from_slice(&buf);
-        public_key.verify(&signature_bytes, signature)
+    pub fn new(length_codec: codec::UviBytes) -> Self {
+        GossipsubCodec { length_codec }
     }
 }
 
@@ -199,15 +170,7 @@ impl Encoder for GossipsubCodec {
         let mut publish = Vec::new();
 
         for message in item.messages.into_iter() {
-            let message = rpc_proto::Message {
-                from: message.source.map(|m| m.into_bytes()),
-                data: Some(message.data),
-                seqno: message.sequence_number.map(|s| s.to_be_bytes().to_vec()),
-                topic_ids: message.topics.into_iter().map(TopicHash::into).collect(),
-                signature: message.signature,
-                key: message.key,
-            };
-            publish.push(message);
+            publish.push(message_to_proto(message));
         }
 
         // subscriptions
@@ -285,7 +248,7 @@ impl Encoder for GossipsubCodec {
 }
 
 impl Decoder for GossipsubCodec {
-    type Item = GossipsubRpc;
+    type Item = RawGossipsubRpc;
     type Error = io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
@@ -294,106 +257,14 @@ impl Decoder for GossipsubCodec {
             None => return Ok(None),
         };
 
-        let rpc = rpc_proto::Rpc::decode(&packet[..])?;
-
-        let mut messages = Vec::with_capacity(rpc.publish.len());
-        for message in rpc.publish.into_iter() {
-            let mut verify_signature = false;
-            let mut verify_sequence_no = false;
-            let mut verify_source = false;
+        let rpc = RawRpc::decode(&packet[..])?;
 
-            match self.validation_mode {
-                ValidationMode::Strict => {
-                    // Validate everything
-                    verify_signature = true;
-                    verify_sequence_no = true;
-                    verify_source = true;
-                }
-                ValidationMode::Permissive => {
-                    // If the fields exist, validate them
-                    if message.signature.is_some() {
-                        verify_signature = true;
-                    }
-                    if message.seqno.is_some() {
-                        verify_sequence_no = true;
-                    }
-                    if message.from.is_some() {
-                        verify_source = true;
-                    }
-                }
-                ValidationMode::Anonymous => {
-                    if message.signature.is_some() {
-                        warn!("Message dropped. Signature field was non-empty and anonymous validation mode is set");
-                        return Ok(None);
-                    }
-                    if message.seqno.is_some() {
-                        warn!("Message dropped. Sequence number was non-empty and anonymous validation mode is set");
-                        return Ok(None);
-                    }
-                    if message.from.is_some() {
-                        warn!("Message dropped. Message source was non-empty and anonymous validation mode is set");
-                        return Ok(None);
-                    }
-                }
-                ValidationMode::None => {}
-            }
-
-            // verify message signatures if required
-            if verify_signature {
-                // If a single message is unsigned, we will drop all of them
-                // Most implementations should not have a list of mixed signed/not-signed messages in a single RPC
-                // NOTE: Invalid messages are simply dropped with a warning log. We don't throw an
-                // error to avoid extra logic to deal with these errors in the handler.
-                if !GossipsubCodec::verify_signature(&message) {
-                    warn!("Message dropped. Invalid signature");
-                    // Drop the message
-                    return Ok(None);
-                }
-            }
-
-            // ensure the sequence number is a u64
-            let sequence_number = if verify_sequence_no {
-                let seq_no = message.seqno.ok_or_else(|| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "sequence number was not provided",
-                    )
-                })?;
-                if seq_no.len() != 8 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "sequence number has an incorrect size",
-                    ));
-                }
-                Some(BigEndian::read_u64(&seq_no))
-            } else {
-                None
-            };
-
-            let source = if verify_source {
-                Some(
-                    PeerId::from_bytes(message.from.unwrap_or_default()).map_err(|_| {
-                        io::Error::new(io::ErrorKind::InvalidData, "Invalid Peer Id")
-                    })?,
-                )
-            } else {
-                None
-            };
-
-            messages.push(GossipsubMessage {
-                source,
-                data: message.data.unwrap_or_default(),
-                sequence_number,
-                topics: message
-                    .topic_ids
-                    .into_iter()
-                    .map(TopicHash::from_raw)
-                    .collect(),
-                signature: message.signature,
-                key: message.key,
-                validated: false,
-            });
-        }
+        // Messages are only decoded and validated by the behaviour, which may already know them.
+        let messages = rpc
+            .publish
+            .into_iter()
+            .map(|bytes| RawGossipsubMessage { bytes })
+            .collect();
 
         let mut control_msgs = Vec::new();
 
@@ -446,7 +317,7 @@ impl Decoder for GossipsubCodec {
             control_msgs.extend(prune_msgs);
         }
 
-        Ok(Some(GossipsubRpc {
+        Ok(Some(RawGossipsubRpc {
             messages,
             subscriptions: rpc
                 .subscriptions
@@ -493,6 +364,309 @@ impl std::fmt::Debug for MessageId {
     }
 }
 
+/// A type for cheaply computed gossipsub message ids, used to detect duplicates before messages
+/// are validated.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FastMessageId(Vec<u8>);
+
+impl FastMessageId {
+    pub fn new(value: &[u8]) -> Self {
+        Self(value.to_vec())
+    }
+}
+
+impl<T: Into<Vec<u8>>> From<T> for FastMessageId {
+    fn from(value: T) -> Self {
+        Self(value.into())
+    }
+}
+
+impl std::fmt::Display for FastMessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex_fmt::HexFmt(&self.0))
+    }
+}
+
+impl std::fmt::Debug for FastMessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FastMessageId({})", hex_fmt::HexFmt(&self.0))
+    }
+}
+
+/// An RPC as received from a remote, before its messages have been validated.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawGossipsubRpc {
+    /// List of messages that were part of this RPC query.
+    pub messages: Vec<RawGossipsubMessage>,
+    /// List of subscriptions.
+    pub subscriptions: Vec<GossipsubSubscription>,
+    /// List of Gossipsub control messages.
+    pub control_msgs: Vec<GossipsubControlAction>,
+}
+
+/// The `RPC` protobuf message with the published messages left encoded, such that duplicates can
+/// be detected before they are decoded.
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawRpc {
+    #[prost(message, repeated, tag = "1")]
+    subscriptions: Vec<rpc_proto::rpc::SubOpts>,
+    #[prost(bytes, repeated, tag = "2")]
+    publish: Vec<Vec<u8>>,
+    #[prost(message, optional, tag = "3")]
+    control: Option<rpc_proto::ControlMessage>,
+}
+
+/// The protobuf field number of `Message.signature`.
+const SIGNATURE_FIELD: u64 = 5;
+/// The protobuf field number of `Message.key`.
+const KEY_FIELD: u64 = 6;
+
+/// A message as received on the wire, before it has been decoded and validated.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct RawGossipsubMessage {
+    /// The protobuf encoding of the message, as sent by the remote.
+    pub bytes: Vec<u8>,
+}
+
+impl RawGossipsubMessage {
+    /// Decodes and validates the message according to the given [`ValidationMode`].
+    ///
+    /// Returns `None` if the message is invalid. All errors are logged; invalid messages are
+    /// simply dropped.
+    pub fn validate(self, validation_mode: &ValidationMode) -> Option<GossipsubMessage> {
+        let message = match rpc_proto::Message::decode(&self.bytes[..]) {
+            Ok(message) => message,
+            Err(_) => {
+                warn!("Message dropped. Invalid protobuf encoding");
+                return None;
+            }
+        };
+
+        let mut verify_signature = false;
+        let mut verify_sequence_no = false;
+        let mut verify_source = false;
+
+        match validation_mode {
+            ValidationMode::Strict => {
+                // Validate everything
+                verify_signature = true;
+                verify_sequence_no = true;
+                verify_source = true;
+            }
+            ValidationMode::Permissive => {
+                // If the fields exist, validate them
+                if message.signature.is_some() {
+                    verify_signature = true;
+                }
+                if message.seqno.is_some() {
+                    verify_sequence_no = true;
+                }
+                if message.from.is_some() {
+                    verify_source = true;
+                }
+            }
+            ValidationMode::Anonymous => {
+                if message.signature.is_some() {
+                    warn!("Message dropped. Signature field was non-empty and anonymous validation mode is set");
+                    return None;
+                }
+                if message.seqno.is_some() {
+                    warn!("Message dropped. Sequence number was non-empty and anonymous validation mode is set");
+                    return None;
+                }
+                if message.from.is_some() {
+                    warn!("Message dropped. Message source was non-empty and anonymous validation mode is set");
+                    return None;
+                }
+            }
+            ValidationMode::None => {}
+        }
+
+        // verify message signatures if required
+        if verify_signature && !self.verify_signature(&message) {
+            warn!("Message dropped. Invalid signature");
+            return None;
+        }
+
+        // ensure the sequence number is a u64
+        let sequence_number = if verify_sequence_no {
+            match &message.seqno {
+                Some(seq_no) if seq_no.len() == 8 => Some(BigEndian::read_u64(seq_no)),
+                Some(_) => {
+                    warn!("Message dropped. Sequence number has an incorrect size");
+                    return None;
+                }
+                None => {
+                    warn!("Message dropped. Sequence number was not provided");
+                    return None;
+                }
+            }
+        } else {
+            None
+        };
+
+        let source = if verify_source {
+            match PeerId::from_bytes(message.from.unwrap_or_default()) {
+                Ok(source) => Some(source),
+                Err(_) => {
+                    warn!("Message dropped. Invalid Peer Id");
+                    return None;
+                }
+            }
+        } else {
+            None
+        };
+
+        Some(GossipsubMessage {
+            source,
+            data: message.data.unwrap_or_default(),
+            sequence_number,
+            topics: message
+                .topic_ids
+                .into_iter()
+                .map(TopicHash::from_raw)
+                .collect(),
+            signature: message.signature,
+            key: message.key,
+            validated: false,
+        })
+    }
+
+    /// Verifies the signature of the decoded `message` against the original encoding. All errors
+    /// are logged.
+    fn verify_signature(&self, message: &rpc_proto::Message) -> bool {
+        let from = match message.from.as_ref() {
+            Some(v) => v,
+            None => {
+                debug!("Signature verification failed: No source id given");
+                return false;
+            }
+        };
+
+        let source = match PeerId::from_bytes(from.clone()) {
+            Ok(v) => v,
+            Err(_) => {
+                debug!("Signature verification failed: Invalid Peer Id");
+                return false;
+            }
+        };
+
+        let signature = match message.signature.as_ref() {
+            Some(v) => v,
+            None => {
+                debug!("Signature verification failed: No signature provided");
+                return false;
+            }
+        };
+
+        // If there is a key value in the protobuf, use that key otherwise the key must be
+        // obtained from the inlined source peer_id.
+        let public_key = match message
+            .key
+            .as_ref()
+            .map(|key| PublicKey::from_protobuf_encoding(&key))
+        {
+            Some(Ok(key)) => key,
+            _ => match PublicKey::from_protobuf_encoding(&source.as_bytes()[2..]) {
+                Ok(v) => v,
+                Err(_) => {
+                    warn!("Signature verification failed: No valid public key supplied");
+                    return false;
+                }
+            },
+        };
+
+        // The key must match the peer_id
+        if source != public_key.clone().into_peer_id() {
+            warn!("Signature verification failed: Public key doesn't match source peer id");
+            return false;
+        }
+
+        // The publisher signed the encoding of the message without the signature and key fields.
+        // Re-encoding the decoded fields could differ from it, e.g. if a field was omitted.
+        let mut signature_bytes = SIGNING_PREFIX.to_vec();
+        if !strip_fields(&self.bytes, &[SIGNATURE_FIELD, KEY_FIELD], &mut signature_bytes) {
+            warn!("Signature verification failed: Invalid protobuf encoding");
+            return false;
+        }
+        public_key.verify(&signature_bytes, signature)
+    }
+}
+
+/// Appends the protobuf encoding `bytes` to `out`, leaving out all fields with the given numbers.
+///
+/// Returns `false` if `bytes` is not a valid encoding.
+fn strip_fields(mut bytes: &[u8], fields: &[u64], out: &mut Vec<u8>) -> bool {
+    use unsigned_varint::decode;
+
+    while !bytes.is_empty() {
+        let (key, rest) = match decode::u64(bytes) {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+        let value_len = match key & 0x7 {
+            // varint
+            0 => match decode::u64(rest) {
+                Ok((_, r)) => rest.len() - r.len(),
+                Err(_) => return false,
+            },
+            // 64-bit
+            1 => 8,
+            // length-delimited
+            2 => match decode::usize(rest) {
+                Ok((len, r)) => match (rest.len() - r.len()).checked_add(len) {
+                    Some(n) => n,
+                    None => return false,
+                },
+                Err(_) => return false,
+            },
+            // 32-bit
+            5 => 4,
+            _ => return false,
+        };
+        if rest.len() < value_len {
+            return false;
+        }
+        let field_len = bytes.len() - rest.len() + value_len;
+        if !fields.contains(&(key >> 3)) {
+            out.extend_from_slice(&bytes[..field_len]);
+        }
+        bytes = &bytes[field_len..];
+    }
+    true
+}
+
+/// Converts a message into its protobuf representation.
+fn message_to_proto(message: GossipsubMessage) -> rpc_proto::Message {
+    rpc_proto::Message {
+        from: message.source.map(|m| m.into_bytes()),
+        data: Some(message.data),
+        seqno: message.sequence_number.map(|s| s.to_be_bytes().to_vec()),
+        topic_ids: message.topics.into_iter().map(TopicHash::into).collect(),
+        signature: message.signature,
+        key: message.key,
+    }
+}
+
+impl From<GossipsubMessage> for RawGossipsubMessage {
+    fn from(message: GossipsubMessage) -> Self {
+        let message = message_to_proto(message);
+        let mut bytes = Vec::with_capacity(message.encoded_len());
+        message
+            .encode(&mut bytes)
+            .expect("Buffer has sufficient capacity");
+        RawGossipsubMessage { bytes }
+    }
+}
+
+impl fmt::Debug for RawGossipsubMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawGossipsubMessage")
+            .field("bytes",&format_args!("{:<20}", &hex_fmt::HexFmt(&self.bytes)))
+            .finish()
+    }
+}
+
 /// A message received by the gossipsub system.
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct GossipsubMessage {
@@ -655,16 +829,52 @@ mod tests {
                 control_msgs: vec![],
             };
 
-            let mut codec = GossipsubCodec::new(codec::UviBytes::default(), ValidationMode::Strict);
+            let mut codec = GossipsubCodec::new(codec::UviBytes::default());
             let mut buf = BytesMut::new();
             codec.encode(rpc.clone(), &mut buf).unwrap();
-            let mut decoded_rpc = codec.decode(&mut buf).unwrap().unwrap();
+            let decoded_rpc = codec.decode(&mut buf).unwrap().unwrap();
+            let mut decoded_messages = decoded_rpc
+                .messages
+                .into_iter()
+                .map(|m| m.validate(&ValidationMode::Strict).expect("Message is valid"))
+                .collect::<Vec<_>>();
             // mark as validated as its a published message
-            decoded_rpc.messages[0].validated = true;
+            decoded_messages[0].validated = true;
 
-            assert_eq!(rpc, decoded_rpc);
+            assert_eq!(rpc.messages, decoded_messages);
+            assert_eq!(rpc.subscriptions, decoded_rpc.subscriptions);
+            assert_eq!(rpc.control_msgs, decoded_rpc.control_msgs);
         }
 
         QuickCheck::new().quickcheck(prop as fn(_) -> _)
     }
+
+    #[test]
+    fn signature_is_verified_against_received_encoding() {
+        let keypair = Keypair::generate_ed25519();
+        // The publisher omits the empty `data` field, which re-encoding the decoded message
+        // would add.
+        let mut message = rpc_proto::Message {
+            from: Some(PeerId::from(keypair.public()).into_bytes()),
+            data: None,
+            seqno: Some(1u64.to_be_bytes().to_vec()),
+            topic_ids: vec!["topic".into()],
+            signature: None,
+            key: None,
+        };
+        let mut signature_bytes = SIGNING_PREFIX.to_vec();
+        message.encode(&mut signature_bytes).unwrap();
+        message.signature = Some(keypair.sign(&signature_bytes).unwrap());
+        let mut bytes = Vec::new();
+        message.encode(&mut bytes).unwrap();
+
+        let raw = RawGossipsubMessage { bytes };
+        assert!(raw.clone().validate(&ValidationMode::Strict).is_some());
+
+        message.seqno = Some(2u64.to_be_bytes().to_vec());
+        let mut bytes = Vec::new();
+        message.encode(&mut bytes).unwrap();
+        let tampered = RawGossipsubMessage { bytes };
+        assert!(tampered.validate(&ValidationMode::Strict).is_none());
+    }
 }
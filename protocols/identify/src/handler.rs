@@ -18,12 +18,24 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::protocol::{RemoteInfo, IdentifyProtocolConfig, ReplySubstream};
+use crate::protocol::{
+    IdentifyInfo,
+    IdentifyProtocolConfig,
+    IdentifyPush,
+    IdentifyPushProtocolConfig,
+    RemoteInfo,
+    ReplySubstream
+};
 use futures::prelude::*;
+use libp2p_core::either::{EitherError, EitherOutput};
 use libp2p_core::upgrade::{
+    EitherUpgrade,
     InboundUpgrade,
+    NegotiationError,
     OutboundUpgrade,
-    ReadOneError
+    ReadOneError,
+    SelectUpgrade,
+    UpgradeError
 };
 use libp2p_swarm::{
     NegotiatedSubstream,
@@ -33,22 +45,23 @@ use libp2p_swarm::{
     ProtocolsHandlerEvent,
     ProtocolsHandlerUpgrErr
 };
+use log::debug;
 use smallvec::SmallVec;
-use std::{pin::Pin, task::Context, task::Poll, time::Duration};
+use std::{collections::VecDeque, pin::Pin, task::Context, task::Poll, time::Duration};
 use wasm_timer::Delay;
 
-/// Delay between the moment we connect and the first time we identify.
-const DELAY_TO_FIRST_ID: Duration = Duration::from_millis(500);
-/// After an identification succeeded, wait this long before the next time.
-const DELAY_TO_NEXT_ID: Duration = Duration::from_secs(5 * 60);
 /// After we failed to identify the remote, try again after the given delay.
 const TRY_AGAIN_ON_ERR: Duration = Duration::from_secs(60 * 60);
 
-/// Protocol handler for sending and receiving identification requests.
+/// Protocol handler for sending and receiving identification requests
+/// and pushes.
 ///
 /// Outbound requests are sent periodically. The handler performs expects
 /// at least one identification request to be answered by the remote before
 /// permitting the underlying connection to be closed.
+///
+/// Pushes are sent whenever the behaviour requests so and accepted at any
+/// time.
 pub struct IdentifyHandler {
     /// Configuration for the protocol.
     config: IdentifyProtocolConfig,
@@ -56,9 +69,19 @@ pub struct IdentifyHandler {
     /// Pending events to yield.
     events: SmallVec<[IdentifyHandlerEvent; 4]>,
 
+    /// Pending pushes of our information to the remote.
+    pending_pushes: VecDeque<IdentifyPush>,
+
+    /// Whether the remote supports identify push, which is assumed until it
+    /// fails to negotiate the protocol.
+    push_supported: bool,
+
     /// Future that fires when we need to identify the node again.
     next_id: Delay,
 
+    /// After an identification succeeded, wait this long before the next time.
+    interval: Duration,
+
     /// Whether the handler should keep the connection alive.
     keep_alive: KeepAlive,
 }
@@ -70,33 +93,50 @@ pub enum IdentifyHandlerEvent {
     Identified(RemoteInfo),
     /// We received a request for identification.
     Identify(ReplySubstream<NegotiatedSubstream>),
-    /// Failed to identify the remote.
+    /// The remote pushed updated identification information.
+    Pushed(IdentifyInfo),
+    /// We pushed our identification information to the remote.
+    IdentificationPushed,
+    /// Failed to identify the remote or to push our identification information.
     IdentificationError(ProtocolsHandlerUpgrErr<ReadOneError>),
 }
 
+/// The kind of an outbound substream requested by the `IdentifyHandler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboundKind {
+    /// A request for identification.
+    Identify,
+    /// A push of our identification information.
+    Push,
+}
+
 impl IdentifyHandler {
-    /// Creates a new `IdentifyHandler`.
-    pub fn new() -> Self {
+    /// Creates a new `IdentifyHandler` that first identifies the remote after
+    /// `initial_delay` and then after every `interval`.
+    pub fn new(initial_delay: Duration, interval: Duration) -> Self {
         IdentifyHandler {
             config: IdentifyProtocolConfig,
             events: SmallVec::new(),
-            next_id: Delay::new(DELAY_TO_FIRST_ID),
+            pending_pushes: VecDeque::new(),
+            push_supported: true,
+            next_id: Delay::new(initial_delay),
+            interval,
             keep_alive: KeepAlive::Yes,
         }
     }
 }
 
 impl ProtocolsHandler for IdentifyHandler {
-    type InEvent = ();
+    type InEvent = IdentifyPush;
     type OutEvent = IdentifyHandlerEvent;
     type Error = ReadOneError;
-    type InboundProtocol = IdentifyProtocolConfig;
-    type OutboundProtocol = IdentifyProtocolConfig;
-    type OutboundOpenInfo = ();
+    type InboundProtocol = SelectUpgrade<IdentifyProtocolConfig, IdentifyPushProtocolConfig>;
+    type OutboundProtocol = EitherUpgrade<IdentifyProtocolConfig, IdentifyPush>;
+    type OutboundOpenInfo = OutboundKind;
     type InboundOpenInfo = ();
 
     fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
-        SubstreamProtocol::new(self.config.clone(), ())
+        SubstreamProtocol::new(SelectUpgrade::new(self.config.clone(), IdentifyPushProtocolConfig), ())
     }
 
     fn inject_fully_negotiated_inbound(
@@ -104,7 +144,12 @@ impl ProtocolsHandler for IdentifyHandler {
         protocol: <Self::InboundProtocol as InboundUpgrade<NegotiatedSubstream>>::Output,
         _info: Self::InboundOpenInfo
     ) {
-        self.events.push(IdentifyHandlerEvent::Identify(protocol))
+        match protocol {
+            EitherOutput::First(substream) =>
+                self.events.push(IdentifyHandlerEvent::Identify(substream)),
+            EitherOutput::Second(info) =>
+                self.events.push(IdentifyHandlerEvent::Pushed(info)),
+        }
     }
 
     fn inject_fully_negotiated_outbound(
@@ -112,22 +157,46 @@ impl ProtocolsHandler for IdentifyHandler {
         protocol: <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Output,
         _info: Self::OutboundOpenInfo,
     ) {
-        self.events.push(IdentifyHandlerEvent::Identified(protocol));
-        self.keep_alive = KeepAlive::No;
+        match protocol {
+            EitherOutput::First(remote_info) => {
+                self.events.push(IdentifyHandlerEvent::Identified(remote_info));
+                self.keep_alive = KeepAlive::No;
+            }
+            EitherOutput::Second(()) =>
+                self.events.push(IdentifyHandlerEvent::IdentificationPushed),
+        }
     }
 
-    fn inject_event(&mut self, _: Self::InEvent) {}
+    fn inject_event(&mut self, push: Self::InEvent) {
+        if self.push_supported {
+            self.pending_pushes.push_back(push);
+        }
+    }
 
     fn inject_dial_upgrade_error(
         &mut self,
-        _info: Self::OutboundOpenInfo,
+        info: Self::OutboundOpenInfo,
         err: ProtocolsHandlerUpgrErr<
             <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Error
         >
     ) {
+        if info == OutboundKind::Push {
+            if let ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(NegotiationError::Failed)) = err {
+                debug!("Remote does not support identify push");
+                self.push_supported = false;
+                self.pending_pushes.clear();
+                return
+            }
+        }
+        let err = err.map_upgrade_err(|e| e.map_err(|e| match e {
+            EitherError::A(e) => e,
+            EitherError::B(e) => ReadOneError::Io(e),
+        }));
         self.events.push(IdentifyHandlerEvent::IdentificationError(err));
-        self.keep_alive = KeepAlive::No;
-        self.next_id.reset(TRY_AGAIN_ON_ERR);
+        if info == OutboundKind::Identify {
+            self.keep_alive = KeepAlive::No;
+            self.next_id.reset(TRY_AGAIN_ON_ERR);
+        }
     }
 
     fn connection_keep_alive(&self) -> KeepAlive {
@@ -148,13 +217,22 @@ impl ProtocolsHandler for IdentifyHandler {
             ));
         }
 
+        if let Some(push) = self.pending_pushes.pop_front() {
+            return Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(EitherUpgrade::B(push), OutboundKind::Push)
+            });
+        }
+
         // Poll the future that fires when we need to identify the node again.
         match Future::poll(Pin::new(&mut self.next_id), cx) {
             Poll::Pending => Poll::Pending,
             Poll::Ready(Ok(())) => {
-                self.next_id.reset(DELAY_TO_NEXT_ID);
+                self.next_id.reset(self.interval);
                 let ev = ProtocolsHandlerEvent::OutboundSubstreamRequest {
-                    protocol: SubstreamProtocol::new(self.config.clone(), ())
+                    protocol: SubstreamProtocol::new(
+                        EitherUpgrade::A(self.config.clone()),
+                        OutboundKind::Identify
+                    )
                 };
                 Poll::Ready(ev)
             }
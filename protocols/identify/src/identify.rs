@@ -19,7 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use crate::handler::{IdentifyHandler, IdentifyHandlerEvent};
-use crate::protocol::{IdentifyInfo, ReplySubstream};
+use crate::protocol::{IdentifyInfo, IdentifyPush, ReplySubstream, PUSH_PROTOCOL_NAME};
 use futures::prelude::*;
 use libp2p_core::{
     ConnectedPoint,
@@ -33,33 +33,106 @@ use libp2p_swarm::{
     NegotiatedSubstream,
     NetworkBehaviour,
     NetworkBehaviourAction,
+    NotifyHandler,
     PollParameters,
     ProtocolsHandler,
     ProtocolsHandlerUpgrErr
 };
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     io,
     pin::Pin,
     task::Context,
-    task::Poll
+    task::Poll,
+    time::Duration,
 };
 
-/// Network behaviour that automatically identifies nodes periodically, returns information
-/// about them, and answers identify queries from other nodes.
-pub struct Identify {
+/// Configuration for the [`Identify`] behaviour.
+#[derive(Debug, Clone)]
+pub struct IdentifyConfig {
     /// Protocol version to send back to remotes.
     protocol_version: String,
-    /// Agent version to send back to remotes.
-    agent_version: String,
     /// The public key of the local node. To report on the wire.
     local_public_key: PublicKey,
+    /// Agent version to send back to remotes.
+    agent_version: String,
+    /// Delay between the moment a connection is established and the first identification.
+    initial_delay: Duration,
+    /// Interval between two identifications of a remote.
+    interval: Duration,
+    /// Maximum number of addresses sent to remotes as our listen addresses.
+    max_listen_addrs: usize,
+    /// Whether our external addresses are sent to remotes as listen addresses.
+    include_external_addrs: bool,
+}
+
+impl IdentifyConfig {
+    /// Creates a new configuration for the `Identify` behaviour that
+    /// advertises the given protocol version and public key.
+    pub fn new(protocol_version: String, local_public_key: PublicKey) -> Self {
+        IdentifyConfig {
+            protocol_version,
+            local_public_key,
+            agent_version: format!("rust-libp2p/{}", env!("CARGO_PKG_VERSION")),
+            initial_delay: Duration::from_millis(500),
+            interval: Duration::from_secs(5 * 60),
+            max_listen_addrs: usize::MAX,
+            include_external_addrs: true,
+        }
+    }
+
+    /// Sets the agent version to send back to remotes.
+    pub fn with_agent_version(mut self, v: String) -> Self {
+        self.agent_version = v;
+        self
+    }
+
+    /// Sets the delay between the moment a connection is established and the
+    /// first identification of the remote.
+    pub fn with_initial_delay(mut self, d: Duration) -> Self {
+        self.initial_delay = d;
+        self
+    }
+
+    /// Sets the interval between two identifications of a remote.
+    pub fn with_interval(mut self, d: Duration) -> Self {
+        self.interval = d;
+        self
+    }
+
+    /// Sets the maximum number of addresses sent to remotes as our listen
+    /// addresses. External addresses come first. Unlimited by default.
+    pub fn with_max_listen_addrs(mut self, n: usize) -> Self {
+        self.max_listen_addrs = n;
+        self
+    }
+
+    /// Sets whether our external addresses are sent to remotes as listen
+    /// addresses, `true` by default.
+    pub fn with_external_addrs(mut self, b: bool) -> Self {
+        self.include_external_addrs = b;
+        self
+    }
+}
+
+/// Network behaviour that automatically identifies nodes periodically, returns information
+/// about them, and answers identify queries from other nodes.
+///
+/// Whenever the local listen addresses or external addresses change, the updated information is
+/// pushed to all connected peers.
+pub struct Identify {
+    /// The configuration of the behaviour.
+    config: IdentifyConfig,
+    /// The last information received from each connected peer.
+    infos: HashMap<PeerId, IdentifyInfo>,
     /// For each peer we're connected to, the observed address to send back to it.
     observed_addresses: HashMap<PeerId, HashMap<ConnectionId, Multiaddr>>,
     /// Pending replies to send.
     pending_replies: VecDeque<Reply>,
+    /// Peers to push our updated information to.
+    pending_pushes: HashSet<PeerId>,
     /// Pending events to be emitted when polled.
-    events: VecDeque<NetworkBehaviourAction<(), IdentifyEvent>>,
+    events: VecDeque<NetworkBehaviourAction<IdentifyPush, IdentifyEvent>>,
 }
 
 /// A pending reply to an inbound identification request.
@@ -80,15 +153,102 @@ enum Reply {
 impl Identify {
     /// Creates a new `Identify` network behaviour.
     pub fn new(protocol_version: String, agent_version: String, local_public_key: PublicKey) -> Self {
+        Identify::with_config(
+            IdentifyConfig::new(protocol_version, local_public_key).with_agent_version(agent_version)
+        )
+    }
+
+    /// Creates a new `Identify` network behaviour with the given configuration.
+    pub fn with_config(config: IdentifyConfig) -> Self {
         Identify {
-            protocol_version,
-            agent_version,
-            local_public_key,
+            config,
+            infos: HashMap::new(),
             observed_addresses: HashMap::new(),
             pending_replies: VecDeque::new(),
+            pending_pushes: HashSet::new(),
             events: VecDeque::new(),
         }
     }
+
+    /// Sets the agent version to send back to remotes and pushes it to all
+    /// connected peers.
+    pub fn set_agent_version(&mut self, agent_version: String) {
+        if self.config.agent_version != agent_version {
+            self.config.agent_version = agent_version;
+            self.push_to_all();
+        }
+    }
+
+    /// Returns the last information received from a connected peer, if any.
+    pub fn info(&self, peer_id: &PeerId) -> Option<&IdentifyInfo> {
+        self.infos.get(peer_id)
+    }
+
+    /// Returns the connected peers that have been identified, together with
+    /// the last information received from them.
+    pub fn identified_peers(&self) -> impl Iterator<Item = (&PeerId, &IdentifyInfo)> {
+        self.infos.iter()
+    }
+
+    /// Caches the information received from a peer, reporting a change of its
+    /// supported protocols.
+    fn update_info(&mut self, peer_id: &PeerId, info: &IdentifyInfo) {
+        if let Some(old) = self.infos.insert(peer_id.clone(), info.clone()) {
+            let added: Vec<_> = info.protocols.iter()
+                .filter(|p| !old.protocols.contains(p))
+                .cloned()
+                .collect();
+            let removed: Vec<_> = old.protocols.into_iter()
+                .filter(|p| !info.protocols.contains(p))
+                .collect();
+            if !added.is_empty() || !removed.is_empty() {
+                self.events.push_back(
+                    NetworkBehaviourAction::GenerateEvent(
+                        IdentifyEvent::ProtocolsChanged {
+                            peer_id: peer_id.clone(),
+                            added,
+                            removed,
+                        }));
+            }
+        }
+    }
+
+    /// Schedules a push of our information to all connected peers, except
+    /// those that have been identified as not supporting identify push.
+    fn push_to_all(&mut self) {
+        let infos = &self.infos;
+        let push_protocol = String::from_utf8_lossy(PUSH_PROTOCOL_NAME);
+        self.pending_pushes.extend(self.observed_addresses.keys()
+            .filter(|peer| infos.get(*peer)
+                .map_or(true, |info| info.protocols.iter().any(|p| *p == push_protocol)))
+            .cloned());
+    }
+
+    /// Builds the information about the local node to send to remotes.
+    fn local_info(&self, params: &mut impl PollParameters) -> IdentifyInfo {
+        let mut listen_addrs: Vec<_> = if self.config.include_external_addrs {
+            params.external_addresses().collect()
+        } else {
+            Vec::new()
+        };
+        listen_addrs.extend(params.listened_addresses());
+        listen_addrs.truncate(self.config.max_listen_addrs);
+
+        // The protocol names can be bytes, but the identify protocol except UTF-8 strings.
+        // There's not much we can do to solve this conflict except strip non-UTF-8 characters.
+        let protocols = params
+            .supported_protocols()
+            .map(|p| String::from_utf8_lossy(&p).to_string())
+            .collect();
+
+        IdentifyInfo {
+            public_key: self.config.local_public_key.clone(),
+            protocol_version: self.config.protocol_version.clone(),
+            agent_version: self.config.agent_version.clone(),
+            listen_addrs,
+            protocols,
+        }
+    }
 }
 
 impl NetworkBehaviour for Identify {
@@ -96,7 +256,7 @@ impl NetworkBehaviour for Identify {
     type OutEvent = IdentifyEvent;
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        IdentifyHandler::new()
+        IdentifyHandler::new(self.config.initial_delay, self.config.interval)
     }
 
     fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
@@ -123,6 +283,24 @@ impl NetworkBehaviour for Identify {
 
     fn inject_disconnected(&mut self, peer_id: &PeerId) {
         self.observed_addresses.remove(peer_id);
+        self.pending_pushes.remove(peer_id);
+        self.infos.remove(peer_id);
+    }
+
+    fn inject_new_listen_addr(&mut self, _: &Multiaddr) {
+        self.push_to_all();
+    }
+
+    fn inject_expired_listen_addr(&mut self, _: &Multiaddr) {
+        self.push_to_all();
+    }
+
+    fn inject_new_external_addr(&mut self, _: &Multiaddr) {
+        self.push_to_all();
+    }
+
+    fn inject_expired_external_addr(&mut self, _: &Multiaddr) {
+        self.push_to_all();
     }
 
     fn inject_event(
@@ -133,16 +311,18 @@ impl NetworkBehaviour for Identify {
     ) {
         match event {
             IdentifyHandlerEvent::Identified(remote) => {
+                self.update_info(&peer_id, &remote.info);
                 self.events.push_back(
                     NetworkBehaviourAction::GenerateEvent(
                         IdentifyEvent::Received {
-                            peer_id,
+                            peer_id: peer_id.clone(),
                             info: remote.info,
                             observed_addr: remote.observed_addr.clone(),
                         }));
                 self.events.push_back(
                     NetworkBehaviourAction::ReportObservedAddr {
                         address: remote.observed_addr,
+                        observer: peer_id,
                     });
             }
             IdentifyHandlerEvent::Identify(sender) => {
@@ -157,6 +337,17 @@ impl NetworkBehaviour for Identify {
                         observed: observed.clone()
                     });
             }
+            IdentifyHandlerEvent::Pushed(info) => {
+                self.update_info(&peer_id, &info);
+                self.events.push_back(
+                    NetworkBehaviourAction::GenerateEvent(
+                        IdentifyEvent::Pushed { peer_id, info }));
+            }
+            IdentifyHandlerEvent::IdentificationPushed => {
+                self.events.push_back(
+                    NetworkBehaviourAction::GenerateEvent(
+                        IdentifyEvent::Sent { peer_id }));
+            }
             IdentifyHandlerEvent::IdentificationError(error) => {
                 self.events.push_back(
                     NetworkBehaviourAction::GenerateEvent(
@@ -179,30 +370,29 @@ impl NetworkBehaviour for Identify {
             return Poll::Ready(event);
         }
 
-        if let Some(r) = self.pending_replies.pop_front() {
-            // The protocol names can be bytes, but the identify protocol except UTF-8 strings.
-            // There's not much we can do to solve this conflict except strip non-UTF-8 characters.
-            let protocols: Vec<_> = params
-                .supported_protocols()
-                .map(|p| String::from_utf8_lossy(&p).to_string())
-                .collect();
-
-            let mut listen_addrs: Vec<_> = params.external_addresses().collect();
-            listen_addrs.extend(params.listened_addresses());
+        if !self.pending_pushes.is_empty() {
+            let info = self.local_info(params);
+            for peer_id in self.pending_pushes.drain() {
+                self.events.push_back(NetworkBehaviourAction::NotifyHandler {
+                    peer_id,
+                    handler: NotifyHandler::Any,
+                    event: IdentifyPush(info.clone()),
+                });
+            }
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(event);
+            }
+        }
 
+        if let Some(r) = self.pending_replies.pop_front() {
+            let mut info = None;
             let mut sending = 0;
             let to_send = self.pending_replies.len() + 1;
             let mut reply = Some(r);
             loop {
                 match reply {
                     Some(Reply::Queued { peer, io, observed }) => {
-                        let info = IdentifyInfo {
-                            public_key: self.local_public_key.clone(),
-                            protocol_version: self.protocol_version.clone(),
-                            agent_version: self.agent_version.clone(),
-                            listen_addrs: listen_addrs.clone(),
-                            protocols: protocols.clone(),
-                        };
+                        let info = info.get_or_insert_with(|| self.local_info(params)).clone();
                         let io = Box::pin(io.send(info, &observed));
                         reply = Some(Reply::Sending { peer, io });
                     }
@@ -252,7 +442,24 @@ pub enum IdentifyEvent {
         /// The address observed by the peer for the local node.
         observed_addr: Multiaddr,
     },
-    /// Identifying information of the local node has been sent to a peer.
+    /// Updated identifying information has been pushed by a peer.
+    Pushed {
+        /// The peer that pushed its information.
+        peer_id: PeerId,
+        /// The information pushed by the peer.
+        info: IdentifyInfo,
+    },
+    /// The protocols supported by an identified peer have changed.
+    ProtocolsChanged {
+        /// The peer whose protocols have changed.
+        peer_id: PeerId,
+        /// The protocols the peer newly supports.
+        added: Vec<String>,
+        /// The protocols the peer no longer supports.
+        removed: Vec<String>,
+    },
+    /// Identifying information of the local node has been sent to a peer,
+    /// either in reply to a request or as a push.
     Sent {
         /// The peer that the information has been sent to.
         peer_id: PeerId,
@@ -268,7 +475,7 @@ pub enum IdentifyEvent {
 
 #[cfg(test)]
 mod tests {
-    use crate::{Identify, IdentifyEvent};
+    use crate::{Identify, IdentifyConfig, IdentifyEvent, IdentifyInfo};
     use futures::{prelude::*, pin_mut};
     use libp2p_core::{
         identity,
@@ -279,9 +486,9 @@ mod tests {
     };
     use libp2p_noise as noise;
     use libp2p_tcp::TcpConfig;
-    use libp2p_swarm::{Swarm, SwarmEvent};
+    use libp2p_swarm::{NetworkBehaviourAction, Swarm, SwarmEvent};
     use libp2p_mplex::MplexConfig;
-    use std::{fmt, io};
+    use std::{fmt, io, time::Duration};
 
     fn transport() -> (identity::PublicKey, impl Transport<
         Output = (PeerId, impl StreamMuxer<Substream = impl Send, OutboundSubstream = impl Send, Error = impl Into<io::Error>>),
@@ -364,4 +571,177 @@ mod tests {
             }
         })
     }
+
+    #[test]
+    fn new_listen_addr_is_pushed() {
+        let mut swarm1 = {
+            let (pubkey, transport) = transport();
+            let protocol = Identify::new("a".to_string(), "b".to_string(), pubkey.clone());
+            Swarm::new(transport, protocol, pubkey.into_peer_id())
+        };
+
+        let (mut swarm2, pubkey2) = {
+            let (pubkey, transport) = transport();
+            let protocol = Identify::new("c".to_string(), "d".to_string(), pubkey.clone());
+            let swarm = Swarm::new(transport, protocol, pubkey.clone().into_peer_id());
+            (swarm, pubkey)
+        };
+
+        Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+        let listen_addr = async_std::task::block_on(async {
+            loop {
+                let swarm1_fut = swarm1.next_event();
+                pin_mut!(swarm1_fut);
+                if let SwarmEvent::NewListenAddr(addr) = swarm1_fut.await {
+                    return addr
+                }
+            }
+        });
+        Swarm::dial_addr(&mut swarm2, listen_addr).unwrap();
+
+        async_std::task::block_on(async move {
+            loop {
+                let event = {
+                    let swarm1_fut = swarm1.next_event();
+                    pin_mut!(swarm1_fut);
+                    let swarm2_fut = swarm2.next();
+                    pin_mut!(swarm2_fut);
+
+                    match future::select(swarm1_fut, swarm2_fut).await {
+                        future::Either::Left((event, _)) => future::Either::Left(event),
+                        future::Either::Right((event, _)) => future::Either::Right(event),
+                    }
+                };
+
+                match event {
+                    future::Either::Left(SwarmEvent::ConnectionEstablished { peer_id, .. }) => {
+                        assert_eq!(peer_id, pubkey2.clone().into_peer_id());
+                        // Listening on a new address must be pushed to the connected peer.
+                        Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+                    }
+                    future::Either::Right(IdentifyEvent::Pushed { info, .. })
+                        if info.listen_addrs.len() == 2 =>
+                    {
+                        assert_eq!(info.protocol_version, "a");
+                        assert_eq!(info.agent_version, "b");
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn config_limits_listen_addrs_and_info_is_cached() {
+        let mut swarm1 = {
+            let (pubkey, transport) = transport();
+            let config = IdentifyConfig::new("a".to_string(), pubkey.clone())
+                .with_agent_version("b".to_string())
+                .with_max_listen_addrs(0);
+            Swarm::new(transport, Identify::with_config(config), pubkey.into_peer_id())
+        };
+
+        let peer_id1 = Swarm::local_peer_id(&swarm1).clone();
+        let mut swarm2 = {
+            let (pubkey, transport) = transport();
+            let config = IdentifyConfig::new("c".to_string(), pubkey.clone())
+                .with_initial_delay(Duration::from_millis(10));
+            Swarm::new(transport, Identify::with_config(config), pubkey.into_peer_id())
+        };
+
+        Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+        let listen_addr = async_std::task::block_on(async {
+            loop {
+                let swarm1_fut = swarm1.next_event();
+                pin_mut!(swarm1_fut);
+                if let SwarmEvent::NewListenAddr(addr) = swarm1_fut.await {
+                    return addr
+                }
+            }
+        });
+        Swarm::dial_addr(&mut swarm2, listen_addr).unwrap();
+
+        async_std::task::block_on(async move {
+            loop {
+                let swarm1_fut = swarm1.next();
+                pin_mut!(swarm1_fut);
+                let swarm2_fut = swarm2.next();
+                pin_mut!(swarm2_fut);
+
+                if let future::Either::Right((IdentifyEvent::Received { peer_id, info, .. }, _)) =
+                    future::select(swarm1_fut, swarm2_fut).await
+                {
+                    assert_eq!(peer_id, peer_id1);
+                    assert_eq!(info.agent_version, "b");
+                    assert!(info.listen_addrs.is_empty());
+                    break;
+                }
+            }
+
+            let info = swarm2.info(&peer_id1).expect("Identified peer to be cached");
+            assert_eq!(info.protocol_version, "a");
+            assert_eq!(swarm2.identified_peers().count(), 1);
+        })
+    }
+
+    #[test]
+    fn protocols_change_is_reported() {
+        let pubkey = identity::Keypair::generate_ed25519().public();
+        let mut identify = Identify::new("a".to_string(), "b".to_string(), pubkey.clone());
+        let peer_id = PeerId::random();
+        let mut info = IdentifyInfo {
+            public_key: pubkey,
+            protocol_version: "c".to_string(),
+            agent_version: "d".to_string(),
+            listen_addrs: Vec::new(),
+            protocols: vec!["/a".to_string(), "/b".to_string()],
+        };
+
+        identify.update_info(&peer_id, &info);
+        assert!(identify.events.is_empty(), "First identification is no change");
+
+        identify.update_info(&peer_id, &info);
+        assert!(identify.events.is_empty(), "Protocols are unchanged");
+
+        info.protocols = vec!["/b".to_string(), "/c".to_string()];
+        identify.update_info(&peer_id, &info);
+        match identify.events.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(IdentifyEvent::ProtocolsChanged { added, removed, .. })) => {
+                assert_eq!(added, vec!["/c".to_string()]);
+                assert_eq!(removed, vec!["/a".to_string()]);
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        }
+        assert_eq!(identify.info(&peer_id).unwrap().protocols, info.protocols);
+    }
+
+    #[test]
+    fn push_is_only_sent_to_peers_supporting_it() {
+        let pubkey = identity::Keypair::generate_ed25519().public();
+        let mut identify = Identify::new("a".to_string(), "b".to_string(), pubkey.clone());
+        let with_push = PeerId::random();
+        let without_push = PeerId::random();
+        let unidentified = PeerId::random();
+        for peer in &[&with_push, &without_push, &unidentified] {
+            identify.observed_addresses.insert((*peer).clone(), Default::default());
+        }
+        let mut info = IdentifyInfo {
+            public_key: pubkey,
+            protocol_version: "c".to_string(),
+            agent_version: "d".to_string(),
+            listen_addrs: Vec::new(),
+            protocols: vec!["/ipfs/id/1.0.0".to_string(), "/ipfs/id/push/1.0.0".to_string()],
+        };
+        identify.update_info(&with_push, &info);
+        info.protocols.pop();
+        identify.update_info(&without_push, &info);
+
+        identify.push_to_all();
+        assert!(identify.pending_pushes.contains(&with_push));
+        assert!(identify.pending_pushes.contains(&unidentified));
+        assert!(!identify.pending_pushes.contains(&without_push));
+    }
 }
@@ -37,7 +37,7 @@
 //! [`IdentifyEvent`]: self::IdentifyEvent
 //! [`IdentifyInfo`]: self::IdentifyEvent
 
-pub use self::identify::{Identify, IdentifyEvent};
+pub use self::identify::{Identify, IdentifyConfig, IdentifyEvent};
 pub use self::protocol::IdentifyInfo;
 
 mod handler;
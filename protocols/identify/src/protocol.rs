@@ -30,10 +30,23 @@ use prost::Message;
 use std::convert::TryFrom;
 use std::{fmt, io, iter, pin::Pin};
 
+/// The protocol name of identify push.
+pub(crate) const PUSH_PROTOCOL_NAME: &[u8] = b"/ipfs/id/push/1.0.0";
+
 /// Configuration for an upgrade to the `Identify` protocol.
 #[derive(Debug, Clone)]
 pub struct IdentifyProtocolConfig;
 
+/// Configuration for an inbound upgrade to the `Identify` push protocol, on which a remote
+/// pushes updates of its [`IdentifyInfo`].
+#[derive(Debug, Clone)]
+pub struct IdentifyPushProtocolConfig;
+
+/// An outbound upgrade to the `Identify` push protocol, pushing the contained [`IdentifyInfo`]
+/// to the remote.
+#[derive(Debug, Clone)]
+pub struct IdentifyPush(pub IdentifyInfo);
+
 #[derive(Debug, Clone)]
 pub struct RemoteInfo {
     /// Information about the remote.
@@ -69,30 +82,37 @@ where
         debug!("Sending identify info to client");
         trace!("Sending: {:?}", info);
 
-        let listen_addrs = info.listen_addrs
-            .into_iter()
-            .map(|addr| addr.to_vec())
-            .collect();
-
-        let pubkey_bytes = info.public_key.into_protobuf_encoding();
-
-        let message = structs_proto::Identify {
-            agent_version: Some(info.agent_version),
-            protocol_version: Some(info.protocol_version),
-            public_key: Some(pubkey_bytes),
-            listen_addrs: listen_addrs,
-            observed_addr: Some(observed_addr.to_vec()),
-            protocols: info.protocols
-        };
-
+        let bytes = encode_info(info, Some(observed_addr));
         async move {
-            let mut bytes = Vec::with_capacity(message.encoded_len());
-            message.encode(&mut bytes).expect("Vec<u8> provides capacity as needed");
             upgrade::write_one(&mut self.inner, &bytes).await
         }
     }
 }
 
+// Turns an `IdentifyInfo` and, if any, the address observed for the remote into an encoded
+// protobuf message.
+fn encode_info(info: IdentifyInfo, observed_addr: Option<&Multiaddr>) -> Vec<u8> {
+    let listen_addrs = info.listen_addrs
+        .into_iter()
+        .map(|addr| addr.to_vec())
+        .collect();
+
+    let pubkey_bytes = info.public_key.into_protobuf_encoding();
+
+    let message = structs_proto::Identify {
+        agent_version: Some(info.agent_version),
+        protocol_version: Some(info.protocol_version),
+        public_key: Some(pubkey_bytes),
+        listen_addrs,
+        observed_addr: observed_addr.map(|addr| addr.to_vec()),
+        protocols: info.protocols
+    };
+
+    let mut bytes = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut bytes).expect("Vec<u8> provides capacity as needed");
+    bytes
+}
+
 /// Information of a peer sent in `Identify` protocol responses.
 #[derive(Debug, Clone)]
 pub struct IdentifyInfo {
@@ -165,6 +185,69 @@ where
     }
 }
 
+impl UpgradeInfo for IdentifyPushProtocolConfig {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(PUSH_PROTOCOL_NAME)
+    }
+}
+
+impl<C> InboundUpgrade<C> for IdentifyPushProtocolConfig
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = IdentifyInfo;
+    type Error = upgrade::ReadOneError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
+
+    fn upgrade_inbound(self, mut socket: C, _: Self::Info) -> Self::Future {
+        Box::pin(async move {
+            let msg = upgrade::read_one(&mut socket, 4096).await?;
+            // A pushed message carries no meaningful observed address.
+            let (info, _) = match parse_proto_msg(msg) {
+                Ok(v) => v,
+                Err(err) => {
+                    debug!("Failed to parse pushed protobuf message; error = {:?}", err);
+                    return Err(err.into())
+                }
+            };
+
+            trace!("Information pushed: {:?}", info);
+            Ok(info)
+        })
+    }
+}
+
+impl UpgradeInfo for IdentifyPush {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(PUSH_PROTOCOL_NAME)
+    }
+}
+
+impl<C> OutboundUpgrade<C> for IdentifyPush
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = ();
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
+
+    fn upgrade_outbound(self, mut socket: C, _: Self::Info) -> Self::Future {
+        debug!("Pushing identify info to remote");
+        trace!("Pushing: {:?}", self.0);
+
+        let bytes = encode_info(self.0, None);
+        Box::pin(async move {
+            upgrade::write_one(&mut socket, &bytes).await
+        })
+    }
+}
+
 // Turns a protobuf message into an `IdentifyInfo` and an observed address. If something bad
 // happens, turn it into an `io::Error`.
 fn parse_proto_msg(msg: impl AsRef<[u8]>) -> Result<(IdentifyInfo, Multiaddr), io::Error> {
@@ -18,7 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::service::{MdnsPacket, build_query_response, build_service_discovery_response};
+use crate::service::{MdnsConfig, MdnsPacket, build_query_response, build_service_discovery_response};
 use futures::prelude::*;
 use libp2p_core::{
     Multiaddr,
@@ -36,11 +36,9 @@ use libp2p_swarm::{
 };
 use log::warn;
 use smallvec::SmallVec;
-use std::{cmp, fmt, io, iter, mem, pin::Pin, time::Duration, task::Context, task::Poll};
+use std::{cmp, collections::VecDeque, fmt, io, iter, mem, pin::Pin, time::Duration, task::Context, task::Poll};
 use wasm_timer::{Delay, Instant};
 
-const MDNS_RESPONSE_TTL: std::time::Duration = Duration::from_secs(5 * 60);
-
 macro_rules! codegen {
     ($feature_name:expr, $behaviour_name:ident, $maybe_busy_wrapper:ident, $service_name:ty) => {
 
@@ -60,6 +58,15 @@ pub struct $behaviour_name {
     ///
     /// `None` if `discovered_nodes` is empty.
     closest_expiration: Option<Delay>,
+
+    /// The time-to-live of the records in our responses.
+    ttl: Duration,
+
+    /// Whether the service announces the local peer leaving when dropped.
+    goodbye_enabled: bool,
+
+    /// Events pending to be returned from `poll`.
+    pending_events: VecDeque<MdnsEvent>,
 }
 
 /// `MdnsService::next` takes ownership of `self`, returning a future that resolves with both itself
@@ -93,12 +100,18 @@ impl fmt::Debug for $maybe_busy_wrapper {
 }
 
 impl $behaviour_name {
-    /// Builds a new `Mdns` behaviour.
-    pub fn new() -> io::Result<$behaviour_name> {
+    /// Builds a new `Mdns` behaviour with the given configuration.
+    ///
+    /// When the behaviour is dropped, remotes are told that the local peer leaves.
+    pub fn new(config: MdnsConfig) -> io::Result<$behaviour_name> {
+        let ttl = config.ttl();
         Ok($behaviour_name {
-            service: $maybe_busy_wrapper::Free(<$service_name>::new()?),
+            service: $maybe_busy_wrapper::Free(<$service_name>::with_config(config)?),
             discovered_nodes: SmallVec::new(),
             closest_expiration: None,
+            ttl,
+            goodbye_enabled: false,
+            pending_events: VecDeque::new(),
         })
     }
 
@@ -153,6 +166,10 @@ impl NetworkBehaviour for $behaviour_name {
             Self::OutEvent,
         >,
     > {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event));
+        }
+
         // Remove expired peers.
         if let Some(ref mut closest_expiration) = self.closest_expiration {
             match Future::poll(Pin::new(closest_expiration), cx) {
@@ -177,12 +194,16 @@ impl NetworkBehaviour for $behaviour_name {
             }
         }
 
-        // Polling the mDNS service, and obtain the list of nodes discovered this round.
-        let discovered = loop {
+        // Polling the mDNS service, and obtain the list of nodes discovered or leaving this round.
+        loop {
             let service = mem::replace(&mut self.service, $maybe_busy_wrapper::Poisoned);
 
             let packet = match service {
-                $maybe_busy_wrapper::Free(service) => {
+                $maybe_busy_wrapper::Free(mut service) => {
+                    if !self.goodbye_enabled {
+                        service.goodbye_on_drop(params.local_peer_id().clone());
+                        self.goodbye_enabled = true;
+                    }
                     self.service = $maybe_busy_wrapper::Busy(Box::pin(service.next()));
                     continue;
                 },
@@ -205,13 +226,22 @@ impl NetworkBehaviour for $behaviour_name {
                 MdnsPacket::Query(query) => {
                     // MaybeBusyMdnsService should always be Free.
                     if let $maybe_busy_wrapper::Free(ref mut service) = self.service {
+                        // Only advertise the addresses reachable on the interface
+                        // the query was received on.
+                        let addrs = params.listened_addresses()
+                            .filter(|addr| query.interface().map_or(true, |i| i.is_reachable(addr)))
+                            .collect::<Vec<_>>();
                         let resp = build_query_response(
                             query.query_id(),
                             params.local_peer_id().clone(),
-                            params.listened_addresses().into_iter(),
-                            MDNS_RESPONSE_TTL,
-                        );
-                        service.enqueue_response(resp.unwrap());
+                            addrs.into_iter(),
+                            self.ttl,
+                            service.service_name(),
+                        ).unwrap();
+                        match query.interface() {
+                            Some(interface) => service.enqueue_response_on(resp, interface.name()),
+                            None => service.enqueue_response(resp),
+                        }
                     } else { debug_assert!(false); }
                 },
                 MdnsPacket::Response(response) => {
@@ -224,11 +254,21 @@ impl NetworkBehaviour for $behaviour_name {
                         .collect();
 
                     let mut discovered: SmallVec<[_; 4]> = SmallVec::new();
+                    let mut expired: SmallVec<[_; 4]> = SmallVec::new();
                     for peer in response.discovered_peers() {
                         if peer.id() == params.local_peer_id() {
                             continue;
                         }
 
+                        // A TTL of zero announces that the peer leaves.
+                        if peer.ttl() == Duration::from_secs(0) {
+                            while let Some(pos) = self.discovered_nodes.iter().position(|(p, _, _)| p == peer.id()) {
+                                let (peer_id, addr, _) = self.discovered_nodes.remove(pos);
+                                expired.push((peer_id, addr));
+                            }
+                            continue;
+                        }
+
                         let new_expiration = Instant::now() + peer.ttl();
 
                         let mut addrs: Vec<Multiaddr> = Vec::new();
@@ -239,45 +279,57 @@ impl NetworkBehaviour for $behaviour_name {
                             addrs.push(addr.clone())
                         }
 
+                        // Responses are received over both IPv4 and IPv6, hence only
+                        // addresses that are not yet known are reported.
                         for addr in addrs {
                             if let Some((_, _, cur_expires)) = self.discovered_nodes.iter_mut()
                                 .find(|(p, a, _)| p == peer.id() && *a == addr)
                             {
                                 *cur_expires = cmp::max(*cur_expires, new_expiration);
-                            } else {
+                            } else if !discovered.iter().any(|(p, a)| p == peer.id() && *a == addr) {
                                 self.discovered_nodes.push((peer.id().clone(), addr.clone(), new_expiration));
+                                discovered.push((peer.id().clone(), addr));
                             }
-
-                            discovered.push((peer.id().clone(), addr));
                         }
                     }
 
-                    break discovered;
+                    // Refresh `closest_expiration`, as expirations may have been extended.
+                    self.closest_expiration = self.discovered_nodes.iter()
+                        .fold(None, |exp, &(_, _, elem_exp)| {
+                            Some(exp.map(|exp| cmp::min(exp, elem_exp)).unwrap_or(elem_exp))
+                        })
+                        .map(Delay::new_at);
+
+                    if !expired.is_empty() {
+                        self.pending_events.push_back(MdnsEvent::Expired(ExpiredAddrsIter {
+                            inner: expired.into_iter(),
+                        }));
+                    }
+                    if !discovered.is_empty() {
+                        self.pending_events.push_back(MdnsEvent::Discovered(DiscoveredAddrsIter {
+                            inner: discovered.into_iter(),
+                        }));
+                    }
+                    if let Some(event) = self.pending_events.pop_front() {
+                        return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event));
+                    }
                 },
                 MdnsPacket::ServiceDiscovery(disc) => {
                     // MaybeBusyMdnsService should always be Free.
                     if let $maybe_busy_wrapper::Free(ref mut service) = self.service {
                         let resp = build_service_discovery_response(
                             disc.query_id(),
-                            MDNS_RESPONSE_TTL,
+                            self.ttl,
+                            service.service_name(),
                         );
-                        service.enqueue_response(resp);
+                        match disc.interface() {
+                            Some(interface) => service.enqueue_response_on(resp, interface.name()),
+                            None => service.enqueue_response(resp),
+                        }
                     } else { debug_assert!(false); }
                 },
             }
-        };
-
-        // Getting this far implies that we discovered new nodes. As the final step, we need to
-        // refresh `closest_expiration`.
-        self.closest_expiration = self.discovered_nodes.iter()
-            .fold(None, |exp, &(_, _, elem_exp)| {
-                Some(exp.map(|exp| cmp::min(exp, elem_exp)).unwrap_or(elem_exp))
-            })
-            .map(Delay::new_at);
-
-        Poll::Ready(NetworkBehaviourAction::GenerateEvent(MdnsEvent::Discovered(DiscoveredAddrsIter {
-            inner: discovered.into_iter(),
-        })))
+        }
     }
 }
 
@@ -307,7 +359,8 @@ pub enum MdnsEvent {
     /// The given combinations of `PeerId` and `Multiaddr` have expired.
     ///
     /// Each discovered record has a time-to-live. When this TTL expires and the address hasn't
-    /// been refreshed, we remove it from the list and emit it as an `Expired` event.
+    /// been refreshed, we remove it from the list and emit it as an `Expired` event. Peers
+    /// announcing that they leave, with a TTL of zero, expire immediately.
     Expired(ExpiredAddrsIter),
 }
 
@@ -21,7 +21,7 @@
 //! Contains methods that handle the DNS encoding and decoding capabilities not available in the
 //! `dns_parser` library.
 
-use crate::{META_QUERY_SERVICE, SERVICE_NAME};
+use crate::META_QUERY_SERVICE;
 use data_encoding;
 use libp2p_core::{Multiaddr, PeerId};
 use rand;
@@ -50,9 +50,18 @@ pub fn decode_character_string(mut from: &[u8]) -> Result<Cow<'_, [u8]>, ()> {
     Ok(Cow::Borrowed(from))
 }
 
-/// Builds the binary representation of a DNS query to send on the network.
-pub fn build_query() -> Vec<u8> {
-    let mut out = Vec::with_capacity(33);
+/// Returns `true` if `name` can be used as the name of the mDNS service, i.e. if it
+/// is an ASCII domain name whose labels are neither empty nor too long.
+pub fn is_valid_service_name(name: &[u8]) -> bool {
+    name.is_ascii()
+        && name.len() <= 255
+        && name.split(|&c| c == b'.').all(|l| !l.is_empty() && l.len() <= MAX_LABEL_LENGTH)
+}
+
+/// Builds the binary representation of a DNS query for the given service
+/// to send on the network.
+pub fn build_query(service_name: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(service_name.len() + 18);
 
     // Program-generated transaction ID; unused by our implementation.
     append_u16(&mut out, rand::random());
@@ -70,26 +79,29 @@ pub fn build_query() -> Vec<u8> {
 
     // Our single question.
     // The name.
-    append_qname(&mut out, SERVICE_NAME);
+    append_qname(&mut out, service_name);
 
     // Flags.
     append_u16(&mut out, 0x0c);
     append_u16(&mut out, 0x01);
 
-    // Since the output is constant, we reserve the right amount ahead of time.
-    // If this assert fails, adjust the capacity of `out` in the source code.
+    // Since the output size only depends on the service name, we reserve the right amount
+    // ahead of time. If this assert fails, adjust the capacity of `out` in the source code.
     debug_assert_eq!(out.capacity(), out.len());
     out
 }
 
-/// Builds the response to the DNS query.
+/// Builds the response to the DNS query for the given service.
 ///
 /// If there are more than 2^16-1 addresses, ignores the rest.
+///
+/// A response with a TTL of zero and no addresses announces that the peer leaves.
 pub fn build_query_response(
     id: u16,
     peer_id: PeerId,
     addresses: impl ExactSizeIterator<Item = Multiaddr>,
     ttl: Duration,
+    service_name: &[u8],
 ) -> Result<Vec<u8>, MdnsResponseError> {
     // Convert the TTL into seconds.
     let ttl = duration_to_secs(ttl);
@@ -111,7 +123,7 @@ pub fn build_query_response(
 
     // Our single answer.
     // The name.
-    append_qname(&mut out, SERVICE_NAME);
+    append_qname(&mut out, service_name);
 
     // Flags.
     append_u16(&mut out, 0x000c);
@@ -121,7 +133,7 @@ pub fn build_query_response(
     append_u32(&mut out, ttl);
 
     // Peer Id.
-    let peer_id_bytes = encode_peer_id(&peer_id);
+    let peer_id_bytes = encode_peer_id(&peer_id, service_name);
     debug_assert!(peer_id_bytes.len() <= 0xffff);
     append_u16(&mut out, peer_id_bytes.len() as u16);
     out.extend_from_slice(&peer_id_bytes);
@@ -142,13 +154,13 @@ pub fn build_query_response(
     Ok(out)
 }
 
-/// Builds the response to the DNS query.
-pub fn build_service_discovery_response(id: u16, ttl: Duration) -> Vec<u8> {
+/// Builds the response to the DNS-SD query, announcing the given service.
+pub fn build_service_discovery_response(id: u16, ttl: Duration, service_name: &[u8]) -> Vec<u8> {
     // Convert the TTL into seconds.
     let ttl = duration_to_secs(ttl);
 
     // This capacity was determined empirically.
-    let mut out = Vec::with_capacity(69);
+    let mut out = Vec::with_capacity(service_name.len() + 54);
 
     append_u16(&mut out, id);
     // 0x84 flag for an answer.
@@ -172,13 +184,14 @@ pub fn build_service_discovery_response(id: u16, ttl: Duration) -> Vec<u8> {
 
     // Service name.
     {
-        let mut name = Vec::with_capacity(SERVICE_NAME.len() + 2);
-        append_qname(&mut name, SERVICE_NAME);
+        let mut name = Vec::with_capacity(service_name.len() + 2);
+        append_qname(&mut name, service_name);
         append_u16(&mut out, name.len() as u16);
         out.extend_from_slice(&name);
     }
 
-    // Since the output size is constant, we reserve the right amount ahead of time.
+    // Since the output size only depends on the service name, we reserve the right amount
+    // ahead of time.
     // If this assert fails, adjust the capacity of `out` in the source code.
     debug_assert_eq!(out.capacity(), out.len());
     out
@@ -225,12 +238,12 @@ fn segment_peer_id(peer_id: String) -> String {
 }
 
 /// Combines and encodes a `PeerId` and service name for a DNS query.
-fn encode_peer_id(peer_id: &PeerId) -> Vec<u8> {
+fn encode_peer_id(peer_id: &PeerId, service_name: &[u8]) -> Vec<u8> {
     // DNS-safe encoding for the Peer ID 
     let raw_peer_id = data_encoding::BASE32_DNSCURVE.encode(&peer_id.as_bytes());
     // ensure we don't have any labels over 63 bytes long
     let encoded_peer_id = segment_peer_id(raw_peer_id);
-    let service_name = str::from_utf8(SERVICE_NAME).expect("SERVICE_NAME is always ASCII");
+    let service_name = str::from_utf8(service_name).expect("the service name is always ASCII");
     let peer_name = [&encoded_peer_id, service_name].join(".");
 
     // allocate with a little extra padding for QNAME encoding
@@ -365,13 +378,14 @@ impl error::Error for MdnsResponseError {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::SERVICE_NAME;
     use dns_parser::Packet;
     use libp2p_core::identity;
     use std::time::Duration;
 
     #[test]
     fn build_query_correct() {
-        let query = build_query();
+        let query = build_query(SERVICE_NAME);
         assert!(Packet::parse(&query).is_ok());
     }
 
@@ -385,6 +399,7 @@ mod tests {
             my_peer_id,
             vec![addr1, addr2].into_iter(),
             Duration::from_secs(60),
+            SERVICE_NAME,
         )
         .unwrap();
         assert!(Packet::parse(&query).is_ok());
@@ -392,10 +407,21 @@ mod tests {
 
     #[test]
     fn build_service_discovery_response_correct() {
-        let query = build_service_discovery_response(0x1234, Duration::from_secs(120));
+        let query = build_service_discovery_response(0x1234, Duration::from_secs(120), SERVICE_NAME);
         assert!(Packet::parse(&query).is_ok());
     }
 
+    #[test]
+    fn custom_service_name() {
+        let name = b"_my-app._udp.local";
+        assert!(Packet::parse(&build_query(name)).is_ok());
+        assert!(Packet::parse(&build_service_discovery_response(1, Duration::from_secs(1), name)).is_ok());
+
+        assert!(is_valid_service_name(SERVICE_NAME));
+        assert!(!is_valid_service_name(b"_p2p.._udp.local"));
+        assert!(!is_valid_service_name(&[b'x'; 64]));
+    }
+
     #[test]
     fn test_segment_peer_id() {
         let str_32 = String::from_utf8(vec![b'x'; 32]).unwrap();
@@ -30,7 +30,7 @@
 //! struct will automatically discover other libp2p nodes on the local network.
 //!
 
-/// Hardcoded name of the mDNS service. Part of the mDNS libp2p specifications.
+/// Default name of the mDNS service. Part of the mDNS libp2p specifications.
 const SERVICE_NAME: &[u8] = b"_p2p._udp.local";
 /// Hardcoded name of the service used for DNS-SD.
 const META_QUERY_SERVICE: &[u8] = b"_services._dns-sd._udp.local";
@@ -41,6 +41,7 @@ pub use self::{behaviour::Mdns, service::MdnsService};
 pub use self::{behaviour::TokioMdns, service::TokioMdnsService};
 
 pub use self::behaviour::MdnsEvent;
+pub use self::service::{InterfaceFilter, MdnsConfig};
 
 mod behaviour;
 mod dns;
@@ -20,22 +20,353 @@
 
 use crate::{SERVICE_NAME, META_QUERY_SERVICE, dns};
 use dns_parser::{Packet, RData};
-use either::Either::{Left, Right};
 use futures::{future, prelude::*};
 use libp2p_core::{multiaddr::{Multiaddr, Protocol}, PeerId};
-use std::{convert::TryFrom as _, fmt, io, net::Ipv4Addr, net::SocketAddr, str, time::{Duration, Instant}};
+use get_if_addrs::IfAddr;
+use std::{
+    convert::TryFrom as _,
+    fmt,
+    io,
+    iter,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str,
+    time::{Duration, Instant}
+};
 use wasm_timer::Interval;
 use lazy_static::lazy_static;
 
 pub use dns::{MdnsResponseError, build_query_response, build_service_discovery_response};
 
+/// The IPv4 mDNS multicast group.
+const IPV4_MDNS_MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// The IPv6 mDNS multicast group.
+const IPV6_MDNS_MULTICAST_GROUP: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+/// The interval at which the network interfaces are checked for changes.
+const INTERFACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 lazy_static! {
     static ref IPV4_MDNS_MULTICAST_ADDRESS: SocketAddr = SocketAddr::from((
-        Ipv4Addr::new(224, 0, 0, 251),
+        IPV4_MDNS_MULTICAST_GROUP,
+        5353,
+    ));
+    static ref IPV6_MDNS_MULTICAST_ADDRESS: SocketAddr = SocketAddr::from((
+        IPV6_MDNS_MULTICAST_GROUP,
         5353,
     ));
 }
 
+/// Configuration of mDNS discovery.
+#[derive(Debug, Clone)]
+pub struct MdnsConfig {
+    /// The time-to-live of the records in our responses.
+    ttl: Duration,
+    /// The interval between queries.
+    query_interval: Duration,
+    /// The name of the mDNS service.
+    service_name: String,
+    /// Decides which network interfaces participate in mDNS.
+    interface_filter: InterfaceFilter,
+}
+
+impl MdnsConfig {
+    /// Creates a new `MdnsConfig` with the following default settings:
+    ///
+    ///   * [`MdnsConfig::with_ttl`] 5 minutes
+    ///   * [`MdnsConfig::with_query_interval`] 20 seconds
+    ///   * [`MdnsConfig::with_service_name`] `_p2p._udp.local`
+    ///   * [`MdnsConfig::with_interface_filter`] all non-loopback interfaces
+    pub fn new() -> Self {
+        MdnsConfig {
+            ttl: Duration::from_secs(5 * 60),
+            query_interval: Duration::from_secs(20),
+            service_name: str::from_utf8(SERVICE_NAME).expect("SERVICE_NAME is always ASCII").to_owned(),
+            interface_filter: InterfaceFilter::new(),
+        }
+    }
+
+    /// Sets the time-to-live of the records in our responses, i.e. how long
+    /// remotes consider our addresses valid without hearing from us again.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the interval between queries.
+    pub fn with_query_interval(mut self, interval: Duration) -> Self {
+        self.query_interval = interval;
+        self
+    }
+
+    /// Sets the name of the mDNS service, e.g. `_my-app._udp.local`, to isolate
+    /// discovery from libp2p nodes of other applications on the local network.
+    ///
+    /// The name must be an ASCII domain name with non-empty labels of at most
+    /// 63 characters, which is checked when the service is started.
+    pub fn with_service_name(mut self, name: impl Into<String>) -> Self {
+        self.service_name = name.into();
+        self
+    }
+
+    /// Sets the filter deciding which network interfaces participate in mDNS.
+    pub fn with_interface_filter(mut self, filter: InterfaceFilter) -> Self {
+        self.interface_filter = filter;
+        self
+    }
+
+    /// Returns the time-to-live of the records in our responses.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        MdnsConfig::new()
+    }
+}
+
+/// Decides which network interfaces participate in mDNS, based on their names.
+///
+/// Loopback interfaces never participate. By default, all other interfaces do.
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceFilter {
+    /// If not empty, only these interfaces participate.
+    allow: Vec<String>,
+    /// These interfaces never participate.
+    deny: Vec<String>,
+}
+
+impl InterfaceFilter {
+    /// Creates a new `InterfaceFilter` that accepts all non-loopback interfaces.
+    pub fn new() -> Self {
+        InterfaceFilter::default()
+    }
+
+    /// Adds an interface to the allow list. Once the allow list is not empty,
+    /// only the interfaces on it participate in mDNS.
+    pub fn allow(mut self, name: impl Into<String>) -> Self {
+        self.allow.push(name.into());
+        self
+    }
+
+    /// Adds an interface to the deny list. The deny list takes precedence
+    /// over the allow list.
+    pub fn deny(mut self, name: impl Into<String>) -> Self {
+        self.deny.push(name.into());
+        self
+    }
+
+    /// Returns `true` if the interface with the given name may participate in mDNS.
+    pub fn is_allowed(&self, name: &str) -> bool {
+        !self.deny.iter().any(|n| n == name)
+            && (self.allow.is_empty() || self.allow.iter().any(|n| n == name))
+    }
+}
+
+/// The network interface an mDNS packet was received on.
+#[derive(Debug, Clone)]
+pub struct InterfaceInfo {
+    /// The name of the interface.
+    name: String,
+    /// The addresses of the interface.
+    addrs: Vec<IfAddr>,
+}
+
+impl InterfaceInfo {
+    /// Returns the name of the interface.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns `true` if the given address is reachable by nodes on this interface,
+    /// i.e. if its IP address is within the network of one of the addresses of the
+    /// interface. Addresses not starting with an IP address are always deemed reachable.
+    pub fn is_reachable(&self, addr: &Multiaddr) -> bool {
+        let ip = match addr.iter().next() {
+            Some(Protocol::Ip4(ip)) => IpAddr::V4(ip),
+            Some(Protocol::Ip6(ip)) => IpAddr::V6(ip),
+            _ => return true,
+        };
+        self.addrs.iter().any(|a| in_network(a, ip))
+    }
+}
+
+/// A network interface participating in mDNS.
+struct Interface<S> {
+    /// The name of the interface.
+    name: String,
+    /// The index of the interface, if known.
+    index: Option<u32>,
+    /// The addresses of the interface, sorted by IP address.
+    addrs: Vec<IfAddr>,
+    /// The IPv4 socket of the interface, if the multicast group has been joined.
+    v4: Option<InterfaceSocket<S>>,
+    /// The IPv6 socket of the interface, if the multicast group has been joined.
+    v6: Option<InterfaceSocket<S>>,
+}
+
+/// A socket bound to port 5353 that has joined the mDNS multicast group on
+/// a single interface and sends multicast packets on that interface.
+struct InterfaceSocket<S> {
+    /// The socket for sending and receiving.
+    socket: S,
+    /// A clone of the socket for sending the goodbye packet on drop.
+    std_socket: std::net::UdpSocket,
+    /// Buffer used for receiving data from the socket.
+    recv_buffer: Vec<u8>,
+}
+
+impl<S> Interface<S> {
+    fn info(&self) -> InterfaceInfo {
+        InterfaceInfo {
+            name: self.name.clone(),
+            addrs: self.addrs.clone(),
+        }
+    }
+
+    fn ipv4(&self) -> Option<Ipv4Addr> {
+        self.addrs.iter().find_map(|a| match a {
+            IfAddr::V4(a) => Some(a.ip),
+            IfAddr::V6(_) => None,
+        })
+    }
+
+    fn has_ipv6(&self) -> bool {
+        self.addrs.iter().any(|a| matches!(a, IfAddr::V6(_)))
+    }
+}
+
+/// Returns the interface a packet from the given address was received on.
+///
+/// Link-local IPv6 senders are identified by their scope, all other senders
+/// by the network of the interface addresses.
+fn find_interface<'a, S>(interfaces: &'a [Interface<S>], from: &SocketAddr) -> Option<&'a Interface<S>> {
+    if let SocketAddr::V6(from) = from {
+        if from.scope_id() != 0 {
+            if let Some(i) = interfaces.iter().find(|i| i.index == Some(from.scope_id())) {
+                return Some(i)
+            }
+        }
+    }
+    interfaces.iter().find(|i| i.addrs.iter().any(|a| in_network(a, from.ip())))
+}
+
+/// Returns `true` if `ip` is within the network of the interface address `addr`.
+fn in_network(addr: &IfAddr, ip: IpAddr) -> bool {
+    match (addr, ip) {
+        (IfAddr::V4(a), IpAddr::V4(ip)) => {
+            let mask = u32::from(a.netmask);
+            u32::from(a.ip) & mask == u32::from(ip) & mask
+        }
+        (IfAddr::V6(a), IpAddr::V6(ip)) => {
+            let mask = u128::from(a.netmask);
+            u128::from(a.ip) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Lists the current network interfaces accepted by the filter, together with
+/// their addresses sorted by IP address.
+fn list_interfaces(filter: &InterfaceFilter) -> io::Result<Vec<(String, Vec<IfAddr>)>> {
+    let mut interfaces: Vec<(String, Vec<IfAddr>)> = Vec::new();
+    for iface in get_if_addrs::get_if_addrs()? {
+        if iface.is_loopback() || !filter.is_allowed(&iface.name) {
+            continue
+        }
+        match interfaces.iter_mut().find(|(name, _)| *name == iface.name) {
+            Some((_, addrs)) => addrs.push(iface.addr),
+            None => interfaces.push((iface.name, vec![iface.addr])),
+        }
+    }
+    for (_, addrs) in &mut interfaces {
+        addrs.sort_by_key(|a| a.ip());
+    }
+    Ok(interfaces)
+}
+
+/// Creates a `UdpBuilder` that allows other processes to bind the mDNS port as well.
+fn mdns_socket_builder(builder: net2::UdpBuilder) -> io::Result<net2::UdpBuilder> {
+    #[cfg(unix)]
+    fn platform_specific(s: &net2::UdpBuilder) -> io::Result<()> {
+        net2::unix::UnixUdpBuilderExt::reuse_port(s, true)?;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    fn platform_specific(_: &net2::UdpBuilder) -> io::Result<()> { Ok(()) }
+    builder.reuse_address(true)?;
+    platform_specific(&builder)?;
+    Ok(builder)
+}
+
+/// Binds an IPv4 mDNS socket that joins the multicast group on, and sends
+/// multicast packets from, the interface with the given address.
+fn bind_v4(ip: Ipv4Addr) -> io::Result<std::net::UdpSocket> {
+    let socket = mdns_socket_builder(net2::UdpBuilder::new_v4()?)?
+        .bind((Ipv4Addr::UNSPECIFIED, 5353))?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.join_multicast_v4(&IPV4_MDNS_MULTICAST_GROUP, &ip)?;
+    net2::UdpSocketExt::set_multicast_if_v4(&socket, &ip)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Binds an IPv6 mDNS socket that joins the multicast group on, and sends
+/// multicast packets from, the interface with the given index.
+fn bind_v6(index: u32) -> io::Result<std::net::UdpSocket> {
+    let builder = mdns_socket_builder(net2::UdpBuilder::new_v6()?)?;
+    builder.only_v6(true)?;
+    let socket = builder.bind((Ipv6Addr::UNSPECIFIED, 5353))?;
+    socket.set_multicast_loop_v6(true)?;
+    socket.join_multicast_v6(&IPV6_MDNS_MULTICAST_GROUP, index)?;
+    net2::UdpSocketExt::set_multicast_if_v6(&socket, index)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Sends a packet on the given interface without waiting for its sockets to
+/// become writable. The packet is dropped if a socket's send buffer is full.
+fn send_nonblocking<S>(iface: &Interface<S>, packet: &[u8]) {
+    let sockets = iface.v4.iter().map(|s| (s, *IPV4_MDNS_MULTICAST_ADDRESS))
+        .chain(iface.v6.iter().map(|s| (s, *IPV6_MDNS_MULTICAST_ADDRESS)));
+    for (socket, addr) in sockets {
+        if let Err(err) = socket.std_socket.send_to(packet, addr) {
+            log::debug!("Failed to send mDNS packet on {}: {}", iface.name, err);
+        }
+    }
+}
+
+/// Returns the index of the network interface with the given name.
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // Safe because `name` is a valid, NUL-terminated C string.
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        index => Some(index),
+    }
+}
+
+/// Returns the index of the network interface with the given name.
+///
+/// Not supported on this platform, such that IPv6 mDNS is not used.
+#[cfg(not(unix))]
+fn interface_index(_: &str) -> Option<u32> {
+    None
+}
+
+/// What woke up the service.
+enum Wakeup {
+    /// A packet was received on the interface with the given position, on its
+    /// IPv6 socket if the flag is set.
+    Packet(io::Result<(usize, SocketAddr)>, usize, bool),
+    /// The query interval fired.
+    Query,
+    /// The interface check interval fired.
+    InterfaceCheck,
+}
+
 macro_rules! codegen {
     ($feature_name:expr, $service_name:ident, $udp_socket:ty, $udp_socket_from_std:tt) => {
 
@@ -52,8 +383,12 @@ macro_rules! codegen {
 /// to a query previously emitted locally. The `MdnsService` will automatically produce queries,
 /// which means that you will receive responses automatically.
 ///
-/// When you receive an `MdnsQuery`, use the `respond` method to send back an answer to the node
-/// that emitted the query.
+/// When you receive an `MdnsQuery`, use the `enqueue_response_on` method to send back an answer
+/// on the network interface the query was received on.
+///
+/// The service participates in mDNS on every network interface accepted by its
+/// [`InterfaceFilter`], joining the multicast groups on interfaces as they appear and
+/// leaving them as they disappear.
 ///
 /// When you receive an `MdnsResponse`, use the provided methods to query the information received
 /// in the response.
@@ -80,13 +415,22 @@ macro_rules! codegen {
 ///     match packet {
 ///         MdnsPacket::Query(query) => {
 ///             println!("Query from {:?}", query.remote_addr());
+///             // Only advertise addresses reachable on the interface of the query.
+///             let addrs = my_listened_addrs.iter()
+///                 .filter(|a| query.interface().map_or(true, |i| i.is_reachable(a)))
+///                 .cloned()
+///                 .collect::<Vec<_>>();
 ///             let resp = build_query_response(
 ///                 query.query_id(),
 ///                 my_peer_id.clone(),
-///                 vec![].into_iter(),
+///                 addrs.into_iter(),
 ///                 Duration::from_secs(120),
+///                 service.service_name(),
 ///             ).unwrap();
-///             service.enqueue_response(resp);
+///             match query.interface() {
+///                 Some(interface) => service.enqueue_response_on(resp, interface.name()),
+///                 None => service.enqueue_response(resp),
+///             }
 ///         }
 ///         MdnsPacket::Response(response) => {
 ///             for peer in response.discovered_peers() {
@@ -100,6 +444,7 @@ macro_rules! codegen {
 ///             let resp = build_service_discovery_response(
 ///                 disc.query_id(),
 ///                 Duration::from_secs(120),
+///                 service.service_name(),
 ///             );
 ///             service.enqueue_response(resp);
 ///         }
@@ -109,11 +454,20 @@ macro_rules! codegen {
 /// # }
 #[cfg_attr(docsrs, doc(cfg(feature = $feature_name)))]
 pub struct $service_name {
-    /// Main socket for listening.
-    socket: $udp_socket,
-
-    /// Socket for sending queries on the network.
-    query_socket: $udp_socket,
+    /// The network interfaces participating in mDNS.
+    ///
+    /// Every interface has its own sockets bound to port 5353, as mDNS requires
+    /// packets to be sent from that port, which join the multicast groups on the
+    /// interface and send queries and responses on it.
+    interfaces: Vec<Interface<$udp_socket>>,
+    /// Decides which network interfaces participate in mDNS.
+    filter: InterfaceFilter,
+    /// The name of the mDNS service.
+    service_name: Vec<u8>,
+    /// The packet sent on all interfaces when the service is dropped, if any.
+    goodbye: Option<Vec<u8>>,
+    /// Interval for checking for changes of the network interfaces.
+    interface_interval: Interval,
 
     /// Interval for sending queries.
     query_interval: Interval,
@@ -121,66 +475,148 @@ pub struct $service_name {
     /// Note that we still need to have an interval for querying, as we need to wake up the socket
     /// regularly to recover from errors. Otherwise we could simply use an `Option<Interval>`.
     silent: bool,
-    /// Buffer used for receiving data from the main socket.
-    recv_buffer: [u8; 2048],
-    /// Buffers pending to send on the main socket.
-    send_buffers: Vec<Vec<u8>>,
-    /// Buffers pending to send on the query socket.
-    query_send_buffers: Vec<Vec<u8>>,
+    /// Buffers pending to send, together with the name of the interface to send
+    /// them on, or `None` to send them on all interfaces.
+    send_buffers: Vec<(Option<String>, Vec<u8>)>,
 }
 
 impl $service_name {
     /// Starts a new mDNS service.
     pub fn new() -> io::Result<$service_name> {
-        Self::new_inner(false)
+        Self::new_inner(false, MdnsConfig::new())
     }
 
     /// Same as `new`, but we don't automatically send queries on the network.
     pub fn silent() -> io::Result<$service_name> {
-        Self::new_inner(true)
+        Self::new_inner(true, MdnsConfig::new())
+    }
+
+    /// Starts a new mDNS service with the given configuration.
+    pub fn with_config(config: MdnsConfig) -> io::Result<$service_name> {
+        Self::new_inner(false, config)
     }
 
     /// Starts a new mDNS service.
-    fn new_inner(silent: bool) -> io::Result<$service_name> {
-        let std_socket = {
-            #[cfg(unix)]
-            fn platform_specific(s: &net2::UdpBuilder) -> io::Result<()> {
-                net2::unix::UnixUdpBuilderExt::reuse_port(s, true)?;
-                Ok(())
-            }
-            #[cfg(not(unix))]
-            fn platform_specific(_: &net2::UdpBuilder) -> io::Result<()> { Ok(()) }
-            let builder = net2::UdpBuilder::new_v4()?;
-            builder.reuse_address(true)?;
-            platform_specific(&builder)?;
-            builder.bind(("0.0.0.0", 5353))?
-        };
+    fn new_inner(silent: bool, config: MdnsConfig) -> io::Result<$service_name> {
+        if !dns::is_valid_service_name(config.service_name.as_bytes()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid mDNS service name"));
+        }
 
-        let socket = $udp_socket_from_std(std_socket)?;
-        // Given that we pass an IP address to bind, which does not need to be resolved, we can
-        // use std::net::UdpSocket::bind, instead of its async counterpart from async-std.
-        let query_socket = $udp_socket_from_std(
-            std::net::UdpSocket::bind((Ipv4Addr::from([0u8, 0, 0, 0]), 0u16))?,
-        )?;
-
-        socket.set_multicast_loop_v4(true)?;
-        socket.set_multicast_ttl_v4(255)?;
-        // TODO: correct interfaces?
-        socket.join_multicast_v4(From::from([224, 0, 0, 251]), Ipv4Addr::UNSPECIFIED)?;
-
-        Ok($service_name {
-            socket,
-            query_socket,
-            query_interval: Interval::new_at(Instant::now(), Duration::from_secs(20)),
+        let mut service = $service_name {
+            interfaces: Vec::new(),
+            filter: config.interface_filter,
+            service_name: config.service_name.into_bytes(),
+            goodbye: None,
+            interface_interval: Interval::new_at(
+                Instant::now() + INTERFACE_CHECK_INTERVAL,
+                INTERFACE_CHECK_INTERVAL
+            ),
+            query_interval: Interval::new_at(Instant::now(), config.query_interval),
             silent,
-            recv_buffer: [0; 2048],
             send_buffers: Vec::new(),
-            query_send_buffers: Vec::new(),
-        })
+        };
+        service.update_interfaces();
+        Ok(service)
     }
 
+    /// Returns the name of the mDNS service, to be passed to `build_query_response`
+    /// and `build_service_discovery_response`.
+    pub fn service_name(&self) -> &[u8] {
+        &self.service_name
+    }
+
+    /// Announces that the given peer leaves when the service is dropped, by sending
+    /// a response with a TTL of zero on all network interfaces. Remotes forget the
+    /// addresses of the peer upon receiving it.
+    pub fn goodbye_on_drop(&mut self, peer_id: PeerId) {
+        self.goodbye = build_query_response(0, peer_id, iter::empty(), Duration::from_secs(0), &self.service_name)
+            .map_err(|err| log::debug!("Failed to build mDNS goodbye packet: {}", err))
+            .ok();
+    }
+
+    /// Enqueues a response to be sent on all network interfaces.
     pub fn enqueue_response(&mut self, rsp: Vec<u8>) {
-        self.send_buffers.push(rsp);
+        self.send_buffers.push((None, rsp));
+    }
+
+    /// Enqueues a response to be sent on the network interface with the given name,
+    /// usually the one a query was received on.
+    pub fn enqueue_response_on(&mut self, rsp: Vec<u8>, interface: &str) {
+        self.send_buffers.push((Some(interface.to_owned()), rsp));
+    }
+
+    /// Returns the names of the network interfaces currently participating in mDNS.
+    pub fn interfaces(&self) -> impl Iterator<Item = &str> {
+        self.interfaces.iter().map(|i| i.name.as_str())
+    }
+
+    /// Binds sockets for new network interfaces and closes the sockets of
+    /// interfaces that disappeared or whose addresses changed.
+    fn update_interfaces(&mut self) {
+        let current = match list_interfaces(&self.filter) {
+            Ok(current) => current,
+            Err(err) => {
+                log::debug!("Failed to list network interfaces: {}", err);
+                return
+            }
+        };
+
+        // Closing the sockets of an interface leaves the multicast groups.
+        self.interfaces.retain(|i| {
+            let kept = current.iter().any(|(name, addrs)| *name == i.name && *addrs == i.addrs);
+            if !kept {
+                log::debug!("Leaving mDNS on interface {}", i.name);
+            }
+            kept
+        });
+
+        for (name, addrs) in current {
+            if !self.interfaces.iter().any(|i| i.name == name) {
+                let iface = Self::join(name, addrs);
+                if iface.v4.is_some() || iface.v6.is_some() {
+                    self.interfaces.push(iface);
+                }
+            }
+        }
+    }
+
+    /// Binds the sockets of the given network interface, joining the multicast groups on it.
+    fn join(name: String, addrs: Vec<IfAddr>) -> Interface<$udp_socket> {
+        fn socket(std_socket: std::net::UdpSocket) -> io::Result<InterfaceSocket<$udp_socket>> {
+            Ok(InterfaceSocket {
+                std_socket: std_socket.try_clone()?,
+                socket: $udp_socket_from_std(std_socket)?,
+                recv_buffer: vec![0; 2048],
+            })
+        }
+
+        log::debug!("Joining mDNS on interface {}", name);
+        let mut iface = Interface {
+            index: interface_index(&name),
+            name,
+            addrs,
+            v4: None,
+            v6: None,
+        };
+
+        if let Some(ip) = iface.ipv4() {
+            match bind_v4(ip).and_then(socket) {
+                Ok(s) => iface.v4 = Some(s),
+                Err(err) => log::debug!("Failed to join IPv4 mDNS on interface {}: {}", iface.name, err),
+            }
+        }
+
+        // IPv6 is optional, as it may be disabled on the host.
+        match (iface.has_ipv6(), iface.index) {
+            (true, Some(index)) => match bind_v6(index).and_then(socket) {
+                Ok(s) => iface.v6 = Some(s),
+                Err(err) => log::debug!("Failed to join IPv6 mDNS on interface {}: {}", iface.name, err),
+            },
+            (true, None) => log::debug!("Unknown index of interface {}, skipping IPv6 mDNS", iface.name),
+            (false, _) => {}
+        }
+
+        iface
     }
 
     /// Returns a future resolving to itself and the next received `MdnsPacket`.
@@ -207,81 +643,107 @@ impl $service_name {
     // resolves, not forcing self-referential structures on the caller.
     pub async fn next(mut self) -> (Self, MdnsPacket) {
         loop {
-            // Flush the send buffer of the main socket.
-            while !self.send_buffers.is_empty() {
-                let to_send = self.send_buffers.remove(0);
-
-                match self.socket.send_to(&to_send, *IPV4_MDNS_MULTICAST_ADDRESS).await {
-                    Ok(bytes_written) => {
-                        debug_assert_eq!(bytes_written, to_send.len());
+            // Flush the send buffers. Errors are non-fatal because they can happen for example
+            // if we lose connection to the network, or if only one of the address families is
+            // routable.
+            for (target, to_send) in self.send_buffers.drain(..) {
+                for iface in self.interfaces.iter_mut() {
+                    if target.as_ref().map_or(false, |t| *t != iface.name) {
+                        continue
                     }
-                    Err(_) => {
-                        // Errors are non-fatal because they can happen for example if we lose
-                        // connection to the network.
-                        self.send_buffers.clear();
-                        break;
+                    let sockets = iface.v4.iter_mut().map(|s| (s, *IPV4_MDNS_MULTICAST_ADDRESS))
+                        .chain(iface.v6.iter_mut().map(|s| (s, *IPV6_MDNS_MULTICAST_ADDRESS)));
+                    for (socket, addr) in sockets {
+                        match socket.socket.send_to(&to_send, addr).await {
+                            Ok(bytes_written) => debug_assert_eq!(bytes_written, to_send.len()),
+                            Err(err) => log::debug!("Failed to send mDNS packet on {}: {}", iface.name, err),
+                        }
                     }
                 }
             }
 
-            // Flush the query send buffer.
-            while !self.query_send_buffers.is_empty() {
-                let to_send = self.query_send_buffers.remove(0);
-
-                match self.query_socket.send_to(&to_send, *IPV4_MDNS_MULTICAST_ADDRESS).await {
-                    Ok(bytes_written) => {
-                        debug_assert_eq!(bytes_written, to_send.len());
-                    }
-                    Err(_) => {
-                        // Errors are non-fatal because they can happen for example if we lose
-                        // connection to the network.
-                        self.query_send_buffers.clear();
-                        break;
-                    }
+            // Listen for incoming packets on the sockets of all interfaces. Without
+            // interfaces, only the pending future remains and no packet is ever received.
+            let mut recvs = vec![future::pending().boxed()];
+            for (position, iface) in self.interfaces.iter_mut().enumerate() {
+                let sockets = iface.v4.iter_mut().map(|s| (s, false))
+                    .chain(iface.v6.iter_mut().map(|s| (s, true)));
+                for (socket, is_v6) in sockets {
+                    let InterfaceSocket { socket, recv_buffer, .. } = socket;
+                    recvs.push(socket.recv_from(recv_buffer)
+                        .map(move |recved| Wakeup::Packet(recved, position, is_v6))
+                        .boxed());
                 }
             }
-
-            // Either (left) listen for incoming packets or (right) send query packets whenever the
-            // query interval fires.
-            let selected_output = match futures::future::select(
-                Box::pin(self.socket.recv_from(&mut self.recv_buffer)),
-                Box::pin(self.query_interval.next()),
+            let recv = future::select_all(recvs).map(|(wakeup, _, _)| wakeup);
+
+            // Either listen for incoming packets, send query packets whenever
+            // the query interval fires or check the network interfaces for changes.
+            let wakeup = match future::select(
+                recv,
+                future::select(
+                    self.query_interval.next(),
+                    self.interface_interval.next(),
+                ),
             ).await {
-                future::Either::Left((recved, _)) => Left(recved),
-                future::Either::Right(_) => Right(()),
+                future::Either::Left((wakeup, _)) => wakeup,
+                future::Either::Right((future::Either::Left(_), _)) => Wakeup::Query,
+                future::Either::Right((future::Either::Right(_), _)) => Wakeup::InterfaceCheck,
             };
 
-            match selected_output {
-                Left(left) => match left {
-                    Ok((len, from)) => {
-                        match MdnsPacket::new_from_bytes(&self.recv_buffer[..len], from) {
-                            Some(packet) => return (self, packet),
-                            None => {},
-                        }
-                    },
-                    Err(_) => {
-                        // Errors are non-fatal and can happen if we get disconnected from the network.
-                        // The query interval will wake up the task at some point so that we can try again.
-                    },
+            match wakeup {
+                Wakeup::Packet(Ok((len, from)), position, is_v6) => {
+                    let iface = &self.interfaces[position];
+                    // A socket may receive packets that arrived on other interfaces,
+                    // which are received by the sockets of those interfaces as well.
+                    if find_interface(&self.interfaces, &from).map_or(false, |i| i.name != iface.name) {
+                        continue
+                    }
+                    let socket = if is_v6 { &iface.v6 } else { &iface.v4 };
+                    let buf = &socket.as_ref().expect("a packet was received on the socket").recv_buffer[..len];
+                    if let Some(packet) = MdnsPacket::new_from_bytes(buf, from, Some(iface.info()), &self.service_name) {
+                        return (self, packet)
+                    }
+                },
+                Wakeup::Packet(Err(_), _, _) => {
+                    // Errors are non-fatal and can happen if we get disconnected from the network.
+                    // The query interval will wake up the task at some point so that we can try again.
                 },
-                Right(_) => {
+                Wakeup::Query => {
                     // Ensure underlying task is woken up on the next interval tick.
                     while let Some(_) = self.query_interval.next().now_or_never() {};
 
                     if !self.silent {
-                        let query = dns::build_query();
-                        self.query_send_buffers.push(query.to_vec());
+                        let query = dns::build_query(&self.service_name);
+                        self.send_buffers.push((None, query.to_vec()));
                     }
-                }
+                },
+                Wakeup::InterfaceCheck => {
+                    while let Some(_) = self.interface_interval.next().now_or_never() {};
+                    self.update_interfaces();
+                },
             };
         }
     }
 }
 
+impl Drop for $service_name {
+    fn drop(&mut self) {
+        // The sockets are non-blocking, such that dropping the service
+        // never blocks the executor.
+        if let Some(goodbye) = self.goodbye.take() {
+            for iface in &self.interfaces {
+                send_nonblocking(iface, &goodbye);
+            }
+        }
+    }
+}
+
 impl fmt::Debug for $service_name {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("$service_name")
             .field("silent", &self.silent)
+            .field("interfaces", &self.interfaces().collect::<Vec<_>>())
             .finish()
     }
 }
@@ -308,18 +770,24 @@ pub enum MdnsPacket {
 }
 
 impl MdnsPacket {
-    fn new_from_bytes(buf: &[u8], from: SocketAddr) -> Option<MdnsPacket> {
+    fn new_from_bytes(
+        buf: &[u8],
+        from: SocketAddr,
+        interface: Option<InterfaceInfo>,
+        service_name: &[u8],
+    ) -> Option<MdnsPacket> {
         match Packet::parse(buf) {
             Ok(packet) => {
                 if packet.header.query {
                     if packet
                         .questions
                         .iter()
-                        .any(|q| q.qname.to_string().as_bytes() == SERVICE_NAME)
+                        .any(|q| q.qname.to_string().as_bytes() == service_name)
                     {
                         let query = MdnsPacket::Query(MdnsQuery {
                             from,
                             query_id: packet.header.id,
+                            interface,
                         });
                         return Some(query);
                     } else if packet
@@ -332,6 +800,7 @@ impl MdnsPacket {
                             MdnsServiceDiscovery {
                                 from,
                                 query_id: packet.header.id,
+                                interface,
                             },
                         );
                         return Some(discovery);
@@ -342,6 +811,7 @@ impl MdnsPacket {
                     let resp = MdnsPacket::Response(MdnsResponse::new (
                         packet,
                         from,
+                        service_name,
                     ));
                     return Some(resp);
                 }
@@ -359,6 +829,8 @@ pub struct MdnsQuery {
     from: SocketAddr,
     /// Id of the received DNS query. We need to pass this ID back in the results.
     query_id: u16,
+    /// The network interface the query was received on, if known.
+    interface: Option<InterfaceInfo>,
 }
 
 impl MdnsQuery {
@@ -371,6 +843,11 @@ impl MdnsQuery {
     pub fn query_id(&self) -> u16 {
         self.query_id
     }
+
+    /// The network interface the query was received on, if known.
+    pub fn interface(&self) -> Option<&InterfaceInfo> {
+        self.interface.as_ref()
+    }
 }
 
 impl fmt::Debug for MdnsQuery {
@@ -378,6 +855,7 @@ impl fmt::Debug for MdnsQuery {
         f.debug_struct("MdnsQuery")
             .field("from", self.remote_addr())
             .field("query_id", &self.query_id)
+            .field("interface", &self.interface)
             .finish()
     }
 }
@@ -388,6 +866,8 @@ pub struct MdnsServiceDiscovery {
     from: SocketAddr,
     /// Id of the received DNS query. We need to pass this ID back in the results.
     query_id: u16,
+    /// The network interface the query was received on, if known.
+    interface: Option<InterfaceInfo>,
 }
 
 impl MdnsServiceDiscovery {
@@ -400,6 +880,11 @@ impl MdnsServiceDiscovery {
     pub fn query_id(&self) -> u16 {
         self.query_id
     }
+
+    /// The network interface the query was received on, if known.
+    pub fn interface(&self) -> Option<&InterfaceInfo> {
+        self.interface.as_ref()
+    }
 }
 
 impl fmt::Debug for MdnsServiceDiscovery {
@@ -407,6 +892,7 @@ impl fmt::Debug for MdnsServiceDiscovery {
         f.debug_struct("MdnsServiceDiscovery")
             .field("from", self.remote_addr())
             .field("query_id", &self.query_id)
+            .field("interface", &self.interface)
             .finish()
     }
 }
@@ -419,9 +905,9 @@ pub struct MdnsResponse {
 
 impl MdnsResponse {
     /// Creates a new `MdnsResponse` based on the provided `Packet`.
-    fn new(packet: Packet<'_>, from: SocketAddr) -> MdnsResponse {
+    fn new(packet: Packet<'_>, from: SocketAddr, service_name: &[u8]) -> MdnsResponse {
         let peers = packet.answers.iter().filter_map(|record| {
-            if record.name.to_string().as_bytes() != SERVICE_NAME {
+            if record.name.to_string().as_bytes() != service_name {
                 return None;
             }
 
@@ -430,9 +916,11 @@ impl MdnsResponse {
                 _ => return None,
             };
 
-            let mut peer_name = match record_value.rsplitn(4, |c| c == '.').last() {
-                Some(n) => n.to_owned(),
-                None => return None,
+            // The record value is the peer name followed by the service name.
+            let mut peer_name = match record_value.len().checked_sub(service_name.len() + 1) {
+                Some(len) if record_value.as_bytes().ends_with(service_name)
+                    && record_value.as_bytes()[len] == b'.' => record_value[..len].to_owned(),
+                _ => return None,
             };
 
             // if we have a segmented name, remove the '.'
@@ -555,6 +1043,8 @@ impl MdnsPeer {
     }
 
     /// Returns the requested time-to-live for the record.
+    ///
+    /// A time-to-live of zero means that the peer is leaving.
     #[inline]
     pub fn ttl(&self) -> Duration {
         Duration::from_secs(u64::from(self.ttl))
@@ -578,6 +1068,51 @@ impl fmt::Debug for MdnsPeer {
 
 #[cfg(test)]
 mod tests {
+    use super::{InterfaceFilter, InterfaceInfo};
+    use get_if_addrs::{IfAddr, Ifv4Addr, Ifv6Addr};
+
+    #[test]
+    fn interface_filter() {
+        let filter = InterfaceFilter::new();
+        assert!(filter.is_allowed("eth0"));
+
+        let filter = InterfaceFilter::new().deny("docker0");
+        assert!(filter.is_allowed("eth0"));
+        assert!(!filter.is_allowed("docker0"));
+
+        let filter = InterfaceFilter::new().allow("eth0").allow("wlan0").deny("wlan0");
+        assert!(filter.is_allowed("eth0"));
+        assert!(!filter.is_allowed("wlan0"));
+        assert!(!filter.is_allowed("tun0"));
+    }
+
+    #[test]
+    fn reachable_addresses() {
+        let interface = InterfaceInfo {
+            name: "eth0".to_owned(),
+            addrs: vec![
+                IfAddr::V4(Ifv4Addr {
+                    ip: [192, 168, 1, 10].into(),
+                    netmask: [255, 255, 255, 0].into(),
+                    broadcast: None,
+                }),
+                IfAddr::V6(Ifv6Addr {
+                    ip: "fd00::2".parse().unwrap(),
+                    netmask: "ffff:ffff:ffff:ffff::".parse().unwrap(),
+                    broadcast: None,
+                }),
+            ],
+        };
+
+        let reachable = |addr: &str| interface.is_reachable(&addr.parse().unwrap());
+        assert!(reachable("/ip4/192.168.1.10/tcp/4001"));
+        assert!(!reachable("/ip4/172.17.0.1/tcp/4001"));
+        assert!(!reachable("/ip4/127.0.0.1/tcp/4001"));
+        assert!(reachable("/ip6/fd00::2/tcp/4001"));
+        assert!(!reachable("/ip6/fd01::2/tcp/4001"));
+        assert!(reachable("/dns4/example.com/tcp/4001"));
+    }
+
     macro_rules! testgen {
         ($runtime_name:ident, $service_name:ty, $block_on_fn:tt) => {
     mod $runtime_name {
@@ -600,6 +1135,7 @@ mod tests {
                                 peer_id.clone(),
                                 vec![].into_iter(),
                                 Duration::from_secs(120),
+                                service.service_name(),
                             ).unwrap();
                             service.enqueue_response(resp);
                         }
@@ -667,6 +1203,60 @@ mod tests {
             $block_on_fn(Box::pin(fut));
         }
 
+        #[test]
+        fn queries_are_sent_from_mdns_port() {
+            let own_ips: Vec<std::net::IpAddr> = get_if_addrs::get_if_addrs().unwrap()
+                .into_iter()
+                .map(|i| i.addr.ip())
+                .collect();
+
+            let fut = async {
+                let mut service = <$service_name>::new().unwrap();
+
+                loop {
+                    let next = service.next().await;
+                    service = next.0;
+
+                    if let MdnsPacket::Query(query) = next.1 {
+                        // Ignore queries from other nodes.
+                        if own_ips.contains(&query.remote_addr().ip()) {
+                            assert_eq!(query.remote_addr().port(), 5353);
+                            return;
+                        }
+                    }
+                }
+            };
+
+            $block_on_fn(Box::pin(fut));
+        }
+
+        #[test]
+        fn goodbye_is_sent_on_drop() {
+            let peer_id = PeerId::random();
+
+            let fut = async {
+                let mut service = <$service_name>::silent().unwrap();
+                let mut leaving = <$service_name>::silent().unwrap();
+                leaving.goodbye_on_drop(peer_id.clone());
+                drop(leaving);
+
+                loop {
+                    let next = service.next().await;
+                    service = next.0;
+
+                    if let MdnsPacket::Response(response) = next.1 {
+                        if let Some(peer) = response.discovered_peers().find(|p| p.id() == &peer_id) {
+                            assert_eq!(peer.ttl(), Duration::from_secs(0));
+                            assert_eq!(response.remote_addr().port(), 5353);
+                            return;
+                        }
+                    }
+                }
+            };
+
+            $block_on_fn(Box::pin(fut));
+        }
+
         #[test]
         fn discover_normal_peer_id() {
             discover(PeerId::random())
@@ -254,24 +254,24 @@ impl snow::resolvers::CryptoResolver for Resolver {
 /// Wrapper around a CSPRNG to implement `snow::Random` trait for.
 struct Rng(rand::rngs::StdRng);
 
-impl rand::RngCore for Rng {
+impl rand_core::RngCore for Rng {
     fn next_u32(&mut self) -> u32 {
-        self.0.next_u32()
+        rand::RngCore::next_u32(&mut self.0)
     }
 
     fn next_u64(&mut self) -> u64 {
-        self.0.next_u64()
+        rand::RngCore::next_u64(&mut self.0)
     }
 
     fn fill_bytes(&mut self, dest: &mut [u8]) {
-        self.0.fill_bytes(dest)
+        rand::RngCore::fill_bytes(&mut self.0, dest)
     }
 
-    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
-        self.0.try_fill_bytes(dest)
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        rand::RngCore::try_fill_bytes(&mut self.0, dest).map_err(rand_core::Error::new)
     }
 }
 
-impl rand::CryptoRng for Rng {}
+impl rand_core::CryptoRng for Rng {}
 
 impl snow::types::Random for Rng {}
@@ -18,7 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::protocol;
+use crate::{protocol, quality::QualityConfig};
 use futures::prelude::*;
 use futures::future::BoxFuture;
 use libp2p_swarm::{
@@ -56,6 +56,8 @@ pub struct PingConfig {
     /// Whether the connection should generally be kept alive unless
     /// `max_failures` occur.
     keep_alive: bool,
+    /// The configuration of the connection quality monitor.
+    quality: QualityConfig,
 }
 
 impl PingConfig {
@@ -65,6 +67,7 @@ impl PingConfig {
     ///   * [`PingConfig::with_timeout`] 20s
     ///   * [`PingConfig::with_max_failures`] 1
     ///   * [`PingConfig::with_keep_alive`] false
+    ///   * [`PingConfig::with_quality`] [`QualityConfig::new`]
     ///
     /// These settings have the following effect:
     ///
@@ -81,7 +84,8 @@ impl PingConfig {
             timeout: Duration::from_secs(20),
             interval: Duration::from_secs(15),
             max_failures: NonZeroU32::new(1).expect("1 != 0"),
-            keep_alive: false
+            keep_alive: false,
+            quality: QualityConfig::new(),
         }
     }
 
@@ -118,6 +122,18 @@ impl PingConfig {
         self.keep_alive = b;
         self
     }
+
+    /// Sets the configuration of the connection quality monitor, i.e. the
+    /// window over which round-trip time statistics are computed and the
+    /// thresholds upon which [`QualityEvent`](crate::QualityEvent)s are reported.
+    pub fn with_quality(mut self, c: QualityConfig) -> Self {
+        self.quality = c;
+        self
+    }
+
+    pub(crate) fn quality(&self) -> &QualityConfig {
+        &self.quality
+    }
 }
 
 /// The result of an inbound or outbound ping.
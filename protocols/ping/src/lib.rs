@@ -34,6 +34,11 @@
 //! The `Ping` network behaviour produces [`PingEvent`]s, which may be consumed from the `Swarm`
 //! by an application, e.g. to collect statistics.
 //!
+//! In addition, `Ping` maintains round-trip time statistics for every connection and every
+//! connected peer, see [`Ping::peer_quality`] and [`Ping::connection_quality`]. If thresholds
+//! are configured via [`PingConfig::with_quality`], crossing them is reported through
+//! [`PingQualityEvent`]s, see [`Ping::next_quality_event`].
+//!
 //! > **Note**: The ping protocol does not keep otherwise idle connections alive
 //! > by default, see [`PingConfig::with_keep_alive`] for changing this behaviour.
 //!
@@ -42,13 +47,16 @@
 
 pub mod protocol;
 pub mod handler;
+pub mod quality;
 
 pub use handler::{PingConfig, PingResult, PingSuccess, PingFailure};
+pub use quality::{QualityConfig, QualityEvent, QualityStats};
 use handler::PingHandler;
+use quality::PeerQuality;
 
-use libp2p_core::{Multiaddr, PeerId, connection::ConnectionId};
+use libp2p_core::{Multiaddr, PeerId, connection::{ConnectionId, ConnectedPoint}};
 use libp2p_swarm::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
-use std::{collections::VecDeque, task::Context, task::Poll};
+use std::{collections::{HashMap, VecDeque}, task::Context, task::Poll};
 use void::Void;
 
 /// `Ping` is a [`NetworkBehaviour`] that responds to inbound pings and
@@ -60,6 +68,13 @@ pub struct Ping {
     config: PingConfig,
     /// Queue of events to yield to the swarm.
     events: VecDeque<PingEvent>,
+    /// Queue of quality changes to be retrieved via [`Ping::next_quality_event`].
+    quality_events: VecDeque<PingQualityEvent>,
+    /// Round-trip time statistics of every connected peer,
+    /// aggregated over all its connections.
+    peers: HashMap<PeerId, PeerQuality>,
+    /// Round-trip time statistics of every established connection.
+    connections: HashMap<(PeerId, ConnectionId), QualityStats>,
 }
 
 /// Event generated by the `Ping` network behaviour.
@@ -71,14 +86,59 @@ pub struct PingEvent {
     pub result: PingResult,
 }
 
+/// A change in the quality of the connections to a peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PingQualityEvent {
+    /// The peer ID of the remote.
+    pub peer: PeerId,
+    /// The threshold that was crossed.
+    pub event: QualityEvent,
+}
+
 impl Ping {
     /// Creates a new `Ping` network behaviour with the given configuration.
     pub fn new(config: PingConfig) -> Self {
         Ping {
             config,
             events: VecDeque::new(),
+            quality_events: VecDeque::new(),
+            peers: HashMap::new(),
+            connections: HashMap::new(),
         }
     }
+
+    /// Returns the round-trip time statistics of a peer, aggregated over
+    /// the outbound pings of all its connections, if the peer is connected.
+    pub fn peer_quality(&self, peer: &PeerId) -> Option<&QualityStats> {
+        self.peers.get(peer).map(|p| &p.stats)
+    }
+
+    /// Returns the round-trip time statistics of a single connection,
+    /// if it is established.
+    pub fn connection_quality(&self, peer: &PeerId, connection: &ConnectionId) -> Option<&QualityStats> {
+        self.connections.get(&(peer.clone(), *connection))
+    }
+
+    /// Returns the next change in the quality of the connections to a peer, if any.
+    ///
+    /// Quality changes are detected upon the results of outbound pings, i.e. they
+    /// can be retrieved after a [`PingEvent`] with such a result has been emitted.
+    /// Only the most recent change of the latency and of the loss rate of every
+    /// connected peer is retained.
+    pub fn next_quality_event(&mut self) -> Option<PingQualityEvent> {
+        self.quality_events.pop_front()
+    }
+
+    /// Returns the connected peers together with their smoothed round-trip time,
+    /// ordered from the lowest to the highest. Peers without any successful
+    /// outbound ping are omitted.
+    pub fn peers_by_rtt(&self) -> Vec<(PeerId, std::time::Duration)> {
+        let mut peers = self.peers.iter()
+            .filter_map(|(peer, q)| q.stats.ewma_rtt().map(|rtt| (peer.clone(), rtt)))
+            .collect::<Vec<_>>();
+        peers.sort_by_key(|(_, rtt)| *rtt);
+        peers
+    }
 }
 
 impl Default for Ping {
@@ -101,10 +161,53 @@ impl NetworkBehaviour for Ping {
 
     fn inject_connected(&mut self, _: &PeerId) {}
 
-    fn inject_disconnected(&mut self, _: &PeerId) {}
+    fn inject_connection_established(&mut self, peer: &PeerId, conn: &ConnectionId, _: &ConnectedPoint) {
+        let config = self.config.quality();
+        self.peers.entry(peer.clone()).or_insert_with(|| PeerQuality::new(config));
+        self.connections.insert((peer.clone(), *conn), QualityStats::new(config));
+    }
+
+    fn inject_connection_closed(&mut self, peer: &PeerId, conn: &ConnectionId, _: &ConnectedPoint) {
+        self.connections.remove(&(peer.clone(), *conn));
+    }
 
-    fn inject_event(&mut self, peer: PeerId, _: ConnectionId, result: PingResult) {
-        self.events.push_front(PingEvent { peer, result })
+    fn inject_disconnected(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        self.quality_events.retain(|e| e.peer != *peer);
+    }
+
+    fn inject_event(&mut self, peer: PeerId, connection: ConnectionId, result: PingResult) {
+        // Only outbound pings contribute to the statistics.
+        let rtt = match &result {
+            Ok(PingSuccess::Ping { rtt }) => Some(*rtt),
+            Ok(PingSuccess::Pong) => {
+                self.events.push_front(PingEvent { peer, result });
+                return
+            }
+            Err(_) => None,
+        };
+
+        if let Some(stats) = self.connections.get_mut(&(peer.clone(), connection)) {
+            match rtt {
+                Some(rtt) => stats.record_success(rtt),
+                None => stats.record_failure(),
+            }
+        }
+
+        if let Some(quality) = self.peers.get_mut(&peer) {
+            match rtt {
+                Some(rtt) => quality.stats.record_success(rtt),
+                None => quality.stats.record_failure(),
+            }
+            for event in quality.check(self.config.quality()) {
+                // A change supersedes an unretrieved change of the same measure.
+                let latency = event.is_latency();
+                self.quality_events.retain(|e| e.peer != peer || e.event.is_latency() != latency);
+                self.quality_events.push_back(PingQualityEvent { peer: peer.clone(), event });
+            }
+        }
+
+        self.events.push_front(PingEvent { peer, result });
     }
 
     fn poll(&mut self, _: &mut Context<'_>, _: &mut impl PollParameters)
@@ -117,3 +220,45 @@ impl NetworkBehaviour for Ping {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn quality_changes_are_queued() {
+        let quality = QualityConfig::new().with_smoothing(1.0).with_max_rtt(Duration::from_millis(100));
+        let mut ping = Ping::new(PingConfig::new().with_quality(quality));
+        let peer = PeerId::random();
+        let connection = ConnectionId::new(0);
+        let endpoint = ConnectedPoint::Dialer { address: Multiaddr::empty() };
+        ping.inject_connection_established(&peer, &connection, &endpoint);
+
+        let rtt = |ms| Ok(PingSuccess::Ping { rtt: Duration::from_millis(ms) });
+        ping.inject_event(peer.clone(), connection, rtt(10));
+        assert_eq!(ping.next_quality_event(), None);
+
+        ping.inject_event(peer.clone(), connection, rtt(200));
+        assert_eq!(ping.next_quality_event(), Some(PingQualityEvent {
+            peer: peer.clone(),
+            event: QualityEvent::LatencyDegraded { rtt: Duration::from_millis(200) },
+        }));
+        assert_eq!(ping.events.len(), 2, "Ping results are reported regardless");
+
+        // An unretrieved change is superseded by a later one.
+        ping.inject_event(peer.clone(), connection, rtt(300));
+        ping.inject_event(peer.clone(), connection, rtt(10));
+        ping.inject_event(peer.clone(), connection, rtt(200));
+        ping.inject_event(peer.clone(), connection, rtt(10));
+        assert_eq!(ping.next_quality_event(), Some(PingQualityEvent {
+            peer: peer.clone(),
+            event: QualityEvent::LatencyRecovered { rtt: Duration::from_millis(10) },
+        }));
+        assert_eq!(ping.next_quality_event(), None);
+
+        ping.inject_event(peer.clone(), connection, rtt(200));
+        ping.inject_disconnected(&peer);
+        assert_eq!(ping.next_quality_event(), None);
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Connection quality statistics derived from outbound pings.
+
+use std::{collections::VecDeque, num::NonZeroUsize, time::Duration};
+
+/// The configuration of the connection quality monitor.
+#[derive(Clone, Debug)]
+pub struct QualityConfig {
+    /// The number of most recent outbound pings over which the minimum,
+    /// maximum, jitter and loss rate are computed.
+    window: NonZeroUsize,
+    /// The weight of a new round-trip time in the moving average.
+    smoothing: f64,
+    /// The smoothed round-trip time above which the latency of a peer
+    /// is considered degraded.
+    max_rtt: Option<Duration>,
+    /// The loss rate above which a peer is considered degraded.
+    max_loss_rate: Option<f64>,
+}
+
+impl QualityConfig {
+    /// Creates a new `QualityConfig` with the following default settings:
+    ///
+    ///   * [`QualityConfig::with_window`] 10
+    ///   * [`QualityConfig::with_smoothing`] 0.125
+    ///   * No latency or loss thresholds.
+    pub fn new() -> Self {
+        QualityConfig {
+            window: NonZeroUsize::new(10).expect("10 != 0"),
+            smoothing: 0.125,
+            max_rtt: None,
+            max_loss_rate: None,
+        }
+    }
+
+    /// Sets the number of most recent outbound pings over which the minimum and
+    /// maximum round-trip time, the jitter and the loss rate are computed.
+    pub fn with_window(mut self, n: NonZeroUsize) -> Self {
+        self.window = n;
+        self
+    }
+
+    /// Sets the weight of every new round-trip time in the exponentially
+    /// weighted moving average.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alpha` is not within `(0, 1]`.
+    pub fn with_smoothing(mut self, alpha: f64) -> Self {
+        assert!(alpha > 0.0 && alpha <= 1.0, "smoothing factor must be within (0, 1]");
+        self.smoothing = alpha;
+        self
+    }
+
+    /// Sets the smoothed round-trip time above which a
+    /// [`QualityEvent::LatencyDegraded`] is reported for a peer.
+    pub fn with_max_rtt(mut self, d: Duration) -> Self {
+        self.max_rtt = Some(d);
+        self
+    }
+
+    /// Sets the loss rate above which a [`QualityEvent::LossDegraded`]
+    /// is reported for a peer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not within `[0, 1]`.
+    pub fn with_max_loss_rate(mut self, rate: f64) -> Self {
+        assert!((0.0 ..= 1.0).contains(&rate), "loss rate must be within [0, 1]");
+        self.max_loss_rate = Some(rate);
+        self
+    }
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A change in the quality of the connections to a peer, reported whenever
+/// one of the thresholds of the [`QualityConfig`] is crossed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QualityEvent {
+    /// The smoothed round-trip time exceeded the configured maximum.
+    LatencyDegraded { rtt: Duration },
+    /// The smoothed round-trip time is back within the configured maximum.
+    LatencyRecovered { rtt: Duration },
+    /// The loss rate exceeded the configured maximum.
+    LossDegraded { loss_rate: f64 },
+    /// The loss rate is back within the configured maximum.
+    LossRecovered { loss_rate: f64 },
+}
+
+impl QualityEvent {
+    /// Whether the event concerns the latency rather than the loss rate.
+    pub(crate) fn is_latency(&self) -> bool {
+        matches!(self, QualityEvent::LatencyDegraded { .. } | QualityEvent::LatencyRecovered { .. })
+    }
+}
+
+/// Round-trip time statistics of a connection or of all connections to a peer.
+#[derive(Debug, Clone)]
+pub struct QualityStats {
+    /// The exponentially weighted moving average of all round-trip times.
+    ewma_rtt: Option<Duration>,
+    /// The outcome of the most recent outbound pings, `None` being a failure.
+    samples: VecDeque<Option<Duration>>,
+    /// The maximum length of `samples`.
+    window: usize,
+    /// The weight of a new round-trip time in `ewma_rtt`.
+    smoothing: f64,
+}
+
+impl QualityStats {
+    pub(crate) fn new(config: &QualityConfig) -> Self {
+        QualityStats {
+            ewma_rtt: None,
+            samples: VecDeque::with_capacity(config.window.get()),
+            window: config.window.get(),
+            smoothing: config.smoothing,
+        }
+    }
+
+    /// Records the round-trip time of a successful outbound ping.
+    pub(crate) fn record_success(&mut self, rtt: Duration) {
+        self.ewma_rtt = Some(match self.ewma_rtt {
+            Some(avg) => Duration::from_secs_f64(
+                avg.as_secs_f64() * (1.0 - self.smoothing) + rtt.as_secs_f64() * self.smoothing),
+            None => rtt,
+        });
+        self.push(Some(rtt))
+    }
+
+    /// Records a failed outbound ping.
+    pub(crate) fn record_failure(&mut self) {
+        self.push(None)
+    }
+
+    fn push(&mut self, sample: Option<Duration>) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample)
+    }
+
+    fn rtts(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.samples.iter().filter_map(|s| *s)
+    }
+
+    /// The exponentially weighted moving average of all round-trip times,
+    /// if any ping succeeded so far.
+    pub fn ewma_rtt(&self) -> Option<Duration> {
+        self.ewma_rtt
+    }
+
+    /// The minimum round-trip time within the window.
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.rtts().min()
+    }
+
+    /// The maximum round-trip time within the window.
+    pub fn max_rtt(&self) -> Option<Duration> {
+        self.rtts().max()
+    }
+
+    /// The mean absolute difference between consecutive round-trip times
+    /// within the window, if there are at least two of them.
+    pub fn jitter(&self) -> Option<Duration> {
+        let rtts = self.rtts().collect::<Vec<_>>();
+        if rtts.len() < 2 {
+            return None
+        }
+        let total = rtts.windows(2)
+            .map(|w| w[0].checked_sub(w[1]).unwrap_or_else(|| w[1] - w[0]))
+            .sum::<Duration>();
+        Some(total / (rtts.len() - 1) as u32)
+    }
+
+    /// The fraction of failed pings within the window, if any ping was sent.
+    pub fn loss_rate(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None
+        }
+        let lost = self.samples.iter().filter(|s| s.is_none()).count();
+        Some(lost as f64 / self.samples.len() as f64)
+    }
+
+    /// The number of pings within the window.
+    pub fn num_samples(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+/// The statistics of a peer together with its current threshold states.
+pub(crate) struct PeerQuality {
+    pub(crate) stats: QualityStats,
+    latency_degraded: bool,
+    loss_degraded: bool,
+}
+
+impl PeerQuality {
+    pub(crate) fn new(config: &QualityConfig) -> Self {
+        PeerQuality {
+            stats: QualityStats::new(config),
+            latency_degraded: false,
+            loss_degraded: false,
+        }
+    }
+
+    /// Checks the statistics against the configured thresholds, returning
+    /// the thresholds crossed since the last check.
+    pub(crate) fn check(&mut self, config: &QualityConfig) -> Vec<QualityEvent> {
+        let mut events = Vec::new();
+
+        if let (Some(max), Some(rtt)) = (config.max_rtt, self.stats.ewma_rtt()) {
+            let degraded = rtt > max;
+            if degraded != self.latency_degraded {
+                self.latency_degraded = degraded;
+                events.push(if degraded {
+                    QualityEvent::LatencyDegraded { rtt }
+                } else {
+                    QualityEvent::LatencyRecovered { rtt }
+                })
+            }
+        }
+
+        if let (Some(max), Some(loss_rate)) = (config.max_loss_rate, self.stats.loss_rate()) {
+            let degraded = loss_rate > max;
+            if degraded != self.loss_degraded {
+                self.loss_degraded = degraded;
+                events.push(if degraded {
+                    QualityEvent::LossDegraded { loss_rate }
+                } else {
+                    QualityEvent::LossRecovered { loss_rate }
+                })
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn stats_over_window() {
+        let config = QualityConfig::new().with_window(NonZeroUsize::new(4).unwrap());
+        let mut stats = QualityStats::new(&config);
+        assert_eq!(stats.loss_rate(), None);
+        assert_eq!(stats.jitter(), None);
+
+        stats.record_success(ms(100));
+        stats.record_success(ms(40));
+        stats.record_failure();
+        stats.record_success(ms(60));
+        assert_eq!(stats.min_rtt(), Some(ms(40)));
+        assert_eq!(stats.max_rtt(), Some(ms(100)));
+        assert_eq!(stats.jitter(), Some(ms(40)));
+        assert_eq!(stats.loss_rate(), Some(0.25));
+
+        // The first sample leaves the window.
+        stats.record_success(ms(50));
+        assert_eq!(stats.num_samples(), 4);
+        assert_eq!(stats.max_rtt(), Some(ms(60)));
+        assert_eq!(stats.jitter(), Some(ms(15)));
+    }
+
+    #[test]
+    fn ewma_follows_samples() {
+        let config = QualityConfig::new().with_smoothing(0.5);
+        let mut stats = QualityStats::new(&config);
+        stats.record_success(ms(100));
+        assert_eq!(stats.ewma_rtt(), Some(ms(100)));
+        stats.record_success(ms(200));
+        assert_eq!(stats.ewma_rtt(), Some(ms(150)));
+        stats.record_failure();
+        assert_eq!(stats.ewma_rtt(), Some(ms(150)));
+    }
+
+    #[test]
+    fn thresholds_are_reported_on_crossing() {
+        let config = QualityConfig::new()
+            .with_window(NonZeroUsize::new(2).unwrap())
+            .with_smoothing(1.0)
+            .with_max_rtt(ms(100))
+            .with_max_loss_rate(0.5);
+        let mut peer = PeerQuality::new(&config);
+
+        peer.stats.record_success(ms(50));
+        assert!(peer.check(&config).is_empty());
+
+        peer.stats.record_success(ms(150));
+        assert_eq!(peer.check(&config), vec![QualityEvent::LatencyDegraded { rtt: ms(150) }]);
+        peer.stats.record_success(ms(200));
+        assert!(peer.check(&config).is_empty());
+
+        peer.stats.record_failure();
+        peer.stats.record_failure();
+        assert_eq!(peer.check(&config), vec![QualityEvent::LossDegraded { loss_rate: 1.0 }]);
+
+        peer.stats.record_success(ms(20));
+        assert_eq!(peer.check(&config), vec![
+            QualityEvent::LatencyRecovered { rtt: ms(20) },
+            QualityEvent::LossRecovered { loss_rate: 0.5 },
+        ]);
+    }
+}
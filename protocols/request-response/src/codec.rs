@@ -18,7 +18,34 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+//! The [`RequestResponseCodec`] trait and ready-made implementations.
+//!
+//! [`LengthPrefixedCodec`] exchanges raw bytes, prefixed with their length as
+//! an unsigned varint. [`FormatCodec`] builds on it to exchange typed messages
+//! in any encoding implementing [`Format`]. The following encodings are
+//! available with the corresponding cargo features:
+//!
+//!   * `cbor`: [`CborCodec`](cbor::CborCodec) for types implementing `serde`'s
+//!     `Serialize` and `Deserialize`, encoded as CBOR.
+//!   * `json`: [`JsonCodec`](json::JsonCodec) for types implementing `serde`'s
+//!     `Serialize` and `Deserialize`, encoded as JSON.
+//!   * `protobuf`: [`ProtobufCodec`](protobuf::ProtobufCodec) for types
+//!     implementing `prost`'s `Message`.
+//!
+//! All of them reject requests and responses exceeding a configurable size.
+
+#[cfg(feature = "cbor")]
+pub mod cbor;
+pub mod format;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod length_prefixed;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+
 pub use libp2p_core::ProtocolName;
+pub use format::{Format, FormatCodec};
+pub use length_prefixed::LengthPrefixedCodec;
 
 use async_trait::async_trait;
 use futures::prelude::*;
@@ -45,6 +72,11 @@ pub trait RequestResponseCodec {
 
     /// Reads a response from the given I/O stream according to the
     /// negotiated protocol.
+    ///
+    /// This is also used to read every chunk of a streamed response,
+    /// whose end is signalled separately by the behaviour. A response
+    /// must not be empty, since an I/O stream closed without any data
+    /// signals that the remote rejected the request.
     async fn read_response<T>(&mut self, protocol: &Self::Protocol, io: &mut T)
         -> io::Result<Self::Response>
     where
@@ -59,6 +91,9 @@ pub trait RequestResponseCodec {
 
     /// Writes a response to the given I/O stream according to the
     /// negotiated protocol.
+    ///
+    /// This is also used to write every chunk of a streamed response,
+    /// so the I/O stream must not be closed after writing.
     async fn write_response<T>(&mut self, protocol: &Self::Protocol, io: &mut T, res: Self::Response)
         -> io::Result<()>
     where
@@ -0,0 +1,70 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The CBOR encoding for requests and responses.
+
+use serde::{Serialize, de::DeserializeOwned};
+use super::format::{Format, FormatCodec};
+
+/// A [`RequestResponseCodec`](super::RequestResponseCodec) for requests and
+/// responses implementing `serde`'s `Serialize` and `Deserialize`, encoded
+/// as CBOR.
+pub type CborCodec<P, Req, Resp> = FormatCodec<Cbor, P, Req, Resp>;
+
+/// The CBOR [`Format`].
+#[derive(Debug, Clone, Copy)]
+pub enum Cbor {}
+
+impl<T> Format<T> for Cbor
+where
+    T: Serialize + DeserializeOwned
+{
+    type Error = serde_cbor::Error;
+
+    fn encode(value: &T) -> Result<Vec<u8>, Self::Error> {
+        serde_cbor::to_vec(value)
+    }
+
+    fn decode(data: &[u8]) -> Result<T, Self::Error> {
+        serde_cbor::from_slice(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::format::tests::{format_tests, request};
+
+    format_tests!(Cbor);
+
+    #[test]
+    fn encoding() {
+        let data: Vec<u8> = Cbor::encode(&request()).unwrap();
+        // A map of two entries with text keys.
+        let mut expected = vec![0xa2, 0x63];
+        expected.extend_from_slice(b"key");
+        expected.push(0x63);
+        expected.extend_from_slice(b"key");
+        expected.push(0x65);
+        expected.extend_from_slice(b"limit");
+        expected.push(10);
+        assert_eq!(data, expected);
+    }
+}
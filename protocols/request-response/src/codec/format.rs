@@ -0,0 +1,264 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A codec for requests and responses in a given encoding, such as CBOR,
+//! JSON or protocol buffers.
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p_core::ProtocolName;
+use std::{error, fmt, io, marker::PhantomData};
+use super::{LengthPrefixedCodec, RequestResponseCodec};
+
+/// An encoding of values of type `T` for a [`FormatCodec`].
+pub trait Format<T> {
+    /// The error of encoding or decoding a value.
+    type Error: error::Error + Send + Sync + 'static;
+
+    /// Encodes a value.
+    fn encode(value: &T) -> Result<Vec<u8>, Self::Error>;
+
+    /// Decodes a value.
+    fn decode(data: &[u8]) -> Result<T, Self::Error>;
+}
+
+/// A [`RequestResponseCodec`] for requests and responses encoded
+/// according to the [`Format`] `F`.
+///
+/// Every request and response is prefixed with its length as an unsigned
+/// varint, see [`LengthPrefixedCodec`] for the default size limits.
+pub struct FormatCodec<F, P, Req, Resp> {
+    inner: LengthPrefixedCodec<P>,
+    _format: PhantomData<fn() -> F>,
+    _messages: PhantomData<fn() -> (Req, Resp)>
+}
+
+impl<F, P, Req, Resp> FormatCodec<F, P, Req, Resp> {
+    /// Creates a new `FormatCodec` with the default size limits.
+    pub fn new() -> Self {
+        FormatCodec {
+            inner: LengthPrefixedCodec::new(),
+            _format: PhantomData,
+            _messages: PhantomData
+        }
+    }
+
+    /// Sets the maximum size of an encoded request in bytes.
+    pub fn set_max_request_size(&mut self, v: usize) -> &mut Self {
+        self.inner.set_max_request_size(v);
+        self
+    }
+
+    /// Sets the maximum size of an encoded response in bytes.
+    pub fn set_max_response_size(&mut self, v: usize) -> &mut Self {
+        self.inner.set_max_response_size(v);
+        self
+    }
+}
+
+impl<F, P, Req, Resp> Default for FormatCodec<F, P, Req, Resp> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F, P, Req, Resp> Clone for FormatCodec<F, P, Req, Resp> {
+    fn clone(&self) -> Self {
+        FormatCodec {
+            inner: self.inner.clone(),
+            _format: PhantomData,
+            _messages: PhantomData
+        }
+    }
+}
+
+impl<F, P: fmt::Debug, Req, Resp> fmt::Debug for FormatCodec<F, P, Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FormatCodec").field(&self.inner).finish()
+    }
+}
+
+#[async_trait]
+impl<F, P, Req, Resp> RequestResponseCodec for FormatCodec<F, P, Req, Resp>
+where
+    F: Format<Req> + Format<Resp>,
+    P: ProtocolName + Send + Sync + Clone,
+    Req: Send,
+    Resp: Send
+{
+    type Protocol = P;
+    type Request = Req;
+    type Response = Resp;
+
+    async fn read_request<T>(&mut self, _: &P, io: &mut T) -> io::Result<Req>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        let data = self.inner.read_request_bytes(io).await?;
+        <F as Format<Req>>::decode(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    async fn read_response<T>(&mut self, _: &P, io: &mut T) -> io::Result<Resp>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        let data = self.inner.read_response_bytes(io).await?;
+        <F as Format<Resp>>::decode(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    async fn write_request<T>(&mut self, _: &P, io: &mut T, req: Req) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        let data = <F as Format<Req>>::encode(&req)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.inner.write_request_bytes(io, &data).await
+    }
+
+    async fn write_response<T>(&mut self, _: &P, io: &mut T, res: Resp) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        let data = <F as Format<Resp>>::encode(&res)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.inner.write_response_bytes(io, &data).await
+    }
+}
+
+/// The tests shared by all formats, instantiated per format with
+/// `format_tests!` for a request and a response that take up at least
+/// two bytes when encoded.
+#[cfg(all(test, any(feature = "cbor", feature = "json", feature = "protobuf")))]
+pub(crate) mod tests {
+    use super::*;
+    use futures::{executor::block_on, io::Cursor};
+    use std::fmt::Debug;
+
+    const PROTOCOL: &[u8] = b"/test/1";
+
+    /// The request shared by the tests of the `serde` formats.
+    #[cfg(any(feature = "cbor", feature = "json"))]
+    #[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    pub(crate) struct Request {
+        pub(crate) key: String,
+        pub(crate) limit: u32
+    }
+
+    /// The response shared by the tests of the `serde` formats.
+    #[cfg(any(feature = "cbor", feature = "json"))]
+    #[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    pub(crate) enum Response {
+        Found(Vec<u8>),
+        NotFound
+    }
+
+    #[cfg(any(feature = "cbor", feature = "json"))]
+    pub(crate) fn request() -> Request {
+        Request { key: "key".into(), limit: 10 }
+    }
+
+    #[cfg(any(feature = "cbor", feature = "json"))]
+    pub(crate) fn response() -> Response {
+        Response::Found(vec![1, 2, 3])
+    }
+
+    pub(crate) fn roundtrip<F, Req, Resp>(request: Req, response: Resp)
+    where
+        F: Format<Req> + Format<Resp>,
+        Req: Clone + Debug + PartialEq + Send,
+        Resp: Clone + Debug + PartialEq + Send
+    {
+        let mut codec = FormatCodec::<F, _, Req, Resp>::new();
+        let mut io = Cursor::new(Vec::new());
+        block_on(codec.write_request(&PROTOCOL, &mut io, request.clone())).unwrap();
+        block_on(codec.write_response(&PROTOCOL, &mut io, response.clone())).unwrap();
+
+        io.set_position(0);
+        assert_eq!(block_on(codec.read_request(&PROTOCOL, &mut io)).unwrap(), request);
+        assert_eq!(block_on(codec.read_response(&PROTOCOL, &mut io)).unwrap(), response);
+    }
+
+    pub(crate) fn size_limits<F, Req, Resp>(request: Req, response: Resp)
+    where
+        F: Format<Req> + Format<Resp>,
+        Req: Clone + Debug + Send,
+        Resp: Send
+    {
+        let mut codec = FormatCodec::<F, _, Req, Resp>::new();
+        codec.set_max_request_size(1).set_max_response_size(1);
+        let mut io = Cursor::new(Vec::new());
+        let err = block_on(codec.write_request(&PROTOCOL, &mut io, request.clone())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = block_on(codec.write_response(&PROTOCOL, &mut io, response)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(io.get_ref().is_empty());
+
+        // A request that is written but too large to be read.
+        block_on(FormatCodec::<F, _, Req, Resp>::new().write_request(&PROTOCOL, &mut io, request)).unwrap();
+        io.set_position(0);
+        let err = block_on(codec.read_request(&PROTOCOL, &mut io)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    pub(crate) fn invalid_data<F, Req, Resp>()
+    where
+        F: Format<Req> + Format<Resp>,
+        Req: Send,
+        Resp: Debug + Send
+    {
+        let mut codec = FormatCodec::<F, _, Req, Resp>::new();
+        let mut io = Cursor::new(Vec::new());
+        block_on(LengthPrefixedCodec::new().write_response(&PROTOCOL, &mut io, vec![0xff; 3])).unwrap();
+        io.set_position(0);
+        let err = block_on(codec.read_response(&PROTOCOL, &mut io)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Instantiates the shared tests for a format, given the request
+    /// and response types and a value of each, or with the [`Request`]
+    /// and [`Response`] of the `serde` formats if only the format is given.
+    macro_rules! format_tests {
+        ($format:ty) => {
+            format_tests!($format,
+                crate::codec::format::tests::Request,
+                crate::codec::format::tests::Response,
+                crate::codec::format::tests::request(),
+                crate::codec::format::tests::response());
+        };
+        ($format:ty, $req:ty, $resp:ty, $request:expr, $response:expr) => {
+            #[test]
+            fn roundtrip() {
+                crate::codec::format::tests::roundtrip::<$format, $req, $resp>($request, $response)
+            }
+
+            #[test]
+            fn size_limits() {
+                crate::codec::format::tests::size_limits::<$format, $req, $resp>($request, $response)
+            }
+
+            #[test]
+            fn invalid_data() {
+                crate::codec::format::tests::invalid_data::<$format, $req, $resp>()
+            }
+        }
+    }
+
+    pub(crate) use format_tests;
+}
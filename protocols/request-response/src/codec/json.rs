@@ -0,0 +1,62 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The JSON encoding for requests and responses.
+
+use serde::{Serialize, de::DeserializeOwned};
+use super::format::{Format, FormatCodec};
+
+/// A [`RequestResponseCodec`](super::RequestResponseCodec) for requests and
+/// responses implementing `serde`'s `Serialize` and `Deserialize`, encoded
+/// as JSON.
+pub type JsonCodec<P, Req, Resp> = FormatCodec<Json, P, Req, Resp>;
+
+/// The JSON [`Format`].
+#[derive(Debug, Clone, Copy)]
+pub enum Json {}
+
+impl<T> Format<T> for Json
+where
+    T: Serialize + DeserializeOwned
+{
+    type Error = serde_json::Error;
+
+    fn encode(value: &T) -> Result<Vec<u8>, Self::Error> {
+        serde_json::to_vec(value)
+    }
+
+    fn decode(data: &[u8]) -> Result<T, Self::Error> {
+        serde_json::from_slice(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::format::tests::{format_tests, request};
+
+    format_tests!(Json);
+
+    #[test]
+    fn encoding() {
+        let data: Vec<u8> = Json::encode(&request()).unwrap();
+        assert_eq!(data, br#"{"key":"key","limit":10}"#.to_vec());
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A codec for requests and responses of raw bytes.
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p_core::ProtocolName;
+use std::{io, marker::PhantomData};
+use super::RequestResponseCodec;
+use unsigned_varint::{aio, io::ReadError};
+
+/// The default maximum size of a request in bytes.
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// The default maximum size of a response in bytes.
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 10 * 1024 * 1024;
+
+/// A [`RequestResponseCodec`] for requests and responses of raw bytes,
+/// each prefixed with its length as an unsigned varint.
+///
+/// Requests and responses exceeding the configured maximum size are
+/// neither read nor written and result in an error.
+#[derive(Debug)]
+pub struct LengthPrefixedCodec<P> {
+    max_request_size: usize,
+    max_response_size: usize,
+    _protocol: PhantomData<fn() -> P>
+}
+
+impl<P> LengthPrefixedCodec<P> {
+    /// Creates a new `LengthPrefixedCodec` with a maximum request size of
+    /// [`DEFAULT_MAX_REQUEST_SIZE`] and a maximum response size of
+    /// [`DEFAULT_MAX_RESPONSE_SIZE`].
+    pub fn new() -> Self {
+        LengthPrefixedCodec {
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            _protocol: PhantomData
+        }
+    }
+
+    /// Sets the maximum size of a request in bytes.
+    pub fn set_max_request_size(&mut self, v: usize) -> &mut Self {
+        self.max_request_size = v;
+        self
+    }
+
+    /// Sets the maximum size of a response in bytes.
+    pub fn set_max_response_size(&mut self, v: usize) -> &mut Self {
+        self.max_response_size = v;
+        self
+    }
+
+    /// Reads a request, failing if it exceeds the maximum request size.
+    pub(crate) async fn read_request_bytes<T>(&self, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        read_length_prefixed(io, self.max_request_size).await
+    }
+
+    /// Reads a response, failing if it exceeds the maximum response size.
+    pub(crate) async fn read_response_bytes<T>(&self, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        read_length_prefixed(io, self.max_response_size).await
+    }
+
+    /// Writes a request, failing if it exceeds the maximum request size.
+    pub(crate) async fn write_request_bytes<T>(&self, io: &mut T, data: &[u8]) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        write_length_prefixed(io, data, self.max_request_size).await
+    }
+
+    /// Writes a response, failing if it exceeds the maximum response size.
+    pub(crate) async fn write_response_bytes<T>(&self, io: &mut T, data: &[u8]) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        write_length_prefixed(io, data, self.max_response_size).await
+    }
+}
+
+impl<P> Default for LengthPrefixedCodec<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P> Clone for LengthPrefixedCodec<P> {
+    fn clone(&self) -> Self {
+        LengthPrefixedCodec {
+            max_request_size: self.max_request_size,
+            max_response_size: self.max_response_size,
+            _protocol: PhantomData
+        }
+    }
+}
+
+#[async_trait]
+impl<P> RequestResponseCodec for LengthPrefixedCodec<P>
+where
+    P: ProtocolName + Send + Sync + Clone
+{
+    type Protocol = P;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(&mut self, _: &P, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        self.read_request_bytes(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &P, io: &mut T) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send
+    {
+        self.read_response_bytes(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &P, io: &mut T, req: Self::Request) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        self.write_request_bytes(io, &req).await
+    }
+
+    async fn write_response<T>(&mut self, _: &P, io: &mut T, res: Self::Response) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+    {
+        self.write_response_bytes(io, &res).await
+    }
+}
+
+/// Reads data prefixed with its length, failing if it exceeds `max_size` bytes.
+async fn read_length_prefixed<T>(io: &mut T, max_size: usize) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin + Send
+{
+    let len = aio::read_usize(&mut *io).await
+        .map_err(|e| match e {
+            ReadError::Io(e) => e,
+            other => io::Error::new(io::ErrorKind::InvalidData, other)
+        })?;
+    if len > max_size {
+        let msg = format!("message of {} bytes exceeds the maximum of {} bytes", len, max_size);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+    }
+    let mut buf = vec![0; len];
+    io.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Writes data prefixed with its length, failing if it exceeds `max_size` bytes.
+async fn write_length_prefixed<T>(io: &mut T, data: &[u8], max_size: usize) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send
+{
+    if data.len() > max_size {
+        let msg = format!("message of {} bytes exceeds the maximum of {} bytes", data.len(), max_size);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+    }
+    let mut b = unsigned_varint::encode::usize_buffer();
+    io.write_all(unsigned_varint::encode::usize(data.len(), &mut b)).await?;
+    io.write_all(data).await?;
+    io.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, io::Cursor};
+
+    const PROTOCOL: &[u8] = b"/test/1";
+
+    #[test]
+    fn roundtrip() {
+        let mut codec = LengthPrefixedCodec::new();
+        let mut io = Cursor::new(Vec::new());
+        block_on(codec.write_request(&PROTOCOL, &mut io, b"request".to_vec())).unwrap();
+        block_on(codec.write_response(&PROTOCOL, &mut io, Vec::new())).unwrap();
+
+        io.set_position(0);
+        assert_eq!(block_on(codec.read_request(&PROTOCOL, &mut io)).unwrap(), b"request");
+        assert_eq!(block_on(codec.read_response(&PROTOCOL, &mut io)).unwrap(), b"");
+        let eof = block_on(codec.read_response(&PROTOCOL, &mut io)).unwrap_err();
+        assert_eq!(eof.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn size_limits() {
+        let mut codec = LengthPrefixedCodec::new();
+        codec.set_max_request_size(4).set_max_response_size(8);
+        let mut io = Cursor::new(Vec::new());
+        let err = block_on(codec.write_request(&PROTOCOL, &mut io, vec![0; 5])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(io.get_ref().is_empty());
+
+        // A response that is written but too large to be read as a request.
+        block_on(codec.write_response(&PROTOCOL, &mut io, vec![0; 8])).unwrap();
+        io.set_position(0);
+        let err = block_on(codec.read_request(&PROTOCOL, &mut io)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
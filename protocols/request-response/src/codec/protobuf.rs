@@ -0,0 +1,71 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The protocol buffers encoding for requests and responses.
+
+use prost::{DecodeError, Message};
+use super::format::{Format, FormatCodec};
+
+/// A [`RequestResponseCodec`](super::RequestResponseCodec) for requests and
+/// responses implementing `prost`'s `Message`, encoded as protocol buffers.
+pub type ProtobufCodec<P, Req, Resp> = FormatCodec<Protobuf, P, Req, Resp>;
+
+/// The protocol buffers [`Format`].
+#[derive(Debug, Clone, Copy)]
+pub enum Protobuf {}
+
+impl<T> Format<T> for Protobuf
+where
+    T: Message + Default
+{
+    type Error = DecodeError;
+
+    fn encode(value: &T) -> Result<Vec<u8>, Self::Error> {
+        let mut data = Vec::with_capacity(value.encoded_len());
+        value.encode(&mut data).expect("Vec<u8> provides capacity as needed");
+        Ok(data)
+    }
+
+    fn decode(data: &[u8]) -> Result<T, Self::Error> {
+        T::decode(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::format::tests::format_tests;
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Request {
+        #[prost(string, tag = "1")]
+        key: String,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Response {
+        #[prost(bytes, repeated, tag = "1")]
+        values: Vec<Vec<u8>>,
+    }
+
+    format_tests!(Protobuf, Request, Response,
+        Request { key: "key".into() },
+        Response { values: vec![vec![1, 2], Vec::new()] });
+}
@@ -20,14 +20,24 @@
 
 mod protocol;
 
-use crate::{EMPTY_QUEUE_SHRINK_THRESHOLD, RequestId};
+use crate::{EMPTY_QUEUE_SHRINK_THRESHOLD, RequestId, RequestResponseConfig};
 use crate::codec::RequestResponseCodec;
+use protocol::{Limited, response_too_large, write_message};
 
-pub use protocol::{RequestProtocol, ResponseProtocol, ProtocolSupport};
+pub use protocol::{
+    InboundOutcome,
+    RequestOutcome,
+    RequestProtocol,
+    ResponseMessage,
+    ResponseProtocol,
+    ResponseSink,
+    ResponseStream,
+    ProtocolSupport
+};
 
 use futures::{
-    channel::oneshot,
-    future::BoxFuture,
+    channel::{mpsc, oneshot},
+    future::{self, AbortHandle, Abortable, Aborted, BoxFuture, Either},
     prelude::*,
     stream::FuturesUnordered
 };
@@ -45,13 +55,24 @@ use libp2p_swarm::{
 };
 use smallvec::SmallVec;
 use std::{
-    collections::VecDeque,
+    cmp,
+    collections::{HashMap, HashSet, VecDeque},
     io,
     sync::{atomic::{AtomicU64, Ordering}, Arc},
     time::Duration,
     task::{Context, Poll}
 };
-use wasm_timer::Instant;
+use wasm_timer::{Delay, Instant};
+
+/// The outcome of reading the next chunk of a streamed outbound response,
+/// unless the request has been cancelled.
+type ChunkRead<TCodec> = (
+    RequestId,
+    Result<
+        Result<Option<(<TCodec as RequestResponseCodec>::Response, ResponseStream<TCodec>)>, StreamError>,
+        Aborted
+    >
+);
 
 /// A connection handler of a `RequestResponse` protocol.
 #[doc(hidden)]
@@ -69,6 +90,19 @@ where
     /// The timeout for inbound and outbound substreams (i.e. request
     /// and response processing).
     substream_timeout: Duration,
+    /// The timeout for every chunk of a streamed response after the first.
+    chunk_timeout: Duration,
+    /// The timeout for all chunks of a streamed response.
+    stream_timeout: Duration,
+    /// The maximum number of inbound requests in progress.
+    max_inbound_requests: Option<usize>,
+    /// The maximum size in bytes of inbound requests.
+    max_request_size: usize,
+    /// The maximum size in bytes of responses, sent or received.
+    max_response_size: usize,
+    /// The number of chunks of a streamed response to an inbound
+    /// request that may be buffered before being written.
+    chunk_buffer: usize,
     /// The current connection keep-alive.
     keep_alive: KeepAlive,
     /// A pending fatal error that results in the connection being closed.
@@ -80,9 +114,22 @@ where
     /// Inbound upgrades waiting for the incoming request.
     inbound: FuturesUnordered<BoxFuture<'static,
         Result<
-            ((RequestId, TCodec::Request), oneshot::Sender<TCodec::Response>),
+            ((RequestId, TCodec::Request, bool), mpsc::Sender<ResponseMessage<TCodec::Response>>),
             oneshot::Canceled
         >>>,
+    /// Streamed responses to outbound requests, reading their next chunk.
+    outbound_streams: FuturesUnordered<BoxFuture<'static, ChunkRead<TCodec>>>,
+    /// Streamed responses to inbound requests, writing their remaining chunks.
+    inbound_streams: FuturesUnordered<BoxFuture<'static, (RequestId, Result<(), StreamError>)>>,
+    /// Inbound requests that have been received and whose
+    /// response is yet to be sent.
+    inbound_active: HashSet<RequestId>,
+    /// Handles for aborting the upgrades and streamed responses of
+    /// outbound requests in progress.
+    aborts: HashMap<RequestId, AbortHandle>,
+    /// Outbound requests that have been aborted but whose outcome is
+    /// yet to be reported, which is then discarded.
+    cancelled: HashSet<RequestId>,
     inbound_request_id: Arc<AtomicU64>
 }
 
@@ -93,23 +140,152 @@ where
     pub(super) fn new(
         inbound_protocols: SmallVec<[TCodec::Protocol; 2]>,
         codec: TCodec,
-        keep_alive_timeout: Duration,
-        substream_timeout: Duration,
+        config: &RequestResponseConfig,
         inbound_request_id: Arc<AtomicU64>
     ) -> Self {
         Self {
             inbound_protocols,
             codec,
             keep_alive: KeepAlive::Yes,
-            keep_alive_timeout,
-            substream_timeout,
+            keep_alive_timeout: config.connection_keep_alive,
+            substream_timeout: config.request_timeout,
+            chunk_timeout: config.chunk_timeout,
+            stream_timeout: config.stream_timeout,
+            max_inbound_requests: config.max_inbound_requests_per_connection,
+            max_request_size: config.max_request_size.unwrap_or(usize::MAX),
+            max_response_size: config.max_response_size.unwrap_or(usize::MAX),
+            chunk_buffer: config.chunk_buffer,
             outbound: VecDeque::new(),
             inbound: FuturesUnordered::new(),
+            outbound_streams: FuturesUnordered::new(),
+            inbound_streams: FuturesUnordered::new(),
+            inbound_active: HashSet::new(),
+            aborts: HashMap::new(),
+            cancelled: HashSet::new(),
             pending_events: VecDeque::new(),
             pending_error: None,
             inbound_request_id
         }
     }
+
+    /// The timeout for the next chunk of a stream that started at the given instant.
+    fn next_chunk_timeout(&self, started: Instant) -> Duration {
+        let remaining = (started + self.stream_timeout)
+            .checked_duration_since(Instant::now())
+            .unwrap_or_default();
+        cmp::min(self.chunk_timeout, remaining)
+    }
+
+    /// Starts reading the next chunk of a streamed outbound response.
+    fn read_next_chunk(&mut self, request_id: RequestId, mut stream: ResponseStream<TCodec>)
+    where
+        TCodec: Send + 'static
+    {
+        let timeout = self.next_chunk_timeout(stream.started);
+        let (handle, registration) = AbortHandle::new_pair();
+        self.aborts.insert(request_id, handle);
+        let read = async move {
+            let read = match future::select(stream.next_chunk().boxed(), Delay::new(timeout)).await {
+                Either::Left((result, _)) => Some(result),
+                Either::Right(_) => None
+            };
+            match read {
+                Some(Ok(Some(chunk))) => Ok(Some((chunk, stream))),
+                Some(Ok(None)) => Ok(None),
+                Some(Err(_)) if stream.exceeded => Err(StreamError::TooLarge),
+                Some(Err(e)) => Err(StreamError::Io(e)),
+                None => Err(StreamError::Timeout)
+            }
+        };
+        self.outbound_streams.push(
+            Abortable::new(read, registration)
+                .map(move |result| (request_id, result))
+                .boxed());
+    }
+
+    /// Cancels an outbound request, which is either dropped if it has not
+    /// yet been sent or otherwise aborted, resetting its substream.
+    fn cancel(&mut self, request_id: RequestId) {
+        if let Some(pos) = self.outbound.iter().position(|r| r.request_id == request_id) {
+            self.outbound.remove(pos);
+        } else if let Some(handle) = self.aborts.remove(&request_id) {
+            handle.abort();
+            self.cancelled.insert(request_id);
+        }
+    }
+
+    /// Starts writing the remaining chunks of a streamed inbound response.
+    fn write_remaining_chunks(&mut self, request_id: RequestId, mut sink: ResponseSink<TCodec>)
+    where
+        TCodec: Send + 'static
+    {
+        let chunk_timeout = self.chunk_timeout;
+        let deadline = sink.started + self.stream_timeout;
+        self.inbound_streams.push(async move {
+            loop {
+                let remaining = deadline.checked_duration_since(Instant::now()).unwrap_or_default();
+                let timeout = cmp::min(chunk_timeout, remaining);
+                let ResponseSink { codec, protocol, io, response_receiver, remaining, .. } = &mut sink;
+                let step = async move {
+                    let message = response_receiver.next().await;
+                    let mut writer = Limited::new(&mut *io, *remaining);
+                    let result = write_message(codec, protocol, &mut writer, message).await;
+                    *remaining = writer.remaining();
+                    match result {
+                        Ok(true) => Ok(true),
+                        Ok(false) => io.close().await.map(|()| false).map_err(StreamError::Io),
+                        Err(_) if writer.exceeded() => Err(StreamError::TooLarge),
+                        Err(e) => Err(StreamError::Io(e))
+                    }
+                };
+                let result = match future::select(step.boxed(), Delay::new(timeout)).await {
+                    Either::Left((result, _)) => result,
+                    Either::Right(_) => Err(StreamError::Timeout)
+                };
+                match result {
+                    Ok(true) => continue,
+                    Ok(false) => return (request_id, Ok(())),
+                    Err(e) => return (request_id, Err(e))
+                }
+            }
+        }.boxed());
+    }
+}
+
+/// A failure while reading or writing the chunks of a streamed response.
+#[derive(Debug)]
+enum StreamError {
+    /// A chunk was not read or written in time.
+    Timeout,
+    /// An I/O error occurred.
+    Io(io::Error),
+    /// The size limit of a response has been exceeded while reading or writing it.
+    TooLarge,
+}
+
+/// The events received by the [`RequestResponseHandler`].
+#[doc(hidden)]
+pub enum RequestResponseHandlerIn<TCodec>
+where
+    TCodec: RequestResponseCodec
+{
+    /// Send an outbound request.
+    Request(RequestProtocol<TCodec>),
+    /// Cancel an outbound request.
+    Cancel(RequestId),
+}
+
+impl<TCodec> Clone for RequestResponseHandlerIn<TCodec>
+where
+    TCodec: RequestResponseCodec + Clone,
+    TCodec::Request: Clone
+{
+    fn clone(&self) -> Self {
+        match self {
+            RequestResponseHandlerIn::Request(r) => RequestResponseHandlerIn::Request(r.clone()),
+            RequestResponseHandlerIn::Cancel(id) => RequestResponseHandlerIn::Cancel(*id)
+        }
+    }
 }
 
 /// The events emitted by the [`RequestResponseHandler`].
@@ -123,13 +299,26 @@ where
     Request {
         request_id: RequestId,
         request: TCodec::Request,
-        sender: oneshot::Sender<TCodec::Response>
+        /// Whether the response is expected to be streamed.
+        streaming: bool,
+        sender: mpsc::Sender<ResponseMessage<TCodec::Response>>
     },
     /// An inbound response.
     Response {
         request_id: RequestId,
         response: TCodec::Response
     },
+    /// An inbound chunk of a streamed response.
+    ResponseChunk {
+        request_id: RequestId,
+        chunk: TCodec::Response
+    },
+    /// The end of an inbound streamed response.
+    ResponseEnd(RequestId),
+    /// Sending an outbound request or reading its response failed.
+    OutboundStreamError(RequestId, io::Error),
+    /// Writing an outbound streamed response failed.
+    InboundStreamError(RequestId, io::Error),
     /// An outbound upgrade (i.e. request) timed out.
     OutboundTimeout(RequestId),
     /// An outbound request failed to negotiate a mutually supported protocol.
@@ -138,13 +327,20 @@ where
     InboundTimeout(RequestId),
     /// An inbound request failed to negotiate a mutually supported protocol.
     InboundUnsupportedProtocols(RequestId),
+    /// An inbound request was rejected because the connection has
+    /// too many inbound requests in progress.
+    InboundTooManyRequests(RequestId),
+    /// An inbound request exceeded the maximum request size.
+    InboundRequestTooLarge(RequestId),
+    /// The response to an inbound request exceeded the maximum response size.
+    InboundResponseTooLarge(RequestId),
 }
 
 impl<TCodec> ProtocolsHandler for RequestResponseHandler<TCodec>
 where
     TCodec: RequestResponseCodec + Send + Clone + 'static,
 {
-    type InEvent = RequestProtocol<TCodec>;
+    type InEvent = RequestResponseHandlerIn<TCodec>;
     type OutEvent = RequestResponseHandlerEvent<TCodec>;
     type Error = ProtocolsHandlerUpgrErr<io::Error>;
     type InboundProtocol = ResponseProtocol<TCodec>;
@@ -159,10 +355,18 @@ where
 
         // A channel for notifying the inbound upgrade when the
         // response is sent.
-        let (rs_send, rs_recv) = oneshot::channel();
+        let (rs_send, rs_recv) = mpsc::channel(self.chunk_buffer);
 
         let request_id = RequestId(self.inbound_request_id.fetch_add(1, Ordering::Relaxed));
 
+        // Inbound requests are rejected while the connection has too
+        // many inbound requests in progress, i.e. requests being read
+        // or waiting for their responses to be sent.
+        let reject = match self.max_inbound_requests {
+            Some(max) => self.inbound.len() + self.inbound_active.len() >= max,
+            None => false
+        };
+
         // By keeping all I/O inside the `ResponseProtocol` and thus the
         // inbound substream upgrade via above channels, we ensure that it
         // is all subject to the configured timeout without extra bookkeeping
@@ -174,38 +378,87 @@ where
             codec: self.codec.clone(),
             request_sender: rq_send,
             response_receiver: rs_recv,
-            request_id
+            request_id,
+            max_request_size: self.max_request_size,
+            max_response_size: self.max_response_size,
+            reject
         };
 
         // The handler waits for the request to come in. It then emits
         // `RequestResponseHandlerEvent::Request` together with a
         // `ResponseChannel`.
-        self.inbound.push(rq_recv.map_ok(move |rq| (rq, rs_send)).boxed());
+        if !reject {
+            self.inbound.push(rq_recv.map_ok(move |rq| (rq, rs_send)).boxed());
+        }
 
         SubstreamProtocol::new(proto, request_id).with_timeout(self.substream_timeout)
     }
 
     fn inject_fully_negotiated_inbound(
         &mut self,
-        (): (),
-        _: RequestId
+        outcome: InboundOutcome<TCodec>,
+        request_id: RequestId
     ) {
+        let event = match outcome {
+            InboundOutcome::Done => {
+                self.inbound_active.remove(&request_id);
+                return
+            }
+            InboundOutcome::Streaming(sink) => {
+                self.keep_alive = KeepAlive::Yes;
+                self.write_remaining_chunks(request_id, sink);
+                return
+            }
+            InboundOutcome::TooManyRequests =>
+                RequestResponseHandlerEvent::InboundTooManyRequests(request_id),
+            InboundOutcome::RequestTooLarge =>
+                RequestResponseHandlerEvent::InboundRequestTooLarge(request_id),
+            InboundOutcome::ResponseTooLarge =>
+                RequestResponseHandlerEvent::InboundResponseTooLarge(request_id),
+        };
+        self.inbound_active.remove(&request_id);
+        self.pending_events.push_back(event);
     }
 
     fn inject_fully_negotiated_outbound(
         &mut self,
-        response: TCodec::Response,
+        outcome: RequestOutcome<TCodec>,
         request_id: RequestId,
     ) {
-        self.pending_events.push_back(
-            RequestResponseHandlerEvent::Response {
-                request_id, response
-            });
+        self.aborts.remove(&request_id);
+        if self.cancelled.remove(&request_id) {
+            return
+        }
+        match outcome {
+            RequestOutcome::Response(response) => {
+                self.pending_events.push_back(
+                    RequestResponseHandlerEvent::Response {
+                        request_id, response
+                    });
+            }
+            RequestOutcome::Chunk(chunk, stream) => {
+                self.keep_alive = KeepAlive::Yes;
+                self.pending_events.push_back(
+                    RequestResponseHandlerEvent::ResponseChunk {
+                        request_id, chunk
+                    });
+                self.read_next_chunk(request_id, stream);
+            }
+            RequestOutcome::End => {
+                self.pending_events.push_back(
+                    RequestResponseHandlerEvent::ResponseEnd(request_id));
+            }
+        }
     }
 
-    fn inject_event(&mut self, request: Self::InEvent) {
-        self.keep_alive = KeepAlive::Yes;
-        self.outbound.push_back(request);
+    fn inject_event(&mut self, event: Self::InEvent) {
+        match event {
+            RequestResponseHandlerIn::Request(request) => {
+                self.keep_alive = KeepAlive::Yes;
+                self.outbound.push_back(request);
+            }
+            RequestResponseHandlerIn::Cancel(request_id) => self.cancel(request_id)
+        }
     }
 
     fn inject_dial_upgrade_error(
@@ -213,6 +466,11 @@ where
         info: RequestId,
         error: ProtocolsHandlerUpgrErr<io::Error>,
     ) {
+        self.aborts.remove(&info);
+        if self.cancelled.remove(&info) {
+            // The upgrade has been aborted, which is no failure.
+            return
+        }
         match error {
             ProtocolsHandlerUpgrErr::Timeout => {
                 self.pending_events.push_back(
@@ -227,6 +485,13 @@ where
                 self.pending_events.push_back(
                     RequestResponseHandlerEvent::OutboundUnsupportedProtocols(info));
             }
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(e)) => {
+                // Sending the request or reading the response failed, e.g.
+                // because the remote rejected the request due to its limits.
+                // Only this request is affected, not the connection.
+                self.pending_events.push_back(
+                    RequestResponseHandlerEvent::OutboundStreamError(info, e));
+            }
             _ => {
                 // Anything else is considered a fatal error or misbehaviour of
                 // the remote peer and results in closing the connection.
@@ -240,6 +505,7 @@ where
         info: RequestId,
         error: ProtocolsHandlerUpgrErr<io::Error>
     ) {
+        self.inbound_active.remove(&info);
         match error {
             ProtocolsHandlerUpgrErr::Timeout => {
                 self.pending_events.push_back(RequestResponseHandlerEvent::InboundTimeout(info))
@@ -287,12 +553,15 @@ where
         // Check for inbound requests.
         while let Poll::Ready(Some(result)) = self.inbound.poll_next_unpin(cx) {
             match result {
-                Ok(((id, rq), rs_sender)) => {
+                Ok(((id, rq, streaming), rs_sender)) => {
                     // We received an inbound request.
                     self.keep_alive = KeepAlive::Yes;
+                    if !rs_sender.is_closed() {
+                        self.inbound_active.insert(id);
+                    }
                     return Poll::Ready(ProtocolsHandlerEvent::Custom(
                         RequestResponseHandlerEvent::Request {
-                            request_id: id, request: rq, sender: rs_sender
+                            request_id: id, request: rq, streaming, sender: rs_sender
                         }))
                 }
                 Err(oneshot::Canceled) => {
@@ -303,13 +572,54 @@ where
             }
         }
 
+        // Check for chunks of streamed outbound responses.
+        while let Poll::Ready(Some((request_id, result))) = self.outbound_streams.poll_next_unpin(cx) {
+            self.aborts.remove(&request_id);
+            let result = match result {
+                Ok(result) if !self.cancelled.remove(&request_id) => result,
+                // The request has been cancelled.
+                _ => continue
+            };
+            let event = match result {
+                Ok(Some((chunk, stream))) => {
+                    self.read_next_chunk(request_id, stream);
+                    RequestResponseHandlerEvent::ResponseChunk { request_id, chunk }
+                }
+                Ok(None) => RequestResponseHandlerEvent::ResponseEnd(request_id),
+                Err(StreamError::Timeout) => RequestResponseHandlerEvent::OutboundTimeout(request_id),
+                Err(StreamError::Io(e)) => RequestResponseHandlerEvent::OutboundStreamError(request_id, e),
+                Err(StreamError::TooLarge) =>
+                    RequestResponseHandlerEvent::OutboundStreamError(request_id, response_too_large())
+            };
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(event))
+        }
+
+        // Check for finished streamed inbound responses.
+        while let Poll::Ready(Some((request_id, result))) = self.inbound_streams.poll_next_unpin(cx) {
+            self.inbound_active.remove(&request_id);
+            match result {
+                Ok(()) => {}
+                Err(StreamError::Timeout) => return Poll::Ready(ProtocolsHandlerEvent::Custom(
+                    RequestResponseHandlerEvent::InboundTimeout(request_id))),
+                Err(StreamError::Io(e)) => return Poll::Ready(ProtocolsHandlerEvent::Custom(
+                    RequestResponseHandlerEvent::InboundStreamError(request_id, e))),
+                Err(StreamError::TooLarge) => return Poll::Ready(ProtocolsHandlerEvent::Custom(
+                    RequestResponseHandlerEvent::InboundResponseTooLarge(request_id)))
+            }
+        }
+
         // Emit outbound requests.
-        if let Some(request) = self.outbound.pop_front() {
+        if let Some(mut request) = self.outbound.pop_front() {
             let info = request.request_id;
+            let timeout = request.timeout;
+            let (handle, registration) = AbortHandle::new_pair();
+            request.abort = Some(registration);
+            request.max_response_size = self.max_response_size;
+            self.aborts.insert(info, handle);
             return Poll::Ready(
                 ProtocolsHandlerEvent::OutboundSubstreamRequest {
                     protocol: SubstreamProtocol::new(request, info)
-                        .with_timeout(self.substream_timeout)
+                        .with_timeout(timeout)
                 },
             )
         }
@@ -320,7 +630,12 @@ where
             self.outbound.shrink_to_fit();
         }
 
-        if self.inbound.is_empty() && self.keep_alive.is_yes() {
+        if self.inbound.is_empty()
+            && self.aborts.is_empty()
+            && self.outbound_streams.is_empty()
+            && self.inbound_streams.is_empty()
+            && self.keep_alive.is_yes()
+        {
             // No new inbound or outbound requests. However, we may just have
             // started the latest inbound or outbound upgrade(s), so make sure
             // the keep-alive timeout is preceded by the substream timeout.
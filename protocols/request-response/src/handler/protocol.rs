@@ -22,15 +22,36 @@
 //! and outbound substream upgrades. The inbound upgrade
 //! receives a request and sends a response, whereas the
 //! outbound upgrade send a request and receives a response.
+//!
+//! Every request is preceded by a flag byte telling the responder whether
+//! the response is expected to be streamed. For streamed responses, the
+//! upgrades only deal with the first chunk. The remaining chunks are read
+//! and written by the handler via [`ResponseStream`] and [`ResponseSink`]
+//! respectively.
+//!
+//! A response, as well as every chunk of a streamed response, is preceded
+//! by a marker byte and a streamed response is terminated by an end marker,
+//! so that a substream closed prematurely is not mistaken for the end of
+//! the response. A refused request is answered with a refusal marker in
+//! place of the response.
+//!
+//! The size limits of inbound requests and of responses, sent or received,
+//! are enforced on the I/O stream handed to the codec, see [`Limited`].
 
 use crate::RequestId;
 use crate::codec::RequestResponseCodec;
 
-use futures::{channel::oneshot, future::BoxFuture, prelude::*};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{AbortRegistration, Abortable, BoxFuture},
+    prelude::*,
+    ready
+};
 use libp2p_core::upgrade::{InboundUpgrade, OutboundUpgrade, UpgradeInfo};
 use libp2p_swarm::NegotiatedSubstream;
 use smallvec::SmallVec;
-use std::io;
+use std::{cmp, io, pin::Pin, task::{Context, Poll}, time::Duration};
+use wasm_timer::Instant;
 
 /// The level of support for a particular protocol.
 #[derive(Debug, Clone)]
@@ -61,6 +82,33 @@ impl ProtocolSupport {
     }
 }
 
+/// Precedes a request whose response is not streamed.
+const PLAIN_REQUEST: u8 = 0;
+/// Precedes a request whose response is streamed.
+const STREAMING_REQUEST: u8 = 1;
+
+/// Precedes a response or every chunk of a streamed response.
+const CHUNK_MARKER: u8 = 1;
+/// Terminates a streamed response.
+const END_MARKER: u8 = 0;
+/// Takes the place of the response to a refused request.
+const REFUSED_MARKER: u8 = 2;
+
+/// A message for the response to an inbound request, sent by the
+/// [`ResponseChannel`](crate::ResponseChannel).
+#[derive(Debug)]
+pub enum ResponseMessage<TResponse> {
+    /// The complete response to a request that is not streamed.
+    Response(TResponse),
+    /// A chunk of a streamed response.
+    Chunk(TResponse),
+    /// The end of a streamed response.
+    End,
+    /// The refusal of the request, e.g. due to the limits on
+    /// inbound requests.
+    Refused,
+}
+
 /// Response substream upgrade protocol.
 ///
 /// Receives a request and sends a response.
@@ -71,10 +119,52 @@ where
 {
     pub(crate) codec: TCodec,
     pub(crate) protocols: SmallVec<[TCodec::Protocol; 2]>,
-    pub(crate) request_sender: oneshot::Sender<(RequestId, TCodec::Request)>,
-    pub(crate) response_receiver: oneshot::Receiver<TCodec::Response>,
-    pub(crate) request_id: RequestId
+    /// Receives the request together with whether its response is streamed.
+    pub(crate) request_sender: oneshot::Sender<(RequestId, TCodec::Request, bool)>,
+    pub(crate) response_receiver: mpsc::Receiver<ResponseMessage<TCodec::Response>>,
+    pub(crate) request_id: RequestId,
+    /// The maximum size in bytes of the request.
+    pub(crate) max_request_size: usize,
+    /// The maximum size in bytes of the response, i.e. of all its chunks.
+    pub(crate) max_response_size: usize,
+    /// Whether the request is rejected without reading it, because
+    /// the connection has too many inbound requests in progress.
+    pub(crate) reject: bool,
+}
 
+/// The outcome of an inbound request.
+pub enum InboundOutcome<TCodec>
+where
+    TCodec: RequestResponseCodec
+{
+    /// The response has been sent, unless the response channel was dropped.
+    Done,
+    /// The first chunks of a streamed response have been sent and the
+    /// remaining ones are left to the sink.
+    Streaming(ResponseSink<TCodec>),
+    /// The request has been rejected because the connection has too
+    /// many inbound requests in progress.
+    TooManyRequests,
+    /// The request exceeds the maximum request size.
+    RequestTooLarge,
+    /// The response exceeds the maximum response size.
+    ResponseTooLarge,
+}
+
+/// The remaining chunks of a streamed response to an inbound request
+/// that are yet to be written.
+pub struct ResponseSink<TCodec>
+where
+    TCodec: RequestResponseCodec
+{
+    pub(crate) codec: TCodec,
+    pub(crate) protocol: TCodec::Protocol,
+    pub(crate) io: NegotiatedSubstream,
+    pub(crate) response_receiver: mpsc::Receiver<ResponseMessage<TCodec::Response>>,
+    /// When the inbound upgrade started.
+    pub(crate) started: Instant,
+    /// The number of bytes the remaining chunks may take up.
+    pub(crate) remaining: usize,
 }
 
 impl<TCodec> UpgradeInfo for ResponseProtocol<TCodec>
@@ -93,30 +183,208 @@ impl<TCodec> InboundUpgrade<NegotiatedSubstream> for ResponseProtocol<TCodec>
 where
     TCodec: RequestResponseCodec + Send + 'static,
 {
-    type Output = ();
+    type Output = InboundOutcome<TCodec>;
     type Error = io::Error;
     type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
     fn upgrade_inbound(mut self, mut io: NegotiatedSubstream, protocol: Self::Info) -> Self::Future {
         async move {
-            let read = self.codec.read_request(&protocol, &mut io);
-            let request = read.await?;
-            if let Ok(()) = self.request_sender.send((self.request_id, request)) {
-                if let Ok(response) = self.response_receiver.await {
-                    let write = self.codec.write_response(&protocol, &mut io, response);
-                    write.await?;
+            // Rejected requests are answered with a refusal without
+            // reading them, see `upgrade_outbound`.
+            if self.reject {
+                io.write_all(&[REFUSED_MARKER]).await?;
+                io.close().await?;
+                return Ok(InboundOutcome::TooManyRequests)
+            }
+            let started = Instant::now();
+            let mut flag = [0];
+            io.read_exact(&mut flag).await?;
+            let streaming = match flag[0] {
+                PLAIN_REQUEST => false,
+                STREAMING_REQUEST => true,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid request flag"))
+            };
+            let mut reader = Limited::new(&mut io, self.max_request_size);
+            let request = match self.codec.read_request(&protocol, &mut reader).await {
+                Ok(request) => request,
+                Err(_) if reader.exceeded() => {
+                    io.write_all(&[REFUSED_MARKER]).await?;
+                    io.close().await?;
+                    return Ok(InboundOutcome::RequestTooLarge)
+                }
+                Err(e) => return Err(e)
+            };
+            if let Ok(()) = self.request_sender.send((self.request_id, request, streaming)) {
+                let mut writer = Limited::new(&mut io, self.max_response_size);
+                let result = if !streaming {
+                    match self.response_receiver.next().await {
+                        Some(ResponseMessage::Response(response)) | Some(ResponseMessage::Chunk(response)) => {
+                            match writer.write_all(&[CHUNK_MARKER]).await {
+                                Ok(()) => self.codec.write_response(&protocol, &mut writer, response).await,
+                                Err(e) => Err(e)
+                            }
+                        }
+                        Some(ResponseMessage::Refused) => writer.write_all(&[REFUSED_MARKER]).await,
+                        // The response channel was dropped without a response.
+                        Some(ResponseMessage::End) | None => Ok(())
+                    }
+                } else {
+                    match self.response_receiver.next().await {
+                        // A response channel that is dropped before sending anything
+                        // closes the substream without an end marker.
+                        None => Ok(()),
+                        Some(message) => {
+                            let mut message = Some(Some(message));
+                            // Write the chunks of a streamed response that are already
+                            // available. If the response channel is still open afterwards,
+                            // the remaining chunks are left to the handler.
+                            loop {
+                                let message = match message.take() {
+                                    Some(message) => message,
+                                    None => match self.response_receiver.next().now_or_never() {
+                                        Some(message) => message,
+                                        None => {
+                                            let remaining = writer.remaining();
+                                            return Ok(InboundOutcome::Streaming(ResponseSink {
+                                                codec: self.codec,
+                                                protocol,
+                                                io,
+                                                response_receiver: self.response_receiver,
+                                                started,
+                                                remaining,
+                                            }))
+                                        }
+                                    }
+                                };
+                                match write_message(&mut self.codec, &protocol, &mut writer, message).await {
+                                    Ok(true) => {}
+                                    Ok(false) => break Ok(()),
+                                    Err(e) => break Err(e)
+                                }
+                            }
+                        }
+                    }
+                };
+                match result {
+                    Ok(()) => {}
+                    Err(_) if writer.exceeded() => return Ok(InboundOutcome::ResponseTooLarge),
+                    Err(e) => return Err(e)
                 }
             }
             io.close().await?;
-            Ok(())
+            Ok(InboundOutcome::Done)
         }.boxed()
     }
 }
 
+/// Writes a message of a streamed response, returning whether the stream
+/// continues. The stream ends with an explicit [`ResponseMessage::End`] or
+/// once the response channel is dropped, i.e. the message is `None`, unless
+/// the request is refused.
+pub(crate) fn write_message<'a, TCodec, T>(
+    codec: &'a mut TCodec,
+    protocol: &TCodec::Protocol,
+    io: &'a mut T,
+    message: Option<ResponseMessage<TCodec::Response>>
+) -> impl Future<Output = io::Result<bool>> + 'a
+where
+    TCodec: RequestResponseCodec,
+    T: AsyncWrite + Unpin + Send
+{
+    // The protocol is cloned, since a reference to it is not `Send`.
+    let protocol = protocol.clone();
+    async move {
+        match message {
+            Some(ResponseMessage::Response(chunk)) | Some(ResponseMessage::Chunk(chunk)) => {
+                io.write_all(&[CHUNK_MARKER]).await?;
+                codec.write_response(&protocol, io, chunk).await?;
+                Ok(true)
+            }
+            Some(ResponseMessage::End) | None => {
+                io.write_all(&[END_MARKER]).await?;
+                Ok(false)
+            }
+            Some(ResponseMessage::Refused) => {
+                io.write_all(&[REFUSED_MARKER]).await?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// An I/O stream that fails once more than a maximum number of bytes
+/// is read from or written to the underlying stream.
+pub(crate) struct Limited<'a, T> {
+    io: &'a mut T,
+    remaining: usize,
+    exceeded: bool,
+}
+
+impl<'a, T> Limited<'a, T> {
+    pub(crate) fn new(io: &'a mut T, max: usize) -> Self {
+        Limited { io, remaining: max, exceeded: false }
+    }
+
+    /// The number of bytes that may still be read or written.
+    pub(crate) fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Whether reading or writing failed due to the limit.
+    pub(crate) fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    fn exceed(&mut self) -> io::Error {
+        self.exceeded = true;
+        io::Error::new(io::ErrorKind::InvalidData, "size limit exceeded")
+    }
+}
+
+impl<'a, T> AsyncRead for Limited<'a, T>
+where
+    T: AsyncRead + Unpin
+{
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        // Read at most one byte more than permitted to detect the excess.
+        let max = cmp::min(buf.len(), this.remaining.saturating_add(1));
+        let n = ready!(Pin::new(&mut *this.io).poll_read(cx, &mut buf[.. max]))?;
+        if n > this.remaining {
+            return Poll::Ready(Err(this.exceed()))
+        }
+        this.remaining -= n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<'a, T> AsyncWrite for Limited<'a, T>
+where
+    T: AsyncWrite + Unpin
+{
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if buf.len() > this.remaining {
+            return Poll::Ready(Err(this.exceed()))
+        }
+        let n = ready!(Pin::new(&mut *this.io).poll_write(cx, buf))?;
+        this.remaining -= n;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.io).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.io).poll_close(cx)
+    }
+}
+
 /// Request substream upgrade protocol.
 ///
 /// Sends a request and receives a response.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct RequestProtocol<TCodec>
 where
     TCodec: RequestResponseCodec
@@ -125,6 +393,143 @@ where
     pub(crate) protocols: SmallVec<[TCodec::Protocol; 2]>,
     pub(crate) request_id: RequestId,
     pub(crate) request: TCodec::Request,
+    /// Whether the response is expected to be streamed.
+    pub(crate) streaming: bool,
+    /// The timeout for sending the request and receiving the
+    /// response, respectively its first chunk.
+    pub(crate) timeout: Duration,
+    /// The maximum size in bytes of the response, i.e. of all its chunks.
+    pub(crate) max_response_size: usize,
+    /// Aborts the upgrade when the request is cancelled.
+    pub(crate) abort: Option<AbortRegistration>,
+}
+
+impl<TCodec> Clone for RequestProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec + Clone,
+    TCodec::Request: Clone
+{
+    fn clone(&self) -> Self {
+        RequestProtocol {
+            codec: self.codec.clone(),
+            protocols: self.protocols.clone(),
+            request_id: self.request_id,
+            request: self.request.clone(),
+            streaming: self.streaming,
+            timeout: self.timeout,
+            max_response_size: self.max_response_size,
+            // The registration is only set by the handler before
+            // sending the request and is not shared by clones.
+            abort: None,
+        }
+    }
+}
+
+/// The outcome of an outbound request.
+pub enum RequestOutcome<TCodec>
+where
+    TCodec: RequestResponseCodec
+{
+    /// The response to a request that does not expect a streamed response.
+    Response(TCodec::Response),
+    /// The first chunk of a streamed response, with the stream to
+    /// read the remaining chunks from.
+    Chunk(TCodec::Response, ResponseStream<TCodec>),
+    /// A streamed response without any chunks.
+    End,
+}
+
+/// The remaining chunks of a streamed response to an outbound
+/// request that are yet to be read.
+pub struct ResponseStream<TCodec>
+where
+    TCodec: RequestResponseCodec
+{
+    pub(crate) codec: TCodec,
+    pub(crate) protocol: TCodec::Protocol,
+    pub(crate) io: NegotiatedSubstream,
+    /// When the outbound upgrade started.
+    pub(crate) started: Instant,
+    /// The number of bytes the remaining chunks may take up.
+    pub(crate) remaining: usize,
+    /// Whether reading a chunk failed due to the size limit.
+    pub(crate) exceeded: bool,
+}
+
+impl<TCodec> ResponseStream<TCodec>
+where
+    TCodec: RequestResponseCodec
+{
+    /// Reads the next chunk, returning `None` at the end of the stream.
+    pub(crate) fn next_chunk(&mut self) -> impl Future<Output = io::Result<Option<TCodec::Response>>> + '_ {
+        let ResponseStream { codec, protocol, io, remaining, exceeded, .. } = self;
+        async move {
+            let mut reader = Limited::new(io, *remaining);
+            let result = read_chunk(codec, protocol, &mut reader).await;
+            *remaining = reader.remaining();
+            *exceeded = reader.exceeded();
+            result
+        }
+    }
+}
+
+/// Reads a chunk of a streamed response, returning `None` at the end of the stream.
+///
+/// A substream that is closed before the end marker fails with an error of
+/// kind `UnexpectedEof`.
+fn read_chunk<'a, TCodec, T>(codec: &'a mut TCodec, protocol: &TCodec::Protocol, io: &'a mut T)
+    -> impl Future<Output = io::Result<Option<TCodec::Response>>> + 'a
+where
+    TCodec: RequestResponseCodec,
+    T: AsyncRead + Unpin + Send
+{
+    // The protocol is cloned, since a reference to it is not `Send`.
+    let protocol = protocol.clone();
+    async move {
+        let mut marker = [0];
+        io.read_exact(&mut marker).await?;
+        match marker[0] {
+            CHUNK_MARKER => codec.read_response(&protocol, io).await.map(Some),
+            END_MARKER => Ok(None),
+            REFUSED_MARKER => Err(refused()),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response stream marker"))
+        }
+    }
+}
+
+/// Reads a response that is not streamed.
+///
+/// A substream that is closed without a response is left to the codec,
+/// which fails unless it accepts an empty response, as for one-way protocols.
+fn read_response<'a, TCodec, T>(codec: &'a mut TCodec, protocol: &TCodec::Protocol, io: &'a mut T)
+    -> impl Future<Output = io::Result<TCodec::Response>> + 'a
+where
+    TCodec: RequestResponseCodec,
+    T: AsyncRead + Unpin + Send
+{
+    // The protocol is cloned, since a reference to it is not `Send`.
+    let protocol = protocol.clone();
+    async move {
+        let mut marker = [0];
+        if io.read(&mut marker).await? == 0 {
+            return codec.read_response(&protocol, io).await
+        }
+        match marker[0] {
+            CHUNK_MARKER => codec.read_response(&protocol, io).await,
+            REFUSED_MARKER => Err(refused()),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response marker"))
+        }
+    }
+}
+
+/// The error of a request that has been refused by the remote.
+fn refused() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, "request refused")
+}
+
+/// The error of a response that exceeds its maximum size.
+pub(crate) fn response_too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "response too large")
 }
 
 impl<TCodec> UpgradeInfo for RequestProtocol<TCodec>
@@ -143,18 +548,57 @@ impl<TCodec> OutboundUpgrade<NegotiatedSubstream> for RequestProtocol<TCodec>
 where
     TCodec: RequestResponseCodec + Send + 'static,
 {
-    type Output = TCodec::Response;
+    type Output = RequestOutcome<TCodec>;
     type Error = io::Error;
     type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
     fn upgrade_outbound(mut self, mut io: NegotiatedSubstream, protocol: Self::Info) -> Self::Future {
-        async move {
+        let abort = self.abort.take();
+        let upgrade = async move {
+            let started = Instant::now();
+            let flag = if self.streaming { STREAMING_REQUEST } else { PLAIN_REQUEST };
+            io.write_all(&[flag]).await?;
             let write = self.codec.write_request(&protocol, &mut io, self.request);
             write.await?;
             io.close().await?;
-            let read = self.codec.read_response(&protocol, &mut io);
-            let response = read.await?;
-            Ok(response)
-        }.boxed()
+            let mut reader = Limited::new(&mut io, self.max_response_size);
+            if !self.streaming {
+                let read = read_response(&mut self.codec, &protocol, &mut reader);
+                return match read.await {
+                    Ok(response) => Ok(RequestOutcome::Response(response)),
+                    Err(_) if reader.exceeded() => Err(response_too_large()),
+                    Err(e) => Err(e)
+                }
+            }
+            let read = read_chunk(&mut self.codec, &protocol, &mut reader);
+            let chunk = match read.await {
+                Ok(chunk) => chunk,
+                Err(_) if reader.exceeded() => return Err(response_too_large()),
+                Err(e) => return Err(e)
+            };
+            let remaining = reader.remaining();
+            match chunk {
+                Some(chunk) => {
+                    let stream = ResponseStream {
+                        codec: self.codec,
+                        protocol,
+                        io,
+                        started,
+                        remaining,
+                        exceeded: false,
+                    };
+                    Ok(RequestOutcome::Chunk(chunk, stream))
+                }
+                None => Ok(RequestOutcome::End)
+            }
+        };
+        match abort {
+            Some(registration) => Abortable::new(upgrade, registration)
+                .map(|result| result.unwrap_or_else(|_| {
+                    Err(io::Error::new(io::ErrorKind::Interrupted, "request cancelled"))
+                }))
+                .boxed(),
+            None => upgrade.boxed()
+        }
     }
 }
@@ -39,6 +39,62 @@
 //! receiving a [`RequestResponseMessage::Request`] via
 //! [`RequestResponseEvent::Message`].
 //!
+//! ## Streaming Responses
+//!
+//! A response may also be streamed as a sequence of chunks, e.g. for
+//! protocols returning large amounts of data. Such requests are sent
+//! using [`RequestResponse::send_streaming_request`] and every chunk is
+//! received as a [`RequestResponseMessage::ResponseChunk`], followed by
+//! a [`RequestResponseMessage::ResponseEnd`] once the stream is complete.
+//! The responder sends the chunks via
+//! [`RequestResponse::send_response_chunk`] and ends the stream by
+//! dropping the [`ResponseChannel`], sending the last chunk with
+//! [`RequestResponse::send_response`] or via
+//! [`RequestResponse::end_response_stream`]. Every request tells the
+//! responder whether its response is expected to be streamed, see
+//! [`ResponseChannel::is_streamed`], and chunks can only be sent in
+//! response to requests that expect them. An empty stream must be ended
+//! explicitly with [`RequestResponse::end_response_stream`], since dropping
+//! the [`ResponseChannel`] before sending anything fails the request.
+//!
+//! Every chunk is written with [`RequestResponseCodec::write_response`],
+//! preceded by a marker byte, and the stream is terminated by an end
+//! marker. A substream that is closed before the end marker fails the
+//! request. Chunks that are yet to be written are buffered in the
+//! [`ResponseChannel`] up to [`RequestResponseConfig::set_chunk_buffer`],
+//! beyond which [`RequestResponse::send_response_chunk`] reports the
+//! buffer as full until [`ResponseChannel::poll_ready`] signals that
+//! there is room again.
+//!
+//! The first chunk is subject to the request timeout, every further chunk
+//! to [`RequestResponseConfig::set_chunk_timeout`] and the whole stream
+//! to [`RequestResponseConfig::set_stream_timeout`].
+//!
+//! ## Sending to Any of a Set of Peers
+//!
+//! A request that can be answered by any of a set of peers is sent using
+//! [`RequestResponse::send_request_to_any`]. The peers are tried one after
+//! the other, ordered according to the [`PeerRanking`] of the given
+//! [`RetryPolicy`], and failed attempts are retried with exponential backoff.
+//! The first response is received as a [`RequestResponseMessage::Response`]
+//! as usual, whereas the failure of all attempts is reported once as a
+//! [`RequestResponseEvent::OutboundRoutingFailure`], which carries the errors
+//! of the individual attempts.
+//!
+//! ## Inbound Limits
+//!
+//! The resources spent on inbound requests can be limited via the
+//! [`RequestResponseConfig`]: the number of inbound requests in progress
+//! per connection, the number of unanswered inbound requests per peer
+//! and in total, as well as the size in bytes of inbound requests and
+//! their responses. Size limits are enforced on the I/O stream, independent
+//! of the limits a [`RequestResponseCodec`] may impose. Every rejection is
+//! reported as a [`RequestResponseEvent::InboundFailure`] and answers the
+//! request with a refusal in place of the response, so that the requester
+//! fails the request with [`OutboundFailure::Io`]. The maximum response
+//! size also applies to the responses received for outbound requests,
+//! which fail with [`OutboundFailure::Io`] if it is exceeded.
+//!
 //! ## Protocol Families
 //!
 //! A single [`RequestResponse`] instance can be used with an entire
@@ -53,7 +109,7 @@
 //! be defined as `()` and [`RequestResponseCodec::read_response`] as well as
 //! [`RequestResponseCodec::write_response`] given the obvious implementations.
 //! Note that `RequestResponseMessage::Response` will still be emitted,
-//! immediately after the request has been sent, since `RequestResponseCodec::read_response`
+//! once the responder closes the substream, since `RequestResponseCodec::read_response`
 //! will not actually read anything from the given I/O stream.
 //! [`RequestResponse::send_response`] need not be called for one-way protocols,
 //! i.e. the [`ResponseChannel`] may just be dropped.
@@ -70,19 +126,24 @@
 
 pub mod codec;
 pub mod handler;
+pub mod retry;
 pub mod throttled;
 
 pub use codec::{RequestResponseCodec, ProtocolName};
 pub use handler::ProtocolSupport;
+pub use retry::{PeerRanking, RetryPolicy};
 pub use throttled::Throttled;
 
 use futures::{
-    channel::oneshot,
+    channel::mpsc,
+    prelude::*,
 };
 use handler::{
     RequestProtocol,
     RequestResponseHandler,
     RequestResponseHandlerEvent,
+    RequestResponseHandlerIn,
+    ResponseMessage,
 };
 use libp2p_core::{
     ConnectedPoint,
@@ -97,14 +158,17 @@ use libp2p_swarm::{
     NotifyHandler,
     PollParameters,
 };
-use smallvec::SmallVec;
+use retry::Route;
+use smallvec::{SmallVec, smallvec};
 use std::{
     collections::{VecDeque, HashMap},
     fmt,
+    io,
     time::Duration,
-    sync::{atomic::AtomicU64, Arc},
+    sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc},
     task::{Context, Poll}
 };
+use wasm_timer::Delay;
 
 /// An inbound request or response.
 #[derive(Debug)]
@@ -129,6 +193,22 @@ pub enum RequestResponseMessage<TRequest, TResponse, TChannelResponse = TRespons
         /// The response message.
         response: TResponse
     },
+    /// A chunk of a streamed response.
+    ResponseChunk {
+        /// The ID of the request that produced this response.
+        ///
+        /// See [`RequestResponse::send_streaming_request`].
+        request_id: RequestId,
+        /// The response chunk.
+        chunk: TResponse
+    },
+    /// The end of a streamed response, after all chunks have been received.
+    ResponseEnd {
+        /// The ID of the request that produced this response.
+        ///
+        /// See [`RequestResponse::send_streaming_request`].
+        request_id: RequestId,
+    },
 }
 
 /// The events emitted by a [`RequestResponse`] protocol.
@@ -150,6 +230,14 @@ pub enum RequestResponseEvent<TRequest, TResponse, TChannelResponse = TResponse>
         /// The error that occurred.
         error: OutboundFailure,
     },
+    /// An outbound request sent via [`RequestResponse::send_request_to_any`]
+    /// failed with every peer that has been tried.
+    OutboundRoutingFailure {
+        /// The (local) ID of the failed request.
+        request_id: RequestId,
+        /// The peer and error of every attempt, in the order of the attempts.
+        attempts: Vec<(PeerId, OutboundFailure)>,
+    },
     /// An inbound request failed.
     InboundFailure {
         /// The peer from whom the request was received.
@@ -179,6 +267,16 @@ pub enum OutboundFailure {
     ConnectionClosed,
     /// The remote supports none of the requested protocols.
     UnsupportedProtocols,
+    /// Sending the request or reading the response failed, e.g.
+    /// because the remote rejected the request due to its limits.
+    ///
+    /// For streamed responses, some of the chunks may have been received.
+    Io(io::Error),
+    /// The request has been cancelled via [`RequestResponse::cancel_request`].
+    ///
+    /// It is not known whether the request may have been
+    /// received (and processed) by the remote peer.
+    Cancelled,
 }
 
 /// Possible failures occurring in the context of receiving an
@@ -194,6 +292,48 @@ pub enum InboundFailure {
     UnsupportedProtocols,
     /// The connection closed before a response was delivered.
     ConnectionClosed,
+    /// Writing a streamed response failed after some of its
+    /// chunks may have been delivered.
+    Io(io::Error),
+    /// The request was rejected without reading it because the connection
+    /// has too many inbound requests in progress.
+    ///
+    /// See [`RequestResponseConfig::set_max_inbound_requests_per_connection`].
+    TooManyRequestsOnConnection,
+    /// The request was rejected because the peer has too many
+    /// unanswered inbound requests.
+    ///
+    /// See [`RequestResponseConfig::set_max_inbound_requests_per_peer`].
+    TooManyRequestsFromPeer,
+    /// The request was rejected because there are too many
+    /// unanswered inbound requests in total.
+    ///
+    /// See [`RequestResponseConfig::set_max_pending_inbound_requests`].
+    TooManyPendingRequests,
+    /// The request exceeds the maximum request size.
+    ///
+    /// See [`RequestResponseConfig::set_max_request_size`].
+    RequestTooLarge,
+    /// The response exceeds the maximum response size and has not been
+    /// delivered completely.
+    ///
+    /// See [`RequestResponseConfig::set_max_response_size`].
+    ResponseTooLarge,
+}
+
+/// The error of [`RequestResponse::send_response_chunk`], returning the chunk.
+#[derive(Debug)]
+pub enum SendChunkError<TResponse> {
+    /// The buffer of chunks waiting to be written is full.
+    ///
+    /// See [`ResponseChannel::poll_ready`].
+    Full(TResponse),
+    /// The response channel is closed, e.g. due to a timeout.
+    Closed(TResponse),
+    /// The response to the request is not streamed.
+    ///
+    /// See [`ResponseChannel::is_streamed`].
+    NotStreamed(TResponse),
 }
 
 /// A channel for sending a response to an inbound request.
@@ -203,7 +343,11 @@ pub enum InboundFailure {
 pub struct ResponseChannel<TResponse> {
     request_id: RequestId,
     peer: PeerId,
-    sender: oneshot::Sender<TResponse>,
+    sender: mpsc::Sender<ResponseMessage<TResponse>>,
+    /// Whether the response is expected to be streamed.
+    streaming: bool,
+    /// Counts the request as unanswered for as long as the channel exists.
+    _pending: PendingGuard,
 }
 
 impl<TResponse> ResponseChannel<TResponse> {
@@ -215,7 +359,22 @@ impl<TResponse> ResponseChannel<TResponse> {
     /// If the response channel is no longer open then the inbound
     /// request timed out waiting for the response.
     pub fn is_open(&self) -> bool {
-        !self.sender.is_canceled()
+        !self.sender.is_closed()
+    }
+
+    /// Whether the requester expects the response to be streamed, i.e.
+    /// sent via [`RequestResponse::send_response_chunk`].
+    pub fn is_streamed(&self) -> bool {
+        self.streaming
+    }
+
+    /// Polls whether a chunk of a streamed response can be sent with
+    /// [`RequestResponse::send_response_chunk`] without the buffer of
+    /// chunks being full.
+    ///
+    /// Also resolves once the channel is closed, see [`ResponseChannel::is_open`].
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.sender.poll_ready(cx).map(|_| ())
     }
 
     /// Get the ID of the inbound request waiting for a response.
@@ -224,6 +383,28 @@ impl<TResponse> ResponseChannel<TResponse> {
     }
 }
 
+/// Counts an unanswered inbound request in the given counters
+/// until it is dropped.
+#[derive(Debug)]
+struct PendingGuard(SmallVec<[Arc<AtomicUsize>; 2]>);
+
+impl PendingGuard {
+    fn new(counters: SmallVec<[Arc<AtomicUsize>; 2]>) -> Self {
+        for c in &counters {
+            c.fetch_add(1, Ordering::Relaxed);
+        }
+        PendingGuard(counters)
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        for c in &self.0 {
+            c.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
 /// The ID of an inbound or outbound request.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct RequestId(u64);
@@ -239,6 +420,14 @@ impl fmt::Display for RequestId {
 pub struct RequestResponseConfig {
     request_timeout: Duration,
     connection_keep_alive: Duration,
+    chunk_timeout: Duration,
+    stream_timeout: Duration,
+    max_inbound_requests_per_connection: Option<usize>,
+    max_inbound_requests_per_peer: Option<usize>,
+    max_pending_inbound_requests: Option<usize>,
+    max_request_size: Option<usize>,
+    max_response_size: Option<usize>,
+    chunk_buffer: usize,
 }
 
 impl Default for RequestResponseConfig {
@@ -246,6 +435,14 @@ impl Default for RequestResponseConfig {
         Self {
             connection_keep_alive: Duration::from_secs(10),
             request_timeout: Duration::from_secs(10),
+            chunk_timeout: Duration::from_secs(10),
+            stream_timeout: Duration::from_secs(60),
+            max_inbound_requests_per_connection: None,
+            max_inbound_requests_per_peer: None,
+            max_pending_inbound_requests: None,
+            max_request_size: None,
+            max_response_size: None,
+            chunk_buffer: 8,
         }
     }
 }
@@ -262,6 +459,77 @@ impl RequestResponseConfig {
         self.request_timeout = v;
         self
     }
+
+    /// Sets the timeout for every chunk of a streamed response after the first.
+    pub fn set_chunk_timeout(&mut self, v: Duration) -> &mut Self {
+        self.chunk_timeout = v;
+        self
+    }
+
+    /// Sets the timeout for all chunks of a streamed response, counted
+    /// from the moment the request is sent respectively received.
+    pub fn set_stream_timeout(&mut self, v: Duration) -> &mut Self {
+        self.stream_timeout = v;
+        self
+    }
+
+    /// Sets the maximum number of inbound requests in progress per
+    /// connection, i.e. requests that are being read or whose response
+    /// is yet to be sent. Further requests are rejected without reading
+    /// them.
+    ///
+    /// By default, the number is unlimited.
+    pub fn set_max_inbound_requests_per_connection(&mut self, v: usize) -> &mut Self {
+        self.max_inbound_requests_per_connection = Some(v);
+        self
+    }
+
+    /// Sets the maximum number of unanswered inbound requests per peer,
+    /// i.e. requests whose [`ResponseChannel`] has not yet been dropped
+    /// or consumed by [`RequestResponse::send_response`]. Further requests
+    /// of the peer are rejected.
+    ///
+    /// By default, the number is unlimited.
+    pub fn set_max_inbound_requests_per_peer(&mut self, v: usize) -> &mut Self {
+        self.max_inbound_requests_per_peer = Some(v);
+        self
+    }
+
+    /// Sets the maximum number of unanswered inbound requests across all
+    /// peers. Further requests are rejected.
+    ///
+    /// By default, the number is unlimited.
+    pub fn set_max_pending_inbound_requests(&mut self, v: usize) -> &mut Self {
+        self.max_pending_inbound_requests = Some(v);
+        self
+    }
+
+    /// Sets the maximum size in bytes of inbound requests.
+    ///
+    /// By default, the size is only limited by the codec.
+    pub fn set_max_request_size(&mut self, v: usize) -> &mut Self {
+        self.max_request_size = Some(v);
+        self
+    }
+
+    /// Sets the maximum size in bytes of responses, both those sent for
+    /// inbound requests and those received for outbound requests,
+    /// including all chunks of streamed responses.
+    ///
+    /// By default, the size is only limited by the codec.
+    pub fn set_max_response_size(&mut self, v: usize) -> &mut Self {
+        self.max_response_size = Some(v);
+        self
+    }
+
+    /// Sets the number of chunks of a streamed response to an inbound
+    /// request that may be buffered before being written.
+    ///
+    /// The default is 8.
+    pub fn set_chunk_buffer(&mut self, v: usize) -> &mut Self {
+        self.chunk_buffer = v;
+        self
+    }
 }
 
 /// A request/response protocol for some message codec.
@@ -284,7 +552,7 @@ where
     /// Pending events to return from `poll`.
     pending_events: VecDeque<
         NetworkBehaviourAction<
-            RequestProtocol<TCodec>,
+            RequestResponseHandlerIn<TCodec>,
             RequestResponseEvent<TCodec::Request, TCodec::Response>>>,
     /// The currently connected peers and their known, reachable addresses, if any.
     connected: HashMap<PeerId, SmallVec<[Connection; 2]>>,
@@ -294,7 +562,17 @@ where
     /// to be established.
     pending_requests: HashMap<PeerId, SmallVec<[RequestProtocol<TCodec>; 10]>>,
     /// Responses that have not yet been received.
-    pending_responses: HashMap<RequestId, (PeerId, ConnectionId)>
+    pending_responses: HashMap<RequestId, (PeerId, ConnectionId)>,
+    /// Requests sent via `send_request_to_any` that have neither
+    /// been answered nor failed for good.
+    routes: HashMap<RequestId, Route<TCodec::Request>>,
+    /// The peers the attempts of requests sent via `send_request_to_any`
+    /// have been sent to, if recorded for `Throttled`.
+    attempts_sent: Option<VecDeque<(RequestId, PeerId)>>,
+    /// The number of unanswered inbound requests.
+    pending_inbound: Arc<AtomicUsize>,
+    /// The number of unanswered inbound requests per peer.
+    pending_inbound_per_peer: HashMap<PeerId, Arc<AtomicUsize>>,
 }
 
 impl<TCodec> RequestResponse<TCodec>
@@ -329,6 +607,10 @@ where
             pending_requests: HashMap::new(),
             pending_responses: HashMap::new(),
             addresses: HashMap::new(),
+            routes: HashMap::new(),
+            attempts_sent: None,
+            pending_inbound: Arc::new(AtomicUsize::new(0)),
+            pending_inbound_per_peer: HashMap::new(),
         }
     }
 
@@ -358,12 +640,143 @@ where
     /// > managed via [`RequestResponse::add_address`] and
     /// > [`RequestResponse::remove_address`].
     pub fn send_request(&mut self, peer: &PeerId, request: TCodec::Request) -> RequestId {
+        let timeout = self.config.request_timeout;
+        self.send_request_inner(peer, request, false, timeout)
+    }
+
+    /// Initiates sending a request with the given timeout instead of
+    /// the one configured via [`RequestResponseConfig::set_request_timeout`].
+    ///
+    /// See [`RequestResponse::send_request`] for details.
+    pub fn send_request_with_timeout(&mut self, peer: &PeerId, request: TCodec::Request, timeout: Duration)
+        -> RequestId
+    {
+        self.send_request_inner(peer, request, false, timeout)
+    }
+
+    /// Initiates sending a request whose response is streamed.
+    ///
+    /// The chunks of the response are received as
+    /// [`RequestResponseMessage::ResponseChunk`], followed by a
+    /// [`RequestResponseMessage::ResponseEnd`]. See
+    /// [`RequestResponse::send_request`] for details.
+    pub fn send_streaming_request(&mut self, peer: &PeerId, request: TCodec::Request) -> RequestId {
+        let timeout = self.config.request_timeout;
+        self.send_request_inner(peer, request, true, timeout)
+    }
+
+    /// Initiates sending a request to any of the given peers.
+    ///
+    /// The peers are tried one after the other in the order given by
+    /// the [`RetryPolicy`]. An attempt that fails with
+    /// [`OutboundFailure::DialFailure`], [`OutboundFailure::Timeout`] or
+    /// [`OutboundFailure::UnsupportedProtocols`] is retried with the next
+    /// peer after a backoff, until the maximum number of attempts is reached.
+    ///
+    /// All attempts share the returned request ID. Either the response is
+    /// received as [`RequestResponseMessage::Response`] or, if no attempt
+    /// succeeds, [`RequestResponseEvent::OutboundRoutingFailure`] is emitted.
+    /// No [`RequestResponseEvent::OutboundFailure`] is emitted for the
+    /// individual attempts, unless the request is cancelled.
+    pub fn send_request_to_any<I>(&mut self, peers: I, request: TCodec::Request, policy: RetryPolicy) -> RequestId
+    where
+        I: IntoIterator<Item = PeerId>,
+        TCodec::Request: Clone + 'static
+    {
         let request_id = self.next_request_id();
+        let connected = &self.connected;
+        let route = Route::new(peers.into_iter().collect(), request, policy, |p| {
+            connected.get(p).map(|c| !c.is_empty()) == Some(true)
+        });
+        self.routes.insert(request_id, route);
+        self.send_next_attempt(request_id);
+        request_id
+    }
+
+    /// Sends the next attempt of a request sent via `send_request_to_any`
+    /// or, if there are no peers to try, reports its failure.
+    fn send_next_attempt(&mut self, request_id: RequestId) {
+        let attempt = match self.routes.get(&request_id) {
+            Some(route) => route.next_attempt(),
+            None => return
+        };
+        if let Some((peer, request)) = attempt {
+            if let Some(sent) = &mut self.attempts_sent {
+                sent.push_back((request_id, peer.clone()))
+            }
+            let timeout = self.config.request_timeout;
+            let protocols = self.outbound_protocols.clone();
+            self.send_request_with_id(&peer, request_id, request, protocols, false, timeout)
+        } else if let Some(route) = self.routes.remove(&request_id) {
+            self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                RequestResponseEvent::OutboundRoutingFailure {
+                    request_id,
+                    attempts: route.into_failures()
+                }
+            ))
+        }
+    }
+
+    fn send_request_inner(&mut self, peer: &PeerId, request: TCodec::Request, streaming: bool, timeout: Duration)
+        -> RequestId
+    {
+        let request_id = self.next_request_id();
+        let protocols = self.outbound_protocols.clone();
+        self.send_request_with_id(peer, request_id, request, protocols, streaming, timeout);
+        request_id
+    }
+
+    /// Initiates sending a request which may only be sent using the
+    /// given protocol, optionally with a timeout other than the configured one.
+    pub(crate) fn send_request_with_protocol(
+        &mut self,
+        peer: &PeerId,
+        request: TCodec::Request,
+        protocol: TCodec::Protocol,
+        timeout: Option<Duration>
+    ) -> RequestId {
+        let request_id = self.next_request_id();
+        let timeout = timeout.unwrap_or(self.config.request_timeout);
+        let protocols = smallvec![protocol];
+        self.send_request_with_id(peer, request_id, request, protocols, false, timeout);
+        request_id
+    }
+
+    /// Records the peers the attempts of requests sent via
+    /// `send_request_to_any` are sent to.
+    pub(crate) fn record_attempts(&mut self) {
+        self.attempts_sent.get_or_insert_with(VecDeque::new);
+    }
+
+    /// The next recorded attempt of a request sent via `send_request_to_any`.
+    pub(crate) fn next_attempt_sent(&mut self) -> Option<(RequestId, PeerId)> {
+        self.attempts_sent.as_mut().and_then(|sent| sent.pop_front())
+    }
+
+    /// The protocols used for outbound requests.
+    pub(crate) fn outbound_protocols(&self) -> &[TCodec::Protocol] {
+        &self.outbound_protocols
+    }
+
+    fn send_request_with_id(
+        &mut self,
+        peer: &PeerId,
+        request_id: RequestId,
+        request: TCodec::Request,
+        protocols: SmallVec<[TCodec::Protocol; 2]>,
+        streaming: bool,
+        timeout: Duration
+    ) {
         let request = RequestProtocol {
             request_id,
             codec: self.codec.clone(),
-            protocols: self.outbound_protocols.clone(),
+            protocols,
             request,
+            streaming,
+            timeout,
+            // The limit is set by the handler.
+            max_response_size: usize::MAX,
+            abort: None,
         };
 
         if let Some(request) = self.try_send_request(peer, request) {
@@ -373,8 +786,55 @@ where
             });
             self.pending_requests.entry(peer.clone()).or_default().push(request);
         }
+    }
 
-        request_id
+    /// Cancels a pending outbound request.
+    ///
+    /// A request that has not yet been sent is dropped, otherwise its
+    /// substream is reset. [`RequestResponseEvent::OutboundFailure`] with
+    /// [`OutboundFailure::Cancelled`] is emitted for the request, after
+    /// which no more events are emitted for it.
+    ///
+    /// Returns `false` if the request is not pending.
+    pub fn cancel_request(&mut self, request_id: RequestId) -> bool {
+        let route = self.routes.remove(&request_id);
+        let peer = if let Some((peer, conn)) = self.pending_responses.remove(&request_id) {
+            self.pending_events.push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id: peer.clone(),
+                handler: NotifyHandler::One(conn),
+                event: RequestResponseHandlerIn::Cancel(request_id)
+            });
+            peer
+        } else {
+            let queued = self.pending_requests.iter_mut()
+                .find_map(|(peer, requests)| {
+                    requests.iter()
+                        .position(|r| r.request_id == request_id)
+                        .map(|pos| (peer.clone(), requests, pos))
+                });
+            match queued {
+                Some((peer, requests, pos)) => {
+                    requests.remove(pos);
+                    if requests.is_empty() {
+                        self.pending_requests.remove(&peer);
+                    }
+                    peer
+                }
+                None => match route.and_then(|r| r.last_peer()) {
+                    // The request is waiting for its next attempt.
+                    Some(peer) => peer,
+                    None => return false
+                }
+            }
+        };
+        self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(
+            RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id,
+                error: OutboundFailure::Cancelled
+            }
+        ));
+        true
     }
 
     /// Initiates sending a response to an inbound request.
@@ -386,10 +846,50 @@ where
     /// The provided `ResponseChannel` is obtained from a
     /// [`RequestResponseMessage::Request`].
     pub fn send_response(&mut self, ch: ResponseChannel<TCodec::Response>, rs: TCodec::Response) {
+        let message = if ch.streaming {
+            ResponseMessage::Chunk(rs)
+        } else {
+            ResponseMessage::Response(rs)
+        };
         // Fails only if the inbound upgrade timed out waiting for the response,
         // in which case the handler emits `RequestResponseHandlerEvent::InboundTimeout`
         // which in turn results in `RequestResponseEvent::InboundFailure`.
-        let _ = ch.sender.send(rs);
+        // A new sender always has room for one message, so the last message
+        // is sent even if the buffer of chunks is full.
+        let _ = ch.sender.clone().try_send(message);
+    }
+
+    /// Sends a chunk of a streamed response to an inbound request.
+    ///
+    /// The stream ends when the `ResponseChannel` is dropped or consumed
+    /// by [`RequestResponse::send_response`], which sends the last chunk,
+    /// or [`RequestResponse::end_response_stream`]. If the buffer of chunks
+    /// is full, the `ResponseChannel` is already closed, e.g. due to a
+    /// timeout, or the response is not streamed, the chunk is returned.
+    pub fn send_response_chunk(&mut self, ch: &mut ResponseChannel<TCodec::Response>, chunk: TCodec::Response)
+        -> Result<(), SendChunkError<TCodec::Response>>
+    {
+        if !ch.streaming {
+            return Err(SendChunkError::NotStreamed(chunk))
+        }
+        ch.sender.try_send(ResponseMessage::Chunk(chunk)).map_err(|e| {
+            let full = e.is_full();
+            let chunk = match e.into_inner() {
+                ResponseMessage::Chunk(chunk) => chunk,
+                _ => unreachable!("a chunk has been sent")
+            };
+            if full { SendChunkError::Full(chunk) } else { SendChunkError::Closed(chunk) }
+        })
+    }
+
+    /// Ends a streamed response to an inbound request, which may
+    /// not have any chunks.
+    ///
+    /// For a response that is not streamed, this is equivalent to
+    /// dropping the `ResponseChannel`.
+    pub fn end_response_stream(&mut self, ch: ResponseChannel<TCodec::Response>) {
+        // See `send_response`.
+        let _ = ch.sender.clone().try_send(ResponseMessage::End);
     }
 
     /// Adds a known address for a peer that can be used for
@@ -426,7 +926,33 @@ where
     /// [`RequestResponse::send_request`] is still pending, i.e. waiting
     /// for a response.
     pub fn is_pending_outbound(&self, req_id: &RequestId) -> bool {
-        self.pending_responses.contains_key(req_id)
+        self.pending_responses.contains_key(req_id) || self.routes.contains_key(req_id)
+    }
+
+    /// Records a failed attempt of a request sent via `send_request_to_any`
+    /// and either schedules the next attempt or reports the failure.
+    fn on_attempt_failure(&mut self, peer: PeerId, request_id: RequestId, error: OutboundFailure) {
+        let backoff = match self.routes.get_mut(&request_id) {
+            Some(route) => match route.on_failure(peer, error) {
+                Some(backoff) => backoff,
+                None => {
+                    let route = self.routes.remove(&request_id).expect("route exists");
+                    self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                        RequestResponseEvent::OutboundRoutingFailure {
+                            request_id,
+                            attempts: route.into_failures()
+                        }
+                    ));
+                    return
+                }
+            },
+            None => return
+        };
+        if backoff == Duration::from_secs(0) {
+            self.send_next_attempt(request_id)
+        } else if let Some(route) = self.routes.get_mut(&request_id) {
+            route.backoff = Some(Delay::new(backoff))
+        }
     }
 
     /// Returns the next request ID.
@@ -452,7 +978,7 @@ where
             self.pending_events.push_back(NetworkBehaviourAction::NotifyHandler {
                 peer_id: peer.clone(),
                 handler: NotifyHandler::One(conn),
-                event: request
+                event: RequestResponseHandlerIn::Request(request)
             });
             None
         } else {
@@ -472,8 +998,7 @@ where
         RequestResponseHandler::new(
             self.inbound_protocols.clone(),
             self.codec.clone(),
-            self.config.connection_keep_alive,
-            self.config.request_timeout,
+            &self.config,
             self.next_inbound_id.clone()
         )
     }
@@ -534,6 +1059,11 @@ where
 
     fn inject_disconnected(&mut self, peer: &PeerId) {
         self.connected.remove(peer);
+        // Forget the counters of disconnected peers without unanswered requests.
+        let connected = &self.connected;
+        self.pending_inbound_per_peer.retain(|p, c| {
+            connected.contains_key(p) || c.load(Ordering::Relaxed) > 0
+        });
     }
 
     fn inject_dial_failure(&mut self, peer: &PeerId) {
@@ -564,14 +1094,82 @@ where
     ) {
         match event {
             RequestResponseHandlerEvent::Response { request_id, response } => {
-                self.pending_responses.remove(&request_id);
+                if self.pending_responses.remove(&request_id).is_none() {
+                    // The request has been cancelled.
+                    return
+                }
                 let message = RequestResponseMessage::Response { request_id, response };
                 self.pending_events.push_back(
                     NetworkBehaviourAction::GenerateEvent(
                         RequestResponseEvent::Message { peer, message }));
             }
-            RequestResponseHandlerEvent::Request { request_id, request, sender } => {
-                let channel = ResponseChannel { request_id, peer: peer.clone(), sender };
+            RequestResponseHandlerEvent::ResponseChunk { request_id, chunk } => {
+                if !self.pending_responses.contains_key(&request_id) {
+                    return
+                }
+                let message = RequestResponseMessage::ResponseChunk { request_id, chunk };
+                self.pending_events.push_back(
+                    NetworkBehaviourAction::GenerateEvent(
+                        RequestResponseEvent::Message { peer, message }));
+            }
+            RequestResponseHandlerEvent::ResponseEnd(request_id) => {
+                if self.pending_responses.remove(&request_id).is_none() {
+                    return
+                }
+                let message = RequestResponseMessage::ResponseEnd { request_id };
+                self.pending_events.push_back(
+                    NetworkBehaviourAction::GenerateEvent(
+                        RequestResponseEvent::Message { peer, message }));
+            }
+            RequestResponseHandlerEvent::OutboundStreamError(request_id, error) => {
+                if let Some((peer, _conn)) = self.pending_responses.remove(&request_id) {
+                    self.pending_events.push_back(
+                        NetworkBehaviourAction::GenerateEvent(
+                            RequestResponseEvent::OutboundFailure {
+                                peer,
+                                request_id,
+                                error: OutboundFailure::Io(error),
+                            }));
+                }
+            }
+            RequestResponseHandlerEvent::InboundStreamError(request_id, error) => {
+                self.pending_events.push_back(
+                    NetworkBehaviourAction::GenerateEvent(
+                        RequestResponseEvent::InboundFailure {
+                            peer,
+                            request_id,
+                            error: InboundFailure::Io(error),
+                        }));
+            }
+            RequestResponseHandlerEvent::Request { request_id, request, streaming, mut sender } => {
+                let peer_pending = self.pending_inbound_per_peer.entry(peer.clone()).or_default().clone();
+                let exceeds = |count: &AtomicUsize, max: Option<usize>| {
+                    max.map(|max| count.load(Ordering::Relaxed) >= max) == Some(true)
+                };
+                let rejection =
+                    if exceeds(&self.pending_inbound, self.config.max_pending_inbound_requests) {
+                        Some(InboundFailure::TooManyPendingRequests)
+                    } else if exceeds(&peer_pending, self.config.max_inbound_requests_per_peer) {
+                        Some(InboundFailure::TooManyRequestsFromPeer)
+                    } else {
+                        None
+                    };
+                if let Some(error) = rejection {
+                    // A new sender always has room for one message.
+                    let _ = sender.try_send(ResponseMessage::Refused);
+                    self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                        RequestResponseEvent::InboundFailure { peer, request_id, error }
+                    ));
+                    return
+                }
+                let pending = PendingGuard::new(smallvec![self.pending_inbound.clone(), peer_pending]);
+                let channel = ResponseChannel {
+                    request_id,
+                    peer: peer.clone(),
+                    sender,
+                    streaming,
+                    _pending: pending
+                };
                 let message = RequestResponseMessage::Request { request_id, request, channel };
                 self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(
                     RequestResponseEvent::Message { peer, message }
@@ -598,39 +1196,106 @@ where
                         }));
             }
             RequestResponseHandlerEvent::OutboundUnsupportedProtocols(request_id) => {
+                if self.pending_responses.remove(&request_id).is_some() {
+                    self.pending_events.push_back(
+                        NetworkBehaviourAction::GenerateEvent(
+                            RequestResponseEvent::OutboundFailure {
+                                peer,
+                                request_id,
+                                error: OutboundFailure::UnsupportedProtocols,
+                            }));
+                }
+            }
+            RequestResponseHandlerEvent::InboundUnsupportedProtocols(request_id) => {
+                self.pending_events.push_back(
+                    NetworkBehaviourAction::GenerateEvent(
+                        RequestResponseEvent::InboundFailure {
+                            peer,
+                            request_id,
+                            error: InboundFailure::UnsupportedProtocols,
+                        }));
+            }
+            RequestResponseHandlerEvent::InboundTooManyRequests(request_id) => {
                 self.pending_events.push_back(
                     NetworkBehaviourAction::GenerateEvent(
-                        RequestResponseEvent::OutboundFailure {
+                        RequestResponseEvent::InboundFailure {
                             peer,
                             request_id,
-                            error: OutboundFailure::UnsupportedProtocols,
+                            error: InboundFailure::TooManyRequestsOnConnection,
                         }));
             }
-            RequestResponseHandlerEvent::InboundUnsupportedProtocols(request_id) => {
+            RequestResponseHandlerEvent::InboundRequestTooLarge(request_id) => {
                 self.pending_events.push_back(
                     NetworkBehaviourAction::GenerateEvent(
                         RequestResponseEvent::InboundFailure {
                             peer,
                             request_id,
-                            error: InboundFailure::UnsupportedProtocols,
+                            error: InboundFailure::RequestTooLarge,
+                        }));
+            }
+            RequestResponseHandlerEvent::InboundResponseTooLarge(request_id) => {
+                self.pending_events.push_back(
+                    NetworkBehaviourAction::GenerateEvent(
+                        RequestResponseEvent::InboundFailure {
+                            peer,
+                            request_id,
+                            error: InboundFailure::ResponseTooLarge,
                         }));
             }
         }
     }
 
-    fn poll(&mut self, _: &mut Context<'_>, _: &mut impl PollParameters)
+    fn poll(&mut self, cx: &mut Context<'_>, _: &mut impl PollParameters)
         -> Poll<NetworkBehaviourAction<
-            RequestProtocol<TCodec>,
+            RequestResponseHandlerIn<TCodec>,
             RequestResponseEvent<TCodec::Request, TCodec::Response>
         >>
     {
-        if let Some(ev) = self.pending_events.pop_front() {
-            return Poll::Ready(ev);
-        } else if self.pending_events.capacity() > EMPTY_QUEUE_SHRINK_THRESHOLD {
-            self.pending_events.shrink_to_fit();
-        }
+        loop {
+            if let Some(ev) = self.pending_events.pop_front() {
+                match ev {
+                    NetworkBehaviourAction::GenerateEvent(RequestResponseEvent::OutboundFailure {
+                        peer,
+                        request_id,
+                        error
+                    }) if self.routes.contains_key(&request_id) => {
+                        self.on_attempt_failure(peer, request_id, error);
+                        continue
+                    }
+                    NetworkBehaviourAction::GenerateEvent(RequestResponseEvent::Message {
+                        message: RequestResponseMessage::Response { request_id, .. }, ..
+                    }) => {
+                        self.routes.remove(&request_id);
+                        return Poll::Ready(ev)
+                    }
+                    ev => return Poll::Ready(ev)
+                }
+            } else if self.pending_events.capacity() > EMPTY_QUEUE_SHRINK_THRESHOLD {
+                self.pending_events.shrink_to_fit();
+            }
 
-        Poll::Pending
+            // Send the next attempts of routed requests whose backoff has elapsed.
+            let ready = self.routes.iter_mut()
+                .filter_map(|(id, route)| {
+                    let delay = route.backoff.as_mut()?;
+                    match delay.poll_unpin(cx) {
+                        Poll::Pending => None,
+                        Poll::Ready(_) => {
+                            route.backoff = None;
+                            Some(*id)
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            if ready.is_empty() {
+                return Poll::Pending
+            }
+
+            for request_id in ready {
+                self.send_next_attempt(request_id)
+            }
+        }
     }
 }
 
@@ -0,0 +1,263 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Sending a request to any of a set of peers.
+//!
+//! A request sent via [`RequestResponse::send_request_to_any`] is tried
+//! with one peer after the other, as ranked by the [`RetryPolicy`], until
+//! a response is received. Failed attempts are retried with exponential
+//! backoff, cycling through the peers, as long as the failure is one of
+//! [`OutboundFailure::DialFailure`], [`OutboundFailure::Timeout`] or
+//! [`OutboundFailure::UnsupportedProtocols`] and the maximum number of
+//! attempts has not been reached. Otherwise
+//! [`RequestResponseEvent::OutboundRoutingFailure`] is emitted with the
+//! errors of all attempts.
+//!
+//! [`RequestResponse::send_request_to_any`]: crate::RequestResponse::send_request_to_any
+//! [`RequestResponseEvent::OutboundRoutingFailure`]: crate::RequestResponseEvent::OutboundRoutingFailure
+
+use crate::OutboundFailure;
+use libp2p_core::PeerId;
+use std::{cmp, fmt, sync::Arc, time::Duration};
+use wasm_timer::Delay;
+
+/// The order in which the peers of a request sent via
+/// [`RequestResponse::send_request_to_any`](crate::RequestResponse::send_request_to_any)
+/// are tried.
+#[derive(Clone)]
+pub enum PeerRanking {
+    /// The peers are tried in the given order.
+    Given,
+    /// Connected peers are tried first, otherwise the given order is kept.
+    ConnectedFirst,
+    /// The peers are tried in ascending order of the key returned by the
+    /// function. Peers with equal keys keep their given order.
+    Custom(Arc<dyn Fn(&PeerId) -> u64 + Send + Sync>),
+}
+
+impl fmt::Debug for PeerRanking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerRanking::Given => f.write_str("Given"),
+            PeerRanking::ConnectedFirst => f.write_str("ConnectedFirst"),
+            PeerRanking::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// The policy for retrying a request sent to any of a set of peers.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    backoff_factor: u32,
+    ranking: PeerRanking,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            backoff_factor: 2,
+            ranking: PeerRanking::Given,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sets the maximum number of attempts, across all peers.
+    ///
+    /// If there are more attempts than peers, the peers are tried again
+    /// in the same order. A value of 0 is treated as 1.
+    pub fn set_max_attempts(&mut self, v: usize) -> &mut Self {
+        self.max_attempts = cmp::max(1, v);
+        self
+    }
+
+    /// Sets the delay before the second attempt.
+    pub fn set_initial_backoff(&mut self, v: Duration) -> &mut Self {
+        self.initial_backoff = v;
+        self
+    }
+
+    /// Sets the upper bound of the delay between two attempts.
+    pub fn set_max_backoff(&mut self, v: Duration) -> &mut Self {
+        self.max_backoff = v;
+        self
+    }
+
+    /// Sets the factor by which the delay grows with every further attempt.
+    pub fn set_backoff_factor(&mut self, v: u32) -> &mut Self {
+        self.backoff_factor = v;
+        self
+    }
+
+    /// Sets the order in which the peers are tried.
+    pub fn set_ranking(&mut self, v: PeerRanking) -> &mut Self {
+        self.ranking = v;
+        self
+    }
+
+    /// The delay before the given (zero-based) attempt.
+    fn backoff(&self, attempt: usize) -> Duration {
+        if attempt == 0 {
+            return Duration::from_secs(0)
+        }
+        let mut delay = self.initial_backoff;
+        for _ in 1 .. attempt {
+            delay = delay.checked_mul(self.backoff_factor).unwrap_or(self.max_backoff);
+            if delay >= self.max_backoff {
+                break
+            }
+        }
+        cmp::min(delay, self.max_backoff)
+    }
+}
+
+/// Whether an attempt that failed with the given error is retried.
+fn is_retryable(error: &OutboundFailure) -> bool {
+    match error {
+        OutboundFailure::DialFailure
+        | OutboundFailure::Timeout
+        | OutboundFailure::UnsupportedProtocols => true,
+        OutboundFailure::ConnectionClosed
+        | OutboundFailure::Io(_)
+        | OutboundFailure::Cancelled => false,
+    }
+}
+
+/// The state of a request sent to any of a set of peers.
+pub(crate) struct Route<TRequest> {
+    /// The peers in the order in which they are tried.
+    peers: Vec<PeerId>,
+    /// Creates the request for every attempt.
+    request: Box<dyn Fn() -> TRequest + Send>,
+    /// The retry policy of this request.
+    policy: RetryPolicy,
+    /// The peer and error of every failed attempt.
+    failures: Vec<(PeerId, OutboundFailure)>,
+    /// The delay until the next attempt, if any.
+    pub(crate) backoff: Option<Delay>,
+}
+
+impl<TRequest> Route<TRequest> {
+    /// Creates a new route, ranking the given peers according to the
+    /// policy. `is_connected` tells whether a peer is currently connected.
+    pub(crate) fn new<F>(mut peers: Vec<PeerId>, request: TRequest, policy: RetryPolicy, is_connected: F) -> Self
+    where
+        TRequest: Clone + Send + 'static,
+        F: Fn(&PeerId) -> bool
+    {
+        match &policy.ranking {
+            PeerRanking::Given => {}
+            PeerRanking::ConnectedFirst => peers.sort_by_key(|p| !is_connected(p)),
+            PeerRanking::Custom(f) => peers.sort_by_key(|p| f(p)),
+        }
+        let request = Box::new(move || request.clone());
+        Route { peers, request, policy, failures: Vec::new(), backoff: None }
+    }
+
+    /// The peer and request of the next attempt, if any.
+    pub(crate) fn next_attempt(&self) -> Option<(PeerId, TRequest)> {
+        if !self.has_attempts_left() {
+            return None
+        }
+        let peer = self.peers[self.failures.len() % self.peers.len()].clone();
+        Some((peer, (self.request)()))
+    }
+
+    fn has_attempts_left(&self) -> bool {
+        !self.peers.is_empty() && self.failures.len() < self.policy.max_attempts
+    }
+
+    /// Records a failed attempt.
+    ///
+    /// Returns the delay before the next attempt, or `None` if no further
+    /// attempt is made.
+    pub(crate) fn on_failure(&mut self, peer: PeerId, error: OutboundFailure) -> Option<Duration> {
+        let retry = is_retryable(&error);
+        self.failures.push((peer, error));
+        if retry && self.has_attempts_left() {
+            Some(self.policy.backoff(self.failures.len()))
+        } else {
+            None
+        }
+    }
+
+    /// The peer of the most recent failed attempt, if any.
+    pub(crate) fn last_peer(&self) -> Option<PeerId> {
+        self.failures.last().map(|(peer, _)| peer.clone())
+    }
+
+    /// Consumes the route, returning the failed attempts.
+    pub(crate) fn into_failures(self) -> Vec<(PeerId, OutboundFailure)> {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_max() {
+        let mut policy = RetryPolicy::default();
+        policy.set_initial_backoff(Duration::from_millis(100))
+            .set_backoff_factor(3)
+            .set_max_backoff(Duration::from_secs(1));
+        assert_eq!(policy.backoff(0), Duration::from_secs(0));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(300));
+        assert_eq!(policy.backoff(3), Duration::from_millis(900));
+        assert_eq!(policy.backoff(4), Duration::from_secs(1));
+        assert_eq!(policy.backoff(100), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn attempts_cycle_through_ranked_peers() {
+        let peers = (0 .. 3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let mut policy = RetryPolicy::default();
+        policy.set_max_attempts(4).set_ranking(PeerRanking::ConnectedFirst);
+        let connected = peers[2].clone();
+        let mut route = Route::new(peers.clone(), (), policy, |p| p == &connected);
+
+        let mut tried = Vec::new();
+        while let Some((peer, ())) = route.next_attempt() {
+            tried.push(peer.clone());
+            if route.on_failure(peer, OutboundFailure::DialFailure).is_none() {
+                break
+            }
+        }
+        assert_eq!(tried, vec![peers[2].clone(), peers[0].clone(), peers[1].clone(), peers[2].clone()]);
+        assert_eq!(route.into_failures().len(), 4);
+    }
+
+    #[test]
+    fn non_retryable_failure_ends_route() {
+        let peers = vec![PeerId::random(), PeerId::random()];
+        let mut route = Route::new(peers.clone(), (), RetryPolicy::default(), |_| false);
+        let (peer, ()) = route.next_attempt().unwrap();
+        assert_eq!(peer, peers[0]);
+        assert!(route.on_failure(peer, OutboundFailure::ConnectionClosed).is_none());
+    }
+}
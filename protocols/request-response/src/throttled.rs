@@ -18,7 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-//! Limit the number of requests peers can send to each other.
+//! Limit the number and size of requests peers can send to each other.
 //!
 //! Each peer is assigned a budget for sending and a budget for receiving
 //! requests. Initially a peer assumes it has a send budget of 1. When its
@@ -28,6 +28,18 @@
 //! retried until they have reached the peer which is assumed once a
 //! corresponding ack or a new request has been received from the peer.
 //!
+//! The budget a peer grants is determined by a [`Quota`], which limits the
+//! number of requests and optionally their total size in bytes, i.e. the
+//! size of their encoding, which the sender counts while writing a request
+//! and the receiver while reading it, see [`Quota::set_max_bytes`]. Quotas
+//! can be configured per peer and per protocol. Requests sent with
+//! [`Throttled::send_request_with_protocol`] are accounted against a budget
+//! of their own for the protocol they are received with, all other requests
+//! share a single budget.
+//! Budgets are refilled when the remote has used them up and its requests
+//! have been answered or, if the quota has a refill interval, once this
+//! interval has elapsed.
+//!
 //! The `Throttled` behaviour wraps an existing `RequestResponse` behaviour
 //! and uses a codec implementation that sends ordinary requests and responses
 //! as well as a special credit message to which an ack message is expected
@@ -36,14 +48,14 @@
 
 mod codec;
 
-use codec::{Codec, Message, ProtocolWrapper, Type};
-use crate::handler::{RequestProtocol, RequestResponseHandler, RequestResponseHandlerEvent};
-use futures::ready;
-use libp2p_core::{ConnectedPoint, connection::ConnectionId, Multiaddr, PeerId};
+use codec::{Codec, Message, ProtocolWrapper, SizeReport, Type, class_name};
+use crate::handler::{RequestResponseHandler, RequestResponseHandlerEvent, RequestResponseHandlerIn};
+use futures::{channel::mpsc, prelude::*, ready};
+use libp2p_core::{ConnectedPoint, connection::ConnectionId, Multiaddr, PeerId, ProtocolName};
 use libp2p_swarm::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use lru::LruCache;
-use std::{collections::{HashMap, VecDeque}, task::{Context, Poll}};
-use std::{cmp::max, num::NonZeroU16};
+use std::{collections::{HashMap, VecDeque}, iter, task::{Context, Poll}, time::Duration};
+use std::{cmp::max, num::{NonZeroU16, NonZeroU64}};
 use super::{
     ProtocolSupport,
     RequestId,
@@ -52,8 +64,17 @@ use super::{
     RequestResponseConfig,
     RequestResponseEvent,
     RequestResponseMessage,
-    ResponseChannel
+    ResponseChannel,
+    RetryPolicy
 };
+use wasm_timer::{Delay, Instant};
+
+/// The name of the protocol a budget applies to, or `None` for the
+/// budget shared by all requests not sent with a specific protocol.
+type Class = Option<String>;
+
+/// The prefix of the names of the protocols used by [`Throttled`].
+const PROTOCOL_PREFIX: &[u8] = b"/t/1";
 
 /// A wrapper around [`RequestResponse`] which adds request limits per peer.
 pub struct Throttled<C>
@@ -69,18 +90,45 @@ where
     peer_info: HashMap<PeerId, PeerInfo>,
     /// Information about previously connected peers.
     offline_peer_info: LruCache<PeerId, PeerInfo>,
-    /// The default limit applies to all peers unless overriden.
-    default_limit: Limit,
-    /// Permanent limit overrides per peer.
-    limit_overrides: HashMap<PeerId, Limit>,
+    /// The default quota applies to all peers unless overriden.
+    default_quota: Quota,
+    /// Permanent quota overrides per peer.
+    quota_overrides: HashMap<PeerId, Quota>,
+    /// Quotas per protocol, which take precedence over the peer quotas.
+    protocol_quotas: HashMap<String, Quota>,
+    /// The smallest refill interval of all quotas and the timer to check
+    /// for budgets due to be refilled.
+    refill_timer: Option<(Duration, Delay)>,
     /// Pending events to report in `Throttled::poll`.
     events: VecDeque<Event<C::Request, C::Response, Message<C::Response>>>,
+    /// The tokens of the requests sent via `send_request_to_any` which
+    /// are still pending, together with their sizes once reported.
+    routes: HashMap<RequestId, (u64, Option<u64>)>,
+    /// The sender of the sizes of outbound requests by token, which the
+    /// codec reports once a request has been written.
+    size_sender: mpsc::UnboundedSender<(u64, u64)>,
+    /// The receiver of the reported sizes of outbound requests.
+    size_receiver: mpsc::UnboundedReceiver<(u64, u64)>,
+    /// Outbound requests whose size is yet to be reported, by token.
+    unmeasured: HashMap<u64, Unmeasured>,
+    /// The token of the next outbound request.
+    next_token: u64,
+    /// The peers and classes of inbound requests yet to be answered.
+    unanswered: HashMap<RequestId, (PeerId, Class)>,
     /// Current outbound credit grants in flight.
-    credit_messages: HashMap<PeerId, Credit>,
+    credit_messages: HashMap<(PeerId, Class), Credit>,
     /// The current credit ID.
     credit_id: u64
 }
 
+/// An outbound request whose size is yet to be reported by the codec.
+#[derive(Debug)]
+struct Unmeasured {
+    request: RequestId,
+    peer: PeerId,
+    class: Class
+}
+
 /// Credit information that is sent to remote peers.
 #[derive(Clone, Copy, Debug)]
 struct Credit {
@@ -89,69 +137,209 @@ struct Credit {
     /// The ID of the outbound credit grant message.
     request: RequestId,
     /// The number of requests the remote is allowed to send.
-    amount: u16
+    amount: u16,
+    /// The number of bytes the remote is allowed to send, if limited.
+    bytes: Option<u64>
 }
 
-/// Max. number of inbound requests that can be received.
-#[derive(Clone, Copy, Debug)]
-struct Limit {
-    /// The current receive limit.
-    max_recv: NonZeroU16,
-    /// The next receive limit which becomes active after
-    /// the current limit has been reached.
-    next_max: NonZeroU16
+/// Max. number and size of inbound requests that can be received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    /// The max. number of requests.
+    requests: NonZeroU16,
+    /// The max. total size of the requests in bytes.
+    bytes: Option<NonZeroU64>,
+    /// The interval after which the budget is refilled.
+    refill: Option<Duration>
 }
 
-impl Limit {
-    /// Create a new limit.
-    fn new(max: NonZeroU16) -> Self {
-        // The max. limit provided will be effective after the initial request
+impl Quota {
+    /// Create a quota of the given number of requests.
+    pub fn new(requests: NonZeroU16) -> Self {
+        Quota { requests, bytes: None, refill: None }
+    }
+
+    /// Limit the total size of the requests.
+    ///
+    /// The size of a request is the number of bytes its encoding takes
+    /// up. Since the size of a request is only known once it has been
+    /// written, the last request within the limit may exceed the bytes
+    /// that remain, which it then uses up. While limited in bytes, the
+    /// remote waits for every request to be written before sending the
+    /// next one.
+    pub fn set_max_bytes(&mut self, v: NonZeroU64) -> &mut Self {
+        self.bytes = Some(v);
+        self
+    }
+
+    /// Refill the budget of a peer once the given interval has elapsed
+    /// since it was last refilled, even if not all of its requests have
+    /// been answered yet.
+    pub fn set_refill_interval(&mut self, v: Duration) -> &mut Self {
+        self.refill = Some(v);
+        self
+    }
+
+    /// The max. number of requests.
+    pub fn requests(&self) -> NonZeroU16 {
+        self.requests
+    }
+
+    /// The max. total size of the requests in bytes, if limited.
+    pub fn max_bytes(&self) -> Option<NonZeroU64> {
+        self.bytes
+    }
+
+    /// The refill interval, if any.
+    pub fn refill_interval(&self) -> Option<Duration> {
+        self.refill
+    }
+
+    /// The quota which applies until the initial request of a peer has
+    /// been answered.
+    fn initial() -> Self {
+        // The configured quota will be effective after the initial request
         // from a peer which is always allowed has been answered. Values greater
         // than 1 would prevent sending the credit grant, leading to a stalling
-        // sender so we must not use `max` right away.
-        Limit {
-            max_recv: NonZeroU16::new(1).expect("1 > 0"),
-            next_max: max
+        // sender so we must not use it right away.
+        Quota::new(NonZeroU16::new(1).expect("1 > 0"))
+    }
+}
+
+/// The remaining number of requests and bytes that can be sent or received.
+#[derive(Clone, Copy, Debug)]
+struct Budget {
+    /// The number of requests.
+    requests: u16,
+    /// The number of bytes, if limited.
+    bytes: Option<u64>
+}
+
+impl Budget {
+    fn new() -> Self {
+        Budget { requests: 1, bytes: None }
+    }
+
+    /// Does the budget allow for another request?
+    ///
+    /// A request is allowed as long as any bytes remain, since its size
+    /// is not known before it has been written.
+    fn allows(&self) -> bool {
+        self.requests > 0 && self.bytes != Some(0)
+    }
+
+    /// Use up the budget of a request of the given size.
+    ///
+    /// A request the budget does not allow for uses up what remains.
+    fn consume(&mut self, size: u64) {
+        self.requests = self.requests.saturating_sub(1);
+        self.consume_bytes(size)
+    }
+
+    /// Use up the bytes of a request whose size has been reported after
+    /// the request was accounted for.
+    fn consume_bytes(&mut self, size: u64) {
+        if let Some(b) = &mut self.bytes {
+            *b = b.saturating_sub(size)
         }
     }
 
-    /// Set a new limit.
+    /// Add a credit grant.
     ///
-    /// The new limit becomes effective when all current inbound
-    /// requests have been processed and replied to.
-    fn set(&mut self, next: NonZeroU16) {
-        self.next_max = next
+    /// A grant without bytes lifts the byte limit. If the budget has no
+    /// byte limit, the granted bytes become the limit.
+    fn add(&mut self, credit: u16, bytes: Option<u64>) {
+        self.requests = self.requests.saturating_add(credit);
+        self.bytes = match (self.bytes, bytes) {
+            (_, None) => None,
+            (None, Some(n)) => Some(n),
+            (Some(b), Some(n)) => Some(b.saturating_add(n))
+        }
+    }
+
+    /// Is the budget below the given quota?
+    fn is_below(&self, q: &Quota) -> bool {
+        if self.requests < q.requests.get() {
+            return true
+        }
+        match (self.bytes, q.bytes) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(b), Some(m)) => b < m.get()
+        }
     }
 
-    /// Activate the new limit.
-    fn switch(&mut self) -> u16 {
-        self.max_recv = self.next_max;
-        self.max_recv.get()
+    /// Has the budget been used up, in requests or in bytes?
+    fn is_exhausted(&self, q: &Quota) -> bool {
+        self.requests == 0 || (q.bytes.is_some() && self.is_below(q))
+    }
+
+    /// Top up the budget to the given quota and return the credit to grant.
+    fn top_up(&mut self, q: &Quota) -> (u16, Option<u64>) {
+        let credit = q.requests.get().saturating_sub(self.requests);
+        let bytes = match (q.bytes, self.bytes) {
+            (None, _) => None,
+            (Some(m), None) => Some(m.get()),
+            (Some(m), Some(b)) => Some(m.get().saturating_sub(b))
+        };
+        self.add(credit, bytes);
+        (credit, bytes)
     }
 }
 
-/// Budget information about a peer.
+/// Budget information about a peer for a single protocol.
 #[derive(Clone, Debug)]
-struct PeerInfo {
-    /// Limit that applies to this peer.
-    limit: Limit,
-    /// Remaining number of outbound requests that can be sent.
-    send_budget: u16,
-    /// Remaining number of inbound requests that can be received.
-    recv_budget: u16,
+struct Budgets {
+    /// The quota currently in effect.
+    quota: Quota,
+    /// Remaining outbound requests that can be sent.
+    send_budget: Budget,
+    /// Remaining inbound requests that can be received.
+    recv_budget: Budget,
     /// The ID of the credit message that granted the current `send_budget`.
-    send_budget_id: Option<u64>
+    send_budget_id: Option<u64>,
+    /// Has sending a request been refused since the last credit grant?
+    blocked: bool,
+    /// The number of requests sent whose size is yet to be reported.
+    unmeasured: usize,
+    /// When the receive budget was last refilled.
+    last_refill: Instant
 }
 
-impl PeerInfo {
-    fn new(limit: Limit) -> Self {
-        PeerInfo {
-            limit,
-            send_budget: 1,
-            recv_budget: 1,
-            send_budget_id: None
+impl Budgets {
+    fn new() -> Self {
+        Budgets {
+            quota: Quota::initial(),
+            send_budget: Budget::new(),
+            recv_budget: Budget::new(),
+            send_budget_id: None,
+            blocked: false,
+            unmeasured: 0,
+            last_refill: Instant::now()
         }
     }
+
+    /// Can another request be sent?
+    ///
+    /// While the send budget is limited in bytes, this waits for the
+    /// sizes of the requests already sent to be reported.
+    fn can_send(&self) -> bool {
+        self.send_budget.allows() && (self.unmeasured == 0 || self.send_budget.bytes.is_none())
+    }
+}
+
+/// Budget information about a peer.
+#[derive(Clone, Debug, Default)]
+struct PeerInfo {
+    /// The budgets per protocol.
+    budgets: HashMap<Class, Budgets>
+}
+
+impl PeerInfo {
+    /// The budgets of the given protocol.
+    fn budgets(&mut self, c: &Class) -> &mut Budgets {
+        self.budgets.entry(c.clone()).or_insert_with(Budgets::new)
+    }
 }
 
 impl<C> Throttled<C>
@@ -166,20 +354,30 @@ where
         C: Send,
         C::Protocol: Sync
     {
-        let protos = protos.into_iter().map(|(p, ps)| (ProtocolWrapper::new(b"/t/1", p), ps));
+        let protos = protos.into_iter().map(|(p, ps)| (ProtocolWrapper::new(PROTOCOL_PREFIX, p), ps));
         Throttled::from(RequestResponse::new(Codec::new(c, 8192), protos, cfg))
     }
 
     /// Wrap an existing `RequestResponse` behaviour and apply send/recv limits.
-    pub fn from(behaviour: RequestResponse<Codec<C>>) -> Self {
+    pub fn from(mut behaviour: RequestResponse<Codec<C>>) -> Self {
+        behaviour.record_attempts();
+        let (size_sender, size_receiver) = mpsc::unbounded();
         Throttled {
             id: rand::random(),
             behaviour,
             peer_info: HashMap::new(),
             offline_peer_info: LruCache::new(8192),
-            default_limit: Limit::new(NonZeroU16::new(1).expect("1 > 0")),
-            limit_overrides: HashMap::new(),
+            default_quota: Quota::initial(),
+            quota_overrides: HashMap::new(),
+            protocol_quotas: HashMap::new(),
+            refill_timer: None,
             events: VecDeque::new(),
+            routes: HashMap::new(),
+            size_sender,
+            size_receiver,
+            unmeasured: HashMap::new(),
+            next_token: 0,
+            unanswered: HashMap::new(),
             credit_messages: HashMap::new(),
             credit_id: 0
         }
@@ -187,30 +385,63 @@ where
 
     /// Set the global default receive limit per peer.
     pub fn set_receive_limit(&mut self, limit: NonZeroU16) {
-        log::trace!("{:08x}: new default limit: {:?}", self.id, limit);
-        self.default_limit = Limit::new(limit)
+        self.set_default_quota(Quota::new(limit))
+    }
+
+    /// Set the global default quota per peer.
+    ///
+    /// The new quota becomes effective when a peer's budget is refilled.
+    pub fn set_default_quota(&mut self, quota: Quota) {
+        log::trace!("{:08x}: new default quota: {:?}", self.id, quota);
+        self.default_quota = quota;
+        self.reset_refill_timer()
     }
 
     /// Override the receive limit of a single peer.
     pub fn override_receive_limit(&mut self, p: &PeerId, limit: NonZeroU16) {
-        log::debug!("{:08x}: override limit for {}: {:?}", self.id, p, limit);
-        if let Some(info) = self.peer_info.get_mut(p) {
-            info.limit.set(limit)
-        } else if let Some(info) = self.offline_peer_info.get_mut(p) {
-            info.limit.set(limit)
-        }
-        self.limit_overrides.insert(p.clone(), Limit::new(limit));
+        self.override_quota(p, Quota::new(limit))
+    }
+
+    /// Override the quota of a single peer.
+    ///
+    /// The new quota becomes effective when the peer's budget is refilled.
+    pub fn override_quota(&mut self, p: &PeerId, quota: Quota) {
+        log::debug!("{:08x}: override quota for {}: {:?}", self.id, p, quota);
+        self.quota_overrides.insert(p.clone(), quota);
+        self.reset_refill_timer()
     }
 
     /// Remove any limit overrides for the given peer.
     pub fn remove_override(&mut self, p: &PeerId) {
         log::trace!("{:08x}: removing limit override for {}", self.id, p);
-        self.limit_overrides.remove(p);
+        self.quota_overrides.remove(p);
+        self.reset_refill_timer()
+    }
+
+    /// Set the quota of every peer for requests sent with the given protocol.
+    ///
+    /// The protocol quota takes precedence over the default quota and any
+    /// quota overrides, but only applies to requests the remote sends with
+    /// [`Throttled::send_request_with_protocol`].
+    pub fn set_protocol_quota(&mut self, p: &C::Protocol, quota: Quota) {
+        let class = class_name(p.protocol_name());
+        log::trace!("{:08x}: new quota for protocol {}: {:?}", self.id, class, quota);
+        self.protocol_quotas.insert(class, quota);
+        self.reset_refill_timer()
+    }
+
+    /// Remove the quota of the given protocol.
+    pub fn remove_protocol_quota(&mut self, p: &C::Protocol) {
+        self.protocol_quotas.remove(&class_name(p.protocol_name()));
+        self.reset_refill_timer()
     }
 
     /// Has the limit of outbound requests been reached for the given peer?
     pub fn can_send(&mut self, p: &PeerId) -> bool {
-        self.peer_info.get(p).map(|i| i.send_budget > 0).unwrap_or(true)
+        self.peer_info.get(p)
+            .and_then(|i| i.budgets.get(&None))
+            .map(|b| b.can_send())
+            .unwrap_or(true)
     }
 
     /// Send a request to a peer.
@@ -219,48 +450,142 @@ where
     /// returned. Sending more outbound requests should only be attempted
     /// once [`Event::ResumeSending`] has been received from [`NetworkBehaviour::poll`].
     pub fn send_request(&mut self, p: &PeerId, req: C::Request) -> Result<RequestId, C::Request> {
-        let info =
-            if let Some(info) = self.peer_info.get_mut(p) {
-                info
-            } else if let Some(info) = self.offline_peer_info.pop(p) {
-                if info.recv_budget > 1 {
-                    self.send_credit(p, info.recv_budget - 1)
-                }
-                self.peer_info.entry(p.clone()).or_insert(info)
-            } else {
-                let limit = self.limit_overrides.get(p).copied().unwrap_or(self.default_limit);
-                self.peer_info.entry(p.clone()).or_insert(PeerInfo::new(limit))
-            };
+        self.send_request_inner(p, None, req, None)
+    }
+
+    /// Send a request to a peer with the given timeout instead of the
+    /// configured one.
+    ///
+    /// See [`Throttled::send_request`] and
+    /// [`RequestResponse::send_request_with_timeout`] for details.
+    pub fn send_request_with_timeout(&mut self, p: &PeerId, req: C::Request, timeout: Duration)
+        -> Result<RequestId, C::Request>
+    {
+        self.send_request_inner(p, None, req, Some(timeout))
+    }
+
+    /// Send a request to a peer using only the given protocol.
+    ///
+    /// The request is accounted against the budget for this protocol,
+    /// which the remote limits with the quota set via
+    /// [`Throttled::set_protocol_quota`], if any. See
+    /// [`Throttled::send_request`] for details.
+    pub fn send_request_with_protocol(&mut self, p: &PeerId, proto: &C::Protocol, req: C::Request)
+        -> Result<RequestId, C::Request>
+    {
+        self.send_request_inner(p, Some(proto), req, None)
+    }
+
+    fn send_request_inner(
+        &mut self,
+        p: &PeerId,
+        proto: Option<&C::Protocol>,
+        req: C::Request,
+        timeout: Option<Duration>
+    ) -> Result<RequestId, C::Request> {
+        self.insert_peer_info(p);
+
+        let class = proto.map(|p| class_name(p.protocol_name()));
+        let budgets = self.peer_info.get_mut(p).expect("inserted above").budgets(&class);
 
-        if info.send_budget == 0 {
+        if !budgets.can_send() {
             log::trace!("{:08x}: no more budget to send another request to {}", self.id, p);
+            budgets.blocked = true;
             return Err(req)
         }
 
-        info.send_budget -= 1;
+        // The bytes are used up once the size of the request is reported.
+        budgets.send_budget.consume(0);
+        budgets.unmeasured += 1;
+        let remaining = budgets.send_budget.requests;
 
-        let rid = self.behaviour.send_request(p, Message::request(req));
+        let token = self.next_token();
+        let msg = Message::request(req, class.clone()).with_size_report(self.size_report(token));
+        let rid =
+            if let Some(proto) = proto {
+                let wrapper = self.behaviour.outbound_protocols().iter()
+                    .find(|w| w.inner().protocol_name() == proto.protocol_name())
+                    .cloned()
+                    .unwrap_or_else(|| ProtocolWrapper::new(PROTOCOL_PREFIX, proto.clone()));
+                self.behaviour.send_request_with_protocol(p, msg, wrapper, timeout)
+            } else if let Some(timeout) = timeout {
+                self.behaviour.send_request_with_timeout(p, msg, timeout)
+            } else {
+                self.behaviour.send_request(p, msg)
+            };
+        self.unmeasured.insert(token, Unmeasured { request: rid, peer: p.clone(), class });
 
         log::trace! { "{:08x}: sending request {} to {} (send budget = {})",
             self.id,
             rid,
             p,
-            info.send_budget + 1
+            remaining + 1
         };
 
         Ok(rid)
     }
 
+    /// Cancel a pending outbound request.
+    ///
+    /// See [`RequestResponse::cancel_request`] for details. The send
+    /// budget used up by the request is not restored, since the remote
+    /// may already have received it.
+    pub fn cancel_request(&mut self, id: RequestId) -> bool {
+        self.routes.remove(&id);
+        self.behaviour.cancel_request(id)
+    }
+
+    /// Send a request to any of the given peers.
+    ///
+    /// Only the peers whose send budget allows for the request are tried
+    /// and every attempt is accounted against the budget of the peer it is
+    /// sent to. If none of the peers has the budget, the request is returned
+    /// and [`Event::ResumeSending`] is eventually emitted for each of them.
+    ///
+    /// See [`RequestResponse::send_request_to_any`] for details.
+    pub fn send_request_to_any<I>(&mut self, peers: I, req: C::Request, policy: RetryPolicy)
+        -> Result<RequestId, C::Request>
+    where
+        I: IntoIterator<Item = PeerId>,
+        C::Request: Clone + 'static
+    {
+        let mut candidates = Vec::new();
+        for p in peers {
+            self.insert_peer_info(&p);
+            let budgets = self.peer_info.get_mut(&p).expect("inserted above").budgets(&None);
+            if budgets.can_send() {
+                candidates.push(p)
+            } else {
+                budgets.blocked = true
+            }
+        }
+
+        if candidates.is_empty() {
+            log::trace!("{:08x}: no more budget to send another request to any peer", self.id);
+            return Err(req)
+        }
+
+        let token = self.next_token();
+        let msg = Message::request(req, None).with_size_report(self.size_report(token));
+        let rid = self.behaviour.send_request_to_any(candidates, msg, policy);
+        self.routes.insert(rid, (token, None));
+        self.consume_attempts();
+        Ok(rid)
+    }
+
     /// Answer an inbound request with a response.
     ///
     /// See [`RequestResponse::send_response`] for details.
     pub fn send_response(&mut self, ch: ResponseChannel<Message<C::Response>>, res: C::Response) {
         log::trace!("{:08x}: sending response {} to peer {}", self.id, ch.request_id(), &ch.peer);
-        if let Some(info) = self.peer_info.get_mut(&ch.peer) {
-            if info.recv_budget == 0 { // need to send more credit to the remote peer
-                let crd = info.limit.switch();
-                info.recv_budget = info.limit.max_recv.get();
-                self.send_credit(&ch.peer, crd)
+        if let Some((_, class)) = self.unanswered.remove(&ch.request_id()) {
+            let exhausted = self.peer_info.get(&ch.peer)
+                .and_then(|info| info.budgets.get(&class))
+                .map(|b| b.recv_budget.is_exhausted(&b.quota))
+                .unwrap_or(false);
+            // need to send more credit to the remote peer
+            if exhausted && !self.credit_messages.contains_key(&(ch.peer.clone(), class.clone())) {
+                self.refill(&ch.peer, &class)
             }
         }
         self.behaviour.send_response(ch, Message::response(res))
@@ -294,13 +619,189 @@ where
         self.behaviour.is_pending_outbound(p)
     }
 
+    /// Add the budget information of the given peer unless present,
+    /// restoring the information of a previous connection, if any.
+    fn insert_peer_info(&mut self, p: &PeerId) {
+        if !self.peer_info.contains_key(p) {
+            let info =
+                if let Some(info) = self.offline_peer_info.pop(p) {
+                    self.restore_credit(p, &info);
+                    info
+                } else {
+                    PeerInfo::default()
+                };
+            self.peer_info.insert(p.clone(), info);
+        }
+    }
+
+    /// Account the attempts of requests sent via `send_request_to_any`
+    /// against the send budgets of the peers they are sent to.
+    fn consume_attempts(&mut self) {
+        while let Some((rid, p)) = self.behaviour.next_attempt_sent() {
+            let (token, size) = if let Some(route) = self.routes.get(&rid) { *route } else { continue };
+            // The previous attempt failed before the request was written.
+            if let Some(u) = self.unmeasured.remove(&token) {
+                self.account_size(u, None)
+            }
+            self.insert_peer_info(&p);
+            let budgets = self.peer_info.get_mut(&p).expect("inserted above").budgets(&None);
+            // A retry may be sent to a peer whose budget has been used
+            // up since the request was sent, which overdraws the budget.
+            if !budgets.can_send() {
+                budgets.blocked = true
+            }
+            budgets.send_budget.consume(size.unwrap_or(0));
+            if size.is_none() {
+                budgets.unmeasured += 1;
+                self.unmeasured.insert(token, Unmeasured { request: rid, peer: p.clone(), class: None });
+            }
+            log::trace! { "{:08x}: sending attempt of request {} to {} (send budget = {})",
+                self.id,
+                rid,
+                p,
+                budgets.send_budget.requests
+            };
+        }
+    }
+
+    /// A report of the size of the outbound request with the given token.
+    fn size_report(&self, token: u64) -> SizeReport {
+        SizeReport::new(token, self.size_sender.clone())
+    }
+
+    /// Create a new token of an outbound request.
+    fn next_token(&mut self) -> u64 {
+        let n = self.next_token;
+        self.next_token += 1;
+        n
+    }
+
+    /// Account the sizes of outbound requests reported by the codec.
+    fn poll_size_reports(&mut self, cx: &mut Context<'_>) {
+        while let Poll::Ready(Some((token, size))) = self.size_receiver.poll_next_unpin(cx) {
+            if let Some(u) = self.unmeasured.remove(&token) {
+                if let Some(route) = self.routes.get_mut(&u.request) {
+                    route.1 = Some(size)
+                }
+                self.account_size(u, Some(size))
+            }
+        }
+    }
+
+    /// Stop waiting for the size of a failed outbound request to be reported.
+    fn forget_size(&mut self, request: RequestId) {
+        let tokens = self.unmeasured.iter()
+            .filter(|(_, u)| u.request == request)
+            .map(|(t, _)| *t)
+            .collect::<Vec<_>>();
+        for t in tokens {
+            if let Some(u) = self.unmeasured.remove(&t) {
+                self.account_size(u, None)
+            }
+        }
+    }
+
+    /// Use up the bytes of an outbound request whose size has been reported,
+    /// if any, resuming sending if it has been waiting for the size.
+    fn account_size(&mut self, u: Unmeasured, size: Option<u64>) {
+        let budgets =
+            if let Some(b) = self.peer_info.get_mut(&u.peer).and_then(|i| i.budgets.get_mut(&u.class)) {
+                b
+            } else {
+                return
+            };
+        budgets.unmeasured = budgets.unmeasured.saturating_sub(1);
+        if let Some(size) = size {
+            budgets.send_budget.consume_bytes(size)
+        }
+        if budgets.blocked && budgets.can_send() {
+            log::trace!("{:08x}: sending to peer {} can resume", self.id, u.peer);
+            budgets.blocked = false;
+            self.events.push_back(Event::ResumeSending(u.peer))
+        }
+    }
+
+    /// The quota which applies to the given peer and protocol.
+    fn quota(&self, p: &PeerId, class: &Class) -> Quota {
+        class.as_ref()
+            .and_then(|c| self.protocol_quotas.get(c))
+            .or_else(|| self.quota_overrides.get(p))
+            .copied()
+            .unwrap_or(self.default_quota)
+    }
+
+    /// Restart the refill timer with the smallest refill interval of all quotas.
+    fn reset_refill_timer(&mut self) {
+        let interval = iter::once(&self.default_quota)
+            .chain(self.quota_overrides.values())
+            .chain(self.protocol_quotas.values())
+            .filter_map(|q| q.refill)
+            .min();
+        self.refill_timer = interval.map(|d| (d, Delay::new(d)))
+    }
+
+    /// Activate the current quota of the given peer and protocol and send
+    /// the credit needed to top up the peer's budget to this quota.
+    fn refill(&mut self, p: &PeerId, class: &Class) {
+        let quota = self.quota(p, class);
+        let budgets =
+            if let Some(b) = self.peer_info.get_mut(p).and_then(|i| i.budgets.get_mut(class)) {
+                b
+            } else {
+                return
+            };
+        budgets.quota = quota;
+        budgets.last_refill = Instant::now();
+        let (amount, bytes) = budgets.recv_budget.top_up(&quota);
+        if amount > 0 || bytes != Some(0) {
+            self.send_credit(p, class, amount, bytes)
+        }
+    }
+
+    /// Refill the budgets whose refill interval has elapsed.
+    fn refill_elapsed(&mut self) {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for (p, info) in &self.peer_info {
+            for (class, budgets) in &info.budgets {
+                let quota = self.quota(p, class);
+                if let Some(interval) = quota.refill {
+                    let key = (p.clone(), class.clone());
+                    if now.duration_since(budgets.last_refill) >= interval
+                        && budgets.recv_budget.is_below(&quota)
+                        && !self.credit_messages.contains_key(&key)
+                    {
+                        due.push(key)
+                    }
+                }
+            }
+        }
+        for (p, class) in due {
+            log::trace!("{:08x}: refilling budget of {} for {:?}", self.id, p, class);
+            self.refill(&p, &class)
+        }
+    }
+
+    /// Send the credit a previously connected peer needs to restore its
+    /// receive budgets, given that it starts over with a send budget of 1
+    /// and no byte limit.
+    fn restore_credit(&mut self, p: &PeerId, info: &PeerInfo) {
+        for (class, budgets) in &info.budgets {
+            let amount = budgets.recv_budget.requests.saturating_sub(1);
+            let bytes = budgets.recv_budget.bytes;
+            if amount > 0 || bytes.is_some() {
+                self.send_credit(p, class, amount, bytes)
+            }
+        }
+    }
+
     /// Send a credit grant to the given peer.
-    fn send_credit(&mut self, p: &PeerId, amount: u16) {
+    fn send_credit(&mut self, p: &PeerId, class: &Class, amount: u16, bytes: Option<u64>) {
         let cid = self.next_credit_id();
-        let rid = self.behaviour.send_request(p, Message::credit(amount, cid));
-        log::trace!("{:08x}: sending {} as credit {} to {}", self.id, amount, cid, p);
-        let credit = Credit { id: cid, request: rid, amount };
-        self.credit_messages.insert(p.clone(), credit);
+        let rid = self.behaviour.send_request(p, Message::credit(amount, bytes, class.clone(), cid));
+        log::trace!("{:08x}: sending {} ({:?} bytes) as credit {} to {}", self.id, amount, bytes, cid, p);
+        let credit = Credit { id: cid, request: rid, amount, bytes };
+        self.credit_messages.insert((p.clone(), class.clone()), credit);
     }
 
     /// Create a new credit message ID.
@@ -347,13 +848,16 @@ where
     fn inject_connection_closed(&mut self, peer: &PeerId, id: &ConnectionId, end: &ConnectedPoint) {
         self.behaviour.inject_connection_closed(peer, id, end);
         if self.is_connected(peer) {
-            if let Some(credit) = self.credit_messages.get_mut(peer) {
+            for ((p, class), credit) in self.credit_messages.iter_mut() {
+                if p != peer {
+                    continue
+                }
                 log::debug! { "{:08x}: resending credit grant {} to {} after connection closed",
                     self.id,
                     credit.id,
                     peer
                 };
-                let msg = Message::credit(credit.amount, credit.id);
+                let msg = Message::credit(credit.amount, credit.bytes, class.clone(), credit.id);
                 credit.request = self.behaviour.send_request(peer, msg)
             }
         }
@@ -363,29 +867,24 @@ where
         log::trace!("{:08x}: connected to {}", self.id, p);
         self.behaviour.inject_connected(p);
         // The limit may have been added by `Throttled::send_request` already.
-        if !self.peer_info.contains_key(p) {
-            let info =
-                if let Some(info) = self.offline_peer_info.pop(p) {
-                    if info.recv_budget > 1 {
-                        self.send_credit(p, info.recv_budget - 1)
-                    }
-                    info
-                } else {
-                    let limit = self.limit_overrides.get(p).copied().unwrap_or(self.default_limit);
-                    PeerInfo::new(limit)
-                };
-            self.peer_info.insert(p.clone(), info);
-        }
+        self.insert_peer_info(p);
     }
 
     fn inject_disconnected(&mut self, p: &PeerId) {
         log::trace!("{:08x}: disconnected from {}", self.id, p);
         if let Some(mut info) = self.peer_info.remove(p) {
-            info.send_budget = 1;
-            info.recv_budget = max(1, info.recv_budget);
+            for budgets in info.budgets.values_mut() {
+                budgets.send_budget = Budget::new();
+                budgets.recv_budget.requests = max(1, budgets.recv_budget.requests);
+                budgets.recv_budget.bytes = budgets.quota.bytes.map(NonZeroU64::get);
+                budgets.blocked = false;
+                budgets.unmeasured = 0
+            }
             self.offline_peer_info.put(p.clone(), info);
         }
-        self.credit_messages.remove(p);
+        self.credit_messages.retain(|(peer, _), _| peer != p);
+        self.unmeasured.retain(|_, u| &u.peer != p);
+        self.unanswered.retain(|_, (peer, _)| peer != p);
         self.behaviour.inject_disconnected(p)
     }
 
@@ -398,9 +897,24 @@ where
     }
 
     fn poll(&mut self, cx: &mut Context<'_>, params: &mut impl PollParameters)
-        -> Poll<NetworkBehaviourAction<RequestProtocol<Codec<C>>, Self::OutEvent>>
+        -> Poll<NetworkBehaviourAction<RequestResponseHandlerIn<Codec<C>>, Self::OutEvent>>
     {
+        let mut refill = false;
+        if let Some((interval, timer)) = &mut self.refill_timer {
+            while timer.poll_unpin(cx).is_ready() {
+                timer.reset(*interval);
+                refill = true
+            }
+        }
+        if refill {
+            self.refill_elapsed()
+        }
+
+        self.poll_size_reports(cx);
+
         loop {
+            self.consume_attempts();
+
             if let Some(ev) = self.events.pop_front() {
                 return Poll::Ready(NetworkBehaviourAction::GenerateEvent(ev))
             } else if self.events.capacity() > super::EMPTY_QUEUE_SHRINK_THRESHOLD {
@@ -413,16 +927,18 @@ where
                         | RequestResponseMessage::Response { request_id, response } =>
                             match &response.header().typ {
                                 | Some(Type::Ack) => {
-                                    if let Some(id) = self.credit_messages.get(&peer).map(|c| c.id) {
+                                    let key = (peer, response.header().class.clone());
+                                    if let Some(id) = self.credit_messages.get(&key).map(|c| c.id) {
                                         if Some(id) == response.header().ident {
-                                            log::trace!("{:08x}: received ack {} from {}", self.id, id, peer);
-                                            self.credit_messages.remove(&peer);
+                                            log::trace!("{:08x}: received ack {} from {}", self.id, id, key.0);
+                                            self.credit_messages.remove(&key);
                                         }
                                     }
                                     continue
                                 }
                                 | Some(Type::Response) => {
                                     log::trace!("{:08x}: received response {} from {}", self.id, request_id, peer);
+                                    self.routes.remove(&request_id);
                                     if let Some(rs) = response.into_parts().1 {
                                         RequestResponseMessage::Response { request_id, response: rs }
                                     } else {
@@ -444,9 +960,23 @@ where
                                     continue
                                 }
                             }
+                        | RequestResponseMessage::ResponseChunk { request_id, chunk } =>
+                            if let Some(rs) = chunk.into_parts().1 {
+                                RequestResponseMessage::ResponseChunk { request_id, chunk: rs }
+                            } else {
+                                log::error! { "{:08x}: missing data for response chunk {} from peer {}",
+                                    self.id,
+                                    request_id,
+                                    peer
+                                }
+                                continue
+                            }
+                        | RequestResponseMessage::ResponseEnd { request_id } =>
+                            RequestResponseMessage::ResponseEnd { request_id },
                         | RequestResponseMessage::Request { request_id, request, channel } =>
                             match &request.header().typ {
                                 | Some(Type::Credit) => {
+                                    let class = request.header().class.clone();
                                     if let Some(info) = self.peer_info.get_mut(&peer) {
                                         let id = if let Some(n) = request.header().ident {
                                             n
@@ -458,41 +988,53 @@ where
                                             continue
                                         };
                                         let credit = request.header().credit.unwrap_or(0);
-                                        log::trace! { "{:08x}: received {} additional credit {} from {}",
+                                        let bytes = request.header().bytes;
+                                        log::trace! { "{:08x}: received {} ({:?} bytes) additional credit {} from {}",
                                             self.id,
                                             credit,
+                                            bytes,
                                             id,
                                             peer
                                         };
-                                        if info.send_budget_id < Some(id) {
-                                            if info.send_budget == 0 && credit > 0 {
+                                        let budgets = info.budgets(&class);
+                                        if budgets.send_budget_id < Some(id) {
+                                            let was_blocked = budgets.blocked || budgets.send_budget.requests == 0;
+                                            budgets.send_budget.add(credit, bytes);
+                                            budgets.send_budget_id = Some(id);
+                                            if was_blocked && budgets.send_budget.requests > 0 {
                                                 log::trace!("{:08x}: sending to peer {} can resume", self.id, peer);
+                                                budgets.blocked = false;
                                                 self.events.push_back(Event::ResumeSending(peer.clone()))
                                             }
-                                            info.send_budget += credit;
-                                            info.send_budget_id = Some(id)
                                         }
-                                        self.behaviour.send_response(channel, Message::ack(id))
+                                        self.behaviour.send_response(channel, Message::ack(id, class))
                                     }
                                     continue
                                 }
                                 | Some(Type::Request) => {
+                                    // The class has been derived from the negotiated
+                                    // protocol by the codec.
+                                    let class = request.header().class.clone();
+                                    let size = request.size();
                                     if let Some(info) = self.peer_info.get_mut(&peer) {
-                                        log::trace! { "{:08x}: received request {} (recv. budget = {})",
+                                        let budgets = info.budgets(&class);
+                                        log::trace! { "{:08x}: received request {} of {} bytes (recv. budget = {:?})",
                                             self.id,
                                             request_id,
-                                            info.recv_budget
+                                            size,
+                                            budgets.recv_budget
                                         };
-                                        if info.recv_budget == 0 {
+                                        if !budgets.recv_budget.allows() {
                                             log::debug!("{:08x}: peer {} exceeds its budget", self.id, peer);
                                             self.events.push_back(Event::TooManyInboundRequests(peer.clone()));
                                             continue
                                         }
-                                        info.recv_budget -= 1;
+                                        budgets.recv_budget.consume(size);
                                         // We consider a request as proof that our credit grant has
                                         // reached the peer. Usually, an ACK has already been
                                         // received.
-                                        self.credit_messages.remove(&peer);
+                                        self.credit_messages.remove(&(peer.clone(), class.clone()));
+                                        self.unanswered.insert(request_id, (peer.clone(), class));
                                     }
                                     if let Some(rq) = request.into_parts().1 {
                                         RequestResponseMessage::Request { request_id, request: rq, channel }
@@ -524,26 +1066,41 @@ where
                     request_id,
                     error
                 }) => {
-                    if let Some(credit) = self.credit_messages.get_mut(&peer) {
-                        if credit.request == request_id {
+                    // The request may have been written before it failed.
+                    self.poll_size_reports(cx);
+                    self.forget_size(request_id);
+                    self.routes.remove(&request_id);
+                    for ((p, class), credit) in self.credit_messages.iter_mut() {
+                        if p == &peer && credit.request == request_id {
                             log::debug! { "{:08x}: failed to send {} as credit {} to {}; retrying...",
                                 self.id,
                                 credit.amount,
                                 credit.id,
                                 peer
                             };
-                            let msg = Message::credit(credit.amount, credit.id);
+                            let msg = Message::credit(credit.amount, credit.bytes, class.clone(), credit.id);
                             credit.request = self.behaviour.send_request(&peer, msg)
                         }
                     }
                     let event = RequestResponseEvent::OutboundFailure { peer, request_id, error };
                     NetworkBehaviourAction::GenerateEvent(Event::Event(event))
                 }
+                | NetworkBehaviourAction::GenerateEvent(RequestResponseEvent::OutboundRoutingFailure {
+                    request_id,
+                    attempts
+                }) => {
+                    self.poll_size_reports(cx);
+                    self.forget_size(request_id);
+                    self.routes.remove(&request_id);
+                    let event = RequestResponseEvent::OutboundRoutingFailure { request_id, attempts };
+                    NetworkBehaviourAction::GenerateEvent(Event::Event(event))
+                }
                 | NetworkBehaviourAction::GenerateEvent(RequestResponseEvent::InboundFailure {
                     peer,
                     request_id,
                     error
                 }) => {
+                    self.unanswered.remove(&request_id);
                     let event = RequestResponseEvent::InboundFailure { peer, request_id, error };
                     NetworkBehaviourAction::GenerateEvent(Event::Event(event))
                 }
@@ -553,8 +1110,8 @@ where
                     NetworkBehaviourAction::DialPeer { peer_id, condition },
                 | NetworkBehaviourAction::NotifyHandler { peer_id, handler, event } =>
                     NetworkBehaviourAction::NotifyHandler { peer_id, handler, event },
-                | NetworkBehaviourAction::ReportObservedAddr { address } =>
-                    NetworkBehaviourAction::ReportObservedAddr { address }
+                | NetworkBehaviourAction::ReportObservedAddr { address, observer } =>
+                    NetworkBehaviourAction::ReportObservedAddr { address, observer }
             };
 
             return Poll::Ready(event)
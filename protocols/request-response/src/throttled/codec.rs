@@ -20,10 +20,10 @@
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
-use futures::prelude::*;
+use futures::{channel::mpsc, prelude::*};
 use libp2p_core::ProtocolName;
 use minicbor::{Encode, Decode};
-use std::io;
+use std::{io, pin::Pin, task::{Context, Poll}};
 use super::RequestResponseCodec;
 use unsigned_varint::{aio, io::ReadError};
 
@@ -36,7 +36,14 @@ pub struct Header {
     /// The number of additional requests the remote is willing to receive.
     #[n(1)] pub credit: Option<u16>,
     /// An identifier used for sending credit grants.
-    #[n(2)] pub ident: Option<u64>
+    #[n(2)] pub ident: Option<u64>,
+    /// The number of additional bytes the remote is willing to receive.
+    ///
+    /// A credit grant without this field lifts any byte limit.
+    #[n(3)] pub bytes: Option<u64>,
+    /// The name of the protocol a request or credit grant is accounted
+    /// against, if any.
+    #[n(4)] pub class: Option<String>
 }
 
 /// A protocol message type.
@@ -52,18 +59,44 @@ pub enum Type {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Message<T> {
     header: Header,
-    data: Option<T>
+    data: Option<T>,
+    /// The number of bytes the data took up when it was read.
+    size: u64,
+    /// Reports the number of bytes the data takes up once it is written.
+    report: Option<SizeReport>
 }
 
+/// Reports the number of bytes the data of a message takes up once it
+/// has been written, tagged with a token identifying the message.
+#[derive(Debug, Clone)]
+pub struct SizeReport {
+    token: u64,
+    sender: mpsc::UnboundedSender<(u64, u64)>
+}
+
+impl SizeReport {
+    pub fn new(token: u64, sender: mpsc::UnboundedSender<(u64, u64)>) -> Self {
+        SizeReport { token, sender }
+    }
+}
+
+impl PartialEq for SizeReport {
+    fn eq(&self, other: &Self) -> bool {
+        self.token == other.token
+    }
+}
+
+impl Eq for SizeReport {}
+
 impl<T> Message<T> {
     /// Create a new message of some type.
     fn new(header: Header) -> Self {
-        Message { header, data: None }
+        Message { header, data: None, size: 0, report: None }
     }
 
     /// Create a request message.
-    pub fn request(data: T) -> Self {
-        let mut m = Message::new(Header { typ: Some(Type::Request), .. Header::default() });
+    pub fn request(data: T, class: Option<String>) -> Self {
+        let mut m = Message::new(Header { typ: Some(Type::Request), class, .. Header::default() });
         m.data = Some(data);
         m
     }
@@ -76,13 +109,25 @@ impl<T> Message<T> {
     }
 
     /// Create a credit grant.
-    pub fn credit(credit: u16, ident: u64) -> Self {
-        Message::new(Header { typ: Some(Type::Credit), credit: Some(credit), ident: Some(ident) })
+    pub fn credit(credit: u16, bytes: Option<u64>, class: Option<String>, ident: u64) -> Self {
+        Message::new(Header {
+            typ: Some(Type::Credit),
+            credit: Some(credit),
+            ident: Some(ident),
+            bytes,
+            class
+        })
     }
 
     /// Create an acknowledge message.
-    pub fn ack(ident: u64) -> Self {
-        Message::new(Header { typ: Some(Type::Ack), credit: None, ident: Some(ident) })
+    pub fn ack(ident: u64, class: Option<String>) -> Self {
+        Message::new(Header { typ: Some(Type::Ack), ident: Some(ident), class, .. Header::default() })
+    }
+
+    /// Report the number of bytes the data takes up once it is written.
+    pub fn with_size_report(mut self, report: SizeReport) -> Self {
+        self.report = Some(report);
+        self
     }
 
     /// Access the message header.
@@ -95,6 +140,12 @@ impl<T> Message<T> {
         self.data.as_ref()
     }
 
+    /// The number of bytes the data of a received message took up,
+    /// not counting the header.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
     /// Consume this message and return header and data.
     pub fn into_parts(self) -> (Header, Option<T>) {
         (self.header, self.data)
@@ -116,6 +167,13 @@ impl<P: ProtocolName> ProtocolWrapper<P> {
     }
 }
 
+impl<P> ProtocolWrapper<P> {
+    /// The wrapped protocol.
+    pub fn inner(&self) -> &P {
+        &self.0
+    }
+}
+
 impl<P> ProtocolName for ProtocolWrapper<P> {
     fn protocol_name(&self) -> &[u8] {
         self.1.as_ref()
@@ -193,7 +251,15 @@ where
         let mut msg = Message::new(self.read_header(io).await?);
         match msg.header.typ {
             Some(Type::Request) => {
-                msg.data = Some(self.inner.read_request(&p.0, io).await?);
+                // The remote merely indicates whether the request is accounted
+                // against the quota of a protocol, which is always the one
+                // negotiated, regardless of the name it sent.
+                if msg.header.class.is_some() {
+                    msg.header.class = Some(class_name(p.0.protocol_name()))
+                }
+                let mut reader = Counter::new(io);
+                msg.data = Some(self.inner.read_request(&p.0, &mut reader).await?);
+                msg.size = reader.count;
                 Ok(msg)
             }
             Some(Type::Credit) => Ok(msg),
@@ -228,7 +294,13 @@ where
     {
         self.write_header(&r.header, io).await?;
         if let Some(data) = r.data {
-            self.inner.write_request(&p.0, io, data).await?
+            let mut writer = Counter::new(&mut *io);
+            self.inner.write_request(&p.0, &mut writer, data).await?;
+            if let Some(report) = r.report {
+                // The receiver of the report may be gone, if the behaviour
+                // has been dropped, in which case the size is of no interest.
+                let _ = report.sender.unbounded_send((report.token, writer.count));
+            }
         }
         Ok(())
     }
@@ -245,6 +317,47 @@ where
     }
 }
 
+/// The name under which requests sent with a protocol are accounted.
+pub fn class_name(protocol_name: &[u8]) -> String {
+    String::from_utf8_lossy(protocol_name).into_owned()
+}
+
+/// An I/O stream wrapper which counts the bytes read or written.
+struct Counter<T> {
+    io: T,
+    count: u64
+}
+
+impl<T> Counter<T> {
+    fn new(io: T) -> Self {
+        Counter { io, count: 0 }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Counter<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.io).poll_read(cx, buf))?;
+        self.count += n as u64;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Counter<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.io).poll_write(cx, buf))?;
+        self.count += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_close(cx)
+    }
+}
+
 #[cfg(any(target_pointer_width = "64", target_pointer_width = "32"))]
 fn u32_to_usize(n: u32) -> usize {
     n as usize
@@ -27,7 +27,7 @@ use libp2p_core::{
     identity,
     muxing::StreamMuxerBox,
     transport::{Transport, boxed::Boxed},
-    upgrade::{self, read_one, write_one}
+    upgrade::{self, read_one, write_one, write_with_len_prefix}
 };
 use libp2p_noise::{NoiseConfig, X25519Spec, Keypair};
 use libp2p_request_response::*;
@@ -35,8 +35,8 @@ use libp2p_swarm::Swarm;
 use libp2p_tcp::TcpConfig;
 use futures::{prelude::*, channel::mpsc};
 use rand::{self, Rng};
-use std::{io, iter};
-use std::{collections::HashSet, num::NonZeroU16};
+use std::{io, iter, time::Duration};
+use std::{collections::HashSet, num::{NonZeroU16, NonZeroU64}};
 
 /// Exercises a simple ping protocol.
 #[test]
@@ -213,6 +213,909 @@ fn ping_protocol_throttled() {
     let () = async_std::task::block_on(peer2);
 }
 
+/// Exercises a per-protocol byte quota which is only refilled over time,
+/// as the requests are not answered before all of them have been received.
+#[test]
+fn ping_protocol_throttled_bytes() {
+    let ping = Ping("ping".to_string().into_bytes());
+    let pong = Pong("pong".to_string().into_bytes());
+
+    let protocols = iter::once((PingProtocol(), ProtocolSupport::Full));
+    let cfg = RequestResponseConfig::default();
+
+    let (peer1_id, trans) = mk_transport();
+    let ping_proto1 = RequestResponse::throttled(PingCodec(), protocols.clone(), cfg.clone());
+    let mut swarm1 = Swarm::new(trans, ping_proto1, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let ping_proto2 = RequestResponse::throttled(PingCodec(), protocols, cfg);
+    let mut swarm2 = Swarm::new(trans, ping_proto2, peer2_id.clone());
+
+    let (mut tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+
+    // Two pings, encoded with 5 bytes each, fit into the quota of every refill.
+    let refill = Duration::from_millis(100);
+    let mut quota = throttled::Quota::new(NonZeroU16::new(100).unwrap());
+    quota.set_max_bytes(NonZeroU64::new(10).unwrap()).set_refill_interval(refill);
+    swarm1.set_protocol_quota(&PingProtocol(), quota);
+
+    let num_pings = 9;
+    let expected_ping = ping.clone();
+    let expected_pong = pong.clone();
+
+    let peer1 = async move {
+        while swarm1.next().now_or_never().is_some() {}
+
+        let l = Swarm::listeners(&swarm1).next().unwrap();
+        tx.send(l.clone()).await.unwrap();
+
+        let mut channels = Vec::new();
+        loop {
+            match swarm1.next().await {
+                throttled::Event::Event(RequestResponseEvent::Message {
+                    peer,
+                    message: RequestResponseMessage::Request { request, channel, .. },
+                }) => {
+                    assert_eq!(&request, &expected_ping);
+                    assert_eq!(&peer, &peer2_id);
+                    channels.push(channel);
+                    if channels.len() == num_pings {
+                        for ch in channels.drain(..) {
+                            swarm1.send_response(ch, pong.clone())
+                        }
+                    }
+                },
+                e => panic!("Peer1: Unexpected event: {:?}", e)
+            }
+        }
+    };
+
+    let peer2 = async move {
+        let addr = rx.next().await.unwrap();
+        swarm2.add_address(&peer1_id, addr.clone());
+
+        let start = std::time::Instant::now();
+        let mut sent = 0;
+        let mut received = 0;
+        let mut blocked = false;
+
+        loop {
+            if !blocked {
+                while sent < num_pings {
+                    match swarm2.send_request_with_protocol(&peer1_id, &PingProtocol(), ping.clone()) {
+                        Ok(_) => sent += 1,
+                        Err(_) => break
+                    }
+                }
+                blocked = true;
+            }
+            match swarm2.next().await {
+                throttled::Event::ResumeSending(peer) => {
+                    assert_eq!(peer, peer1_id);
+                    blocked = false
+                }
+                throttled::Event::Event(RequestResponseEvent::Message {
+                    peer,
+                    message: RequestResponseMessage::Response { response, .. }
+                }) => {
+                    assert_eq!(&response, &expected_pong);
+                    assert_eq!(&peer, &peer1_id);
+                    received += 1;
+                    if received == num_pings {
+                        break
+                    }
+                }
+                e => panic!("Peer2: Unexpected event: {:?}", e)
+            }
+        }
+
+        // The initial request plus two requests per refill.
+        assert!(start.elapsed() >= refill * 3);
+    };
+
+    async_std::task::spawn(Box::pin(peer1));
+    let () = async_std::task::block_on(peer2);
+}
+
+/// Exercises streamed responses, with chunks being sent both
+/// right away and after the request has been answered initially.
+#[test]
+fn ping_protocol_streaming() {
+    let ping = Ping("ping".to_string().into_bytes());
+    let pong = Pong("pong".to_string().into_bytes());
+
+    let protocols = iter::once((PingProtocol(), ProtocolSupport::Full));
+    let mut cfg = RequestResponseConfig::default();
+    cfg.set_chunk_timeout(Duration::from_secs(5));
+
+    let (peer1_id, trans) = mk_transport();
+    let ping_proto1 = RequestResponse::new(PingCodec(), protocols.clone(), cfg.clone());
+    let mut swarm1 = Swarm::new(trans, ping_proto1, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let ping_proto2 = RequestResponse::new(PingCodec(), protocols, cfg);
+    let mut swarm2 = Swarm::new(trans, ping_proto2, peer2_id.clone());
+
+    let (mut tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+
+    let num_chunks: usize = rand::thread_rng().gen_range(1, 20);
+    let expected_ping = ping.clone();
+    let expected_pong = pong.clone();
+
+    let peer1 = async move {
+        while swarm1.next().now_or_never().is_some() {}
+
+        let l = Swarm::listeners(&swarm1).next().unwrap();
+        tx.send(l.clone()).await.unwrap();
+
+        let mut answered_empty = false;
+        // The open response channel and the number of chunks sent on it.
+        let mut streaming = None;
+
+        loop {
+            let delay = async_std::task::sleep(Duration::from_millis(10));
+            let event = match future::select(swarm1.next().boxed(), delay.boxed()).await {
+                future::Either::Left((event, _)) => Some(event),
+                future::Either::Right(_) => None
+            };
+            match event {
+                Some(RequestResponseEvent::Message {
+                    peer,
+                    message: RequestResponseMessage::Request { request, channel, .. }
+                }) => {
+                    assert_eq!(&request, &expected_ping);
+                    assert_eq!(&peer, &peer2_id);
+                    if !answered_empty {
+                        // Answer the first request with an empty stream,
+                        // the second with a chunk at a time.
+                        answered_empty = true;
+                        swarm1.end_response_stream(channel);
+                    } else {
+                        let mut channel = channel;
+                        swarm1.send_response_chunk(&mut channel, pong.clone()).unwrap();
+                        streaming = Some((channel, 1));
+                    }
+                }
+                Some(e) => panic!("Peer1: Unexpected event: {:?}", e),
+                None => if let Some((mut channel, sent)) = streaming.take() {
+                    if sent < num_chunks {
+                        swarm1.send_response_chunk(&mut channel, pong.clone()).unwrap();
+                        streaming = Some((channel, sent + 1));
+                    }
+                }
+            }
+        }
+    };
+
+    let peer2 = async move {
+        let addr = rx.next().await.unwrap();
+        swarm2.add_address(&peer1_id, addr.clone());
+
+        let req_id = swarm2.send_streaming_request(&peer1_id, ping.clone());
+        match swarm2.next().await {
+            RequestResponseEvent::Message {
+                message: RequestResponseMessage::ResponseEnd { request_id }, ..
+            } => assert_eq!(request_id, req_id),
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+        assert!(!swarm2.is_pending_outbound(&req_id));
+
+        let req_id = swarm2.send_streaming_request(&peer1_id, ping.clone());
+        let mut count = 0;
+        loop {
+            match swarm2.next().await {
+                RequestResponseEvent::Message {
+                    peer,
+                    message: RequestResponseMessage::ResponseChunk { request_id, chunk }
+                } => {
+                    count += 1;
+                    assert_eq!(&chunk, &expected_pong);
+                    assert_eq!(&peer, &peer1_id);
+                    assert_eq!(request_id, req_id);
+                    assert!(swarm2.is_pending_outbound(&req_id));
+                }
+                RequestResponseEvent::Message {
+                    message: RequestResponseMessage::ResponseEnd { request_id }, ..
+                } => {
+                    assert_eq!(request_id, req_id);
+                    assert_eq!(count, num_chunks);
+                    return
+                }
+                e => panic!("Peer2: Unexpected event: {:?}", e)
+            }
+        }
+    };
+
+    async_std::task::spawn(Box::pin(peer1));
+    let () = async_std::task::block_on(peer2);
+}
+
+/// Exercises a plain and a streaming request answered by the same
+/// responder, which learns from each request whether to stream.
+#[test]
+fn ping_protocol_streaming_is_negotiated() {
+    let ping = Ping("ping".to_string().into_bytes());
+    let pong = Pong("pong".to_string().into_bytes());
+
+    let protocols = iter::once((PingProtocol(), ProtocolSupport::Full));
+    let cfg = RequestResponseConfig::default();
+
+    let (peer1_id, trans) = mk_transport();
+    let ping_proto1 = RequestResponse::new(PingCodec(), protocols.clone(), cfg.clone());
+    let mut swarm1 = Swarm::new(trans, ping_proto1, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let ping_proto2 = RequestResponse::new(PingCodec(), protocols, cfg);
+    let mut swarm2 = Swarm::new(trans, ping_proto2, peer2_id.clone());
+
+    let (mut tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+
+    let expected_pong = pong.clone();
+
+    let peer1 = async move {
+        while swarm1.next().now_or_never().is_some() {}
+
+        let l = Swarm::listeners(&swarm1).next().unwrap();
+        tx.send(l.clone()).await.unwrap();
+
+        loop {
+            match swarm1.next().await {
+                RequestResponseEvent::Message {
+                    message: RequestResponseMessage::Request { mut channel, .. }, ..
+                } => {
+                    if channel.is_streamed() {
+                        swarm1.send_response_chunk(&mut channel, pong.clone()).unwrap();
+                    } else {
+                        match swarm1.send_response_chunk(&mut channel, pong.clone()) {
+                            Err(SendChunkError::NotStreamed(_)) => {}
+                            r => panic!("Peer1: Unexpected result: {:?}", r)
+                        }
+                    }
+                    swarm1.send_response(channel, pong.clone());
+                }
+                e => panic!("Peer1: Unexpected event: {:?}", e)
+            }
+        }
+    };
+
+    let peer2 = async move {
+        let addr = rx.next().await.unwrap();
+        swarm2.add_address(&peer1_id, addr.clone());
+
+        let req_id = swarm2.send_request(&peer1_id, ping.clone());
+        match swarm2.next().await {
+            RequestResponseEvent::Message {
+                message: RequestResponseMessage::Response { request_id, response }, ..
+            } => {
+                assert_eq!(request_id, req_id);
+                assert_eq!(response, expected_pong);
+            }
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+
+        let req_id = swarm2.send_streaming_request(&peer1_id, ping.clone());
+        let mut count = 0;
+        loop {
+            match swarm2.next().await {
+                RequestResponseEvent::Message {
+                    message: RequestResponseMessage::ResponseChunk { request_id, chunk }, ..
+                } => {
+                    count += 1;
+                    assert_eq!(request_id, req_id);
+                    assert_eq!(&chunk, &expected_pong);
+                }
+                RequestResponseEvent::Message {
+                    message: RequestResponseMessage::ResponseEnd { request_id }, ..
+                } => {
+                    assert_eq!(request_id, req_id);
+                    assert_eq!(count, 2);
+                    return
+                }
+                e => panic!("Peer2: Unexpected event: {:?}", e)
+            }
+        }
+    };
+
+    async_std::task::spawn(Box::pin(peer1));
+    let () = async_std::task::block_on(peer2);
+}
+
+/// Exercises streamed responses whose chunks exceed the buffer of the
+/// response channel, as well as a stream closed without an end marker.
+#[test]
+fn ping_protocol_streaming_buffer_and_end() {
+    let ping = Ping("ping".to_string().into_bytes());
+    let pong = Pong("pong".to_string().into_bytes());
+
+    let protocols = iter::once((PingProtocol(), ProtocolSupport::Full));
+    let mut cfg = RequestResponseConfig::default();
+    cfg.set_chunk_buffer(0);
+
+    let (peer1_id, trans) = mk_transport();
+    let ping_proto1 = RequestResponse::new(PingCodec(), protocols.clone(), cfg.clone());
+    let mut swarm1 = Swarm::new(trans, ping_proto1, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let ping_proto2 = RequestResponse::new(PingCodec(), protocols, cfg);
+    let mut swarm2 = Swarm::new(trans, ping_proto2, peer2_id.clone());
+
+    let (mut tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+
+    let num_chunks = 10;
+    let expected_pong = pong.clone();
+
+    let peer1 = async move {
+        while swarm1.next().now_or_never().is_some() {}
+
+        let l = Swarm::listeners(&swarm1).next().unwrap();
+        tx.send(l.clone()).await.unwrap();
+
+        // The first request is answered without any chunks or an end marker.
+        match swarm1.next().await {
+            RequestResponseEvent::Message {
+                message: RequestResponseMessage::Request { channel, .. }, ..
+            } => drop(channel),
+            e => panic!("Peer1: Unexpected event: {:?}", e)
+        }
+
+        let mut channel = loop {
+            match swarm1.next().await {
+                RequestResponseEvent::Message {
+                    message: RequestResponseMessage::Request { channel, .. }, ..
+                } => break channel,
+                RequestResponseEvent::InboundFailure { .. } => {}
+                e => panic!("Peer1: Unexpected event: {:?}", e)
+            }
+        };
+
+        let mut full = 0;
+        let mut sent = 0;
+        while sent < num_chunks {
+            match swarm1.send_response_chunk(&mut channel, pong.clone()) {
+                Ok(()) => sent += 1,
+                Err(SendChunkError::Full(_)) => {
+                    full += 1;
+                    // Drive the swarm until the buffered chunk has been written.
+                    let ready = future::poll_fn(|cx| channel.poll_ready(cx));
+                    if let future::Either::Left(_) = future::select(swarm1.next().boxed(), ready).await {
+                        panic!("Peer1: Unexpected event")
+                    }
+                }
+                Err(SendChunkError::Closed(_)) => panic!("Peer1: Channel closed"),
+                Err(SendChunkError::NotStreamed(_)) => panic!("Peer1: Response not streamed")
+            }
+        }
+        assert!(full > 0);
+        swarm1.end_response_stream(channel);
+
+        loop {
+            swarm1.next().await;
+        }
+    };
+
+    let peer2 = async move {
+        let addr = rx.next().await.unwrap();
+        swarm2.add_address(&peer1_id, addr.clone());
+
+        let req_id = swarm2.send_streaming_request(&peer1_id, ping.clone());
+        match swarm2.next().await {
+            RequestResponseEvent::OutboundFailure { request_id, error: OutboundFailure::Io(e), .. } => {
+                assert_eq!(request_id, req_id);
+                assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+            }
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+
+        let req_id = swarm2.send_streaming_request(&peer1_id, ping.clone());
+        let mut count = 0;
+        loop {
+            match swarm2.next().await {
+                RequestResponseEvent::Message {
+                    message: RequestResponseMessage::ResponseChunk { request_id, chunk }, ..
+                } => {
+                    count += 1;
+                    assert_eq!(&chunk, &expected_pong);
+                    assert_eq!(request_id, req_id);
+                }
+                RequestResponseEvent::Message {
+                    message: RequestResponseMessage::ResponseEnd { request_id }, ..
+                } => {
+                    assert_eq!(request_id, req_id);
+                    assert_eq!(count, num_chunks);
+                    return
+                }
+                e => panic!("Peer2: Unexpected event: {:?}", e)
+            }
+        }
+    };
+
+    async_std::task::spawn(Box::pin(peer1));
+    let () = async_std::task::block_on(peer2);
+}
+
+/// Exercises cancelling requests before and after they have been
+/// sent, as well as per-request timeouts.
+#[test]
+fn ping_protocol_cancel_and_timeout() {
+    let ping = Ping("ping".to_string().into_bytes());
+
+    let protocols = iter::once((PingProtocol(), ProtocolSupport::Full));
+    let cfg = RequestResponseConfig::default();
+
+    let (peer1_id, trans) = mk_transport();
+    let ping_proto1 = RequestResponse::new(PingCodec(), protocols.clone(), cfg.clone());
+    let mut swarm1 = Swarm::new(trans, ping_proto1, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let ping_proto2 = RequestResponse::new(PingCodec(), protocols, cfg);
+    let mut swarm2 = Swarm::new(trans, ping_proto2, peer2_id.clone());
+
+    let (mut tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+    let (mut req_tx, mut req_rx) = mpsc::channel::<()>(1);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+
+    let peer1 = async move {
+        while swarm1.next().now_or_never().is_some() {}
+
+        let l = Swarm::listeners(&swarm1).next().unwrap();
+        tx.send(l.clone()).await.unwrap();
+
+        // Requests are never answered.
+        let mut channels = Vec::new();
+        loop {
+            match swarm1.next().await {
+                RequestResponseEvent::Message {
+                    peer,
+                    message: RequestResponseMessage::Request { channel, .. }
+                } => {
+                    assert_eq!(&peer, &peer2_id);
+                    channels.push(channel);
+                    req_tx.send(()).await.unwrap();
+                }
+                RequestResponseEvent::InboundFailure { .. } => {}
+                e => panic!("Peer1: Unexpected event: {:?}", e)
+            }
+        }
+    };
+
+    let peer2 = async move {
+        let addr = rx.next().await.unwrap();
+        swarm2.add_address(&peer1_id, addr.clone());
+
+        // Cancel a request before it has been sent.
+        let req_id = swarm2.send_request(&peer1_id, ping.clone());
+        assert!(swarm2.cancel_request(req_id));
+        assert!(!swarm2.cancel_request(req_id));
+        match swarm2.next().await {
+            RequestResponseEvent::OutboundFailure {
+                request_id, error: OutboundFailure::Cancelled, ..
+            } => assert_eq!(request_id, req_id),
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+
+        // A request with a timeout shorter than the configured one.
+        let req_id = swarm2.send_request_with_timeout(&peer1_id, ping.clone(), Duration::from_millis(100));
+        match swarm2.next().await {
+            RequestResponseEvent::OutboundFailure {
+                request_id, error: OutboundFailure::Timeout, ..
+            } => assert_eq!(request_id, req_id),
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+        req_rx.next().await.unwrap();
+
+        // Cancel a request after it has been received by the remote.
+        let req_id = swarm2.send_request(&peer1_id, ping.clone());
+        if let future::Either::Left((e, _)) = future::select(swarm2.next().boxed(), req_rx.next()).await {
+            panic!("Peer2: Unexpected event: {:?}", e)
+        }
+        assert!(swarm2.is_pending_outbound(&req_id));
+        assert!(swarm2.cancel_request(req_id));
+        assert!(!swarm2.is_pending_outbound(&req_id));
+        match swarm2.next().await {
+            RequestResponseEvent::OutboundFailure {
+                request_id, error: OutboundFailure::Cancelled, ..
+            } => assert_eq!(request_id, req_id),
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+    };
+
+    async_std::task::spawn(Box::pin(peer1));
+    let () = async_std::task::block_on(peer2);
+}
+
+/// Exercises sending a throttled request to any of a set of peers,
+/// which accounts every attempt against the budget of its peer.
+#[test]
+fn ping_protocol_throttled_send_to_any() {
+    let ping = Ping("ping".to_string().into_bytes());
+    let pong = Pong("pong".to_string().into_bytes());
+
+    let protocols = iter::once((PingProtocol(), ProtocolSupport::Full));
+    let cfg = RequestResponseConfig::default();
+
+    let (peer1_id, trans) = mk_transport();
+    let ping_proto1 = RequestResponse::throttled(PingCodec(), protocols.clone(), cfg.clone());
+    let mut swarm1 = Swarm::new(trans, ping_proto1, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let ping_proto2 = RequestResponse::throttled(PingCodec(), protocols, cfg);
+    let mut swarm2 = Swarm::new(trans, ping_proto2, peer2_id.clone());
+
+    let (mut tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+
+    let expected_pong = pong.clone();
+
+    let peer1 = async move {
+        while swarm1.next().now_or_never().is_some() {}
+
+        let l = Swarm::listeners(&swarm1).next().unwrap();
+        tx.send(l.clone()).await.unwrap();
+
+        loop {
+            match swarm1.next().await {
+                throttled::Event::Event(RequestResponseEvent::Message {
+                    peer,
+                    message: RequestResponseMessage::Request { channel, .. }
+                }) => {
+                    assert_eq!(&peer, &peer2_id);
+                    swarm1.send_response(channel, pong.clone());
+                },
+                e => panic!("Peer1: Unexpected event: {:?}", e)
+            }
+        }
+    };
+
+    let peer2 = async move {
+        let addr = rx.next().await.unwrap();
+        swarm2.add_address(&peer1_id, addr.clone());
+
+        let mut policy = RetryPolicy::default();
+        policy.set_initial_backoff(Duration::from_millis(10));
+
+        // The first peer has no known addresses, the second one answers.
+        let unknown = PeerId::random();
+        let peers = vec![unknown.clone(), peer1_id.clone()];
+        let req_id = swarm2.send_request_to_any(peers, ping.clone(), policy.clone()).unwrap();
+        match swarm2.next().await {
+            throttled::Event::Event(RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { request_id, response }
+            }) => {
+                assert_eq!(&peer, &peer1_id);
+                assert_eq!(request_id, req_id);
+                assert_eq!(&response, &expected_pong);
+            }
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+
+        // The failed attempt used up the initial budget of the first peer.
+        assert!(!swarm2.can_send(&unknown));
+        assert!(swarm2.send_request_to_any(vec![unknown], ping.clone(), policy).is_err());
+    };
+
+    async_std::task::spawn(Box::pin(peer1));
+    let () = async_std::task::block_on(peer2);
+}
+
+/// Exercises sending requests to any of a set of peers, some of
+/// which cannot be dialed.
+#[test]
+fn ping_protocol_send_to_any() {
+    let ping = Ping("ping".to_string().into_bytes());
+    let pong = Pong("pong".to_string().into_bytes());
+
+    let protocols = iter::once((PingProtocol(), ProtocolSupport::Full));
+    let cfg = RequestResponseConfig::default();
+
+    let (peer1_id, trans) = mk_transport();
+    let ping_proto1 = RequestResponse::new(PingCodec(), protocols.clone(), cfg.clone());
+    let mut swarm1 = Swarm::new(trans, ping_proto1, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let ping_proto2 = RequestResponse::new(PingCodec(), protocols, cfg);
+    let mut swarm2 = Swarm::new(trans, ping_proto2, peer2_id.clone());
+
+    let (mut tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+
+    let expected_pong = pong.clone();
+
+    let peer1 = async move {
+        while swarm1.next().now_or_never().is_some() {}
+
+        let l = Swarm::listeners(&swarm1).next().unwrap();
+        tx.send(l.clone()).await.unwrap();
+
+        loop {
+            match swarm1.next().await {
+                RequestResponseEvent::Message {
+                    peer,
+                    message: RequestResponseMessage::Request { channel, .. }
+                } => {
+                    assert_eq!(&peer, &peer2_id);
+                    swarm1.send_response(channel, pong.clone());
+                },
+                e => panic!("Peer1: Unexpected event: {:?}", e)
+            }
+        }
+    };
+
+    let peer2 = async move {
+        let addr = rx.next().await.unwrap();
+        swarm2.add_address(&peer1_id, addr.clone());
+
+        let mut policy = RetryPolicy::default();
+        policy.set_initial_backoff(Duration::from_millis(10));
+
+        // The first peer has no known addresses, the second one answers.
+        let unknown = PeerId::random();
+        let peers = vec![unknown.clone(), peer1_id.clone()];
+        let req_id = swarm2.send_request_to_any(peers, ping.clone(), policy.clone());
+        match swarm2.next().await {
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { request_id, response }
+            } => {
+                assert_eq!(&peer, &peer1_id);
+                assert_eq!(request_id, req_id);
+                assert_eq!(&response, &expected_pong);
+            }
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+        assert!(!swarm2.is_pending_outbound(&req_id));
+
+        // None of the peers can be dialed.
+        let unknown2 = PeerId::random();
+        let peers = vec![unknown.clone(), unknown2.clone()];
+        let req_id = swarm2.send_request_to_any(peers, ping.clone(), policy);
+        match swarm2.next().await {
+            RequestResponseEvent::OutboundRoutingFailure { request_id, attempts } => {
+                assert_eq!(request_id, req_id);
+                let peers = attempts.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>();
+                assert_eq!(peers, vec![unknown.clone(), unknown2, unknown]);
+                assert!(attempts.iter().all(|(_, e)| matches!(e, OutboundFailure::DialFailure)));
+            }
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+    };
+
+    async_std::task::spawn(Box::pin(peer1));
+    let () = async_std::task::block_on(peer2);
+}
+
+/// Exercises the limits on inbound requests.
+#[test]
+fn ping_protocol_inbound_limits() {
+    let mut per_connection = RequestResponseConfig::default();
+    per_connection.set_max_inbound_requests_per_connection(1).set_max_request_size(16);
+    inbound_limits(per_connection, |e| matches!(e, InboundFailure::TooManyRequestsOnConnection));
+
+    let mut per_peer = RequestResponseConfig::default();
+    per_peer.set_max_inbound_requests_per_peer(1).set_max_request_size(16);
+    inbound_limits(per_peer, |e| matches!(e, InboundFailure::TooManyRequestsFromPeer));
+
+    let mut pending = RequestResponseConfig::default();
+    pending.set_max_pending_inbound_requests(1).set_max_request_size(16);
+    inbound_limits(pending, |e| matches!(e, InboundFailure::TooManyPendingRequests));
+}
+
+/// Sends a request that is not answered right away, another one that
+/// is expected to be rejected by `is_limit` and one that is too large.
+/// The requester must fail both rejected requests.
+fn inbound_limits(cfg: RequestResponseConfig, is_limit: fn(&InboundFailure) -> bool) {
+    let ping = Ping("ping".to_string().into_bytes());
+    let pong = Pong("pong".to_string().into_bytes());
+    let large = Ping(vec![0; 100]);
+
+    let protocols = iter::once((PingProtocol(), ProtocolSupport::Full));
+
+    let (peer1_id, trans) = mk_transport();
+    let ping_proto1 = RequestResponse::new(PingCodec(), protocols.clone(), cfg);
+    let mut swarm1 = Swarm::new(trans, ping_proto1, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let ping_proto2 = RequestResponse::new(PingCodec(), protocols, RequestResponseConfig::default());
+    let mut swarm2 = Swarm::new(trans, ping_proto2, peer2_id.clone());
+
+    let (mut tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+    let (mut next_tx, mut next_rx) = mpsc::channel::<()>(1);
+    let (mut fail_tx, mut fail_rx) = mpsc::channel::<RequestId>(2);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+
+    let peer1 = async move {
+        while swarm1.next().now_or_never().is_some() {}
+
+        let l = Swarm::listeners(&swarm1).next().unwrap();
+        tx.send(l.clone()).await.unwrap();
+
+        // The first request is held unanswered.
+        let channel = match swarm1.next().await {
+            RequestResponseEvent::Message {
+                message: RequestResponseMessage::Request { channel, .. }, ..
+            } => channel,
+            e => panic!("Peer1: Unexpected event: {:?}", e)
+        };
+        next_tx.send(()).await.unwrap();
+
+        match swarm1.next().await {
+            RequestResponseEvent::InboundFailure { peer, error, .. } if is_limit(&error) => {
+                assert_eq!(&peer, &peer2_id)
+            }
+            e => panic!("Peer1: Unexpected event: {:?}", e)
+        }
+        swarm1.send_response(channel, pong);
+        next_tx.send(()).await.unwrap();
+
+        match swarm1.next().await {
+            RequestResponseEvent::InboundFailure { error: InboundFailure::RequestTooLarge, .. } => {}
+            e => panic!("Peer1: Unexpected event: {:?}", e)
+        }
+
+        // Keep the connection going until the requester failed both requests.
+        let mut failed = HashSet::new();
+        while failed.len() < 2 {
+            if let future::Either::Right((Some(id), _)) = future::select(swarm1.next().boxed(), fail_rx.next()).await {
+                failed.insert(id);
+            }
+        }
+    };
+
+    let peer2 = async move {
+        let addr = rx.next().await.unwrap();
+        swarm2.add_address(&peer1_id, addr.clone());
+        let first = swarm2.send_request(&peer1_id, ping.clone());
+        let mut answered = false;
+        let mut signals = 0;
+        let mut requests = vec![ping, large].into_iter();
+        loop {
+            let event = match future::select(swarm2.next().boxed(), next_rx.next()).await {
+                future::Either::Left((event, _)) => Some(event),
+                future::Either::Right((Some(()), _)) => None,
+                future::Either::Right((None, _)) => return
+            };
+            match event {
+                Some(RequestResponseEvent::Message {
+                    message: RequestResponseMessage::Response { request_id, .. }, ..
+                }) if request_id == first => answered = true,
+                Some(RequestResponseEvent::OutboundFailure {
+                    request_id, error: OutboundFailure::Io(e), ..
+                }) => {
+                    assert_ne!(request_id, first);
+                    assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+                    fail_tx.send(request_id).await.unwrap();
+                    continue
+                }
+                Some(e) => panic!("Peer2: Unexpected event: {:?}", e),
+                None => signals += 1
+            }
+            // The last request must only be sent once the first one is no
+            // longer in progress, i.e. after its response has been received.
+            let sent = 2 - requests.len();
+            if sent < signals && (sent == 0 || answered) {
+                if let Some(request) = requests.next() {
+                    swarm2.send_request(&peer1_id, request);
+                }
+            }
+        }
+    };
+
+    async_std::task::spawn(Box::pin(peer2));
+    let () = async_std::task::block_on(peer1);
+}
+
+/// Exercises the limit on the size of received responses, both
+/// plain and streamed ones.
+#[test]
+fn ping_protocol_response_too_large() {
+    let ping = Ping("ping".to_string().into_bytes());
+    let pong = Pong("pong".to_string().into_bytes());
+    let large = Pong(vec![0; 100]);
+
+    let protocols = iter::once((PingProtocol(), ProtocolSupport::Full));
+    let mut cfg = RequestResponseConfig::default();
+    cfg.set_max_response_size(16);
+
+    let (peer1_id, trans) = mk_transport();
+    let ping_proto1 = RequestResponse::new(PingCodec(), protocols.clone(), RequestResponseConfig::default());
+    let mut swarm1 = Swarm::new(trans, ping_proto1, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let ping_proto2 = RequestResponse::new(PingCodec(), protocols, cfg);
+    let mut swarm2 = Swarm::new(trans, ping_proto2, peer2_id.clone());
+
+    let (mut tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+
+    let expected_pong = pong.clone();
+
+    let peer1 = async move {
+        while swarm1.next().now_or_never().is_some() {}
+
+        let l = Swarm::listeners(&swarm1).next().unwrap();
+        tx.send(l.clone()).await.unwrap();
+
+        // The first request is answered with a single large response,
+        // the second one with a small chunk followed by a large one.
+        let mut answered = false;
+        loop {
+            match swarm1.next().await {
+                RequestResponseEvent::Message {
+                    message: RequestResponseMessage::Request { channel, .. }, ..
+                } => if !answered {
+                    answered = true;
+                    swarm1.send_response(channel, large.clone());
+                } else {
+                    let mut channel = channel;
+                    swarm1.send_response_chunk(&mut channel, pong.clone()).unwrap();
+                    swarm1.send_response_chunk(&mut channel, large.clone()).unwrap();
+                    swarm1.end_response_stream(channel);
+                }
+                RequestResponseEvent::InboundFailure { .. } => {}
+                e => panic!("Peer1: Unexpected event: {:?}", e)
+            }
+        }
+    };
+
+    let peer2 = async move {
+        let addr = rx.next().await.unwrap();
+        swarm2.add_address(&peer1_id, addr.clone());
+
+        let req_id = swarm2.send_request(&peer1_id, ping.clone());
+        match swarm2.next().await {
+            RequestResponseEvent::OutboundFailure { request_id, error: OutboundFailure::Io(e), .. } => {
+                assert_eq!(request_id, req_id);
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            }
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+
+        let req_id = swarm2.send_streaming_request(&peer1_id, ping.clone());
+        match swarm2.next().await {
+            RequestResponseEvent::Message {
+                message: RequestResponseMessage::ResponseChunk { request_id, chunk }, ..
+            } => {
+                assert_eq!(request_id, req_id);
+                assert_eq!(&chunk, &expected_pong);
+            }
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+        match swarm2.next().await {
+            RequestResponseEvent::OutboundFailure { request_id, error: OutboundFailure::Io(e), .. } => {
+                assert_eq!(request_id, req_id);
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            }
+            e => panic!("Peer2: Unexpected event: {:?}", e)
+        }
+        assert!(!swarm2.is_pending_outbound(&req_id));
+    };
+
+    async_std::task::spawn(Box::pin(peer1));
+    let () = async_std::task::block_on(peer2);
+}
+
 fn mk_transport() -> (PeerId, Boxed<(PeerId, StreamMuxerBox), io::Error>) {
     let id_keys = identity::Keypair::generate_ed25519();
     let peer_id = id_keys.public().into_peer_id();
@@ -292,7 +1195,8 @@ impl RequestResponseCodec for PingCodec {
     where
         T: AsyncWrite + Unpin + Send
     {
-        write_one(io, data).await
+        // Does not close the stream, which may carry more response chunks.
+        write_with_len_prefix(io, data).await
     }
 }
 
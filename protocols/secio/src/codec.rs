@@ -25,7 +25,7 @@ mod decode;
 mod encode;
 mod len_prefix;
 
-use aes_ctr::stream_cipher;
+use ctr::cipher;
 use crate::algo_support::Digest;
 use decode::DecoderMiddleware;
 use encode::EncoderMiddleware;
@@ -38,7 +38,7 @@ pub use len_prefix::LenPrefixCodec;
 /// Type returned by `full_codec`.
 pub type FullCodec<S> = DecoderMiddleware<EncoderMiddleware<LenPrefixCodec<S>>>;
 
-pub type StreamCipher = Box<dyn stream_cipher::StreamCipher + Send>;
+pub type StreamCipher = Box<dyn cipher::StreamCipher + Send>;
 
 #[derive(Debug, Clone)]
 pub enum Hmac {
@@ -93,7 +93,7 @@ where
 
         let mut data_buf = frame;
         data_buf.truncate(content_length);
-        this.cipher_state.decrypt(&mut data_buf);
+        this.cipher_state.apply_keystream(&mut data_buf);
 
         if !this.nonce.is_empty() {
             let n = min(data_buf.len(), this.nonce.len());
@@ -63,7 +63,7 @@ where
     fn start_send(self: Pin<&mut Self>, mut data_buf: Vec<u8>) -> Result<(), Self::Error> {
         let this = self.project();
         // TODO if SinkError gets refactor to SecioError, then use try_apply_keystream
-        this.cipher_state.encrypt(&mut data_buf[..]);
+        this.cipher_state.apply_keystream(&mut data_buf[..]);
         let signature = this.hmac.sign(&data_buf[..]);
         data_buf.extend_from_slice(signature.as_ref());
         Sink::start_send(this.raw_sink, data_buf)
@@ -20,7 +20,7 @@
 
 //! Defines the `SecioError` enum that groups all possible errors in SECIO.
 
-use aes_ctr::stream_cipher::LoopError;
+use ctr::cipher::StreamCipherError;
 use std::error;
 use std::fmt;
 use std::io::Error as IoError;
@@ -59,7 +59,7 @@ pub enum SecioError {
     NonceVerificationFailed,
 
     /// Error with block cipher.
-    CipherError(LoopError),
+    CipherError(StreamCipherError),
 
     /// The received frame was of invalid length.
     FrameTooShort,
@@ -125,8 +125,8 @@ impl fmt::Display for SecioError {
     }
 }
 
-impl From<LoopError> for SecioError {
-    fn from(err: LoopError) -> SecioError {
+impl From<StreamCipherError> for SecioError {
+    fn from(err: StreamCipherError) -> SecioError {
         SecioError::CipherError(err)
     }
 }
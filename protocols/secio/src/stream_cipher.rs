@@ -19,10 +19,11 @@
 // DEALINGS IN THE SOFTWARE.
 
 use super::codec::StreamCipher;
-use aes_ctr::stream_cipher::generic_array::GenericArray;
-use aes_ctr::stream_cipher::{NewStreamCipher, LoopError, SyncStreamCipher};
-use aes_ctr::{Aes128Ctr, Aes256Ctr};
-use ctr::Ctr128;
+use aes::{Aes128, Aes256};
+use ctr::Ctr128BE;
+use ctr::cipher::{self, KeyIvInit, StreamCipherError};
+use ctr::cipher::generic_array::GenericArray;
+use ctr::cipher::inout::InOutBuf;
 use twofish::Twofish;
 
 /// Possible encryption ciphers.
@@ -60,122 +61,44 @@ impl Cipher {
 #[derive(Clone, Copy, Debug)]
 pub struct NullCipher;
 
-impl SyncStreamCipher for NullCipher {
-    fn try_apply_keystream(&mut self, _data: &mut [u8]) -> Result<(), LoopError> {
+impl cipher::StreamCipher for NullCipher {
+    fn try_apply_keystream_inout(&mut self, mut buf: InOutBuf<'_, '_, u8>) -> Result<(), StreamCipherError> {
+        for i in 0 .. buf.len() {
+            let mut b = buf.get(i);
+            *b.get_out() = *b.get_in()
+        }
         Ok(())
     }
 }
 
-/// Returns your stream cipher depending on `Cipher`.
-#[cfg(not(all(feature = "aes-all", any(target_arch = "x86_64", target_arch = "x86"))))]
-pub fn ctr(key_size: Cipher, key: &[u8], iv: &[u8]) -> StreamCipher {
-    ctr_int(key_size, key, iv)
-}
+/// The `aes-all` feature used to select AES-NI, which is now detected at runtime.
+#[cfg(feature = "aes-all")]
+#[deprecated(
+    since = "0.23.0",
+    note = "The `aes-all` feature of `libp2p-secio` has no effect and will be removed."
+)]
+const AES_ALL: () = ();
+
+#[cfg(feature = "aes-all")]
+const _: () = AES_ALL;
 
 /// Returns your stream cipher depending on `Cipher`.
-#[cfg(all(feature = "aes-all", any(target_arch = "x86_64", target_arch = "x86")))]
+///
+/// AES uses the AES-NI instructions if they are detected at runtime.
 pub fn ctr(key_size: Cipher, key: &[u8], iv: &[u8]) -> StreamCipher {
-    if *aes_alt::AES_NI {
-        aes_alt::ctr_alt(key_size, key, iv)
-    } else {
-        ctr_int(key_size, key, iv)
-    }
-}
-
-
-#[cfg(all(feature = "aes-all", any(target_arch = "x86_64", target_arch = "x86")))]
-mod aes_alt {
-    use crate::codec::StreamCipher;
-    use ctr::Ctr128;
-    use aesni::{Aes128, Aes256};
-    use ctr::stream_cipher::NewStreamCipher;
-    use ctr::stream_cipher::generic_array::GenericArray;
-    use lazy_static::lazy_static;
-    use twofish::Twofish;
-    use super::{Cipher, NullCipher};
-
-    lazy_static! {
-        pub static ref AES_NI: bool = is_x86_feature_detected!("aes")
-            && is_x86_feature_detected!("sse2")
-            && is_x86_feature_detected!("sse3");
-
-   }
-
-    /// AES-128 in CTR mode
-    pub type Aes128Ctr = Ctr128<Aes128>;
-    /// AES-256 in CTR mode
-    pub type Aes256Ctr = Ctr128<Aes256>;
-    /// Returns alternate stream cipher if target functionalities does not allow standard one.
-    /// Eg : aes without sse
-    pub fn ctr_alt(key_size: Cipher, key: &[u8], iv: &[u8]) -> StreamCipher {
-        match key_size {
-            Cipher::Aes128 => Box::new(Aes128Ctr::new(
-                GenericArray::from_slice(key),
-                GenericArray::from_slice(iv),
-            )),
-            Cipher::Aes256 => Box::new(Aes256Ctr::new(
-                GenericArray::from_slice(key),
-                GenericArray::from_slice(iv),
-            )),
-            Cipher::TwofishCtr => Box::new(Ctr128::<Twofish>::new(
-                GenericArray::from_slice(key),
-                GenericArray::from_slice(iv),
-            )),
-            Cipher::Null => Box::new(NullCipher),
-        }
-    }
-
-}
-
-#[inline]
-fn ctr_int(key_size: Cipher, key: &[u8], iv: &[u8]) -> StreamCipher {
     match key_size {
-        Cipher::Aes128 => Box::new(Aes128Ctr::new(
+        Cipher::Aes128 => Box::new(Ctr128BE::<Aes128>::new(
             GenericArray::from_slice(key),
             GenericArray::from_slice(iv),
         )),
-        Cipher::Aes256 => Box::new(Aes256Ctr::new(
+        Cipher::Aes256 => Box::new(Ctr128BE::<Aes256>::new(
             GenericArray::from_slice(key),
             GenericArray::from_slice(iv),
         )),
-        Cipher::TwofishCtr => Box::new(Ctr128::<Twofish>::new(
+        Cipher::TwofishCtr => Box::new(Ctr128BE::<Twofish>::new(
             GenericArray::from_slice(key),
             GenericArray::from_slice(iv),
         )),
         Cipher::Null => Box::new(NullCipher),
     }
 }
-
-#[cfg(all(
-        feature = "aes-all",
-        any(target_arch = "x86_64", target_arch = "x86"),
-))]
-#[cfg(test)]
-mod tests {
-    use super::{Cipher, ctr};
-
-    #[test]
-    fn assert_non_native_run() {
-        // this test is for asserting aes unsuported opcode does not break on old cpu
-        let key = [0;16];
-        let iv = [0;16];
-
-        let mut aes = ctr(Cipher::Aes128, &key, &iv);
-        let mut content = [0;16];
-        aes.encrypt(&mut content);
-
-    }
-}
-
-// aesni compile check for aes-all (aes-all import aesni through aes_ctr only if those checks pass)
-#[cfg(all(
-    feature = "aes-all",
-    any(target_arch = "x86_64", target_arch = "x86"),
-    any(target_feature = "aes", target_feature = "ssse3"),
-))]
-compile_error!(
-    "aes-all must be compile without aes and sse3 flags : currently \
-    is_x86_feature_detected macro will not detect feature correctly otherwhise. \
-    RUSTFLAGS=\"-C target-feature=+aes,+ssse3\" enviromental variable. \
-    For x86 target arch additionally enable sse2 target feature."
-);
@@ -171,8 +171,8 @@ pub use libp2p_core as core;
 #[cfg(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")))]
 #[doc(inline)]
 pub use libp2p_deflate as deflate;
-#[cfg(feature = "dns")]
-#[cfg_attr(docsrs, doc(cfg(feature = "dns")))]
+#[cfg(any(feature = "dns-async-std", feature = "dns-tokio"))]
+#[cfg_attr(docsrs, doc(cfg(any(feature = "dns-async-std", feature = "dns-tokio"))))]
 #[cfg(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")))]
 #[doc(inline)]
 pub use libp2p_dns as dns;
@@ -213,6 +213,11 @@ pub use libp2p_ping as ping;
 #[cfg_attr(docsrs, doc(cfg(feature = "plaintext")))]
 #[doc(inline)]
 pub use libp2p_plaintext as plaintext;
+#[cfg(feature = "socks")]
+#[cfg_attr(docsrs, doc(cfg(feature = "socks")))]
+#[cfg(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")))]
+#[doc(inline)]
+pub use libp2p_socks as socks;
 #[doc(inline)]
 pub use libp2p_swarm as swarm;
 #[cfg(any(feature = "tcp-async-std", feature = "tcp-tokio"))]
@@ -268,29 +273,28 @@ pub use self::transport_ext::TransportExt;
 ///
 /// > **Note**: This `Transport` is not suitable for production usage, as its implementation
 /// >           reserves the right to support additional protocols or remove deprecated protocols.
-#[cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(feature = "tcp-async-std", feature = "tcp-tokio"), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux"))]
-#[cfg_attr(docsrs, doc(cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(feature = "tcp-async-std", feature = "tcp-tokio"), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux"))))]
-pub fn build_development_transport(keypair: identity::Keypair)
+#[cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(all(feature = "tcp-async-std", feature = "dns-async-std"), all(feature = "tcp-tokio", feature = "dns-tokio")), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux"))]
+#[cfg_attr(docsrs, doc(cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(all(feature = "tcp-async-std", feature = "dns-async-std"), all(feature = "tcp-tokio", feature = "dns-tokio")), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux"))))]
+pub async fn build_development_transport(keypair: identity::Keypair)
     -> std::io::Result<impl Transport<Output = (PeerId, impl core::muxing::StreamMuxer<OutboundSubstream = impl Send, Substream = impl Send, Error = impl Into<std::io::Error>> + Send + Sync), Error = impl std::error::Error + Send, Listener = impl Send, Dial = impl Send, ListenerUpgrade = impl Send> + Clone>
 {
-     build_tcp_ws_noise_mplex_yamux(keypair)
+    build_tcp_ws_noise_mplex_yamux(keypair).await
 }
 
 /// Builds an implementation of `Transport` that is suitable for usage with the `Swarm`.
 ///
 /// The implementation supports TCP/IP, WebSockets over TCP/IP, noise as the encryption layer,
 /// and mplex or yamux as the multiplexing layer.
-#[cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(feature = "tcp-async-std", feature = "tcp-tokio"), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux"))]
-#[cfg_attr(docsrs, doc(cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(feature = "tcp-async-std", feature = "tcp-tokio"), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux"))))]
-pub fn build_tcp_ws_noise_mplex_yamux(keypair: identity::Keypair)
+#[cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(all(feature = "tcp-async-std", feature = "dns-async-std"), all(feature = "tcp-tokio", feature = "dns-tokio")), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux"))]
+#[cfg_attr(docsrs, doc(cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(all(feature = "tcp-async-std", feature = "dns-async-std"), all(feature = "tcp-tokio", feature = "dns-tokio")), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux"))))]
+pub async fn build_tcp_ws_noise_mplex_yamux(keypair: identity::Keypair)
     -> std::io::Result<impl Transport<Output = (PeerId, impl core::muxing::StreamMuxer<OutboundSubstream = impl Send, Substream = impl Send, Error = impl Into<std::io::Error>> + Send + Sync), Error = impl std::error::Error + Send, Listener = impl Send, Dial = impl Send, ListenerUpgrade = impl Send> + Clone>
 {
     let transport = {
-        #[cfg(feature = "tcp-async-std")]
-        let tcp = tcp::TcpConfig::new().nodelay(true);
-        #[cfg(feature = "tcp-tokio")]
-        let tcp = tcp::TokioTcpConfig::new().nodelay(true);
-        let transport = dns::DnsConfig::new(tcp)?;
+        #[cfg(all(feature = "tcp-async-std", feature = "dns-async-std"))]
+        let transport = dns::DnsConfig::new(tcp::TcpConfig::new().nodelay(true)).await?;
+        #[cfg(all(feature = "tcp-tokio", feature = "dns-tokio"))]
+        let transport = dns::TokioDnsConfig::new(tcp::TokioTcpConfig::new().nodelay(true)).await?;
         let trans_clone = transport.clone();
         transport.or_transport(websocket::WsConfig::new(trans_clone))
     };
@@ -311,17 +315,16 @@ pub fn build_tcp_ws_noise_mplex_yamux(keypair: identity::Keypair)
 ///
 /// The implementation supports TCP/IP, WebSockets over TCP/IP, noise as the encryption layer,
 /// and mplex or yamux as the multiplexing layer.
-#[cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(feature = "tcp-async-std", feature = "tcp-tokio"), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux", feature = "pnet"))]
-#[cfg_attr(docsrs, doc(cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(feature = "tcp-async-std", feature = "tcp-tokio"), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux", feature = "pnet"))))]
-pub fn build_tcp_ws_pnet_noise_mplex_yamux(keypair: identity::Keypair, psk: PreSharedKey)
+#[cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(all(feature = "tcp-async-std", feature = "dns-async-std"), all(feature = "tcp-tokio", feature = "dns-tokio")), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux", feature = "pnet"))]
+#[cfg_attr(docsrs, doc(cfg(all(not(any(target_os = "emscripten", target_os = "wasi", target_os = "unknown")), any(all(feature = "tcp-async-std", feature = "dns-async-std"), all(feature = "tcp-tokio", feature = "dns-tokio")), feature = "websocket", feature = "noise", feature = "mplex", feature = "yamux", feature = "pnet"))))]
+pub async fn build_tcp_ws_pnet_noise_mplex_yamux(keypair: identity::Keypair, psk: PreSharedKey)
     -> std::io::Result<impl Transport<Output = (PeerId, impl core::muxing::StreamMuxer<OutboundSubstream = impl Send, Substream = impl Send, Error = impl Into<std::io::Error>> + Send + Sync), Error = impl std::error::Error + Send, Listener = impl Send, Dial = impl Send, ListenerUpgrade = impl Send> + Clone>
 {
     let transport = {
-        #[cfg(feature = "tcp-async-std")]
-        let tcp = tcp::TcpConfig::new().nodelay(true);
-        #[cfg(feature = "tcp-tokio")]
-        let tcp = tcp::TokioTcpConfig::new().nodelay(true);
-        let transport = dns::DnsConfig::new(tcp)?;
+        #[cfg(all(feature = "tcp-async-std", feature = "dns-async-std"))]
+        let transport = dns::DnsConfig::new(tcp::TcpConfig::new().nodelay(true)).await?;
+        #[cfg(all(feature = "tcp-tokio", feature = "dns-tokio"))]
+        let transport = dns::TokioDnsConfig::new(tcp::TokioTcpConfig::new().nodelay(true)).await?;
         let trans_clone = transport.clone();
         transport.or_transport(websocket::WsConfig::new(trans_clone))
     };
@@ -159,6 +159,12 @@ pub trait NetworkBehaviour: Send + 'static {
     fn inject_new_external_addr(&mut self, _addr: &Multiaddr) {
     }
 
+    /// Indicates to the behaviour that an external address of ours has expired,
+    /// because no remote has observed it recently or because it has been
+    /// displaced by addresses observed more often.
+    fn inject_expired_external_addr(&mut self, _addr: &Multiaddr) {
+    }
+
     /// A listener experienced an error.
     fn inject_listener_error(&mut self, _id: ListenerId, _err: &(dyn std::error::Error + 'static)) {
     }
@@ -275,9 +281,14 @@ pub enum NetworkBehaviourAction<TInEvent, TOutEvent> {
     /// It is advisable to issue `ReportObservedAddr` actions at a fixed frequency
     /// per node. This way address information will be more accurate over time
     /// and individual outliers carry less weight.
+    ///
+    /// An observed address only becomes an external address of the local node
+    /// once it has been reported by enough distinct observers.
     ReportObservedAddr {
         /// The observed address of the local node.
         address: Multiaddr,
+        /// The remote that observed the address.
+        observer: PeerId,
     },
 }
 
@@ -297,8 +308,8 @@ impl<TInEvent, TOutEvent> NetworkBehaviourAction<TInEvent, TOutEvent> {
                     handler,
                     event: f(event)
                 },
-            NetworkBehaviourAction::ReportObservedAddr { address } =>
-                NetworkBehaviourAction::ReportObservedAddr { address }
+            NetworkBehaviourAction::ReportObservedAddr { address, observer } =>
+                NetworkBehaviourAction::ReportObservedAddr { address, observer }
         }
     }
 
@@ -313,8 +324,8 @@ impl<TInEvent, TOutEvent> NetworkBehaviourAction<TInEvent, TOutEvent> {
                 NetworkBehaviourAction::DialPeer { peer_id, condition },
             NetworkBehaviourAction::NotifyHandler { peer_id, handler, event } =>
                 NetworkBehaviourAction::NotifyHandler { peer_id, handler, event },
-            NetworkBehaviourAction::ReportObservedAddr { address } =>
-                NetworkBehaviourAction::ReportObservedAddr { address }
+            NetworkBehaviourAction::ReportObservedAddr { address, observer } =>
+                NetworkBehaviourAction::ReportObservedAddr { address, observer }
         }
     }
 }
@@ -121,9 +121,9 @@ use libp2p_core::{
     },
     upgrade::ProtocolName,
 };
-use registry::{Addresses, AddressIntoIter};
+use registry::{Addresses, AddressIntoIter, ObservedAddresses, ObservedAddrEvent};
 use smallvec::SmallVec;
-use std::{error, fmt, hash::Hash, io, ops::{Deref, DerefMut}, pin::Pin, task::{Context, Poll}};
+use std::{error, fmt, hash::Hash, io, ops::{Deref, DerefMut}, pin::Pin, task::{Context, Poll}, time::Duration};
 use std::collections::HashSet;
 use std::num::{NonZeroU32, NonZeroUsize};
 use upgrade::UpgradeInfoSend as _;
@@ -252,6 +252,12 @@ pub enum SwarmEvent<TBvEv, THandleErr> {
     /// [`UnreachableAddr`](SwarmEvent::UnreachableAddr) event is reported
     /// with `attempts_remaining` equal to 0.
     Dialing(PeerId),
+    /// An address observed by remotes for the local node has been reported by
+    /// enough distinct remotes and is now considered an external address.
+    ExternalAddrConfirmed(Multiaddr),
+    /// A confirmed external address has not been observed by any remote
+    /// recently and is no longer considered an external address.
+    ExternalAddrExpired(Multiaddr),
 }
 
 /// Contains the state of the network, plus the way it should behave.
@@ -283,6 +289,10 @@ where
     /// similar mechanisms.
     external_addrs: Addresses,
 
+    /// Addresses observed by remotes for the local node, from which external
+    /// addresses are confirmed.
+    observed_addrs: ObservedAddresses,
+
     /// List of nodes for which we deny any incoming connection.
     banned_peers: HashSet<PeerId>,
 
@@ -429,8 +439,22 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
     ///
     /// An external address is an address we are listening on but that accounts for things such as
     /// NAT traversal.
+    ///
+    /// Contrary to addresses confirmed by observations of remotes, an address
+    /// added with this method never expires.
     pub fn add_external_address(me: &mut Self, addr: Multiaddr) {
-        me.external_addrs.add(addr)
+        me.observed_addrs.add_permanent(addr.clone());
+        ExpandedSwarm::add_external_addr(me, addr)
+    }
+
+    /// Adds a confirmation of an external address, expiring the address
+    /// that is evicted to make room for it, if any.
+    fn add_external_addr(me: &mut Self, addr: Multiaddr) {
+        if let Some(evicted) = me.external_addrs.add(addr) {
+            log::debug!("External address evicted: {:?}", evicted);
+            me.observed_addrs.remove(&evicted);
+            me.behaviour.inject_expired_external_addr(&evicted);
+        }
     }
 
     /// Returns the connection info for an arbitrary connection with the peer, or `None`
@@ -493,6 +517,24 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
         loop {
             let mut network_not_ready = false;
 
+            // Report the external addresses that have been confirmed or
+            // have expired since the last iteration.
+            match this.observed_addrs.poll(cx) {
+                Poll::Pending => (),
+                Poll::Ready(ObservedAddrEvent::Confirmed(addr)) => {
+                    log::debug!("External address confirmed: {:?}", addr);
+                    ExpandedSwarm::add_external_addr(this, addr.clone());
+                    this.behaviour.inject_new_external_addr(&addr);
+                    return Poll::Ready(SwarmEvent::ExternalAddrConfirmed(addr));
+                }
+                Poll::Ready(ObservedAddrEvent::Expired(addr)) => {
+                    log::debug!("External address expired: {:?}", addr);
+                    this.external_addrs.remove(&addr);
+                    this.behaviour.inject_expired_external_addr(&addr);
+                    return Poll::Ready(SwarmEvent::ExternalAddrExpired(addr));
+                }
+            }
+
             // First let the network make progress.
             match this.network.poll(cx) {
                 Poll::Pending => network_not_ready = true,
@@ -749,12 +791,14 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
                         }
                     }
                 },
-                Poll::Ready(NetworkBehaviourAction::ReportObservedAddr { address }) => {
-                    for addr in this.network.address_translation(&address) {
-                        if this.external_addrs.iter().all(|a| *a != addr) {
-                            this.behaviour.inject_new_external_addr(&addr);
+                Poll::Ready(NetworkBehaviourAction::ReportObservedAddr { address, observer }) => {
+                    let addrs = this.network.address_translation(&address).collect::<Vec<_>>();
+                    for addr in addrs {
+                        // Further reports of a confirmed address raise its score.
+                        if this.external_addrs.iter().any(|a| *a == addr) {
+                            ExpandedSwarm::add_external_addr(this, addr.clone());
                         }
-                        this.external_addrs.add(addr);
+                        this.observed_addrs.report(addr, observer.clone());
                     }
                 },
             }
@@ -975,6 +1019,8 @@ pub struct SwarmBuilder<TBehaviour, TConnInfo> {
     transport: BoxTransport<(TConnInfo, StreamMuxerBox), io::Error>,
     behaviour: TBehaviour,
     network_config: NetworkConfig,
+    external_addr_min_observers: NonZeroUsize,
+    external_addr_ttl: Duration,
 }
 
 impl<TBehaviour, TConnInfo> SwarmBuilder<TBehaviour, TConnInfo>
@@ -1006,6 +1052,8 @@ where TBehaviour: NetworkBehaviour,
             transport,
             behaviour,
             network_config: Default::default(),
+            external_addr_min_observers: NonZeroUsize::new(2).expect("2 > 0"),
+            external_addr_ttl: Duration::from_secs(15 * 60),
         }
     }
 
@@ -1081,6 +1129,26 @@ where TBehaviour: NetworkBehaviour,
         self
     }
 
+    /// Configures the number of distinct remotes that must have observed an
+    /// address of the local node before it is considered an external address.
+    ///
+    /// Observations are reported by behaviours via
+    /// [`NetworkBehaviourAction::ReportObservedAddr`]. Defaults to 2.
+    pub fn external_addr_min_observers(mut self, n: NonZeroUsize) -> Self {
+        self.external_addr_min_observers = n;
+        self
+    }
+
+    /// Configures how long an observation of an address of the local node
+    /// by a remote remains valid.
+    ///
+    /// A confirmed external address expires once none of the remotes that
+    /// observed it has done so within this duration. Defaults to 15 minutes.
+    pub fn external_addr_ttl(mut self, d: Duration) -> Self {
+        self.external_addr_ttl = d;
+        self
+    }
+
     /// Builds a `Swarm` with the current configuration.
     pub fn build(mut self) -> Swarm<TBehaviour, TConnInfo> {
         let supported_protocols = self.behaviour
@@ -1114,6 +1182,10 @@ where TBehaviour: NetworkBehaviour,
             supported_protocols,
             listened_addrs: SmallVec::new(),
             external_addrs: Addresses::default(),
+            observed_addrs: ObservedAddresses::new(
+                self.external_addr_min_observers,
+                self.external_addr_ttl,
+            ),
             banned_peers: HashSet::new(),
             pending_event: None
         }
@@ -1195,6 +1267,7 @@ mod tests {
     use crate::test::{MockBehaviour, CallTraceBehaviour};
     use futures::{future, executor};
     use libp2p_core::{
+        address_translation,
         identity,
         upgrade,
         multiaddr,
@@ -1213,11 +1286,27 @@ mod tests {
         T: ProtocolsHandler + Clone,
         T::OutEvent: Clone,
         O: Send + 'static
+    {
+        new_test_swarm_builder(transport::MemoryTransport::default(), handler_proto).build()
+    }
+
+    fn new_test_swarm_builder<TTrans, T, O>(base: TTrans, handler_proto: T)
+        -> SwarmBuilder<CallTraceBehaviour<MockBehaviour<T, O>>, PeerId>
+    where
+        TTrans: Transport + Clone + Send + Sync + 'static,
+        TTrans::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        TTrans::Error: Send + Sync + 'static,
+        TTrans::Listener: Send + 'static,
+        TTrans::ListenerUpgrade: Send + 'static,
+        TTrans::Dial: Send + 'static,
+        T: ProtocolsHandler + Clone,
+        T::OutEvent: Clone,
+        O: Send + 'static
     {
         let id_keys = identity::Keypair::generate_ed25519();
         let pubkey = id_keys.public();
         let noise_keys = noise::Keypair::<noise::X25519Spec>::new().into_authentic(&id_keys).unwrap();
-        let transport = transport::MemoryTransport::default()
+        let transport = base
             .upgrade(upgrade::Version::V1)
             .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
             .multiplex(libp2p_mplex::MplexConfig::new())
@@ -1225,7 +1314,7 @@ mod tests {
             .map_err(|e| -> io::Error { panic!("Failed to create transport: {:?}", e); })
             .boxed();
         let behaviour = CallTraceBehaviour::new(MockBehaviour::new(handler_proto));
-        SwarmBuilder::new(transport, behaviour, pubkey.into()).build()
+        SwarmBuilder::new(transport, behaviour, pubkey.into())
     }
 
     #[test]
@@ -1350,4 +1439,89 @@ mod tests {
             }
         }))
     }
+
+    /// Two remotes connect to a swarm listening on TCP, after which the
+    /// swarm's behaviour reports an observed address on behalf of each.
+    ///
+    /// The test expects the address to only be confirmed as an external
+    /// address once both remotes have observed it, and to expire once it
+    /// has not been observed again within the TTL.
+    #[test]
+    fn test_external_addr_confirm_and_expire() {
+        let mut handler_proto = DummyProtocolsHandler::default();
+        handler_proto.keep_alive = KeepAlive::Yes;
+
+        let tcp = libp2p_tcp::TcpConfig::new();
+        let mut swarm = new_test_swarm_builder::<_, _, ()>(tcp.clone(), handler_proto.clone())
+            .external_addr_min_observers(NonZeroUsize::new(2).unwrap())
+            .external_addr_ttl(Duration::from_millis(500))
+            .build();
+        let mut remotes = vec![
+            new_test_swarm_builder::<_, _, ()>(tcp.clone(), handler_proto.clone()).build(),
+            new_test_swarm_builder::<_, _, ()>(tcp, handler_proto).build(),
+        ];
+
+        Swarm::listen_on(&mut swarm, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+        let observed: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+        let mut external = None;
+        let mut reports = std::collections::VecDeque::new();
+        let mut confirmed = false;
+
+        let test = future::poll_fn(move |cx| {
+            loop {
+                for remote in &mut remotes {
+                    while Swarm::poll_next_event(Pin::new(remote), cx).is_ready() {}
+                }
+
+                // The mock behaviour reports a single observation per poll.
+                if swarm.behaviour.inner.next_action.is_none() {
+                    if let Some(observer) = reports.pop_front() {
+                        swarm.behaviour.inner.next_action =
+                            Some(NetworkBehaviourAction::ReportObservedAddr {
+                                address: observed.clone(),
+                                observer,
+                            });
+                    }
+                }
+
+                match Swarm::poll_next_event(Pin::new(&mut swarm), cx) {
+                    Poll::Ready(SwarmEvent::NewListenAddr(addr)) => {
+                        // The external address keeps the port of the listener.
+                        external = Some(address_translation(&addr, &observed).unwrap());
+                        for remote in &mut remotes {
+                            Swarm::dial_addr(remote, addr.clone()).unwrap();
+                        }
+                    }
+                    Poll::Ready(SwarmEvent::ConnectionEstablished { peer_id, .. }) => {
+                        reports.push_back(peer_id);
+                    }
+                    Poll::Ready(SwarmEvent::ExternalAddrConfirmed(addr)) => {
+                        assert_eq!(Some(&addr), external.as_ref());
+                        assert!(!confirmed);
+                        assert!(reports.is_empty() && swarm.behaviour.inner.next_action.is_none(),
+                            "Address confirmed before both remotes observed it");
+                        assert_eq!(swarm.behaviour.inject_new_external_addr, vec![addr.clone()]);
+                        assert!(Swarm::external_addresses(&swarm).any(|a| *a == addr));
+                        confirmed = true;
+                    }
+                    Poll::Ready(SwarmEvent::ExternalAddrExpired(addr)) => {
+                        assert_eq!(Some(&addr), external.as_ref());
+                        assert!(confirmed);
+                        assert_eq!(swarm.behaviour.inject_expired_external_addr, vec![addr.clone()]);
+                        assert!(Swarm::external_addresses(&swarm).all(|a| *a != addr));
+                        return Poll::Ready(())
+                    }
+                    Poll::Ready(_) => {}
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        });
+
+        let timeout = wasm_timer::Delay::new(Duration::from_secs(10));
+        match executor::block_on(future::select(Box::pin(test), timeout)) {
+            future::Either::Left(((), _)) => {}
+            future::Either::Right(_) => panic!("External address not confirmed and expired in time"),
+        }
+    }
 }
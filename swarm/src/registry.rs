@@ -18,9 +18,17 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use libp2p_core::Multiaddr;
+use futures::prelude::*;
+use libp2p_core::{Multiaddr, PeerId};
 use smallvec::SmallVec;
-use std::{collections::VecDeque, num::NonZeroUsize};
+use std::{
+    collections::{HashMap, VecDeque},
+    num::NonZeroUsize,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use wasm_timer::{Delay, Instant};
 
 /// Hold a ranked collection of [`Multiaddr`] values.
 ///
@@ -65,7 +73,10 @@ impl Addresses {
     ///
     /// Adding an existing address is interpreted as additional
     /// confirmation and thus increases its score.
-    pub fn add(&mut self, a: Multiaddr) {
+    ///
+    /// Returns the address that has been removed from the collection to
+    /// make room for the new report, if any.
+    pub fn add(&mut self, a: Multiaddr) -> Option<Multiaddr> {
 
         let oldest = if self.reports.len() == self.limit.get() {
             self.reports.pop_front()
@@ -81,8 +92,9 @@ impl Addresses {
         }
 
         // Remove addresses that have a score of 0.
+        let mut evicted = None;
         while self.registry.last().map(|e| e.score == 0).unwrap_or(false) {
-            self.registry.pop();
+            evicted = self.registry.pop().map(|r| r.addr);
         }
 
         self.reports.push_back(a.clone());
@@ -91,12 +103,20 @@ impl Addresses {
             if r.addr == a {
                 r.score = r.score.saturating_add(1);
                 isort(&mut self.registry);
-                return
+                return evicted
             }
         }
 
+        let evicted = evicted.filter(|e| *e != a);
         let r = Record { score: 1, addr: a };
-        self.registry.push(r)
+        self.registry.push(r);
+        evicted
+    }
+
+    /// Remove a [`Multiaddr`] from the collection, regardless of its score.
+    pub fn remove(&mut self, a: &Multiaddr) {
+        self.registry.retain(|r| r.addr != *a);
+        self.reports.retain(|r| r != a);
     }
 
     /// Return an iterator over all [`Multiaddr`] values.
@@ -166,6 +186,148 @@ impl Iterator for AddressIntoIter {
 
 impl ExactSizeIterator for AddressIntoIter {}
 
+/// Addresses of the local node as observed by remotes.
+///
+/// An observed address is a candidate until it has been reported by a minimum number
+/// of distinct observers, upon which it is confirmed. Every report of an observer is
+/// valid for a limited time. A confirmed address expires once none of its observers
+/// has reported it within that time.
+#[derive(Debug)]
+pub struct ObservedAddresses {
+    /// Number of distinct observers required to confirm an address.
+    min_observers: NonZeroUsize,
+    /// How long a report of an observer is valid.
+    ttl: Duration,
+    /// The observed addresses and their observers, with the time of their last report.
+    addrs: HashMap<Multiaddr, ObservedAddr>,
+    /// Events to report.
+    events: VecDeque<ObservedAddrEvent>,
+    /// Fires when the next report expires.
+    next_expiry: Option<Delay>,
+}
+
+#[derive(Debug)]
+struct ObservedAddr {
+    observers: HashMap<PeerId, Instant>,
+    state: ObservedAddrState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ObservedAddrState {
+    /// Not (yet) reported by enough distinct observers.
+    Candidate,
+    /// Reported by enough distinct observers.
+    Confirmed,
+    /// Added as external address by the user, never expires.
+    Permanent,
+}
+
+/// An event emitted by [`ObservedAddresses`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObservedAddrEvent {
+    /// An address has been reported by enough distinct observers.
+    Confirmed(Multiaddr),
+    /// No observer has reported a confirmed address recently.
+    Expired(Multiaddr),
+}
+
+impl ObservedAddresses {
+    /// Creates a new collection of observed addresses.
+    pub fn new(min_observers: NonZeroUsize, ttl: Duration) -> Self {
+        ObservedAddresses {
+            min_observers,
+            ttl,
+            addrs: HashMap::new(),
+            events: VecDeque::new(),
+            next_expiry: None,
+        }
+    }
+
+    /// Records that `observer` has observed the local node at `addr`.
+    pub fn report(&mut self, addr: Multiaddr, observer: PeerId) {
+        self.report_at(addr, observer, Instant::now())
+    }
+
+    fn report_at(&mut self, addr: Multiaddr, observer: PeerId, now: Instant) {
+        let min_observers = self.min_observers.get();
+        let observed = self.addrs.entry(addr.clone()).or_insert_with(|| ObservedAddr {
+            observers: HashMap::new(),
+            state: ObservedAddrState::Candidate,
+        });
+        observed.observers.insert(observer, now);
+        if observed.state == ObservedAddrState::Candidate && observed.observers.len() >= min_observers {
+            observed.state = ObservedAddrState::Confirmed;
+            self.events.push_back(ObservedAddrEvent::Confirmed(addr));
+        }
+        if self.next_expiry.is_none() {
+            self.next_expiry = Some(Delay::new_at(now + self.ttl));
+        }
+    }
+
+    /// Adds an address that is known to be external and never expires.
+    pub fn add_permanent(&mut self, addr: Multiaddr) {
+        self.addrs.entry(addr)
+            .or_insert_with(|| ObservedAddr {
+                observers: HashMap::new(),
+                state: ObservedAddrState::Permanent,
+            })
+            .state = ObservedAddrState::Permanent;
+    }
+
+    /// Forgets an address and all reports of it.
+    pub fn remove(&mut self, addr: &Multiaddr) {
+        self.addrs.remove(addr);
+    }
+
+    /// Removes reports older than the configured time to live, expiring
+    /// confirmed addresses that are left without observers.
+    ///
+    /// Returns the instant at which the next report expires, if any.
+    fn expire(&mut self, now: Instant) -> Option<Instant> {
+        let ttl = self.ttl;
+        let events = &mut self.events;
+        let mut next_expiry: Option<Instant> = None;
+        self.addrs.retain(|addr, observed| {
+            observed.observers.retain(|_, last_seen| *last_seen + ttl > now);
+            for last_seen in observed.observers.values() {
+                let expiry = *last_seen + ttl;
+                next_expiry = Some(next_expiry.map_or(expiry, |e| e.min(expiry)));
+            }
+            if !observed.observers.is_empty() {
+                return true
+            }
+            match observed.state {
+                ObservedAddrState::Permanent => true,
+                ObservedAddrState::Candidate => false,
+                ObservedAddrState::Confirmed => {
+                    events.push_back(ObservedAddrEvent::Expired(addr.clone()));
+                    false
+                }
+            }
+        });
+        next_expiry
+    }
+
+    /// Polls for the next confirmed or expired address.
+    pub fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ObservedAddrEvent> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(event)
+            }
+
+            match self.next_expiry.as_mut().map(|d| Future::poll(Pin::new(d), cx)) {
+                Some(Poll::Ready(_)) => {
+                    self.next_expiry = self.expire(Instant::now()).map(Delay::new_at);
+                    if self.events.is_empty() && self.next_expiry.is_none() {
+                        return Poll::Pending
+                    }
+                }
+                Some(Poll::Pending) | None => return Poll::Pending,
+            }
+        }
+    }
+}
+
 // Reverse insertion sort.
 fn isort(xs: &mut [Record]) {
     for i in 1 .. xs.len() {
@@ -184,7 +346,10 @@ mod tests {
     use quickcheck::{Arbitrary, Gen, QuickCheck};
     use rand::Rng;
     use std::num::NonZeroUsize;
-    use super::{isort, Addresses, Record};
+    use super::{isort, Addresses, ObservedAddresses, ObservedAddrEvent, Record};
+    use libp2p_core::PeerId;
+    use std::time::Duration;
+    use wasm_timer::Instant;
 
     #[test]
     fn isort_sorts() {
@@ -223,6 +388,23 @@ mod tests {
         assert!(addresses.iter().find(|a| **a == single).is_none());
     }
 
+    #[test]
+    fn evicted_addresses_are_returned() {
+        let mut addresses = Addresses::new(NonZeroUsize::new(2).unwrap());
+        let a: Multiaddr = "/tcp/1".parse().unwrap();
+        let b: Multiaddr = "/tcp/2".parse().unwrap();
+
+        assert_eq!(addresses.add(a.clone()), None);
+        assert_eq!(addresses.add(b.clone()), None);
+        assert_eq!(addresses.add(b.clone()), Some(a.clone()));
+        assert_eq!(addresses.add(b.clone()), None);
+
+        // An address that is reported again right away is not evicted.
+        let mut addresses = Addresses::new(NonZeroUsize::new(1).unwrap());
+        assert_eq!(addresses.add(a.clone()), None);
+        assert_eq!(addresses.add(a), None);
+    }
+
     #[test]
     fn record_score_equals_last_n_reports() {
         #[derive(PartialEq, Eq, Clone, Hash, Debug)]
@@ -238,7 +420,7 @@ mod tests {
             let n = std::cmp::max(n, 1);
             let mut addresses = Addresses::new(NonZeroUsize::new(usize::from(n)).unwrap());
             for Ma(a) in &xs {
-                addresses.add(a.clone())
+                addresses.add(a.clone());
             }
             for r in &addresses.registry {
                 let count = xs.iter()
@@ -255,4 +437,55 @@ mod tests {
 
         QuickCheck::new().quickcheck(property as fn(Vec<Ma>, u8) -> bool)
     }
+
+    #[test]
+    fn observed_addr_confirmed_by_distinct_observers() {
+        let mut observed = ObservedAddresses::new(NonZeroUsize::new(2).unwrap(), Duration::from_secs(60));
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/1234".parse().unwrap();
+        let observer = PeerId::random();
+        let now = Instant::now();
+
+        observed.report_at(addr.clone(), observer.clone(), now);
+        observed.report_at(addr.clone(), observer, now);
+        assert!(observed.events.is_empty(), "The same observer must not confirm an address");
+        assert_eq!(observed.addrs[&addr].observers.len(), 1);
+
+        observed.report_at(addr.clone(), PeerId::random(), now);
+        assert_eq!(observed.events.pop_front(), Some(ObservedAddrEvent::Confirmed(addr.clone())));
+        assert_eq!(observed.addrs[&addr].observers.len(), 2);
+
+        observed.report_at(addr, PeerId::random(), now);
+        assert!(observed.events.is_empty(), "An address is only confirmed once");
+    }
+
+    #[test]
+    fn observed_addr_expires() {
+        let ttl = Duration::from_secs(60);
+        let mut observed = ObservedAddresses::new(NonZeroUsize::new(1).unwrap(), ttl);
+        let confirmed: Multiaddr = "/ip4/1.2.3.4/tcp/1234".parse().unwrap();
+        let permanent: Multiaddr = "/ip4/5.6.7.8/tcp/1234".parse().unwrap();
+        let now = Instant::now();
+
+        observed.report_at(confirmed.clone(), PeerId::random(), now);
+        observed.report_at(permanent.clone(), PeerId::random(), now);
+        observed.add_permanent(permanent.clone());
+        observed.events.clear();
+
+        // A later report keeps the address alive.
+        let observer = PeerId::random();
+        observed.report_at(confirmed.clone(), observer.clone(), now + ttl / 2);
+        assert_eq!(observed.expire(now + ttl), Some(now + ttl / 2 + ttl));
+        assert!(observed.events.is_empty());
+        assert_eq!(observed.addrs[&confirmed].observers.len(), 1);
+
+        assert_eq!(observed.expire(now + ttl * 2), None);
+        assert_eq!(observed.events.pop_front(), Some(ObservedAddrEvent::Expired(confirmed.clone())));
+        assert!(observed.events.is_empty(), "Permanent addresses never expire");
+        assert!(!observed.addrs.contains_key(&confirmed));
+        assert!(observed.addrs.contains_key(&permanent));
+
+        // An expired address needs to be confirmed again.
+        observed.report_at(confirmed.clone(), observer, now + ttl * 2);
+        assert_eq!(observed.events.pop_front(), Some(ObservedAddrEvent::Confirmed(confirmed)));
+    }
 }
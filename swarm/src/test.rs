@@ -105,7 +105,7 @@ pub struct CallTraceBehaviour<TInner>
 where
     TInner: NetworkBehaviour,
 {
-    inner: TInner,
+    pub inner: TInner,
 
     pub addresses_of_peer: Vec<PeerId>,
     pub inject_connected: Vec<PeerId>,
@@ -117,6 +117,7 @@ where
     pub inject_dial_failure: Vec<PeerId>,
     pub inject_new_listen_addr: Vec<Multiaddr>,
     pub inject_new_external_addr: Vec<Multiaddr>,
+    pub inject_expired_external_addr: Vec<Multiaddr>,
     pub inject_expired_listen_addr: Vec<Multiaddr>,
     pub inject_listener_error: Vec<ListenerId>,
     pub inject_listener_closed: Vec<(ListenerId, bool)>,
@@ -140,6 +141,7 @@ where
             inject_dial_failure: Vec::new(),
             inject_new_listen_addr: Vec::new(),
             inject_new_external_addr: Vec::new(),
+            inject_expired_external_addr: Vec::new(),
             inject_expired_listen_addr: Vec::new(),
             inject_listener_error: Vec::new(),
             inject_listener_closed: Vec::new(),
@@ -158,6 +160,7 @@ where
         self.inject_dial_failure = Vec::new();
         self.inject_new_listen_addr = Vec::new();
         self.inject_new_external_addr = Vec::new();
+        self.inject_expired_external_addr = Vec::new();
         self.inject_expired_listen_addr = Vec::new();
         self.inject_listener_error = Vec::new();
         self.inject_listener_closed = Vec::new();
@@ -232,6 +235,11 @@ where
         self.inner.inject_new_external_addr(a);
     }
 
+    fn inject_expired_external_addr(&mut self, a: &Multiaddr) {
+        self.inject_expired_external_addr.push(a.clone());
+        self.inner.inject_expired_external_addr(a);
+    }
+
     fn inject_listener_error(&mut self, l: ListenerId, e: &(dyn std::error::Error + 'static)) {
         self.inject_listener_error.push(l.clone());
         self.inner.inject_listener_error(l, e);
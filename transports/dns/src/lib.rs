@@ -20,79 +20,189 @@
 
 //! # libp2p-dns
 //!
-//! This crate provides the type `DnsConfig` that allows one to resolve the `/dns4/` and `/dns6/`
-//! components of multiaddresses.
+//! This crate provides the type `GenDnsConfig` that allows one to resolve the `/dns/`, `/dns4/`,
+//! `/dns6/` and `/dnsaddr/` components of multiaddresses, using the asynchronous resolver of
+//! [`trust-dns-resolver`](https://docs.rs/trust-dns-resolver).
 //!
 //! ## Usage
 //!
 //! In order to use this crate, create a `DnsConfig` with one of its constructors and pass it an
-//! implementation of the `Transport` trait.
+//! implementation of the `Transport` trait. `DnsConfig` is the `GenDnsConfig` for the async-std
+//! runtime and requires the `async-std` feature, which is enabled by default. `TokioDnsConfig` is
+//! the `GenDnsConfig` for the tokio runtime and requires the `tokio` feature. A resolver for
+//! another runtime can be passed to `GenDnsConfig::with_resolver`.
 //!
 //! Whenever we want to dial an address through the `DnsConfig` and that address contains a
 //! `/dns/`, `/dns4/`, or `/dns6/` component, a DNS resolve will be performed and the component
 //! will be replaced with `/ip4/` and/or `/ip6/` components.
 //!
+//! ## Resolution
+//!
+//! The nameservers, search domains, timeouts and the size of the cache are taken from the
+//! system configuration by `DnsConfig::new` and can be given explicitly to `DnsConfig::custom`.
+//! Resolved addresses are cached for as long as their time to live permits.
+//!
+//! A `/dns/` component may resolve to several IPv4 and IPv6 addresses, which alternate between
+//! both IP versions, starting with IPv6. As in [RFC 8305](https://tools.ietf.org/html/rfc8305),
+//! they are dialed one after the other, but an attempt is not waited for longer than
+//! `CONNECTION_ATTEMPT_DELAY` before the next one is started concurrently, so that a broken
+//! network path of one IP version does not delay the dial until its attempts time out. The first
+//! attempt to succeed is used and the others are dropped.
+//!
+//! A `/dnsaddr/<domain>` component is resolved to the multiaddresses in the TXT records of
+//! `_dnsaddr.<domain>`, as described in the
+//! [specification](https://github.com/multiformats/multiaddr/blob/master/protocols/DNSADDR.md).
+//! Only those multiaddresses are used that end with the components following the `/dnsaddr/`
+//! component, usually `/p2p/<peer-id>`. They may in turn contain `/dnsaddr/` components, which
+//! are resolved up to a depth of `MAX_DNSADDR_DEPTH`, and `/dns/` components.
+//!
+
+#[cfg(feature = "tokio")]
+mod tokio_runtime;
 
-use futures::{prelude::*, channel::oneshot, future::BoxFuture};
+use futures::{prelude::*, future::BoxFuture, stream::FuturesUnordered};
 use libp2p_core::{
     Transport,
     multiaddr::{Protocol, Multiaddr},
     transport::{TransportError, ListenerEvent}
 };
-use log::{error, debug, trace};
-use std::{error, fmt, io, net::ToSocketAddrs};
+use futures_timer::Delay;
+use log::{debug, trace};
+use std::{collections::VecDeque, error, fmt, net::IpAddr, time::Duration};
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::io;
+use trust_dns_resolver::{AsyncResolver, ConnectionProvider, proto::xfer::dns_handle::DnsHandle};
+
+pub use trust_dns_resolver::{config, Name};
+pub use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+
+/// The maximum number of resolved addresses that are dialed in turn.
+const MAX_DIAL_ATTEMPTS: usize = 16;
+
+/// The time after which the next resolved address is dialed while the previous attempts are
+/// still in progress, as recommended by RFC 8305.
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// The maximum number of `/dnsaddr/` lookups nested in one another.
+pub const MAX_DNSADDR_DEPTH: usize = 8;
+
+/// The prefix of the TXT records of a `/dnsaddr/` lookup containing a multiaddress.
+const DNSADDR_PREFIX: &str = "dnsaddr=";
+
+/// A `GenDnsConfig` using the async-std runtime for resolving.
+#[cfg(feature = "async-std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async-std")))]
+pub type DnsConfig<T> = GenDnsConfig<T, async_std_resolver::AsyncStdConnection, async_std_resolver::AsyncStdConnectionProvider>;
+
+/// A `GenDnsConfig` using the tokio runtime for resolving.
+#[cfg(feature = "tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+pub type TokioDnsConfig<T> = GenDnsConfig<T, tokio_runtime::TokioConnection, tokio_runtime::TokioConnectionProvider>;
 
 /// Represents the configuration for a DNS transport capability of libp2p.
 ///
 /// This struct implements the `Transport` trait and holds an underlying transport. Any call to
-/// `dial` with a multiaddr that contains `/dns/`, `/dns4/`, or `/dns6/` will be first be resolved,
-/// then passed to the underlying transport.
+/// `dial` with a multiaddr that contains `/dns/`, `/dns4/`, `/dns6/` or `/dnsaddr/` will be first
+/// be resolved, then passed to the underlying transport.
 ///
 /// Listening is unaffected.
 #[derive(Clone)]
-pub struct DnsConfig<T> {
+pub struct GenDnsConfig<T, C, P>
+where
+    C: DnsHandle<Error = ResolveError>,
+    P: ConnectionProvider<Conn = C>
+{
     /// Underlying transport to use once the DNS addresses have been resolved.
     inner: T,
-    /// Pool of threads to use when resolving DNS addresses.
-    thread_pool: futures::executor::ThreadPool,
+    /// The resolver of DNS addresses, including its cache.
+    resolver: AsyncResolver<C, P>,
 }
 
+#[cfg(feature = "async-std")]
 impl<T> DnsConfig<T> {
-    /// Creates a new configuration object for DNS.
-    pub fn new(inner: T) -> Result<DnsConfig<T>, io::Error> {
-        DnsConfig::with_resolve_threads(inner, 1)
+    /// Creates a new configuration object for DNS, using the system's resolver configuration,
+    /// e.g. `/etc/resolv.conf` on Unix.
+    ///
+    /// Both A and AAAA records are looked up for `/dns/` components.
+    pub async fn new(inner: T) -> Result<DnsConfig<T>, io::Error> {
+        let (config, mut opts) = trust_dns_resolver::system_conf::read_system_conf()?;
+        opts.ip_strategy = config::LookupIpStrategy::Ipv4AndIpv6;
+        DnsConfig::custom(inner, config, opts).await
+    }
+
+    /// Creates a new configuration object for DNS with the given nameservers, search domains
+    /// and resolver options.
+    ///
+    /// `/dns/` components are only resolved to both IPv4 and IPv6 addresses if the
+    /// `ip_strategy` of the options is `LookupIpStrategy::Ipv4AndIpv6`.
+    pub async fn custom(inner: T, config: config::ResolverConfig, opts: config::ResolverOpts)
+        -> Result<DnsConfig<T>, io::Error>
+    {
+        let resolver = async_std_resolver::resolver(config, opts).await?;
+
+        trace!("Created a DNS resolver");
+
+        Ok(GenDnsConfig::with_resolver(inner, resolver))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> TokioDnsConfig<T> {
+    /// Creates a new configuration object for DNS, using the system's resolver configuration,
+    /// e.g. `/etc/resolv.conf` on Unix.
+    ///
+    /// Both A and AAAA records are looked up for `/dns/` components.
+    pub async fn new(inner: T) -> Result<TokioDnsConfig<T>, io::Error> {
+        let (config, mut opts) = trust_dns_resolver::system_conf::read_system_conf()?;
+        opts.ip_strategy = config::LookupIpStrategy::Ipv4AndIpv6;
+        TokioDnsConfig::custom(inner, config, opts).await
     }
 
-    /// Same as `new`, but allows specifying a number of threads for the resolving.
-    pub fn with_resolve_threads(inner: T, num_threads: usize) -> Result<DnsConfig<T>, io::Error> {
-        let thread_pool = futures::executor::ThreadPool::builder()
-            .pool_size(num_threads)
-            .name_prefix("libp2p-dns-")
-            .create()?;
+    /// Creates a new configuration object for DNS with the given nameservers, search domains
+    /// and resolver options.
+    ///
+    /// `/dns/` components are only resolved to both IPv4 and IPv6 addresses if the
+    /// `ip_strategy` of the options is `LookupIpStrategy::Ipv4AndIpv6`.
+    pub async fn custom(inner: T, config: config::ResolverConfig, opts: config::ResolverOpts)
+        -> Result<TokioDnsConfig<T>, io::Error>
+    {
+        let resolver = AsyncResolver::new(config, opts, tokio_runtime::TokioHandle)?;
 
-        trace!("Created a DNS thread pool");
+        trace!("Created a DNS resolver");
 
-        Ok(DnsConfig {
-            inner,
-            thread_pool,
-        })
+        Ok(GenDnsConfig::with_resolver(inner, resolver))
     }
 }
 
-impl<T> fmt::Debug for DnsConfig<T>
+impl<T, C, P> GenDnsConfig<T, C, P>
+where
+    C: DnsHandle<Error = ResolveError>,
+    P: ConnectionProvider<Conn = C>
+{
+    /// Creates a new configuration object for DNS using the given resolver.
+    pub fn with_resolver(inner: T, resolver: AsyncResolver<C, P>) -> Self {
+        GenDnsConfig { inner, resolver }
+    }
+}
+
+impl<T, C, P> fmt::Debug for GenDnsConfig<T, C, P>
 where
     T: fmt::Debug,
+    C: DnsHandle<Error = ResolveError>,
+    P: ConnectionProvider<Conn = C>
 {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_tuple("DnsConfig").field(&self.inner).finish()
+        fmt.debug_tuple("GenDnsConfig").field(&self.inner).finish()
     }
 }
 
-impl<T> Transport for DnsConfig<T>
+impl<T, C, P> Transport for GenDnsConfig<T, C, P>
 where
-    T: Transport + Send + 'static,
+    T: Transport + Clone + Send + 'static,
     T::Error: Send,
-    T::Dial: Send
+    T::Dial: Send,
+    C: DnsHandle<Error = ResolveError>,
+    P: ConnectionProvider<Conn = C>
 {
     type Output = T::Output;
     type Error = DnsErr<T::Error>;
@@ -127,6 +237,7 @@ where
             Protocol::Dns(_) => true,
             Protocol::Dns4(_) => true,
             Protocol::Dns6(_) => true,
+            Protocol::Dnsaddr(_) => true,
             _ => false,
         });
 
@@ -138,72 +249,245 @@ where
         }
 
         trace!("Dialing address with DNS: {}", addr);
-        let resolve_futs = addr.iter()
-            .map(|cmp| match cmp {
-                Protocol::Dns(ref name) | Protocol::Dns4(ref name) | Protocol::Dns6(ref name) => {
-                    let name = name.to_string();
-                    let to_resolve = format!("{}:0", name);
-                    let (tx, rx) = oneshot::channel();
-                    self.thread_pool.spawn_ok(async {
-                        let to_resolve = to_resolve;
-                        let _ = tx.send(match to_resolve[..].to_socket_addrs() {
-                            Ok(list) => Ok(list.map(|s| s.ip()).collect::<Vec<_>>()),
-                            Err(e) => Err(e),
-                        });
-                    });
-
-                    let (dns4, dns6) = match cmp {
-                        Protocol::Dns(_) => (true, true),
-                        Protocol::Dns4(_) => (true, false),
-                        Protocol::Dns6(_) => (false, true),
-                        _ => unreachable!(),
-                    };
-
-                    async move {
-                        let list = rx.await
-                            .map_err(|_| {
-                                error!("DNS resolver crashed");
-                                DnsErr::ResolveFail(name.clone())
-                            })?
-                            .map_err(|err| DnsErr::ResolveError {
-                                domain_name: name.clone(),
-                                error: err,
-                            })?;
-
-                        list.into_iter()
-                            .filter_map(|addr| {
-                                if (dns4 && addr.is_ipv4()) || (dns6 && addr.is_ipv6()) {
-                                    Some(Protocol::from(addr))
-                                } else {
-                                    None
-                                }
-                            })
-                            .next()
-                            .ok_or_else(|| DnsErr::ResolveFail(name))
-                    }.left_future()
-                },
-                cmp => future::ready(Ok(cmp.acquire())).right_future()
-            })
-            .collect::<stream::FuturesOrdered<_>>();
-
-        let future = resolve_futs.collect::<Vec<_>>()
-            .then(move |outcome| async move {
-                let outcome = outcome.into_iter().collect::<Result<Vec<_>, _>>()?;
-                let outcome = outcome.into_iter().collect::<Multiaddr>();
-                debug!("DNS resolution outcome: {} => {}", addr, outcome);
-
-                match self.inner.dial(outcome) {
-                    Ok(d) => d.await.map_err(DnsErr::Underlying),
-                    Err(TransportError::MultiaddrNotSupported(_addr)) =>
-                        Err(DnsErr::MultiaddrNotSupported),
-                    Err(TransportError::Other(err)) => Err(DnsErr::Underlying(err))
-                }
-            });
+        let future = async move {
+            let addrs = resolve(&self.resolver, &addr).await?;
+            debug!("DNS resolution outcome: {} => {:?}", addr, addrs);
+            dial_staggered(self.inner, addrs).await
+        };
 
         Ok(future.boxed().right_future())
     }
 }
 
+/// Dials the given addresses in turn until one succeeds, starting the next attempt as soon as
+/// the previous one fails or after `CONNECTION_ATTEMPT_DELAY` at the latest.
+///
+/// The attempts still in progress once one succeeds are dropped. If all attempts fail, the
+/// last error is returned.
+async fn dial_staggered<T>(inner: T, addrs: Vec<Multiaddr>) -> Result<T::Output, DnsErr<T::Error>>
+where
+    T: Transport + Clone
+{
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut error = None;
+
+    loop {
+        // Start the next attempt if none is in progress, skipping unsupported addresses.
+        if attempts.is_empty() {
+            match dial_next(&inner, &mut addrs, &mut error) {
+                Some(dial) => attempts.push(dial),
+                None => return Err(error.unwrap_or(DnsErr::MultiaddrNotSupported))
+            }
+        }
+
+        let delay = if addrs.len() > 0 {
+            Delay::new(CONNECTION_ATTEMPT_DELAY).left_future()
+        } else {
+            future::pending().right_future()
+        };
+        let outcome = match future::select(attempts.next(), delay).await {
+            future::Either::Left((outcome, _)) => outcome,
+            future::Either::Right(((), _)) => None
+        };
+        match outcome {
+            Some(Ok(output)) => return Ok(output),
+            Some(Err(err)) => error = Some(DnsErr::Underlying(err)),
+            None => {}
+        }
+        // Either the delay has elapsed or an attempt has failed.
+        if let Some(dial) = dial_next(&inner, &mut addrs, &mut error) {
+            attempts.push(dial)
+        }
+    }
+}
+
+/// Dials the next of the given addresses supported by the transport, if any.
+fn dial_next<T>(inner: &T, addrs: &mut impl Iterator<Item = Multiaddr>, error: &mut Option<DnsErr<T::Error>>)
+    -> Option<T::Dial>
+where
+    T: Transport + Clone
+{
+    for addr in addrs {
+        trace!("Dialing resolved address {}", addr);
+        match inner.clone().dial(addr) {
+            Ok(dial) => return Some(dial),
+            Err(TransportError::MultiaddrNotSupported(addr)) => {
+                trace!("Resolved address not supported: {}", addr);
+                if error.is_none() {
+                    *error = Some(DnsErr::MultiaddrNotSupported)
+                }
+            }
+            Err(TransportError::Other(err)) => *error = Some(DnsErr::Underlying(err))
+        }
+    }
+    None
+}
+
+/// Resolves the DNS components of an address to the addresses to dial in turn, up to
+/// `MAX_DIAL_ATTEMPTS`.
+async fn resolve<C, P, E>(resolver: &AsyncResolver<C, P>, addr: &Multiaddr) -> Result<Vec<Multiaddr>, DnsErr<E>>
+where
+    C: DnsHandle<Error = ResolveError>,
+    P: ConnectionProvider<Conn = C>
+{
+    let mut addrs = Vec::new();
+    let mut error = None;
+    for addr in resolve_dnsaddr(resolver, addr).await? {
+        if addrs.len() >= MAX_DIAL_ATTEMPTS {
+            break
+        }
+        match resolve_ips(resolver, &addr).await {
+            Ok(ips) => addrs.extend(ips),
+            Err(err) => {
+                debug!("Failed to resolve {}", addr);
+                error = Some(err)
+            }
+        }
+    }
+    addrs.truncate(MAX_DIAL_ATTEMPTS);
+    match error {
+        Some(err) if addrs.is_empty() => Err(err),
+        _ => Ok(addrs)
+    }
+}
+
+/// Replaces the `/dnsaddr/` component of an address, if any, recursively by the addresses
+/// it resolves to.
+async fn resolve_dnsaddr<C, P, E>(resolver: &AsyncResolver<C, P>, addr: &Multiaddr) -> Result<Vec<Multiaddr>, DnsErr<E>>
+where
+    C: DnsHandle<Error = ResolveError>,
+    P: ConnectionProvider<Conn = C>
+{
+    let mut pending = VecDeque::new();
+    pending.push_back((addr.clone(), 0));
+    let mut addrs = Vec::new();
+    let mut error = None;
+
+    while let Some((addr, depth)) = pending.pop_front() {
+        let mut prefix = Multiaddr::empty();
+        let mut iter = addr.iter();
+        let name = loop {
+            match iter.next() {
+                Some(Protocol::Dnsaddr(name)) => break Some(name.into_owned()),
+                Some(cmp) => prefix.push(cmp),
+                None => break None
+            }
+        };
+        let name = match name {
+            Some(name) => name,
+            None => {
+                addrs.push(addr);
+                continue
+            }
+        };
+        let suffix = iter.collect::<Vec<_>>();
+
+        if depth >= MAX_DNSADDR_DEPTH {
+            debug!("Not resolving {}: maximum /dnsaddr depth reached", addr);
+            error = Some(DnsErr::ResolveFail(name));
+            continue
+        }
+
+        let lookup = match resolver.txt_lookup(format!("_dnsaddr.{}", name)).await {
+            Ok(lookup) => lookup,
+            Err(err) => {
+                error = Some(DnsErr::ResolveError { domain_name: name, error: Box::new(err) });
+                continue
+            }
+        };
+        for txt in lookup.iter() {
+            let data = txt.txt_data().iter().flat_map(|s| s.iter().cloned()).collect::<Vec<u8>>();
+            let entry = match std::str::from_utf8(&data) {
+                Ok(s) if s.starts_with(DNSADDR_PREFIX) => &s[DNSADDR_PREFIX.len() ..],
+                _ => continue
+            };
+            let entry = match entry.parse::<Multiaddr>() {
+                Ok(entry) => entry,
+                Err(err) => {
+                    debug!("Invalid multiaddr {:?} for /dnsaddr/{}: {}", entry, name, err);
+                    continue
+                }
+            };
+            let components = entry.iter().collect::<Vec<_>>();
+            if !components.ends_with(&suffix) {
+                trace!("Ignoring {} for {}", entry, addr);
+                continue
+            }
+            if addrs.len() + pending.len() >= MAX_DIAL_ATTEMPTS {
+                debug!("Ignoring further addresses for /dnsaddr/{}", name);
+                break
+            }
+            let resolved = prefix.iter().chain(components).collect::<Multiaddr>();
+            pending.push_back((resolved, depth + 1))
+        }
+    }
+
+    if addrs.is_empty() {
+        return Err(error.unwrap_or_else(|| DnsErr::ResolveFail(addr.to_string())))
+    }
+    Ok(addrs)
+}
+
+/// Resolves the `/dns/`, `/dns4/` and `/dns6/` components of an address to the addresses
+/// to dial in turn.
+///
+/// If several components resolve to more than one IP address, all combinations are
+/// returned, up to `MAX_DIAL_ATTEMPTS`.
+async fn resolve_ips<C, P, E>(resolver: &AsyncResolver<C, P>, addr: &Multiaddr) -> Result<Vec<Multiaddr>, DnsErr<E>>
+where
+    C: DnsHandle<Error = ResolveError>,
+    P: ConnectionProvider<Conn = C>
+{
+    let mut addrs = vec![Multiaddr::empty()];
+    for cmp in addr.iter() {
+        let (name, dns4, dns6) = match cmp {
+            Protocol::Dns(ref name) => (name, true, true),
+            Protocol::Dns4(ref name) => (name, true, false),
+            Protocol::Dns6(ref name) => (name, false, true),
+            cmp => {
+                for a in &mut addrs {
+                    a.push(cmp.clone())
+                }
+                continue
+            }
+        };
+
+        let lookup = resolver.lookup_ip(name.as_ref()).await
+            .map_err(|error| DnsErr::ResolveError { domain_name: name.to_string(), error: Box::new(error) })?;
+        let (ipv6, ipv4) = lookup.iter()
+            .filter(|ip| (dns4 && ip.is_ipv4()) || (dns6 && ip.is_ipv6()))
+            .partition(|ip| ip.is_ipv6());
+        let ips = interleave(ipv6, ipv4);
+        if ips.is_empty() {
+            return Err(DnsErr::ResolveFail(name.to_string()))
+        }
+
+        addrs = addrs.iter()
+            .flat_map(|a| ips.iter().map(move |ip| a.clone().with(Protocol::from(*ip))))
+            .take(MAX_DIAL_ATTEMPTS)
+            .collect();
+    }
+    Ok(addrs)
+}
+
+/// Alternates between IPv6 and IPv4 addresses, starting with IPv6, as in
+/// [RFC 8305](https://tools.ietf.org/html/rfc8305#section-4).
+fn interleave(ipv6: Vec<IpAddr>, ipv4: Vec<IpAddr>) -> Vec<IpAddr> {
+    let mut ips = Vec::with_capacity(ipv6.len() + ipv4.len());
+    let mut ipv6 = ipv6.into_iter();
+    let mut ipv4 = ipv4.into_iter();
+    loop {
+        match (ipv6.next(), ipv4.next()) {
+            (None, None) => return ips,
+            (a, b) => {
+                ips.extend(a);
+                ips.extend(b)
+            }
+        }
+    }
+}
+
 /// Error that can be generated by the DNS layer.
 #[derive(Debug)]
 pub enum DnsErr<TErr> {
@@ -214,7 +498,7 @@ pub enum DnsErr<TErr> {
     /// Error while resolving a DNS address.
     ResolveError {
         domain_name: String,
-        error: io::Error,
+        error: Box<ResolveError>,
     },
     /// Found an IP address, but the underlying transport doesn't support the multiaddr.
     MultiaddrNotSupported,
@@ -250,14 +534,116 @@ where TErr: error::Error + 'static
 
 #[cfg(test)]
 mod tests {
-    use super::DnsConfig;
+    #[cfg(feature = "tokio")]
+    use super::TokioDnsConfig;
+    use super::{CONNECTION_ATTEMPT_DELAY, DnsConfig, config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts}};
     use futures::{future::BoxFuture, prelude::*, stream::BoxStream};
     use libp2p_core::{
+        PeerId,
         Transport,
         multiaddr::{Protocol, Multiaddr},
         transport::ListenerEvent,
         transport::TransportError,
     };
+    use std::{io, net::{SocketAddr, UdpSocket}, sync::{Arc, Mutex}, thread, time::Instant};
+    use trust_dns_resolver::{
+        Name,
+        proto::{op::{Message, MessageType, ResponseCode}, rr::{RData, Record, RecordType, rdata::TXT}}
+    };
+
+    /// A nameserver on localhost answering queries from a fixed set of records.
+    struct Nameserver {
+        addr: SocketAddr,
+        /// The queries received so far.
+        queries: Arc<Mutex<Vec<(Name, RecordType)>>>
+    }
+
+    impl Nameserver {
+        fn new(records: Vec<(&str, u32, RData)>) -> Self {
+            let records = records.into_iter()
+                .map(|(name, ttl, rdata)| Record::from_rdata(Name::from_ascii(name).unwrap(), ttl, rdata))
+                .collect::<Vec<_>>();
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = socket.local_addr().unwrap();
+            let queries = Arc::new(Mutex::new(Vec::new()));
+            let queries2 = queries.clone();
+            thread::spawn(move || {
+                let mut buf = [0u8; 512];
+                loop {
+                    let (n, from) = socket.recv_from(&mut buf).unwrap();
+                    let request = Message::from_vec(&buf[.. n]).unwrap();
+                    let mut response = Message::new();
+                    response.set_id(request.id())
+                        .set_message_type(MessageType::Response)
+                        .set_op_code(request.op_code())
+                        .set_recursion_desired(request.recursion_desired())
+                        .set_recursion_available(true);
+                    for query in request.queries() {
+                        queries2.lock().unwrap().push((query.name().clone(), query.query_type()));
+                        response.add_query(query.clone());
+                        if !records.iter().any(|r| r.name() == query.name()) {
+                            response.set_response_code(ResponseCode::NXDomain);
+                        }
+                        for r in records.iter().filter(|r| r.name() == query.name() && r.record_type() == query.query_type()) {
+                            response.add_answer(r.clone());
+                        }
+                    }
+                    socket.send_to(&response.to_vec().unwrap(), from).unwrap();
+                }
+            });
+            Nameserver { addr, queries }
+        }
+
+        /// The number of queries received for the given name and record type.
+        fn queries(&self, name: &str, typ: RecordType) -> usize {
+            let name = Name::from_ascii(name).unwrap();
+            self.queries.lock().unwrap().iter().filter(|(n, t)| n == &name && *t == typ).count()
+        }
+
+        /// A resolver configuration using this nameserver and the given search domain.
+        fn config(&self, search: &str) -> (ResolverConfig, ResolverOpts) {
+            let servers = NameServerConfigGroup::from_ips_clear(&[self.addr.ip()], self.addr.port(), true);
+            let config = ResolverConfig::from_parts(None, vec![Name::from_ascii(search).unwrap()], servers);
+            let opts = ResolverOpts {
+                ip_strategy: LookupIpStrategy::Ipv4AndIpv6,
+                use_hosts_file: false,
+                .. ResolverOpts::default()
+            };
+            (config, opts)
+        }
+    }
+
+    /// A transport recording the addresses dialed, of which only one succeeds.
+    #[derive(Clone)]
+    struct RecordingTransport {
+        dialed: Arc<Mutex<Vec<Multiaddr>>>,
+        reachable: Multiaddr,
+        /// The addresses whose dials never complete.
+        unresponsive: Vec<Multiaddr>
+    }
+
+    impl Transport for RecordingTransport {
+        type Output = ();
+        type Error = io::Error;
+        type Listener = BoxStream<'static, Result<ListenerEvent<Self::ListenerUpgrade, Self::Error>, Self::Error>>;
+        type ListenerUpgrade = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+        type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+        fn listen_on(self, _: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+            unreachable!()
+        }
+
+        fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+            self.dialed.lock().unwrap().push(addr.clone());
+            if addr == self.reachable {
+                Ok(Box::pin(future::ready(Ok(()))))
+            } else if self.unresponsive.contains(&addr) {
+                Ok(Box::pin(future::pending()))
+            } else {
+                Ok(Box::pin(future::ready(Err(io::ErrorKind::ConnectionRefused.into()))))
+            }
+        }
+    }
 
     #[test]
     fn basic_resolve() {
@@ -292,7 +678,7 @@ mod tests {
         }
 
         futures::executor::block_on(async move {
-            let transport = DnsConfig::new(CustomTransport).unwrap();
+            let transport = DnsConfig::new(CustomTransport).await.unwrap();
 
             let _ = transport
                 .clone()
@@ -315,4 +701,146 @@ mod tests {
                 .unwrap();
         });
     }
+
+    #[test]
+    fn custom_resolve() {
+        let nameserver = Nameserver::new(vec![
+            ("host.test.", 3600, RData::A("10.0.0.1".parse().unwrap())),
+            ("host.test.", 3600, RData::A("10.0.0.2".parse().unwrap())),
+            ("host.test.", 3600, RData::AAAA("fc00::1".parse().unwrap())),
+            ("host.test.", 3600, RData::AAAA("fc00::2".parse().unwrap())),
+            ("short.test.", 0, RData::A("10.0.1.1".parse().unwrap())),
+            ("short.test.", 0, RData::AAAA("fc00::11".parse().unwrap())),
+        ]);
+        let (config, opts) = nameserver.config("test.");
+        let dialed = Arc::new(Mutex::new(Vec::new()));
+        let transport = RecordingTransport {
+            dialed: dialed.clone(),
+            reachable: "/ip4/10.0.0.2/tcp/1".parse().unwrap(),
+            unresponsive: Vec::new()
+        };
+
+        async_std::task::block_on(async move {
+            let transport = DnsConfig::custom(transport, config, opts).await.unwrap();
+
+            // The name is qualified with the search domain and the addresses
+            // of both IP versions are dialed alternately until one succeeds.
+            transport.clone().dial("/dns/host/tcp/1".parse().unwrap()).unwrap().await.unwrap();
+            assert_eq!(*dialed.lock().unwrap(), vec![
+                "/ip6/fc00::1/tcp/1".parse::<Multiaddr>().unwrap(),
+                "/ip4/10.0.0.1/tcp/1".parse().unwrap(),
+                "/ip6/fc00::2/tcp/1".parse().unwrap(),
+                "/ip4/10.0.0.2/tcp/1".parse().unwrap(),
+            ]);
+
+            // The addresses are cached.
+            let queries = nameserver.queries("host.test.", RecordType::A);
+            assert_eq!(queries, 1);
+            transport.clone().dial("/dns4/host.test./tcp/1".parse().unwrap()).unwrap().await.unwrap();
+            assert_eq!(nameserver.queries("host.test.", RecordType::A), queries);
+
+            // Unless their time to live has expired.
+            dialed.lock().unwrap().clear();
+            for i in 1 ..= 2 {
+                let err = transport.clone().dial("/dns4/short.test./tcp/1".parse().unwrap()).unwrap().await;
+                assert!(err.is_err());
+                assert_eq!(nameserver.queries("short.test.", RecordType::A), i);
+            }
+            assert_eq!(*dialed.lock().unwrap(), vec!["/ip4/10.0.1.1/tcp/1".parse::<Multiaddr>().unwrap(); 2]);
+        });
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_resolve() {
+        let nameserver = Nameserver::new(vec![
+            ("host.test.", 3600, RData::A("10.0.0.1".parse().unwrap())),
+        ]);
+        let (config, opts) = nameserver.config("test.");
+        let dialed = Arc::new(Mutex::new(Vec::new()));
+        let transport = RecordingTransport {
+            dialed: dialed.clone(),
+            reachable: "/ip4/10.0.0.1/tcp/1".parse().unwrap(),
+            unresponsive: Vec::new()
+        };
+
+        let mut runtime = tokio::runtime::Builder::new().basic_scheduler().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let transport = TokioDnsConfig::custom(transport, config, opts).await.unwrap();
+            transport.dial("/dns4/host/tcp/1".parse().unwrap()).unwrap().await.unwrap();
+        });
+        assert_eq!(*dialed.lock().unwrap(), vec!["/ip4/10.0.0.1/tcp/1".parse::<Multiaddr>().unwrap()]);
+    }
+
+    #[test]
+    fn staggered_dial() {
+        let nameserver = Nameserver::new(vec![
+            ("host.test.", 3600, RData::A("10.0.0.1".parse().unwrap())),
+            ("host.test.", 3600, RData::AAAA("fc00::1".parse().unwrap())),
+            ("host.test.", 3600, RData::AAAA("fc00::2".parse().unwrap())),
+        ]);
+        let (config, opts) = nameserver.config("test.");
+        let dialed = Arc::new(Mutex::new(Vec::new()));
+        let transport = RecordingTransport {
+            dialed: dialed.clone(),
+            reachable: "/ip4/10.0.0.1/tcp/1".parse().unwrap(),
+            unresponsive: vec!["/ip6/fc00::1/tcp/1".parse().unwrap()]
+        };
+
+        async_std::task::block_on(async move {
+            let transport = DnsConfig::custom(transport, config, opts).await.unwrap();
+
+            // The unresponsive address does not hold up the next one for longer
+            // than the connection attempt delay.
+            let start = Instant::now();
+            transport.dial("/dns/host.test./tcp/1".parse().unwrap()).unwrap().await.unwrap();
+            assert!(start.elapsed() >= CONNECTION_ATTEMPT_DELAY);
+            assert_eq!(*dialed.lock().unwrap(), vec![
+                "/ip6/fc00::1/tcp/1".parse::<Multiaddr>().unwrap(),
+                "/ip4/10.0.0.1/tcp/1".parse().unwrap(),
+            ]);
+        });
+    }
+
+    #[test]
+    fn dnsaddr_resolve() {
+        let peer1 = PeerId::random();
+        let peer2 = PeerId::random();
+        let txt = |addr: String| RData::TXT(TXT::new(vec![format!("dnsaddr={}", addr)]));
+        let nameserver = Nameserver::new(vec![
+            ("_dnsaddr.bootstrap.test.", 3600, txt(format!("/dnsaddr/nested.test/p2p/{}", peer1))),
+            ("_dnsaddr.bootstrap.test.", 3600, txt(format!("/ip4/10.0.0.1/tcp/1/p2p/{}", peer2))),
+            ("_dnsaddr.nested.test.", 3600, txt(format!("/ip4/10.0.0.3/tcp/3/p2p/{}", peer2))),
+            ("_dnsaddr.nested.test.", 3600, txt(format!("/dns4/host.test/tcp/2/p2p/{}", peer1))),
+            ("_dnsaddr.loop.test.", 3600, txt(format!("/dnsaddr/loop.test/p2p/{}", peer1))),
+            ("host.test.", 3600, RData::A("10.0.0.2".parse().unwrap())),
+        ]);
+        let (config, opts) = nameserver.config("test.");
+        let dialed = Arc::new(Mutex::new(Vec::new()));
+        let reachable = format!("/ip4/10.0.0.2/tcp/2/p2p/{}", peer1).parse::<Multiaddr>().unwrap();
+        let transport = RecordingTransport { dialed: dialed.clone(), reachable: reachable.clone(), unresponsive: Vec::new() };
+
+        async_std::task::block_on(async move {
+            let transport = DnsConfig::custom(transport, config, opts).await.unwrap();
+
+            // Nested `/dnsaddr/` and `/dns4/` components are resolved and only the
+            // addresses of the requested peer are dialed.
+            let addr = format!("/dnsaddr/bootstrap.test/p2p/{}", peer1).parse().unwrap();
+            transport.clone().dial(addr).unwrap().await.unwrap();
+            assert_eq!(*dialed.lock().unwrap(), vec![reachable]);
+
+            // Without a peer ID, the addresses of all peers are dialed.
+            dialed.lock().unwrap().clear();
+            transport.clone().dial("/dnsaddr/nested.test".parse().unwrap()).unwrap().await.unwrap();
+            assert_eq!(dialed.lock().unwrap().len(), 2);
+
+            // Cycles end at the maximum depth.
+            dialed.lock().unwrap().clear();
+            let addr = format!("/dnsaddr/loop.test/p2p/{}", peer1).parse().unwrap();
+            let err = transport.dial(addr).unwrap().await;
+            assert!(err.is_err());
+            assert!(dialed.lock().unwrap().is_empty());
+            assert_eq!(nameserver.queries("_dnsaddr.loop.test.", RecordType::TXT), 1);
+        });
+    }
 }
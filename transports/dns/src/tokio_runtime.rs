@@ -0,0 +1,135 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Runs the resolver of `trust-dns-resolver` on the tokio runtime used by the
+//! `tokio` feature of `libp2p-tcp`.
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use std::{io, net::SocketAddr, pin::Pin, task::{Context, Poll}, time::Duration};
+use trust_dns_resolver::{
+    name_server::{GenericConnection, GenericConnectionProvider, RuntimeProvider, Spawn},
+    proto::{Time, error::ProtoError, tcp::{Connect, DnsTcpStream}, udp::UdpSocket}
+};
+
+/// The connection of a resolver running on tokio.
+pub type TokioConnection = GenericConnection;
+
+/// The connection provider of a resolver running on tokio.
+pub type TokioConnectionProvider = GenericConnectionProvider<TokioRuntime>;
+
+/// Performs the I/O and the timeouts of the resolver with tokio.
+#[derive(Clone, Copy)]
+pub struct TokioRuntime;
+
+impl RuntimeProvider for TokioRuntime {
+    type Handle = TokioHandle;
+    type Tcp = TokioTcpStream;
+    type Timer = TokioTime;
+    type Udp = TokioUdpSocket;
+}
+
+/// Spawns the background tasks of the resolver on the current tokio runtime.
+#[derive(Clone, Copy)]
+pub struct TokioHandle;
+
+impl Spawn for TokioHandle {
+    fn spawn_bg<F>(&mut self, future: F)
+    where
+        F: Future<Output = Result<(), ProtoError>> + Send + 'static
+    {
+        tokio::spawn(future);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct TokioTime;
+
+#[async_trait]
+impl Time for TokioTime {
+    async fn delay_for(duration: Duration) {
+        tokio::time::delay_for(duration).await
+    }
+
+    async fn timeout<F: 'static + Future + Send>(duration: Duration, future: F)
+        -> Result<F::Output, io::Error>
+    {
+        tokio::time::timeout(duration, future).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "future timed out"))
+    }
+}
+
+pub struct TokioUdpSocket(tokio::net::UdpSocket);
+
+#[async_trait]
+impl UdpSocket for TokioUdpSocket {
+    type Time = TokioTime;
+
+    async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        tokio::net::UdpSocket::bind(addr).await.map(TokioUdpSocket)
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8])
+        -> Poll<io::Result<(usize, SocketAddr)>>
+    {
+        self.0.poll_recv_from(cx, buf)
+    }
+
+    fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr)
+        -> Poll<io::Result<usize>>
+    {
+        self.0.poll_send_to(cx, buf, &target)
+    }
+}
+
+pub struct TokioTcpStream(tokio::net::TcpStream);
+
+impl DnsTcpStream for TokioTcpStream {
+    type Time = TokioTime;
+}
+
+#[async_trait]
+impl Connect for TokioTcpStream {
+    async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(TokioTcpStream(stream))
+    }
+}
+
+impl AsyncRead for TokioTcpStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, buf)
+    }
+}
+
+impl AsyncWrite for TokioTcpStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.0), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.0), cx)
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! # libp2p-socks
+//!
+//! This crate provides the type `Socks5Config`, which dials addresses through a SOCKS5 proxy,
+//! e.g. an egress proxy or the SOCKS port of a local Tor client.
+//!
+//! ## Usage
+//!
+//! Create a `Socks5Config` with the address of the proxy and a transport that can dial it,
+//! usually a TCP transport, and optionally set the credentials for username and password
+//! authentication. Dialing `/ip4/<ip>/tcp/<port>`, `/ip6/<ip>/tcp/<port>`,
+//! `/dns/<name>/tcp/<port>` (or `/dns4/`, `/dns6/`) and `/onion3/<address>:<port>` connects
+//! to the proxy and asks it to connect to the target. Names are resolved by the proxy.
+//!
+//! Note that SOCKS5 has no means to restrict the IP version of a name, so `/dns4/` and `/dns6/`
+//! are treated like `/dns/` and the proxy may connect to an address of either IP version.
+//!
+//! Listening is not supported.
+//!
+
+mod protocol;
+
+pub use protocol::Reply;
+
+use data_encoding::BASE32;
+use futures::{prelude::*, future::BoxFuture};
+use libp2p_core::{
+    Transport,
+    multiaddr::{Protocol, Multiaddr},
+    transport::{TransportError, ListenerEvent}
+};
+use log::debug;
+use protocol::{Credentials, HandshakeError, Target};
+use std::{error, fmt, io};
+
+/// Represents the configuration for dialing through a SOCKS5 proxy.
+///
+/// This struct implements the `Transport` trait and holds an underlying transport, which is
+/// used to connect to the proxy.
+#[derive(Debug, Clone)]
+pub struct Socks5Config<T> {
+    /// Underlying transport to connect to the proxy with.
+    inner: T,
+    /// The address of the proxy.
+    proxy: Multiaddr,
+    /// The credentials for username and password authentication, if any.
+    credentials: Option<Credentials>,
+}
+
+impl<T> Socks5Config<T> {
+    /// Creates a new configuration object for dialing through the proxy at the given address,
+    /// which the underlying transport must be able to dial.
+    pub fn new(inner: T, proxy: Multiaddr) -> Self {
+        Socks5Config { inner, proxy, credentials: None }
+    }
+
+    /// Authenticates with the given username and password.
+    ///
+    /// Returns an error if the username or password is empty or longer than 255 bytes.
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>)
+        -> Result<Self, InvalidCredentials>
+    {
+        let (username, password) = (username.into(), password.into());
+        let valid = |s: &str| !s.is_empty() && s.len() <= 255;
+        if !valid(&username) || !valid(&password) {
+            return Err(InvalidCredentials)
+        }
+        self.credentials = Some(Credentials { username, password });
+        Ok(self)
+    }
+}
+
+impl<T> Transport for Socks5Config<T>
+where
+    T: Transport + Send + 'static,
+    T::Error: Send,
+    T::Dial: Send,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send
+{
+    type Output = T::Output;
+    type Error = Socks5Error<T::Error>;
+    type Listener = stream::Pending<Result<ListenerEvent<Self::ListenerUpgrade, Self::Error>, Self::Error>>;
+    type ListenerUpgrade = future::Pending<Result<Self::Output, Self::Error>>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let target = match multiaddr_to_target(&addr) {
+            Some(target) => target,
+            None => return Err(TransportError::MultiaddrNotSupported(addr))
+        };
+
+        let credentials = self.credentials;
+        debug!("Dialing {} via SOCKS5 proxy {}", addr, self.proxy);
+        let dial = self.inner.dial(self.proxy).map_err(|err| err.map(Socks5Error::Transport))?;
+
+        Ok(async move {
+            let mut io = dial.await.map_err(Socks5Error::Transport)?;
+            protocol::connect(&mut io, &target, credentials.as_ref()).await?;
+            Ok(io)
+        }.boxed())
+    }
+}
+
+/// Converts a multiaddr to the target of a SOCKS5 request.
+fn multiaddr_to_target(addr: &Multiaddr) -> Option<Target> {
+    let mut iter = addr.iter();
+    let target = match iter.next()? {
+        Protocol::Ip4(ip) => match iter.next()? {
+            Protocol::Tcp(port) => Target::Ipv4(ip, port),
+            _ => return None
+        },
+        Protocol::Ip6(ip) => match iter.next()? {
+            Protocol::Tcp(port) => Target::Ipv6(ip, port),
+            _ => return None
+        },
+        Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) => match iter.next()? {
+            Protocol::Tcp(port) if !name.is_empty() && name.len() <= 255 =>
+                Target::Domain(name.into_owned(), port),
+            _ => return None
+        },
+        Protocol::Onion3(onion) => {
+            let name = format!("{}.onion", BASE32.encode(onion.hash()).to_lowercase());
+            Target::Domain(name, onion.port())
+        }
+        _ => return None
+    };
+    if iter.next().is_some() {
+        return None
+    }
+    Some(target)
+}
+
+/// Error that can be generated by the SOCKS5 transport.
+#[derive(Debug)]
+pub enum Socks5Error<TErr> {
+    /// Error in the underlying transport layer, e.g. when connecting to the proxy.
+    Transport(TErr),
+    /// Error while communicating with the proxy.
+    Io(io::Error),
+    /// The proxy sent an invalid message, e.g. because it does not support SOCKS5.
+    InvalidResponse(&'static str),
+    /// The proxy requires authentication, but no credentials are set, or it does not
+    /// support username and password authentication.
+    NoAcceptableAuthMethod,
+    /// The proxy rejected the credentials.
+    AuthenticationFailed,
+    /// The proxy failed to connect to the target.
+    ConnectFailed(Reply),
+}
+
+impl<TErr> From<HandshakeError> for Socks5Error<TErr> {
+    fn from(err: HandshakeError) -> Self {
+        match err {
+            HandshakeError::Io(err) => Socks5Error::Io(err),
+            HandshakeError::InvalidResponse(msg) => Socks5Error::InvalidResponse(msg),
+            HandshakeError::NoAcceptableAuthMethod => Socks5Error::NoAcceptableAuthMethod,
+            HandshakeError::AuthenticationFailed => Socks5Error::AuthenticationFailed,
+            HandshakeError::ConnectFailed(reply) => Socks5Error::ConnectFailed(reply),
+        }
+    }
+}
+
+impl<TErr> fmt::Display for Socks5Error<TErr>
+where TErr: fmt::Display
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Socks5Error::Transport(err) => write!(f, "{}", err),
+            Socks5Error::Io(err) => write!(f, "I/O error with SOCKS5 proxy: {}", err),
+            Socks5Error::InvalidResponse(msg) => write!(f, "Invalid response of SOCKS5 proxy: {}", msg),
+            Socks5Error::NoAcceptableAuthMethod =>
+                write!(f, "SOCKS5 proxy accepts none of the authentication methods"),
+            Socks5Error::AuthenticationFailed => write!(f, "SOCKS5 authentication failed"),
+            Socks5Error::ConnectFailed(reply) => write!(f, "SOCKS5 proxy failed to connect: {}", reply),
+        }
+    }
+}
+
+impl<TErr> error::Error for Socks5Error<TErr>
+where TErr: error::Error + 'static
+{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Socks5Error::Transport(err) => Some(err),
+            Socks5Error::Io(err) => Some(err),
+            Socks5Error::InvalidResponse(_) => None,
+            Socks5Error::NoAcceptableAuthMethod => None,
+            Socks5Error::AuthenticationFailed => None,
+            Socks5Error::ConnectFailed(_) => None,
+        }
+    }
+}
+
+/// The username or password passed to `Socks5Config::credentials` is empty or longer than
+/// 255 bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCredentials;
+
+impl fmt::Display for InvalidCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid SOCKS5 credentials")
+    }
+}
+
+impl error::Error for InvalidCredentials {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reply, Socks5Config, Socks5Error, multiaddr_to_target, protocol::connect_request};
+    use async_std::net::TcpListener;
+    use futures::{channel::oneshot, prelude::*};
+    use libp2p_core::{Transport, multiaddr::Multiaddr};
+    use libp2p_tcp::TcpConfig;
+
+    /// The messages received by a proxy stand-in.
+    #[derive(Debug, Default)]
+    struct Received {
+        methods: Vec<u8>,
+        credentials: Option<(Vec<u8>, Vec<u8>)>,
+        request: Vec<u8>,
+    }
+
+    /// Spawns a SOCKS5 proxy stand-in which accepts a single connection, selects the given
+    /// authentication method, answers authentication with the given status and the request
+    /// with the given reply code. After a successful request, it echoes all data.
+    async fn proxy(method: u8, auth_status: u8, reply: u8) -> (Multiaddr, oneshot::Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("/ip4/127.0.0.1/tcp/{}", listener.local_addr().unwrap().port()).parse().unwrap();
+        let (tx, rx) = oneshot::channel();
+        async_std::task::spawn(async move {
+            let (mut io, _) = listener.accept().await.unwrap();
+            let mut received = Received::default();
+
+            let mut buf = [0u8; 2];
+            io.read_exact(&mut buf).await.unwrap();
+            received.methods = vec![0; usize::from(buf[1])];
+            io.read_exact(&mut received.methods).await.unwrap();
+            io.write_all(&[5, method]).await.unwrap();
+            if method == 0xff {
+                return tx.send(received).unwrap()
+            }
+
+            if method == 2 {
+                io.read_exact(&mut buf).await.unwrap();
+                let mut username = vec![0; usize::from(buf[1])];
+                io.read_exact(&mut username).await.unwrap();
+                io.read_exact(&mut buf[.. 1]).await.unwrap();
+                let mut password = vec![0; usize::from(buf[0])];
+                io.read_exact(&mut password).await.unwrap();
+                received.credentials = Some((username, password));
+                io.write_all(&[1, auth_status]).await.unwrap();
+                if auth_status != 0 {
+                    return tx.send(received).unwrap()
+                }
+            }
+
+            let mut header = [0u8; 4];
+            io.read_exact(&mut header).await.unwrap();
+            received.request.extend_from_slice(&header);
+            let len = match header[3] {
+                1 => 4,
+                4 => 16,
+                _ => {
+                    io.read_exact(&mut buf[.. 1]).await.unwrap();
+                    received.request.push(buf[0]);
+                    usize::from(buf[0])
+                }
+            };
+            let mut rest = vec![0; len + 2];
+            io.read_exact(&mut rest).await.unwrap();
+            received.request.extend_from_slice(&rest);
+            io.write_all(&[5, reply, 0, 1, 127, 0, 0, 1, 0, 0]).await.unwrap();
+            tx.send(received).unwrap();
+
+            if reply == 0 {
+                let mut buf = [0u8; 64];
+                loop {
+                    let n = io.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return
+                    }
+                    io.write_all(&buf[.. n]).await.unwrap();
+                }
+            }
+        });
+        (addr, rx)
+    }
+
+    #[test]
+    fn connect_with_credentials() {
+        async_std::task::block_on(async {
+            let (proxy_addr, received) = proxy(2, 0, 0).await;
+            let transport = Socks5Config::new(TcpConfig::new(), proxy_addr).credentials("user", "pass").unwrap();
+
+            let mut io = transport.dial("/dns/example.com/tcp/80".parse().unwrap()).unwrap().await.unwrap();
+            io.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            io.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+
+            let received = received.await.unwrap();
+            assert_eq!(received.methods, vec![0, 2]);
+            assert_eq!(received.credentials, Some((b"user".to_vec(), b"pass".to_vec())));
+            assert_eq!(received.request, [&[5, 1, 0, 3, 11][..], b"example.com", &[0, 80]].concat());
+        });
+    }
+
+    #[test]
+    fn connect_errors() {
+        async fn dial(method: u8, auth_status: u8, reply: u8) -> Socks5Error<std::io::Error> {
+            let (proxy_addr, _received) = proxy(method, auth_status, reply).await;
+            let transport = Socks5Config::new(TcpConfig::new(), proxy_addr).credentials("user", "pass").unwrap();
+            match transport.dial("/ip4/10.0.0.1/tcp/1".parse().unwrap()).unwrap().await {
+                Ok(_) => panic!("Unexpected success"),
+                Err(err) => err
+            }
+        }
+
+        async_std::task::block_on(async {
+            match dial(0xff, 0, 0).await {
+                Socks5Error::NoAcceptableAuthMethod => {}
+                e => panic!("Unexpected error: {:?}", e)
+            }
+            match dial(2, 1, 0).await {
+                Socks5Error::AuthenticationFailed => {}
+                e => panic!("Unexpected error: {:?}", e)
+            }
+            match dial(0, 0, 5).await {
+                Socks5Error::ConnectFailed(Reply::ConnectionRefused) => {}
+                e => panic!("Unexpected error: {:?}", e)
+            }
+        });
+
+        let transport = Socks5Config::new(TcpConfig::new(), "/ip4/127.0.0.1/tcp/1".parse().unwrap());
+        assert!(transport.clone().credentials("", "pass").is_err());
+        assert!(transport.credentials("user", "p".repeat(256)).is_err());
+    }
+
+    #[test]
+    fn connect_requests() {
+        let request = |addr: &str| multiaddr_to_target(&addr.parse().unwrap()).map(|t| connect_request(&t));
+
+        assert_eq!(request("/ip4/10.0.0.1/tcp/1234"), Some(vec![5, 1, 0, 1, 10, 0, 0, 1, 4, 210]));
+        assert_eq!(
+            request("/ip6/::1/tcp/80"),
+            Some(vec![5, 1, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 80])
+        );
+        let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+        assert_eq!(
+            request("/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:1234"),
+            Some([&[5, 1, 0, 3, onion.len() as u8][..], onion.as_bytes(), &[4, 210]].concat())
+        );
+        assert_eq!(request("/ip4/10.0.0.1/udp/1234"), None);
+        assert_eq!(request("/ip4/10.0.0.1/tcp/1234/ws"), None);
+        assert_eq!(request("/onion/aaimaq4ygg2iegci:80"), None);
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The client side of the SOCKS5 protocol ([RFC 1928]) with username and
+//! password authentication ([RFC 1929]).
+//!
+//! [RFC 1928]: https://tools.ietf.org/html/rfc1928
+//! [RFC 1929]: https://tools.ietf.org/html/rfc1929
+
+use futures::prelude::*;
+use std::{fmt, io, net::{Ipv4Addr, Ipv6Addr}};
+
+const VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_PASSWORD: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+const CMD_CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// The address of the target to connect to via the proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Ipv4(Ipv4Addr, u16),
+    Ipv6(Ipv6Addr, u16),
+    /// A domain name of at most 255 bytes, resolved by the proxy.
+    Domain(String, u16),
+}
+
+/// The credentials for username and password authentication.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials").field("username", &self.username).finish()
+    }
+}
+
+/// An error of the SOCKS5 handshake.
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// Reading from or writing to the proxy failed.
+    Io(io::Error),
+    /// The proxy sent an invalid message.
+    InvalidResponse(&'static str),
+    /// The proxy accepts none of the offered authentication methods.
+    NoAcceptableAuthMethod,
+    /// The proxy rejected the credentials.
+    AuthenticationFailed,
+    /// The proxy failed to connect to the target.
+    ConnectFailed(Reply),
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(err: io::Error) -> Self {
+        HandshakeError::Io(err)
+    }
+}
+
+/// The reply of the proxy to a failed request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    /// General SOCKS server failure.
+    GeneralFailure,
+    /// Connection not allowed by ruleset.
+    NotAllowed,
+    /// Network unreachable.
+    NetworkUnreachable,
+    /// Host unreachable.
+    HostUnreachable,
+    /// Connection refused.
+    ConnectionRefused,
+    /// TTL expired.
+    TtlExpired,
+    /// Command not supported.
+    CommandNotSupported,
+    /// Address type not supported.
+    AddressTypeNotSupported,
+    /// A reply code not defined by RFC 1928.
+    Other(u8),
+}
+
+impl From<u8> for Reply {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => Reply::GeneralFailure,
+            0x02 => Reply::NotAllowed,
+            0x03 => Reply::NetworkUnreachable,
+            0x04 => Reply::HostUnreachable,
+            0x05 => Reply::ConnectionRefused,
+            0x06 => Reply::TtlExpired,
+            0x07 => Reply::CommandNotSupported,
+            0x08 => Reply::AddressTypeNotSupported,
+            code => Reply::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::GeneralFailure => f.write_str("general SOCKS server failure"),
+            Reply::NotAllowed => f.write_str("connection not allowed by ruleset"),
+            Reply::NetworkUnreachable => f.write_str("network unreachable"),
+            Reply::HostUnreachable => f.write_str("host unreachable"),
+            Reply::ConnectionRefused => f.write_str("connection refused"),
+            Reply::TtlExpired => f.write_str("TTL expired"),
+            Reply::CommandNotSupported => f.write_str("command not supported"),
+            Reply::AddressTypeNotSupported => f.write_str("address type not supported"),
+            Reply::Other(code) => write!(f, "unknown reply code {}", code),
+        }
+    }
+}
+
+/// Performs the SOCKS5 handshake on a connection to the proxy, after which
+/// the connection is tunneled to the target.
+pub async fn connect<S>(io: &mut S, target: &Target, credentials: Option<&Credentials>)
+    -> Result<(), HandshakeError>
+where
+    S: AsyncRead + AsyncWrite + Unpin
+{
+    // Method selection.
+    let greeting = if credentials.is_some() {
+        vec![VERSION, 2, METHOD_NO_AUTH, METHOD_PASSWORD]
+    } else {
+        vec![VERSION, 1, METHOD_NO_AUTH]
+    };
+    io.write_all(&greeting).await?;
+    io.flush().await?;
+
+    let mut buf = [0u8; 2];
+    io.read_exact(&mut buf).await?;
+    if buf[0] != VERSION {
+        return Err(HandshakeError::InvalidResponse("unsupported version"))
+    }
+    match (buf[1], credentials) {
+        (METHOD_NO_AUTH, _) => {}
+        (METHOD_PASSWORD, Some(credentials)) => authenticate(io, credentials).await?,
+        (METHOD_NONE_ACCEPTABLE, _) => return Err(HandshakeError::NoAcceptableAuthMethod),
+        _ => return Err(HandshakeError::InvalidResponse("method not offered"))
+    }
+
+    // Request.
+    io.write_all(&connect_request(target)).await?;
+    io.flush().await?;
+
+    let mut buf = [0u8; 4];
+    io.read_exact(&mut buf).await?;
+    if buf[0] != VERSION {
+        return Err(HandshakeError::InvalidResponse("unsupported version"))
+    }
+    if buf[1] != 0 {
+        return Err(HandshakeError::ConnectFailed(Reply::from(buf[1])))
+    }
+
+    // The address the proxy bound for the connection is of no interest.
+    let len = match buf[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            io.read_exact(&mut len).await?;
+            usize::from(len[0])
+        }
+        _ => return Err(HandshakeError::InvalidResponse("unknown address type"))
+    };
+    let mut bound = vec![0u8; len + 2];
+    io.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+/// Performs username and password authentication.
+async fn authenticate<S>(io: &mut S, credentials: &Credentials) -> Result<(), HandshakeError>
+where
+    S: AsyncRead + AsyncWrite + Unpin
+{
+    let username = credentials.username.as_bytes();
+    let password = credentials.password.as_bytes();
+    let mut msg = Vec::with_capacity(3 + username.len() + password.len());
+    msg.push(AUTH_VERSION);
+    msg.push(username.len() as u8);
+    msg.extend_from_slice(username);
+    msg.push(password.len() as u8);
+    msg.extend_from_slice(password);
+    io.write_all(&msg).await?;
+    io.flush().await?;
+
+    let mut buf = [0u8; 2];
+    io.read_exact(&mut buf).await?;
+    if buf[0] != AUTH_VERSION {
+        return Err(HandshakeError::InvalidResponse("unsupported authentication version"))
+    }
+    if buf[1] != 0 {
+        return Err(HandshakeError::AuthenticationFailed)
+    }
+    Ok(())
+}
+
+/// Encodes a CONNECT request for the given target.
+pub fn connect_request(target: &Target) -> Vec<u8> {
+    let mut msg = vec![VERSION, CMD_CONNECT, 0];
+    let port = match target {
+        Target::Ipv4(ip, port) => {
+            msg.push(ATYP_IPV4);
+            msg.extend_from_slice(&ip.octets());
+            port
+        }
+        Target::Ipv6(ip, port) => {
+            msg.push(ATYP_IPV6);
+            msg.extend_from_slice(&ip.octets());
+            port
+        }
+        Target::Domain(name, port) => {
+            msg.push(ATYP_DOMAIN);
+            msg.push(name.len() as u8);
+            msg.extend_from_slice(name.as_bytes());
+            port
+        }
+    };
+    msg.extend_from_slice(&port.to_be_bytes());
+    msg
+}
@@ -0,0 +1,273 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Watching for changes of the addresses of the host's network interfaces.
+//!
+//! On Linux, address changes are received from the kernel via a netlink
+//! socket which is read on a background thread, shared by all watchers of
+//! the process. Elsewhere, or if the netlink socket can not be used, the
+//! addresses may have changed whenever the polling interval has elapsed.
+
+use futures::prelude::*;
+use futures_timer::Delay;
+use std::{pin::Pin, task::{Context, Poll}, time::Duration};
+
+/// The interval after which the interface addresses are checked again
+/// if no change notifications are available.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Notifies about possible changes of the interface addresses.
+pub(crate) struct IfWatcher {
+    inner: Inner
+}
+
+enum Inner {
+    /// Change notifications received from the netlink thread.
+    #[cfg(target_os = "linux")]
+    Netlink(futures::channel::mpsc::UnboundedReceiver<()>),
+    /// Periodic checks.
+    Poll(Delay)
+}
+
+impl IfWatcher {
+    /// Creates a new watcher, falling back to polling if change
+    /// notifications are not available.
+    pub(crate) fn new() -> Self {
+        #[cfg(target_os = "linux")]
+        match netlink::watch() {
+            Ok(rx) => return IfWatcher { inner: Inner::Netlink(rx) },
+            Err(err) => log::debug!("Failed to watch interface addresses via netlink: {}; polling instead", err)
+        }
+        IfWatcher { inner: Inner::Poll(Delay::new(POLL_INTERVAL)) }
+    }
+
+    /// Resolves when the interface addresses may have changed.
+    pub(crate) fn poll_changed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        match &mut self.inner {
+            #[cfg(target_os = "linux")]
+            Inner::Netlink(rx) => match rx.poll_next_unpin(cx) {
+                Poll::Ready(Some(())) => {
+                    // A single change is commonly reported by several
+                    // messages, which are all covered by a single check.
+                    while let Poll::Ready(Some(())) = rx.poll_next_unpin(cx) {}
+                    Poll::Ready(())
+                }
+                Poll::Ready(None) => {
+                    log::debug!("Netlink thread terminated; polling interface addresses instead");
+                    self.inner = Inner::Poll(Delay::new(POLL_INTERVAL));
+                    Poll::Ready(())
+                }
+                Poll::Pending => Poll::Pending
+            }
+            Inner::Poll(delay) => {
+                futures::ready!(Pin::new(&mut *delay).poll(cx));
+                delay.reset(POLL_INTERVAL);
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use futures::channel::mpsc;
+    use lazy_static::lazy_static;
+    use log::debug;
+    use std::{io, mem, sync::{Mutex, MutexGuard, PoisonError}, thread};
+
+    lazy_static! {
+        /// The senders of all watchers while the netlink thread is running.
+        static ref SUBSCRIBERS: Mutex<Option<Vec<mpsc::UnboundedSender<()>>>> = Mutex::new(None);
+    }
+
+    fn subscribers() -> MutexGuard<'static, Option<Vec<mpsc::UnboundedSender<()>>>> {
+        SUBSCRIBERS.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// A netlink socket which is closed on drop.
+    struct Socket(libc::c_int);
+
+    impl Drop for Socket {
+        fn drop(&mut self) {
+            // Safe because the file descriptor is owned by this value.
+            unsafe { libc::close(self.0); }
+        }
+    }
+
+    /// Subscribes to the IPv4 and IPv6 address changes of all interfaces.
+    ///
+    /// The returned receiver gets an item for every batch of netlink messages
+    /// reporting a change. The thread reading the socket is started by the
+    /// first subscription and terminates when all receivers are dropped or
+    /// the socket can no longer be read, which closes the receivers.
+    pub(super) fn watch() -> io::Result<mpsc::UnboundedReceiver<()>> {
+        let mut subscribers = subscribers();
+        let (tx, rx) = mpsc::unbounded();
+        if let Some(senders) = subscribers.as_mut() {
+            senders.push(tx);
+            return Ok(rx)
+        }
+        let socket = open()?;
+        thread::Builder::new()
+            .name("libp2p-tcp-if-watch".into())
+            .spawn(move || run(socket))?;
+        *subscribers = Some(vec![tx]);
+        Ok(rx)
+    }
+
+    /// Opens a netlink socket subscribed to address changes.
+    fn open() -> io::Result<Socket> {
+        // Safe because the arguments are valid constants.
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        let socket = Socket(fd);
+
+        // Safe because an all-zero `sockaddr_nl` is a valid value.
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        // Safe because `addr` is a valid `sockaddr_nl` of the given size.
+        let res = unsafe {
+            libc::bind(
+                socket.0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error())
+        }
+
+        // The receive timeout lets the thread notice dropped receivers
+        // even if no address changes.
+        let timeout = libc::timeval { tv_sec: 1, tv_usec: 0 };
+        // Safe because `timeout` is a valid `timeval` of the given size.
+        let res = unsafe {
+            libc::setsockopt(
+                socket.0,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error())
+        }
+
+        Ok(socket)
+    }
+
+    /// Reads the socket and notifies the subscribers until there are none.
+    fn run(socket: Socket) {
+        let mut buf = [0u8; 4096];
+        loop {
+            // Safe because `buf` is valid for writes of its length.
+            let n = unsafe { libc::recv(socket.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            let changed =
+                if n >= 0 {
+                    has_address_change(&buf[.. n as usize])
+                } else {
+                    let err = io::Error::last_os_error();
+                    match err.kind() {
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted => false,
+                        // ENOBUFS: messages were lost, so report a change.
+                        _ if err.raw_os_error() == Some(libc::ENOBUFS) => true,
+                        _ => {
+                            debug!("Failed to read from netlink socket: {}", err);
+                            // Dropping the senders closes the receivers.
+                            *subscribers() = None;
+                            return
+                        }
+                    }
+                };
+            let mut subscribers = subscribers();
+            let senders = subscribers.get_or_insert_with(Vec::new);
+            if changed {
+                senders.retain(|tx| tx.unbounded_send(()).is_ok())
+            } else {
+                senders.retain(|tx| !tx.is_closed())
+            }
+            if senders.is_empty() {
+                *subscribers = None;
+                return
+            }
+        }
+    }
+
+    /// Does the buffer contain a message about a new or deleted address?
+    ///
+    /// Only the headers of the messages are inspected, as far as they are
+    /// within the buffer and their lengths are valid.
+    pub(super) fn has_address_change(buf: &[u8]) -> bool {
+        let header_len = mem::size_of::<libc::nlmsghdr>();
+        let mut offset = 0;
+        while buf.len().saturating_sub(offset) >= header_len {
+            let header = &buf[offset .. offset + header_len];
+            let len = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            let typ = u16::from_ne_bytes([header[4], header[5]]);
+            if typ == libc::RTM_NEWADDR || typ == libc::RTM_DELADDR {
+                return true
+            }
+            if len < header_len || len > buf.len() - offset {
+                debug!("Invalid netlink message length {}", len);
+                return false
+            }
+            // Messages are aligned to 4 bytes.
+            offset += (len + 3) & !3
+        }
+        false
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::netlink::has_address_change;
+
+    fn message(typ: u16, len: u32) -> Vec<u8> {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&len.to_ne_bytes());
+        msg.extend_from_slice(&typ.to_ne_bytes());
+        msg.resize(16, 0);
+        msg
+    }
+
+    #[test]
+    fn netlink_messages() {
+        assert!(!has_address_change(&[]));
+        assert!(!has_address_change(&[0; 15]));
+        assert!(has_address_change(&message(libc::RTM_NEWADDR, 16)));
+        assert!(has_address_change(&message(libc::RTM_DELADDR, 16)));
+        assert!(!has_address_change(&message(libc::RTM_NEWLINK, 16)));
+
+        let mut msgs = message(libc::RTM_NEWLINK, 16);
+        msgs.extend(message(libc::RTM_NEWADDR, 16));
+        assert!(has_address_change(&msgs));
+
+        // Invalid lengths end the inspection.
+        for &len in &[0, 15, 33] {
+            let mut msgs = message(libc::RTM_NEWLINK, len);
+            msgs.extend(message(libc::RTM_NEWADDR, 16));
+            assert!(!has_address_change(&msgs));
+        }
+    }
+}
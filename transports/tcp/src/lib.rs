@@ -28,10 +28,28 @@
 //! Both the `TcpConfig` and `TokioTcpConfig` structs implement the `Transport` trait of the
 //! `core` library. See the documentation of `core` and of libp2p in general to learn how to
 //! use the `Transport` trait.
+//!
+//! # Port reuse
+//!
+//! By default outbound connections use an ephemeral local port. With
+//! `port_reuse(true)`, an outbound connection is bound to the listen address
+//! of an active listener of the same transport, so that remotes observe the
+//! listen port of the local node as the source port of its connections.
+//!
+//! # Listen addresses
+//!
+//! A listener on a wildcard IP address reports the addresses of all network
+//! interfaces as its listen addresses. Changes of these addresses are
+//! reported as they happen, as notified by the kernel on Linux or otherwise
+//! detected by periodically checking the interface addresses.
+
+mod if_watch;
+mod socket_options;
 
 use futures::{future::{self, Ready}, prelude::*};
 use futures_timer::Delay;
 use get_if_addrs::{IfAddr, get_if_addrs};
+use if_watch::IfWatcher;
 use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use libp2p_core::{
     Transport,
@@ -40,19 +58,22 @@ use libp2p_core::{
 };
 use log::{debug, trace};
 use socket2::{Socket, Domain, Type};
+use socket_options::SocketOptions;
 use std::{
-    collections::VecDeque,
+    cmp,
+    collections::{HashSet, VecDeque},
     convert::TryFrom,
     io,
     iter::{self, FromIterator},
     net::{IpAddr, SocketAddr},
     pin::Pin,
+    sync::{Arc, RwLock},
     task::{Context, Poll},
     time::Duration
 };
 
 macro_rules! codegen {
-    ($feature_name:expr, $tcp_config:ident, $tcp_trans_stream:ident, $tcp_listen_stream:ident, $apply_config:ident, $tcp_stream:ty, $tcp_listener:ty) => {
+    ($feature_name:expr, $tcp_config:ident, $tcp_trans_stream:ident, $tcp_listen_stream:ident, $apply_config:ident, $connect_socket:ident, $tcp_stream:ty, $tcp_listener:ty) => {
 
 /// Represents the configuration for a TCP/IP transport capability for libp2p.
 ///
@@ -67,6 +88,14 @@ pub struct $tcp_config {
     ttl: Option<u32>,
     /// `TCP_NODELAY` to set for opened sockets, or `None` to keep default.
     nodelay: Option<bool>,
+    /// The listen addresses to reuse for outbound connections, if enabled.
+    port_reuse: Option<PortReuse>,
+    /// Size of the listen backlog.
+    backlog: u32,
+    /// Options to set for listening and outbound sockets.
+    socket_options: SocketOptions,
+    /// The local address to bind outbound sockets to, if any.
+    dial_local_addr: Option<SocketAddr>,
 }
 
 impl $tcp_config {
@@ -76,6 +105,10 @@ impl $tcp_config {
             sleep_on_error: Duration::from_millis(100),
             ttl: None,
             nodelay: None,
+            port_reuse: None,
+            backlog: 1024,
+            socket_options: SocketOptions::default(),
+            dial_local_addr: None,
         }
     }
 
@@ -90,6 +123,101 @@ impl $tcp_config {
         self.nodelay = Some(value);
         self
     }
+
+    /// Configures port reuse for outbound connections.
+    ///
+    /// If enabled, the sockets of listeners are created with `SO_REUSEPORT`
+    /// (on Unix) in addition to `SO_REUSEADDR` and an outbound connection
+    /// is bound to the listen address of a listener of the same IP version,
+    /// which must not be a loopback address unless the remote address is
+    /// one as well. If there is no such listener or the local address can
+    /// not be used, an ephemeral port is used instead.
+    ///
+    /// Only listeners created from this configuration or its clones after
+    /// port reuse has been enabled are taken into account.
+    pub fn port_reuse(mut self, value: bool) -> Self {
+        self.port_reuse = if value { Some(PortReuse::default()) } else { None };
+        self
+    }
+
+    /// Sets the size of the listen backlog. Defaults to 1024.
+    pub fn listen_backlog(mut self, value: u32) -> Self {
+        self.backlog = value;
+        self
+    }
+
+    /// Enables TCP keepalive with the given idle time before the first probe.
+    ///
+    /// Like all of the following socket options, keepalive is set on the
+    /// sockets of listeners, from which the sockets of inbound connections
+    /// inherit it, and on the sockets of outbound connections.
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.socket_options.keepalive = Some(idle);
+        self
+    }
+
+    /// Sets the interval between TCP keepalive probes, in whole seconds.
+    ///
+    /// Only has an effect if keepalive is enabled and is ignored on platforms
+    /// other than Linux, Android, macOS, iOS, FreeBSD and NetBSD.
+    pub fn keepalive_interval(mut self, value: Duration) -> Self {
+        self.socket_options.keepalive_interval = Some(value);
+        self
+    }
+
+    /// Sets the number of unanswered TCP keepalive probes after which the
+    /// connection is closed.
+    ///
+    /// Only has an effect if keepalive is enabled and is ignored on platforms
+    /// other than Linux, Android, macOS, iOS, FreeBSD and NetBSD.
+    pub fn keepalive_retries(mut self, value: u32) -> Self {
+        self.socket_options.keepalive_retries = Some(value);
+        self
+    }
+
+    /// Sets the size of the send buffer (`SO_SNDBUF`).
+    pub fn send_buffer_size(mut self, value: usize) -> Self {
+        self.socket_options.send_buffer_size = Some(value);
+        self
+    }
+
+    /// Sets the size of the receive buffer (`SO_RCVBUF`).
+    pub fn recv_buffer_size(mut self, value: usize) -> Self {
+        self.socket_options.recv_buffer_size = Some(value);
+        self
+    }
+
+    /// Sets `SO_LINGER`, i.e. how long closing a socket blocks while unsent
+    /// data remains, or disables lingering with `None`.
+    ///
+    /// Note that a non-zero duration makes dropping a connection with unsent
+    /// data block the thread of the executor it is dropped on, for up to
+    /// the given duration and regardless of the socket being non-blocking.
+    /// A duration of zero instead discards unsent data and resets the
+    /// connection immediately.
+    pub fn linger(mut self, value: Option<Duration>) -> Self {
+        self.socket_options.linger = Some(value);
+        self
+    }
+
+    /// Sets the type of service of outgoing IPv4 packets (`IP_TOS`), e.g. a
+    /// DSCP value shifted left by two bits.
+    ///
+    /// Ignored for IPv6 sockets and on Fuchsia, Redox, Solaris and illumos.
+    pub fn tos(mut self, value: u8) -> Self {
+        self.socket_options.tos = Some(value);
+        self
+    }
+
+    /// Sets the local address to bind outbound sockets to.
+    ///
+    /// The address is only used for remote addresses of the same IP version
+    /// and takes precedence over port reuse. A port of 0 lets the operating
+    /// system choose an ephemeral port.
+    pub fn dial_local_addr(mut self, value: SocketAddr) -> Self {
+        self.dial_local_addr = Some(value);
+        self
+    }
 }
 
 impl Transport for $tcp_config {
@@ -110,28 +238,38 @@ impl Transport for $tcp_config {
         async fn do_listen(cfg: $tcp_config, socket_addr: SocketAddr)
             -> Result<impl Stream<Item = Result<ListenerEvent<Ready<Result<$tcp_trans_stream, io::Error>>, io::Error>, io::Error>>, io::Error>
         {
-            let socket = if socket_addr.is_ipv4() {
-                Socket::new(Domain::ipv4(), Type::stream(), Some(socket2::Protocol::tcp()))?
-            } else {
-                let s = Socket::new(Domain::ipv6(), Type::stream(), Some(socket2::Protocol::tcp()))?;
-                s.set_only_v6(true)?;
-                s
-            };
+            let socket = create_socket(&socket_addr)?;
             if cfg!(target_family = "unix") {
                 socket.set_reuse_address(true)?;
             }
+            if cfg.port_reuse.is_some() {
+                set_reuse_port(&socket)?;
+            }
+            cfg.socket_options.apply(&socket, &socket_addr)?;
             socket.bind(&socket_addr.into())?;
-            socket.listen(1024)?; // we may want to make this configurable
+            socket.listen(cmp::min(cfg.backlog, i32::MAX as u32) as i32)?;
 
-            let listener = <$tcp_listener>::try_from(socket.into_tcp_listener())
+            let listener = <$tcp_listener>::try_from(std::net::TcpListener::from(socket))
                 .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
             let local_addr = listener.local_addr()?;
             let port = local_addr.port();
 
+            if let Some(port_reuse) = &cfg.port_reuse {
+                port_reuse.register(local_addr);
+            }
+
             // Determine all our listen addresses which is either a single local IP address
             // or (if a wildcard IP address was used) the addresses of all our interfaces,
             // as reported by `get_if_addrs`.
+            let if_watcher =
+                if socket_addr.ip().is_unspecified() {
+                    // Start watching before determining the addresses so
+                    // that no change goes unnoticed.
+                    Some(IfWatcher::new())
+                } else {
+                    None
+                };
             let addrs =
                 if socket_addr.ip().is_unspecified() {
                     let addrs = host_addresses(port)?;
@@ -163,10 +301,12 @@ impl Transport for $tcp_config {
 
             let listen_stream = $tcp_listen_stream {
                 stream: listener,
+                listen_addr: local_addr,
                 pause: None,
                 pause_duration: cfg.sleep_on_error,
                 port,
                 addrs,
+                if_watcher,
                 pending,
                 config: cfg
             };
@@ -191,8 +331,41 @@ impl Transport for $tcp_config {
 
         debug!("Dialing {}", addr);
 
+        // Connects from an ephemeral port.
+        async fn connect(cfg: &$tcp_config, socket_addr: SocketAddr) -> Result<$tcp_stream, io::Error> {
+            if cfg.socket_options.is_empty() {
+                <$tcp_stream>::connect(&socket_addr).await
+            } else {
+                let socket = create_dial_socket(&socket_addr, None, &cfg.socket_options)?;
+                $connect_socket(socket, socket_addr).await
+            }
+        }
+
         async fn do_dial(cfg: $tcp_config, socket_addr: SocketAddr) -> Result<$tcp_trans_stream, io::Error> {
-            let stream = <$tcp_stream>::connect(&socket_addr).await?;
+            let bind_addr = cfg.dial_local_addr.filter(|a| a.is_ipv4() == socket_addr.is_ipv4());
+            let reuse_addr = cfg.port_reuse.as_ref().and_then(|p| p.local_dial_addr(&socket_addr.ip()));
+            let stream =
+                if let Some(local_addr) = bind_addr {
+                    let socket = create_dial_socket(&socket_addr, Some(&local_addr), &cfg.socket_options)?;
+                    $connect_socket(socket, socket_addr).await?
+                } else if let Some(local_addr) = reuse_addr {
+                    let stream = match create_dial_socket(&socket_addr, Some(&local_addr), &cfg.socket_options) {
+                        Ok(socket) => $connect_socket(socket, socket_addr).await,
+                        Err(err) => Err(err)
+                    };
+                    match stream {
+                        Ok(stream) => stream,
+                        Err(err) if err.kind() == io::ErrorKind::AddrInUse
+                            || err.kind() == io::ErrorKind::AddrNotAvailable =>
+                        {
+                            debug!("Failed to dial {} from {}: {}; using an ephemeral port", socket_addr, local_addr, err);
+                            connect(&cfg, socket_addr).await?
+                        }
+                        Err(err) => return Err(err)
+                    }
+                } else {
+                    connect(&cfg, socket_addr).await?
+                };
             $apply_config(&cfg, &stream)?;
             Ok($tcp_trans_stream { inner: stream })
         }
@@ -206,6 +379,8 @@ impl Transport for $tcp_config {
 pub struct $tcp_listen_stream {
     /// The incoming connections.
     stream: $tcp_listener,
+    /// The local address of the listener.
+    listen_addr: SocketAddr,
     /// The current pause if any.
     pause: Option<Delay>,
     /// How long to pause after an error.
@@ -214,6 +389,9 @@ pub struct $tcp_listen_stream {
     port: u16,
     /// The set of known addresses.
     addrs: Addresses,
+    /// Watches for changes of the interface addresses if listening
+    /// on a wildcard IP address.
+    if_watcher: Option<IfWatcher>,
     /// Temporary buffer of listener events.
     pending: Buffer<$tcp_trans_stream>,
     /// Original configuration.
@@ -232,10 +410,33 @@ impl $tcp_listen_stream {
                 let _ = pause.await;
             }
 
+            let accepted = {
+                let accept = self.stream.accept();
+                futures::pin_mut!(accept);
+                let if_watcher = &mut self.if_watcher;
+                let changed = future::poll_fn(|cx| match if_watcher {
+                    Some(w) => w.poll_changed(cx),
+                    None => Poll::Pending
+                });
+                match future::select(accept, changed).await {
+                    future::Either::Left((accepted, _)) => Some(accepted),
+                    future::Either::Right(((), _)) => None
+                }
+            };
+
             // TODO: do we get the peer_addr at the same time?
-            let (sock, _) = match self.stream.accept().await {
-                Ok(s) => s,
-                Err(e) => {
+            let (sock, _) = match accepted {
+                Some(Ok(s)) => s,
+                None => {
+                    // The interface addresses may have changed.
+                    if let Addresses::Many(ref mut addrs) = self.addrs {
+                        if let Err(err) = update_addresses(self.port, addrs, &mut self.pending) {
+                            return (Ok(ListenerEvent::Error(err)), self);
+                        }
+                    }
+                    continue
+                }
+                Some(Err(e)) => {
                     debug!("error accepting incoming connection: {}", e);
                     self.pause = Some(Delay::new(self.pause_duration));
                     return (Ok(ListenerEvent::Error(e)), self);
@@ -289,6 +490,14 @@ impl $tcp_listen_stream {
     }
 }
 
+impl Drop for $tcp_listen_stream {
+    fn drop(&mut self) {
+        if let Some(port_reuse) = &self.config.port_reuse {
+            port_reuse.unregister(&self.listen_addr)
+        }
+    }
+}
+
 /// Wraps around a `TcpStream` and adds logging for important events.
 #[cfg_attr(docsrs, doc(cfg(feature = $feature_name)))]
 #[derive(Debug)]
@@ -323,10 +532,39 @@ fn $apply_config(config: &$tcp_config, socket: &$tcp_stream) -> Result<(), io::E
 }
 
 #[cfg(feature = "async-std")]
-codegen!("async-std", TcpConfig, TcpTransStream, TcpListenStream, apply_config_async_std, async_std::net::TcpStream, async_std::net::TcpListener);
+codegen!("async-std", TcpConfig, TcpTransStream, TcpListenStream, apply_config_async_std, connect_socket_async_std, async_std::net::TcpStream, async_std::net::TcpListener);
+
+#[cfg(feature = "tokio")]
+codegen!("tokio", TokioTcpConfig, TokioTcpTransStream, TokioTcpListenStream, apply_config_tokio, connect_socket_tokio, tokio::net::TcpStream, tokio::net::TcpListener);
+
+/// Connects the given socket to `remote`.
+///
+/// The connection is initiated without blocking and completes once the
+/// socket becomes writable, after which a failure to connect is reported
+/// as the pending error of the socket.
+#[cfg(feature = "async-std")]
+async fn connect_socket_async_std(socket: Socket, remote: SocketAddr) -> io::Result<async_std::net::TcpStream> {
+    socket.set_nonblocking(true)?;
+    match socket.connect(&remote.into()) {
+        Ok(()) => {}
+        #[cfg(unix)]
+        Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+        Err(err) => return Err(err)
+    }
+    let stream = async_io::Async::new(std::net::TcpStream::from(socket))?;
+    stream.writable().await?;
+    if let Some(err) = stream.get_ref().take_error()? {
+        return Err(err)
+    }
+    Ok(async_std::net::TcpStream::from(stream.into_inner()?))
+}
 
+/// Connects the given socket to `remote`.
 #[cfg(feature = "tokio")]
-codegen!("tokio", TokioTcpConfig, TokioTcpTransStream, TokioTcpListenStream, apply_config_tokio, tokio::net::TcpStream, tokio::net::TcpListener);
+async fn connect_socket_tokio(socket: Socket, remote: SocketAddr) -> io::Result<tokio::net::TcpStream> {
+    tokio::net::TcpStream::connect_std(std::net::TcpStream::from(socket), &remote).await
+}
 
 #[cfg(feature = "async-std")]
 impl AsyncRead for TcpTransStream {
@@ -372,6 +610,79 @@ impl AsyncWrite for TokioTcpTransStream {
     }
 }
 
+/// The listen addresses of the listeners of a transport, shared between
+/// all clones of its configuration.
+#[derive(Debug, Clone, Default)]
+struct PortReuse {
+    listen_addrs: Arc<RwLock<HashSet<SocketAddr>>>
+}
+
+impl PortReuse {
+    /// Registers the address of a new listener.
+    fn register(&self, addr: SocketAddr) {
+        trace!("Registering for port reuse: {}", addr);
+        self.listen_addrs.write().expect("lock is not poisoned").insert(addr);
+    }
+
+    /// Unregisters the address of a closed listener.
+    fn unregister(&self, addr: &SocketAddr) {
+        trace!("Unregistering for port reuse: {}", addr);
+        self.listen_addrs.write().expect("lock is not poisoned").remove(addr);
+    }
+
+    /// Selects the listen address to bind an outbound connection to the
+    /// given remote IP address to, if any.
+    ///
+    /// Addresses of the same IP version qualify if they are unspecified or
+    /// if they are loopback addresses exactly when the remote IP address is.
+    fn local_dial_addr(&self, remote_ip: &IpAddr) -> Option<SocketAddr> {
+        self.listen_addrs.read().expect("lock is not poisoned")
+            .iter()
+            .find(|addr| {
+                addr.is_ipv4() == remote_ip.is_ipv4()
+                    && (addr.ip().is_unspecified() || addr.ip().is_loopback() == remote_ip.is_loopback())
+            })
+            .copied()
+    }
+}
+
+/// Creates a TCP socket for the IP version of the given address.
+fn create_socket(addr: &SocketAddr) -> io::Result<Socket> {
+    if addr.is_ipv4() {
+        Socket::new(Domain::IPV4, Type::STREAM, Some(socket2::Protocol::TCP))
+    } else {
+        let s = Socket::new(Domain::IPV6, Type::STREAM, Some(socket2::Protocol::TCP))?;
+        s.set_only_v6(true)?;
+        Ok(s)
+    }
+}
+
+/// Creates a TCP socket for dialing the given remote address, bound to
+/// the given local address, if any.
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+fn create_dial_socket(remote: &SocketAddr, local: Option<&SocketAddr>, options: &SocketOptions) -> io::Result<Socket> {
+    let socket = create_socket(remote)?;
+    options.apply(&socket, remote)?;
+    if let Some(local) = local {
+        socket.set_reuse_address(true)?;
+        set_reuse_port(&socket)?;
+        socket.bind(&(*local).into())?;
+    }
+    Ok(socket)
+}
+
+/// Sets `SO_REUSEPORT` on the given socket.
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+/// `SO_REUSEPORT` is not supported on this platform.
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_: &Socket) -> io::Result<()> {
+    Ok(())
+}
+
 // This type of logic should probably be moved into the multiaddr package
 fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Result<SocketAddr, ()> {
     let mut iter = addr.iter();
@@ -457,37 +768,55 @@ fn check_for_interface_changes<T>(
     // The local IP address of this socket is new to us.
     // We check for changes in the set of host addresses and report new
     // and expired addresses.
-    //
-    // TODO: We do not detect expired addresses unless there is a new address.
-    let old_listen_addrs = std::mem::replace(listen_addrs, host_addresses(listen_port)?);
+    update_addresses(listen_port, listen_addrs, pending)?;
 
+    // We should now be able to find the local address, if not something
+    // is seriously wrong and we report an error.
+    if listen_addrs.iter()
+        .find(|(ip, net, _)| ip == &socket_addr.ip() || net.contains(&socket_addr.ip()))
+        .is_none()
+    {
+        let msg = format!("{} does not match any listen address", socket_addr.ip());
+        return Err(io::Error::new(io::ErrorKind::Other, msg))
+    }
+
+    Ok(())
+}
+
+// Check all host interfaces again and report new and expired listen addresses.
+fn update_addresses<T>(
+    listen_port: u16,
+    listen_addrs: &mut Vec<(IpAddr, IpNet, Multiaddr)>,
+    pending: &mut Buffer<T>
+) -> Result<(), io::Error> {
+    let new_listen_addrs = host_addresses(listen_port)?;
+    let old_listen_addrs = std::mem::replace(listen_addrs, new_listen_addrs);
+    report_changes(&old_listen_addrs, listen_addrs, pending);
+    Ok(())
+}
+
+// Report the addresses no longer in use as expired and the ones not
+// used before as new.
+fn report_changes<T>(
+    old_listen_addrs: &[(IpAddr, IpNet, Multiaddr)],
+    new_listen_addrs: &[(IpAddr, IpNet, Multiaddr)],
+    pending: &mut Buffer<T>
+) {
     // Check for addresses no longer in use.
     for (ip, _, ma) in old_listen_addrs.iter() {
-        if listen_addrs.iter().find(|(i, ..)| i == ip).is_none() {
+        if new_listen_addrs.iter().find(|(i, ..)| i == ip).is_none() {
             debug!("Expired listen address: {}", ma);
             pending.push_back(Ok(ListenerEvent::AddressExpired(ma.clone())));
         }
     }
 
     // Check for new addresses.
-    for (ip, _, ma) in listen_addrs.iter() {
+    for (ip, _, ma) in new_listen_addrs.iter() {
         if old_listen_addrs.iter().find(|(i, ..)| i == ip).is_none() {
             debug!("New listen address: {}", ma);
             pending.push_back(Ok(ListenerEvent::NewAddress(ma.clone())));
         }
     }
-
-    // We should now be able to find the local address, if not something
-    // is seriously wrong and we report an error.
-    if listen_addrs.iter()
-        .find(|(ip, net, _)| ip == &socket_addr.ip() || net.contains(&socket_addr.ip()))
-        .is_none()
-    {
-        let msg = format!("{} does not match any listen address", socket_addr.ip());
-        return Err(io::Error::new(io::ErrorKind::Other, msg))
-    }
-
-    Ok(())
 }
 
 #[cfg(test)]
@@ -495,7 +824,7 @@ mod tests {
     use futures::prelude::*;
     use libp2p_core::{Transport, multiaddr::{Multiaddr, Protocol}, transport::ListenerEvent};
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-    use super::multiaddr_to_socketaddr;
+    use super::{multiaddr_to_socketaddr, report_changes};
     #[cfg(feature = "async-std")]
     use super::TcpConfig;
 
@@ -543,6 +872,31 @@ mod tests {
         test("/ip6/::1/tcp/0".parse().unwrap());
     }
 
+    #[test]
+    fn interface_address_changes() {
+        use ipnet::IpNet;
+
+        let addr = |ip: &str| {
+            let ip = ip.parse::<IpAddr>().unwrap();
+            (ip, IpNet::from(ip), super::ip_to_multiaddr(ip, 1234))
+        };
+        let old = vec![addr("127.0.0.1"), addr("192.168.1.2")];
+        let new = vec![addr("127.0.0.1"), addr("10.0.0.2")];
+
+        let mut pending = super::Buffer::<()>::new();
+        report_changes(&old, &new, &mut pending);
+        let events = pending.into_iter().map(|e| e.unwrap()).collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            ListenerEvent::AddressExpired(a) => assert_eq!(a, &old[1].2),
+            e => panic!("Unexpected event: {:?}", e)
+        }
+        match &events[1] {
+            ListenerEvent::NewAddress(a) => assert_eq!(a, &new[1].2),
+            e => panic!("Unexpected event: {:?}", e)
+        }
+    }
+
     #[test]
     fn multiaddr_to_tcp_conversion() {
         use std::net::Ipv6Addr;
@@ -638,6 +992,113 @@ mod tests {
         test("/ip6/::1/tcp/0".parse().unwrap());
     }
 
+    #[test]
+    #[cfg(feature = "async-std")]
+    fn port_reuse_dialing() {
+        async fn listen_addr(listener: &mut <TcpConfig as Transport>::Listener) -> Multiaddr {
+            match listener.next().await.unwrap().unwrap() {
+                ListenerEvent::NewAddress(a) => a,
+                e => panic!("Unexpected event: {:?}", e)
+            }
+        }
+
+        async_std::task::block_on(async {
+            let mut remote = TcpConfig::new().listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+            let remote_addr = listen_addr(&mut remote).await;
+
+            let tcp = TcpConfig::new().port_reuse(true);
+
+            // Without a listener, an ephemeral port is used.
+            let _conn1 = tcp.clone().dial(remote_addr.clone()).unwrap().await.unwrap();
+            let observed1 = match remote.next().await.unwrap().unwrap() {
+                ListenerEvent::Upgrade { remote_addr, .. } => remote_addr,
+                e => panic!("Unexpected event: {:?}", e)
+            };
+
+            let mut local = tcp.clone().listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+            let local_addr = listen_addr(&mut local).await;
+            assert_ne!(observed1, local_addr);
+
+            // With a listener, its address is used.
+            let _conn2 = tcp.clone().dial(remote_addr.clone()).unwrap().await.unwrap();
+            match remote.next().await.unwrap().unwrap() {
+                ListenerEvent::Upgrade { remote_addr, .. } => assert_eq!(remote_addr, local_addr),
+                e => panic!("Unexpected event: {:?}", e)
+            }
+
+            // Once the listener is closed, an ephemeral port is used again.
+            drop(local);
+            let _conn3 = tcp.dial(remote_addr).unwrap().await.unwrap();
+            match remote.next().await.unwrap().unwrap() {
+                ListenerEvent::Upgrade { remote_addr, .. } => assert_ne!(remote_addr, local_addr),
+                e => panic!("Unexpected event: {:?}", e)
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(all(feature = "async-std", target_os = "linux"))]
+    fn socket_options_dialing() {
+        use std::os::unix::io::AsRawFd;
+        use std::time::Duration;
+
+        fn getsockopt(fd: libc::c_int, level: libc::c_int, name: libc::c_int) -> libc::c_int {
+            let mut value: libc::c_int = 0;
+            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            // Safe because `value` is a valid `c_int` of the given size.
+            let res = unsafe {
+                libc::getsockopt(fd, level, name, &mut value as *mut libc::c_int as *mut libc::c_void, &mut len)
+            };
+            assert_eq!(res, 0);
+            value
+        }
+
+        async_std::task::block_on(async {
+            let mut remote = TcpConfig::new().listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+            let remote_addr = match remote.next().await.unwrap().unwrap() {
+                ListenerEvent::NewAddress(a) => a,
+                e => panic!("Unexpected event: {:?}", e)
+            };
+
+            let local_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+            let tcp = TcpConfig::new()
+                .keepalive(Duration::from_secs(60))
+                .keepalive_interval(Duration::from_secs(7))
+                .keepalive_retries(3)
+                .tos(0x10)
+                .dial_local_addr(local_addr);
+
+            let conn = tcp.dial(remote_addr).unwrap().await.unwrap();
+            let fd = conn.inner.as_raw_fd();
+            assert_eq!(getsockopt(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 1);
+            assert_eq!(getsockopt(fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE), 60);
+            assert_eq!(getsockopt(fd, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL), 7);
+            assert_eq!(getsockopt(fd, libc::IPPROTO_TCP, libc::TCP_KEEPCNT), 3);
+            assert_eq!(getsockopt(fd, libc::IPPROTO_IP, libc::IP_TOS), 0x10);
+
+            let observed = match remote.next().await.unwrap().unwrap() {
+                ListenerEvent::Upgrade { remote_addr, .. } => remote_addr,
+                e => panic!("Unexpected event: {:?}", e)
+            };
+            assert_eq!(multiaddr_to_socketaddr(&observed).unwrap(), conn.inner.local_addr().unwrap());
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "async-std")]
+    fn socket_options_dialing_refused() {
+        // A port nobody listens on.
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap();
+
+        async_std::task::block_on(async {
+            // The socket option requires connecting a socket of its own.
+            let tcp = TcpConfig::new().send_buffer_size(1 << 16);
+            let err = tcp.dial(addr).unwrap().await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+        });
+    }
+
     #[test]
     #[cfg(feature = "async-std")]
     fn replace_port_0_in_returned_multiaddr_ipv4() {
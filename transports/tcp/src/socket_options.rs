@@ -0,0 +1,132 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Socket options which are set before a socket listens or connects.
+
+use socket2::{Socket, TcpKeepalive};
+use std::{io, net::SocketAddr, time::Duration};
+
+/// Socket options of listening and outbound sockets.
+///
+/// Sockets of inbound connections inherit the options of the listening socket.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SocketOptions {
+    /// The idle time before the first keepalive probe, if enabled.
+    pub(crate) keepalive: Option<Duration>,
+    /// The interval between keepalive probes.
+    pub(crate) keepalive_interval: Option<Duration>,
+    /// The number of unanswered keepalive probes before the connection is closed.
+    pub(crate) keepalive_retries: Option<u32>,
+    /// `SO_SNDBUF`.
+    pub(crate) send_buffer_size: Option<usize>,
+    /// `SO_RCVBUF`.
+    pub(crate) recv_buffer_size: Option<usize>,
+    /// `SO_LINGER`.
+    pub(crate) linger: Option<Option<Duration>>,
+    /// `IP_TOS`.
+    pub(crate) tos: Option<u8>,
+}
+
+impl SocketOptions {
+    /// Are all options left at their defaults?
+    pub(crate) fn is_empty(&self) -> bool {
+        self == &SocketOptions::default()
+    }
+
+    /// Applies the options to a socket for the given address.
+    pub(crate) fn apply(&self, socket: &Socket, addr: &SocketAddr) -> io::Result<()> {
+        if let Some(idle) = self.keepalive {
+            let params = keepalive_params(idle, self.keepalive_interval, self.keepalive_retries);
+            socket.set_tcp_keepalive(&params)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(linger) = self.linger {
+            socket.set_linger(linger)?;
+        }
+        if let Some(tos) = self.tos {
+            set_tos(socket, addr, tos)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds the keepalive parameters, leaving out those which are not
+/// supported on this platform.
+fn keepalive_params(idle: Duration, interval: Option<Duration>, retries: Option<u32>) -> TcpKeepalive {
+    let params = TcpKeepalive::new().with_time(idle);
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    ))]
+    let params = {
+        let params = match interval {
+            Some(interval) => params.with_interval(interval),
+            None => params
+        };
+        match retries {
+            Some(retries) => params.with_retries(retries),
+            None => params
+        }
+    };
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    )))]
+    let _ = (interval, retries);
+    params
+}
+
+/// Sets `IP_TOS` on IPv4 sockets.
+#[cfg(not(any(
+    target_os = "fuchsia",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "illumos"
+)))]
+fn set_tos(socket: &Socket, addr: &SocketAddr, tos: u8) -> io::Result<()> {
+    if addr.is_ipv4() {
+        socket.set_tos(u32::from(tos))?;
+    }
+    Ok(())
+}
+
+/// The type of service is not supported on this platform.
+#[cfg(any(
+    target_os = "fuchsia",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "illumos"
+))]
+fn set_tos(_: &Socket, _: &SocketAddr, _: u8) -> io::Result<()> {
+    Ok(())
+}
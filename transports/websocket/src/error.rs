@@ -19,7 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use libp2p_core::Multiaddr;
-use crate::tls;
+use crate::{proxy, tls};
 use std::{error, fmt};
 
 /// Error in WebSockets.
@@ -29,6 +29,8 @@ pub enum Error<E> {
     Transport(E),
     /// A TLS related error.
     Tls(tls::Error),
+    /// An HTTP proxy related error.
+    Proxy(proxy::Error),
     /// Websocket handshake error.
     Handshake(Box<dyn error::Error + Send + Sync>),
     /// The configured maximum of redirects have been made.
@@ -46,6 +48,7 @@ impl<E: fmt::Display> fmt::Display for Error<E> {
         match self {
             Error::Transport(err) => write!(f, "{}", err),
             Error::Tls(err) => write!(f, "{}", err),
+            Error::Proxy(err) => write!(f, "{}", err),
             Error::Handshake(err) => write!(f, "{}", err),
             Error::InvalidMultiaddr(ma) => write!(f, "invalid multi-address: {}", ma),
             Error::TooManyRedirects => f.write_str("too many redirects"),
@@ -60,6 +63,7 @@ impl<E: error::Error + 'static> error::Error for Error<E> {
         match self {
             Error::Transport(err) => Some(err),
             Error::Tls(err) => Some(err),
+            Error::Proxy(err) => Some(err),
             Error::Handshake(err) => Some(&**err),
             Error::Base(err) => Some(&**err),
             Error::InvalidMultiaddr(_)
@@ -74,3 +78,9 @@ impl<E> From<tls::Error> for Error<E> {
         Error::Tls(e)
     }
 }
+
+impl<E> From<proxy::Error> for Error<E> {
+    fn from(e: proxy::Error) -> Self {
+        Error::Proxy(e)
+    }
+}
@@ -19,7 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use async_tls::{client, server};
-use crate::{error::Error, tls};
+use crate::{error::Error, proxy, tls};
 use either::Either;
 use futures::{future::BoxFuture, prelude::*, ready, stream::BoxStream};
 use libp2p_core::{
@@ -45,7 +45,8 @@ pub struct WsConfig<T> {
     max_data_size: usize,
     tls_config: tls::Config,
     max_redirects: u8,
-    use_deflate: bool
+    use_deflate: bool,
+    proxy: Option<proxy::Config>
 }
 
 impl<T> WsConfig<T> {
@@ -56,7 +57,8 @@ impl<T> WsConfig<T> {
             max_data_size: MAX_DATA_SIZE,
             tls_config: tls::Config::client(),
             max_redirects: 0,
-            use_deflate: false
+            use_deflate: false,
+            proxy: None
         }
     }
 
@@ -93,6 +95,12 @@ impl<T> WsConfig<T> {
         self.use_deflate = flag;
         self
     }
+
+    /// Set the HTTP proxy through which outbound connections are tunneled.
+    pub fn set_proxy(&mut self, c: proxy::Config) -> &mut Self {
+        self.proxy = Some(c);
+        self
+    }
 }
 
 type TlsOrPlain<T> = EitherOutput<EitherOutput<client::TlsStream<T>, server::TlsStream<T>>, T>;
@@ -293,13 +301,32 @@ where
                 }
             };
 
-        let dial = self.transport.dial(inner_addr)
+        let proxy = self.proxy.as_ref().filter(|p| !p.is_excluded(&address));
+
+        let dial_addr = if let Some(proxy) = proxy {
+            trace!("dialing {} via proxy {}", address, proxy.addr);
+            proxy.addr.clone()
+        } else {
+            inner_addr
+        };
+
+        let dial = self.transport.dial(dial_addr)
             .map_err(|e| match e {
                 TransportError::MultiaddrNotSupported(a) => Error::InvalidMultiaddr(a),
                 TransportError::Other(e) => Error::Transport(e)
             })?;
 
-        let stream = dial.map_err(Error::Transport).await?;
+        let mut stream = dial.map_err(Error::Transport).await?;
+
+        if let Some(proxy) = proxy { // establish tunnel
+            proxy.connect(&mut stream, &host_port)
+                .map_err(|e| {
+                    debug!("proxy tunnel to {} failed: {}", address, e);
+                    Error::Proxy(e)
+                })
+                .await?
+        }
+
         trace!("connected to {}", address);
 
         let stream =
@@ -351,7 +378,7 @@ fn host_and_dnsname<T>(addr: &Multiaddr) -> Result<(String, Option<webpki::DNSNa
         (Some(Protocol::Ip4(ip)), Some(Protocol::Tcp(port))) =>
             Ok((format!("{}:{}", ip, port), None)),
         (Some(Protocol::Ip6(ip)), Some(Protocol::Tcp(port))) =>
-            Ok((format!("{}:{}", ip, port), None)),
+            Ok((format!("[{}]:{}", ip, port), None)),
         (Some(Protocol::Dns(h)), Some(Protocol::Tcp(port))) =>
             Ok((format!("{}:{}", &h, port), Some(tls::dns_name_ref(&h)?.to_owned()))),
         (Some(Protocol::Dns4(h)), Some(Protocol::Tcp(port))) =>
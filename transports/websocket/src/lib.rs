@@ -22,6 +22,7 @@
 
 pub mod error;
 pub mod framed;
+pub mod proxy;
 pub mod tls;
 
 use error::Error;
@@ -81,6 +82,12 @@ impl<T> WsConfig<T> {
         self.transport.use_deflate(flag);
         self
     }
+
+    /// Set the HTTP proxy through which outbound connections are tunneled.
+    pub fn set_proxy(&mut self, c: proxy::Config) -> &mut Self {
+        self.transport.set_proxy(c);
+        self
+    }
 }
 
 impl<T> From<framed::WsConfig<T>> for WsConfig<T> {
@@ -187,7 +194,7 @@ mod tests {
     use libp2p_tcp as tcp;
     use futures::prelude::*;
     use libp2p_core::{Transport, multiaddr::Protocol};
-    use super::WsConfig;
+    use super::{WsConfig, error::Error, proxy};
 
     #[test]
     fn dialer_connects_to_listener_ipv4() {
@@ -201,8 +208,92 @@ mod tests {
         futures::executor::block_on(connect(a))
     }
 
+    #[test]
+    fn dialer_connects_via_proxy() {
+        futures::executor::block_on(async {
+            let proxy_addr = proxy_server("HTTP/1.1 200 Connection established\r\n\r\n").await;
+            let mut config = proxy::Config::new(proxy_addr);
+            config.set_basic_auth("user", "pass").unwrap();
+            connect_with("/ip4/127.0.0.1/tcp/0/ws".parse().unwrap(), Some(config)).await
+        })
+    }
+
+    #[test]
+    fn dialer_connects_via_proxy_ipv6() {
+        futures::executor::block_on(async {
+            let proxy_addr = proxy_server("HTTP/1.1 200 Connection established\r\n\r\n").await;
+            let mut config = proxy::Config::new(proxy_addr);
+            config.set_basic_auth("user", "pass").unwrap();
+            connect_with("/ip6/::1/tcp/0/ws".parse().unwrap(), Some(config)).await
+        })
+    }
+
+    #[test]
+    fn proxy_rejects_tunnel() {
+        futures::executor::block_on(async {
+            let proxy_addr = proxy_server("HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await;
+            let mut ws_config = WsConfig::new(tcp::TcpConfig::new());
+            ws_config.set_proxy(proxy::Config::new(proxy_addr));
+            match ws_config.dial("/ip4/127.0.0.1/tcp/1/ws".parse().unwrap()).unwrap().await {
+                Err(Error::Proxy(proxy::Error::Status(407))) => {}
+                Err(e) => panic!("unexpected error: {}", e),
+                Ok(_) => panic!("unexpected connection")
+            }
+        })
+    }
+
+    #[test]
+    fn no_proxy_dials_directly() {
+        futures::executor::block_on(async {
+            let mut config = proxy::Config::new("/ip4/127.0.0.1/tcp/1".parse().unwrap());
+            config.add_no_proxy("127.0.0.0/8");
+            connect_with("/ip4/127.0.0.1/tcp/0/ws".parse().unwrap(), Some(config)).await
+        })
+    }
+
+    /// Starts an HTTP proxy which answers a single `CONNECT` request with
+    /// `reply` and, if successful, relays the tunneled connection.
+    async fn proxy_server(reply: &'static str) -> Multiaddr {
+        let listener = async_std::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        async_std::task::spawn(async move {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                client.read_exact(&mut byte).await.unwrap();
+                head.push(byte[0])
+            }
+            let head = String::from_utf8(head).unwrap();
+            assert!(head.starts_with("CONNECT "));
+            let authority = head.split(' ').nth(1).unwrap();
+            let authority = authority.parse::<std::net::SocketAddr>().expect("valid authority");
+            let target = async_std::net::TcpStream::connect(authority).await;
+            client.write_all(reply.as_bytes()).await.unwrap();
+            if reply.starts_with("HTTP/1.1 200") {
+                // "user:pass"
+                assert!(head.contains("\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+                let target = target.unwrap();
+                let (mut client_r, mut target_w) = (client.clone(), target.clone());
+                async_std::task::spawn(async move {
+                    futures::io::copy(&mut client_r, &mut target_w).await
+                });
+                let (mut target_r, mut client_w) = (target, client);
+                let _ = futures::io::copy(&mut target_r, &mut client_w).await;
+            }
+        });
+        format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()
+    }
+
     async fn connect(listen_addr: Multiaddr) {
-        let ws_config = WsConfig::new(tcp::TcpConfig::new());
+        connect_with(listen_addr, None).await
+    }
+
+    async fn connect_with(listen_addr: Multiaddr, proxy: Option<proxy::Config>) {
+        let mut ws_config = WsConfig::new(tcp::TcpConfig::new());
+        if let Some(proxy) = proxy {
+            ws_config.set_proxy(proxy);
+        }
 
         let mut listener = ws_config.clone()
             .listen_on(listen_addr)
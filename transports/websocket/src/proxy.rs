@@ -0,0 +1,320 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Tunneling of websocket connections through an HTTP proxy.
+//!
+//! The connection to the proxy is established with the underlying transport,
+//! followed by an HTTP `CONNECT` request for the host and port of the websocket
+//! address. TLS and the websocket handshake then take place inside the tunnel.
+
+use data_encoding::BASE64;
+use futures::prelude::*;
+use libp2p_core::multiaddr::{Multiaddr, Protocol};
+use std::{fmt, io, net::IpAddr};
+
+/// Max. size of the HTTP response to a `CONNECT` request, excluding any body.
+const MAX_RESPONSE_HEAD: usize = 8 * 1024;
+
+/// HTTP proxy configuration.
+#[derive(Clone)]
+pub struct Config {
+    pub(crate) addr: Multiaddr,
+    authorization: Option<String>,
+    exclusions: Vec<Exclusion>
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The credentials are deliberately left out.
+        f.debug_struct("Config")
+            .field("addr", &self.addr)
+            .field("exclusions", &self.exclusions)
+            .finish()
+    }
+}
+
+impl Config {
+    /// Create a configuration for the HTTP proxy at the given address,
+    /// which is dialed with the underlying transport, e.g. `/dns/proxy/tcp/3128`.
+    pub fn new(addr: Multiaddr) -> Self {
+        Config {
+            addr,
+            authorization: None,
+            exclusions: Vec::new()
+        }
+    }
+
+    /// Authenticate with the proxy using the "Basic" HTTP authentication scheme.
+    ///
+    /// Fails if the username contains a `:`, which the scheme uses to
+    /// separate it from the password.
+    pub fn set_basic_auth(&mut self, username: &str, password: &str) -> Result<&mut Self, InvalidUsername> {
+        if username.contains(':') {
+            return Err(InvalidUsername)
+        }
+        let credentials = format!("{}:{}", username, password);
+        self.authorization = Some(format!("Basic {}", BASE64.encode(credentials.as_bytes())));
+        Ok(self)
+    }
+
+    /// Add hosts which are dialed directly instead of through the proxy.
+    ///
+    /// The list uses the format of the `NO_PROXY` environment variable:
+    /// comma-separated host names, which also match all of their subdomains,
+    /// IP addresses and IP networks in CIDR notation, e.g.
+    /// `localhost,.example.com,10.0.0.0/8`. A single `*` matches all hosts.
+    /// Ports are ignored.
+    pub fn add_no_proxy(&mut self, list: &str) -> &mut Self {
+        self.exclusions.extend(list.split(',').filter_map(Exclusion::parse));
+        self
+    }
+
+    /// Should the given websocket address be dialed directly?
+    pub(crate) fn is_excluded(&self, addr: &Multiaddr) -> bool {
+        let host = match addr.iter().next() {
+            Some(Protocol::Ip4(ip)) => Host::Ip(IpAddr::V4(ip)),
+            Some(Protocol::Ip6(ip)) => Host::Ip(IpAddr::V6(ip)),
+            Some(Protocol::Dns(h)) | Some(Protocol::Dns4(h)) | Some(Protocol::Dns6(h)) =>
+                Host::Domain(h.trim_end_matches('.').to_ascii_lowercase()),
+            _ => return false
+        };
+        self.exclusions.iter().any(|e| e.matches(&host))
+    }
+
+    /// Request a tunnel to `authority` (i.e. `host:port`) from the proxy
+    /// connected to via `io`.
+    ///
+    /// On success, `io` is ready to carry the tunneled connection.
+    pub(crate) async fn connect<T>(&self, io: &mut T, authority: &str) -> Result<(), Error>
+    where
+        T: AsyncRead + AsyncWrite + Unpin
+    {
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        if let Some(authorization) = &self.authorization {
+            request.push_str("Proxy-Authorization: ");
+            request.push_str(authorization);
+            request.push_str("\r\n")
+        }
+        request.push_str("\r\n");
+        io.write_all(request.as_bytes()).await?;
+        io.flush().await?;
+
+        // The response is read byte by byte in order to not consume
+        // any data of the tunneled connection that follows it.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() == MAX_RESPONSE_HEAD {
+                return Err(Error::InvalidResponse)
+            }
+            let mut byte = [0];
+            io.read_exact(&mut byte).await?;
+            head.push(byte[0])
+        }
+
+        match status_code(&head) {
+            Some(code) if (200 .. 300).contains(&code) => Ok(()),
+            Some(code) => Err(Error::Status(code)),
+            None => Err(Error::InvalidResponse)
+        }
+    }
+}
+
+/// Extract the status code from the head of an HTTP/1.x response.
+fn status_code(head: &[u8]) -> Option<u16> {
+    let line = head.split(|b| *b == b'\n').next()?;
+    let mut parts = std::str::from_utf8(line).ok()?.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/1.") {
+        return None
+    }
+    let code = parts.next()?;
+    if code.len() != 3 {
+        return None
+    }
+    code.parse().ok()
+}
+
+/// A host to connect to.
+enum Host {
+    Ip(IpAddr),
+    /// A lowercase domain name without trailing dot.
+    Domain(String)
+}
+
+/// A `NO_PROXY` entry.
+#[derive(Debug, Clone)]
+enum Exclusion {
+    /// All hosts.
+    All,
+    /// A lowercase domain name without leading or trailing dot, and its subdomains.
+    Domain(String),
+    /// An IP address.
+    Ip(IpAddr),
+    /// An IP network, given by address and prefix length.
+    Network(IpAddr, u8)
+}
+
+impl Exclusion {
+    fn parse(entry: &str) -> Option<Self> {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            return None
+        }
+        if entry == "*" {
+            return Some(Exclusion::All)
+        }
+        if let Some(i) = entry.find('/') {
+            let ip = entry[.. i].parse::<IpAddr>().ok()?;
+            let prefix = entry[i + 1 ..].parse::<u8>().ok()?;
+            let max = if ip.is_ipv4() { 32 } else { 128 };
+            if prefix > max {
+                return None
+            }
+            return Some(Exclusion::Network(ip, prefix))
+        }
+        if let Ok(ip) = entry.parse::<IpAddr>() {
+            return Some(Exclusion::Ip(ip))
+        }
+        // Bracketed IPv6 address, optionally with a port.
+        if entry.starts_with('[') {
+            let end = entry.find(']')?;
+            return entry[1 .. end].parse().ok().map(Exclusion::Ip)
+        }
+        // Host name or IPv4 address, optionally with a port.
+        let host = match entry.rfind(':') {
+            Some(i) => &entry[.. i],
+            None => entry
+        };
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Some(Exclusion::Ip(ip))
+        }
+        let domain = host.trim_matches('.').to_ascii_lowercase();
+        if domain.is_empty() {
+            return None
+        }
+        Some(Exclusion::Domain(domain))
+    }
+
+    fn matches(&self, host: &Host) -> bool {
+        match (self, host) {
+            (Exclusion::All, _) => true,
+            (Exclusion::Domain(d), Host::Domain(h)) =>
+                h == d || (h.ends_with(d.as_str()) && h[.. h.len() - d.len()].ends_with('.')),
+            (Exclusion::Ip(ip), Host::Ip(h)) => ip == h,
+            (Exclusion::Network(IpAddr::V4(net), prefix), Host::Ip(IpAddr::V4(h))) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(*prefix)).unwrap_or(0);
+                u32::from(*net) & mask == u32::from(*h) & mask
+            }
+            (Exclusion::Network(IpAddr::V6(net), prefix), Host::Ip(IpAddr::V6(h))) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(*prefix)).unwrap_or(0);
+                u128::from(*net) & mask == u128::from(*h) & mask
+            }
+            _ => false
+        }
+    }
+}
+
+/// Proxy errors.
+#[derive(Debug)]
+pub enum Error {
+    /// An underlying I/O error.
+    Io(io::Error),
+    /// The proxy sent an invalid response.
+    InvalidResponse,
+    /// The proxy refused to establish the tunnel with the given HTTP status code.
+    Status(u16)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "i/o error: {}", e),
+            Error::InvalidResponse => f.write_str("invalid proxy response"),
+            Error::Status(407) => f.write_str("proxy authentication required"),
+            Error::Status(code) => write!(f, "proxy rejected tunnel; status code = {}", code)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::InvalidResponse | Error::Status(_) => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// The username passed to [`Config::set_basic_auth`] contains a `:`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidUsername;
+
+impl fmt::Display for InvalidUsername {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid proxy username")
+    }
+}
+
+impl std::error::Error for InvalidUsername {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, InvalidUsername};
+
+    #[test]
+    fn no_proxy() {
+        let mut config = Config::new("/ip4/127.0.0.1/tcp/3128".parse().unwrap());
+        config.add_no_proxy(" localhost, .Example.com,10.0.0.0/8,192.168.1.1:8080,[::1],fd00::/8,");
+
+        let excluded = |a: &str| config.is_excluded(&a.parse().unwrap());
+
+        assert!(excluded("/dns/localhost/tcp/80/ws"));
+        assert!(excluded("/dns4/example.com/tcp/443/wss"));
+        assert!(excluded("/dns/a.b.EXAMPLE.com./tcp/443/wss"));
+        assert!(excluded("/ip4/10.1.2.3/tcp/80/ws"));
+        assert!(excluded("/ip4/192.168.1.1/tcp/80/ws"));
+        assert!(excluded("/ip6/::1/tcp/80/ws"));
+        assert!(excluded("/ip6/fd12::1/tcp/80/ws"));
+
+        assert!(!excluded("/dns/notexample.com/tcp/443/wss"));
+        assert!(!excluded("/dns/localhost.localdomain/tcp/80/ws"));
+        assert!(!excluded("/ip4/11.0.0.1/tcp/80/ws"));
+        assert!(!excluded("/ip4/192.168.1.2/tcp/80/ws"));
+        assert!(!excluded("/ip6/fe80::1/tcp/80/ws"));
+
+        config.add_no_proxy("*");
+        assert!(config.is_excluded(&"/dns/libp2p.io/tcp/443/wss".parse().unwrap()));
+    }
+
+    #[test]
+    fn basic_auth() {
+        let mut config = Config::new("/ip4/127.0.0.1/tcp/3128".parse().unwrap());
+        assert_eq!(config.set_basic_auth("us:er", "pass").err(), Some(InvalidUsername));
+        assert!(config.authorization.is_none());
+
+        config.set_basic_auth("user", "pa:ss").unwrap();
+        assert_eq!(config.authorization.as_deref(), Some("Basic dXNlcjpwYTpzcw=="));
+    }
+}